@@ -0,0 +1,559 @@
+use super::aps::*;
+use super::binary_reader::*;
+use super::bins::*;
+use super::bool_coder::*;
+use super::common::*;
+use super::encoder_context::*;
+use super::extractor::*;
+use super::nal::*;
+use super::opi_encoder::*;
+use super::ph_encoder::*;
+use super::ph_parser::*;
+use super::picture_header::*;
+use super::pps::*;
+use super::pps_encoder::*;
+use super::pps_parser::*;
+use super::sh_parser::*;
+use super::slice::*;
+use super::slice_encoder::*;
+use super::sps::*;
+use super::sps_encoder::*;
+use super::sps_parser::*;
+use super::vps_encoder::*;
+use super::vps_parser::*;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+/// A syntax element of a parameter set or a header. The name is the one traced by the header
+/// encoder for the same position, and is None when the encoder does not trace it.
+pub struct AnalyzedSyntaxElement {
+    pub name: Option<&'static str>,
+    pub descriptor: String,
+    pub value: SyntaxElementValue,
+}
+
+pub struct AnalyzedNalUnit {
+    pub nal_unit_type: NALUnitType,
+    pub layer_id: usize,
+    pub temporal_id: usize,
+    /// The size in bytes excluding the start code.
+    pub size: usize,
+    pub syntax_elements: Vec<AnalyzedSyntaxElement>,
+}
+
+pub struct AnalyzedPicture {
+    pub pic_order_cnt_lsb: usize,
+    /// Indices of the PH NAL unit and the VCL NAL units of the picture.
+    pub nal_units: Vec<usize>,
+    pub bits: usize,
+}
+
+pub struct StreamAnalysis {
+    pub nal_units: Vec<AnalyzedNalUnit>,
+    pub pictures: Vec<AnalyzedPicture>,
+}
+
+/// Lists the NAL units of a byte stream with their parameter sets and headers, and groups the PH
+/// and VCL NAL units into pictures.
+pub fn analyze_byte_stream(reader: &mut BinaryReader) -> Result<StreamAnalysis, String> {
+    let mut analyzer = StreamAnalyzer::new();
+    for nal_unit in read_byte_stream_nal_units(reader) {
+        analyzer.analyze_nal_unit(&nal_unit)?;
+    }
+    Ok(analyzer.analysis)
+}
+
+/// Analyzes NAL units one by one, keeping the parameter sets and the picture header referred to
+/// by the following NAL units. Parameter sets and headers are parsed as the decoder does, and
+/// coded again by the header encoders so that each syntax element is named as in their traces.
+/// Only the common part of an APS is parsed.
+pub struct StreamAnalyzer {
+    encoder_context: Arc<Mutex<EncoderContext>>,
+    coder: BoolCoder,
+    aps: [AdaptationParameterSet; 3],
+    sps: Option<SequenceParameterSet>,
+    pps: Option<PictureParameterSet>,
+    ph: Option<PictureHeader>,
+    pub analysis: StreamAnalysis,
+}
+
+impl StreamAnalyzer {
+    pub fn new() -> StreamAnalyzer {
+        StreamAnalyzer {
+            encoder_context: Arc::new(Mutex::new(EncoderContext::new())),
+            coder: BoolCoder::new(),
+            aps: [
+                AdaptationParameterSet::new_alf(0),
+                AdaptationParameterSet::new_lmcs(0),
+                AdaptationParameterSet::new_sl(0),
+            ],
+            sps: None,
+            pps: None,
+            ph: None,
+            analysis: StreamAnalysis {
+                nal_units: vec![],
+                pictures: vec![],
+            },
+        }
+    }
+
+    pub fn analyze_nal_unit(&mut self, nal_unit: &[u8]) -> Result<&AnalyzedNalUnit, String> {
+        let ectx = &self.encoder_context;
+        let coder = &mut self.coder;
+        let pictures = &mut self.analysis.pictures;
+        let (layer_id, nal_unit_type, temporal_id) = read_nal_unit_header(nal_unit)?;
+        let rbsp = remove_emulation_prevention_bytes(nal_unit);
+        let mut rbsp_reader = BinaryReader::vec(&rbsp).unwrap();
+        rbsp_reader.start_recording();
+        let mut bits = vec![];
+        start_syntax_element_trace();
+        match nal_unit_type {
+            NALUnitType::OPI_NUT => {
+                let opi = parse_opi(&mut rbsp_reader);
+                bits = OpiEncoder::new(ectx, coder).encode(&opi);
+            }
+            NALUnitType::VPS_NUT => {
+                let vps = VpsParser::new(ectx, &mut rbsp_reader).parse();
+                ectx.lock().unwrap().update_from_vps(&vps);
+                bits = VpsEncoder::new(ectx, coder).encode(&vps);
+            }
+            NALUnitType::SPS_NUT => {
+                let parsed_sps = SpsParser::new(ectx, &mut rbsp_reader).parse();
+                ectx.lock().unwrap().update_from_sps(&parsed_sps);
+                bits = SpsEncoder::new(ectx, coder).encode(&parsed_sps);
+                self.sps = Some(parsed_sps);
+            }
+            NALUnitType::PPS_NUT => {
+                let sps = self.sps.as_ref().ok_or("PPS before SPS")?;
+                let parsed_pps = PpsParser::new(ectx, &mut rbsp_reader).parse(sps);
+                ectx.lock()
+                    .unwrap()
+                    .update_from_sps_and_pps(sps, &parsed_pps);
+                bits = PpsEncoder::new(ectx, coder).encode(&parsed_pps);
+                self.pps = Some(parsed_pps);
+            }
+            NALUnitType::PREFIX_APS_NUT | NALUnitType::SUFFIX_APS_NUT => {
+                // the APS payload is not parsed as ApsEncoder is not used, and the names of the
+                // preceding syntax elements are given here
+                record_syntax_element("aps.params_type ", 0);
+                rbsp_reader.read_value(3);
+                record_syntax_element("aps.id ", 3);
+                rbsp_reader.read_value(5);
+                record_syntax_element("aps.chroma_present_flag ", 8);
+                rbsp_reader.read_bit();
+                bits = rbsp_bits(&rbsp, 9);
+            }
+            NALUnitType::PH_NUT => {
+                let (sps, pps) = get_active_parameter_sets(&self.sps, &self.pps)?;
+                let parsed_ph = PhParser::new(ectx, &mut rbsp_reader).parse(sps, pps);
+                ectx.lock().unwrap().update_from_ph(&parsed_ph, sps, pps);
+                let mut bins = Bins::new();
+                PhEncoder::new(ectx, coder).encode(&mut bins, &parsed_ph, sps, pps);
+                bits = bins.into_iter().collect();
+                pictures.push(AnalyzedPicture {
+                    pic_order_cnt_lsb: parsed_ph.pic_order_cnt_lsb,
+                    nal_units: vec![],
+                    bits: 0,
+                });
+                self.ph = Some(parsed_ph);
+            }
+            _ => {}
+        }
+        let is_vcl = (nal_unit_type as usize) < NALUnitType::OPI_NUT as usize;
+        if is_vcl {
+            let (sps, pps) = get_active_parameter_sets(&self.sps, &self.pps)?;
+            let aps = &self.aps;
+            let sh = ShParser::new(ectx, &mut rbsp_reader).parse(
+                nal_unit_type,
+                sps,
+                pps,
+                [&aps[0], &aps[1], &aps[2]],
+                self.ph.as_ref(),
+            );
+            if let Some(ph_in_sh) = &sh.ph_in_sh {
+                let mut ectx = ectx.lock().unwrap();
+                ectx.update_from_ph(ph_in_sh, sps, pps);
+                ectx.update_from_sh(&sh, pps);
+            }
+            // only the NAL unit type of a slice is referred to when coding its slice header
+            let slice = Slice {
+                slice_struct: SliceStruct::Rectangle {
+                    tile_col: 0,
+                    tile_row: 0,
+                    num_tile_cols: 1,
+                    num_tile_rows: 1,
+                },
+                nal_unit_type,
+                tiles: Arc::new(Mutex::new(vec![])),
+            };
+            let mut bins = Bins::new();
+            SliceEncoder::new(ectx, coder).encode_sh(&mut bins, &sh, &slice);
+            bits = bins.into_iter().collect();
+            if let Some(ph_in_sh) = &sh.ph_in_sh {
+                pictures.push(AnalyzedPicture {
+                    pic_order_cnt_lsb: ph_in_sh.pic_order_cnt_lsb,
+                    nal_units: vec![],
+                    bits: 0,
+                });
+            }
+        }
+        let nal_unit_idx = self.analysis.nal_units.len();
+        if nal_unit_type == NALUnitType::PH_NUT || is_vcl {
+            let picture = pictures.last_mut().ok_or("slice before picture header")?;
+            picture.nal_units.push(nal_unit_idx);
+            picture.bits += nal_unit.len() * 8;
+        }
+        let names = take_syntax_element_trace();
+        let syntax_elements = rbsp_reader.take_recorded_syntax_elements();
+        self.analysis.nal_units.push(AnalyzedNalUnit {
+            nal_unit_type,
+            layer_id,
+            temporal_id,
+            size: nal_unit.len(),
+            syntax_elements: name_syntax_elements(syntax_elements, &names, &bits, &rbsp),
+        });
+        Ok(&self.analysis.nal_units[nal_unit_idx])
+    }
+}
+
+/// Parses operating_point_information_rbsp( ) as written by OpiEncoder.
+fn parse_opi(reader: &mut BinaryReader) -> OperatingPointInformation {
+    let ols_info_present_flag = reader.read_bit();
+    let htid_info_present_flag = reader.read_bit();
+    let opi_ols_idx = if ols_info_present_flag {
+        Some(reader.read_unsigned_exp_golomb())
+    } else {
+        None
+    };
+    let opi_htid = if htid_info_present_flag {
+        Some(reader.read_value(3) - 1)
+    } else {
+        None
+    };
+    let mut opi = OperatingPointInformation::new(opi_ols_idx, opi_htid);
+    if reader.read_bit() {
+        while reader.more_rbsp_data() {
+            opi.opi_extension_data.push(reader.read_bit());
+        }
+    }
+    let _rbsp_stop_one_bit = reader.read_bit();
+    opi
+}
+
+/// Names the syntax elements read by a parser after the syntax elements traced at the same
+/// positions, where the last name traced at a position is taken since elements of zero bits are
+/// also traced. No names are given when the recoded bins differ from the RBSP, as the positions
+/// cannot be trusted then.
+fn name_syntax_elements(
+    syntax_elements: Vec<ReadSyntaxElement>,
+    names: &[(&'static str, usize)],
+    bits: &[bool],
+    rbsp: &[u8],
+) -> Vec<AnalyzedSyntaxElement> {
+    let is_recoded = bits.len() <= rbsp.len() * 8 && bits == rbsp_bits(rbsp, bits.len());
+    let names: HashMap<usize, &'static str> = if is_recoded {
+        names
+            .iter()
+            .map(|&(name, position)| (position, name.trim()))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+    syntax_elements
+        .into_iter()
+        .map(|syntax_element| AnalyzedSyntaxElement {
+            name: names.get(&syntax_element.position).copied(),
+            descriptor: syntax_element.descriptor,
+            value: syntax_element.value,
+        })
+        .collect()
+}
+
+/// Formats a syntax element in the layout of the header traces of VTM.
+pub fn format_trace_line(name: &str, descriptor: &str, value: &str) -> String {
+    if descriptor.starts_with("u(") {
+        format!("{name:<50} {descriptor}  : {value}")
+    } else {
+        format!("{name:<50} {descriptor} : {value}")
+    }
+}
+
+impl AnalyzedNalUnit {
+    /// Formats the NAL unit header and the syntax elements in the layout of the header traces of
+    /// VTM, where a syntax element which is not named is traced as "-".
+    pub fn to_trace(&self) -> String {
+        let mut trace = format!(
+            "*********** NAL UNIT ({:?}) ***********\n",
+            self.nal_unit_type
+        );
+        for (name, descriptor, value) in [
+            ("forbidden_zero_bit", "u(1)", 0),
+            ("nuh_reserved_zero_bit", "u(1)", 0),
+            ("nuh_layer_id", "u(6)", self.layer_id),
+            ("nal_unit_type", "u(5)", self.nal_unit_type as usize),
+            ("nuh_temporal_id_plus1", "u(3)", self.temporal_id + 1),
+        ] {
+            writeln!(
+                trace,
+                "{}",
+                format_trace_line(name, descriptor, &value.to_string())
+            )
+            .unwrap();
+        }
+        let section = match self.nal_unit_type {
+            NALUnitType::OPI_NUT => "Operating Point Information",
+            NALUnitType::VPS_NUT => "Video Parameter Set",
+            NALUnitType::SPS_NUT => "Sequence Parameter Set",
+            NALUnitType::PPS_NUT => "Picture Parameter Set",
+            NALUnitType::PREFIX_APS_NUT | NALUnitType::SUFFIX_APS_NUT => "Adaptation Parameter Set",
+            NALUnitType::PH_NUT => "Picture Header",
+            _ => "Slice",
+        };
+        writeln!(trace, "=========== {section} ===========").unwrap();
+        for syntax_element in &self.syntax_elements {
+            writeln!(
+                trace,
+                "{}",
+                format_trace_line(
+                    syntax_element.name.unwrap_or("-"),
+                    &syntax_element.descriptor,
+                    &format_value(&syntax_element.value)
+                )
+            )
+            .unwrap();
+        }
+        trace
+    }
+}
+
+fn rbsp_bits(rbsp: &[u8], n_bits: usize) -> Vec<bool> {
+    rbsp.iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 > 0))
+        .take(n_bits)
+        .collect()
+}
+
+fn format_value(value: &SyntaxElementValue) -> String {
+    match value {
+        SyntaxElementValue::Unsigned(value) => value.to_string(),
+        SyntaxElementValue::Signed(value) => value.to_string(),
+        SyntaxElementValue::Bits(bits) => bits
+            .iter()
+            .map(|&bit| if bit { '1' } else { '0' })
+            .collect(),
+    }
+}
+
+impl StreamAnalysis {
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (idx, nal_unit) in self.nal_units.iter().enumerate() {
+            writeln!(
+                text,
+                "NAL unit {}: {:?}, layer {}, TemporalId {}, {} bytes",
+                idx, nal_unit.nal_unit_type, nal_unit.layer_id, nal_unit.temporal_id, nal_unit.size
+            )
+            .unwrap();
+            for syntax_element in &nal_unit.syntax_elements {
+                writeln!(
+                    text,
+                    "    {:<64} {:<6} {}",
+                    syntax_element.name.unwrap_or("-"),
+                    syntax_element.descriptor,
+                    format_value(&syntax_element.value)
+                )
+                .unwrap();
+            }
+        }
+        for (idx, picture) in self.pictures.iter().enumerate() {
+            writeln!(
+                text,
+                "Picture {}: POC LSB {}, {} NAL units, {} bits",
+                idx,
+                picture.pic_order_cnt_lsb,
+                picture.nal_units.len(),
+                picture.bits
+            )
+            .unwrap();
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        let nal_units = self
+            .nal_units
+            .iter()
+            .map(|nal_unit| {
+                let syntax_elements = nal_unit
+                    .syntax_elements
+                    .iter()
+                    .map(|syntax_element| {
+                        let name = match syntax_element.name {
+                            Some(name) => format!("\"{name}\""),
+                            None => "null".to_string(),
+                        };
+                        let value = match &syntax_element.value {
+                            SyntaxElementValue::Bits(_) => {
+                                format!("\"{}\"", format_value(&syntax_element.value))
+                            }
+                            value => format_value(value),
+                        };
+                        format!(
+                            "{{\"name\":{},\"descriptor\":\"{}\",\"value\":{}}}",
+                            name, syntax_element.descriptor, value
+                        )
+                    })
+                    .collect::<Vec<String>>();
+                format!(
+                    "{{\"type\":\"{:?}\",\"layer_id\":{},\"temporal_id\":{},\"size\":{},\"syntax_elements\":[{}]}}",
+                    nal_unit.nal_unit_type,
+                    nal_unit.layer_id,
+                    nal_unit.temporal_id,
+                    nal_unit.size,
+                    syntax_elements.join(",")
+                )
+            })
+            .collect::<Vec<String>>();
+        let pictures = self
+            .pictures
+            .iter()
+            .map(|picture| {
+                let indices = picture
+                    .nal_units
+                    .iter()
+                    .map(|idx| idx.to_string())
+                    .collect::<Vec<String>>();
+                format!(
+                    "{{\"pic_order_cnt_lsb\":{},\"nal_units\":[{}],\"bits\":{}}}",
+                    picture.pic_order_cnt_lsb,
+                    indices.join(","),
+                    picture.bits
+                )
+            })
+            .collect::<Vec<String>>();
+        format!(
+            "{{\"nal_units\":[{}],\"pictures\":[{}]}}\n",
+            nal_units.join(","),
+            pictures.join(",")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary_writer::*;
+    use crate::picture::*;
+    use crate::slice_header::*;
+
+    /// Encodes the parameter sets and the slice headers of an IDR picture with a PH NAL unit and
+    /// a trailing picture carrying its picture header in the slice header.
+    fn encode_stream() -> Vec<u8> {
+        let sps = SequenceParameterSet::new(0, 0, 128, 64, 8, 1);
+        let pps = PictureParameterSet::new(0, &sps, None);
+        let aps = [
+            AdaptationParameterSet::new_alf(0),
+            AdaptationParameterSet::new_lmcs(0),
+            AdaptationParameterSet::new_sl(0),
+        ];
+        let mut ectx = EncoderContext::new();
+        ectx.update_from_sps(&sps);
+        ectx.update_from_sps_and_pps(&sps, &pps);
+        let ectx = Arc::new(Mutex::new(ectx));
+        let mut coder = BoolCoder::new();
+        let mut stream = vec![];
+        let mut writer = BinaryWriter::vec(&mut stream);
+        let sps_bits = SpsEncoder::new(&ectx, &mut coder).encode(&sps);
+        write_byte_stream_nal_unit_bits(0, NALUnitType::SPS_NUT, 0, &sps_bits, &mut writer);
+        let pps_bits = PpsEncoder::new(&ectx, &mut coder).encode(&pps);
+        write_byte_stream_nal_unit_bits(0, NALUnitType::PPS_NUT, 0, &pps_bits, &mut writer);
+        for poc in 0..2 {
+            let ph_in_sh = poc > 0;
+            let (picture_type, nal_unit_type) = if poc == 0 {
+                (PictureType::IRAP_IDR, NALUnitType::IDR_N_LP)
+            } else {
+                (PictureType::Trailing, NALUnitType::TRAIL_NUT)
+            };
+            let ph = PictureHeader::new(&pps, picture_type, true, poc);
+            ectx.lock().unwrap().update_from_ph(&ph, &sps, &pps);
+            if !ph_in_sh {
+                let mut bins = Bins::new();
+                PhEncoder::new(&ectx, &mut coder).encode(&mut bins, &ph, &sps, &pps);
+                let bits = bins.into_iter().collect::<Vec<bool>>();
+                write_byte_stream_nal_unit_bits(0, NALUnitType::PH_NUT, 0, &bits, &mut writer);
+            }
+            let mut sh = {
+                let ectx = ectx.lock().unwrap();
+                let ph = if ph_in_sh { None } else { Some(&ph) };
+                SliceHeader::new(&sps, &pps, [&aps[0], &aps[1], &aps[2]], ph, None, &ectx)
+            };
+            if ph_in_sh {
+                sh.ph_in_sh = Some(PictureHeader::new(&pps, picture_type, true, poc));
+            }
+            ectx.lock().unwrap().update_from_sh(&sh, &pps);
+            let slice = Slice {
+                slice_struct: SliceStruct::Rectangle {
+                    tile_col: 0,
+                    tile_row: 0,
+                    num_tile_cols: 1,
+                    num_tile_rows: 1,
+                },
+                nal_unit_type,
+                tiles: Arc::new(Mutex::new(vec![])),
+            };
+            let mut bins = Bins::new();
+            SliceEncoder::new(&ectx, &mut coder).encode_sh(&mut bins, &sh, &slice);
+            let bits = bins.into_iter().collect::<Vec<bool>>();
+            write_byte_stream_nal_unit_bits(0, nal_unit_type, 0, &bits, &mut writer);
+        }
+        drop(writer);
+        stream
+    }
+
+    #[test]
+    fn analyze_byte_stream_works() {
+        let stream = encode_stream();
+        let mut reader = BinaryReader::vec(&stream).unwrap();
+        let analysis = analyze_byte_stream(&mut reader).unwrap();
+        let nal_unit_types = analysis
+            .nal_units
+            .iter()
+            .map(|nal_unit| nal_unit.nal_unit_type)
+            .collect::<Vec<NALUnitType>>();
+        assert_eq!(
+            nal_unit_types,
+            [
+                NALUnitType::SPS_NUT,
+                NALUnitType::PPS_NUT,
+                NALUnitType::PH_NUT,
+                NALUnitType::IDR_N_LP,
+                NALUnitType::TRAIL_NUT,
+            ]
+        );
+        // all the syntax elements are named after the traces of the header encoders
+        for nal_unit in &analysis.nal_units {
+            assert!(!nal_unit.syntax_elements.is_empty());
+            assert!(nal_unit.syntax_elements.iter().all(|se| se.name.is_some()));
+        }
+        let sps_width = analysis.nal_units[0]
+            .syntax_elements
+            .iter()
+            .find(|se| se.name == Some("sps_pic_width_max_in_luma_samples"))
+            .unwrap();
+        assert_eq!(sps_width.descriptor, "ue(v)");
+        assert_eq!(sps_width.value, SyntaxElementValue::Unsigned(128));
+        let pictures = analysis
+            .pictures
+            .iter()
+            .map(|picture| (picture.pic_order_cnt_lsb, picture.nal_units.clone()))
+            .collect::<Vec<(usize, Vec<usize>)>>();
+        assert_eq!(pictures, [(0, vec![2, 3]), (1, vec![4])]);
+        assert_eq!(analysis.pictures[1].bits, analysis.nal_units[4].size * 8);
+        let json = analysis.to_json();
+        assert!(json.starts_with(
+            "{\"nal_units\":[{\"type\":\"SPS_NUT\",\"layer_id\":0,\"temporal_id\":0,"
+        ));
+        assert!(json.contains("{\"name\":\"sps_id\",\"descriptor\":\"u(4)\",\"value\":0}"));
+    }
+}
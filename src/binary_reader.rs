@@ -3,10 +3,29 @@ use debug_print::*;
 use std::fs::File;
 use std::io::{self, BufRead, Read};
 
+/// The value of a syntax element read by BinaryReader.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyntaxElementValue {
+    Unsigned(usize),
+    Signed(isize),
+    Bits(Vec<bool>),
+}
+
+/// A syntax element read while recording, with the position of its first bit in the input and its
+/// descriptor such as u(4), ue(v) or se(v).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReadSyntaxElement {
+    pub position: usize,
+    pub descriptor: String,
+    pub value: SyntaxElementValue,
+}
+
 pub struct BinaryReader<'a> {
     input: Box<dyn BufRead + 'a>,
     buffer: u8,
     bit_offset: usize,
+    position: usize,
+    syntax_elements: Option<Vec<ReadSyntaxElement>>,
 }
 
 impl<'a> BinaryReader<'a> {
@@ -15,6 +34,8 @@ impl<'a> BinaryReader<'a> {
             input: Box::new(stdin.lock()),
             buffer: 0,
             bit_offset: 0,
+            position: 0,
+            syntax_elements: None,
         }
     }
 
@@ -23,6 +44,8 @@ impl<'a> BinaryReader<'a> {
             input: Box::new(io::BufReader::new(file)),
             buffer: 0,
             bit_offset: 0,
+            position: 0,
+            syntax_elements: None,
         })
     }
 
@@ -31,11 +54,34 @@ impl<'a> BinaryReader<'a> {
             input: Box::new(v),
             buffer: 0,
             bit_offset: 0,
+            position: 0,
+            syntax_elements: None,
         })
     }
 
-    pub fn read_bit(&mut self) -> bool {
-        //println!("read bit");
+    /// Starts recording the syntax elements read by read_bit, read_bits, read_value,
+    /// read_unsigned_exp_golomb and read_signed_exp_golomb.
+    pub fn start_recording(&mut self) {
+        self.syntax_elements = Some(vec![]);
+    }
+
+    /// Stops recording and returns the syntax elements read since start_recording.
+    pub fn take_recorded_syntax_elements(&mut self) -> Vec<ReadSyntaxElement> {
+        self.syntax_elements.take().unwrap_or_default()
+    }
+
+    fn record(&mut self, position: usize, descriptor: String, value: SyntaxElementValue) {
+        if let Some(syntax_elements) = self.syntax_elements.as_mut() {
+            syntax_elements.push(ReadSyntaxElement {
+                position,
+                descriptor,
+                value,
+            });
+        }
+    }
+
+    fn next_bit(&mut self) -> bool {
+        self.position += 1;
         if self.bit_offset > 0 {
             let bit = (self.buffer >> (7 - self.bit_offset)) & 1 > 0;
             self.bit_offset = (self.bit_offset + 1) % 8;
@@ -54,15 +100,126 @@ impl<'a> BinaryReader<'a> {
         }
     }
 
+    fn next_value(&mut self, n_bits: usize) -> usize {
+        (0..n_bits).fold(0, |acc, _| (acc << 1) | self.next_bit() as usize)
+    }
+
+    fn next_unsigned_exp_golomb(&mut self) -> usize {
+        let mut leading_zero_bits = 0;
+        while !self.next_bit() {
+            leading_zero_bits += 1;
+            assert!(leading_zero_bits < 64, "invalid exp-golomb code");
+        }
+        (1 << leading_zero_bits) - 1 + self.next_value(leading_zero_bits)
+    }
+
+    pub fn read_bit(&mut self) -> bool {
+        let position = self.position;
+        let bit = self.next_bit();
+        if self.syntax_elements.is_some() {
+            let value = SyntaxElementValue::Unsigned(bit as usize);
+            self.record(position, "u(1)".to_string(), value);
+        }
+        bit
+    }
+
     // FIXME speedup
     pub fn read_bits(&mut self, n_bits: usize) -> Vec<bool> {
+        let position = self.position;
         let mut bits = vec![];
         for _ in 0..n_bits {
-            bits.push(self.read_bit());
+            bits.push(self.next_bit());
+        }
+        if self.syntax_elements.is_some() && n_bits > 0 {
+            let value = SyntaxElementValue::Bits(bits.clone());
+            self.record(position, format!("u({n_bits})"), value);
         }
         bits
     }
 
+    /// u(n): unsigned integer using n bits, most significant bit first.
+    pub fn read_value(&mut self, n_bits: usize) -> usize {
+        let position = self.position;
+        let value = self.next_value(n_bits);
+        if self.syntax_elements.is_some() && n_bits > 0 {
+            let recorded_value = SyntaxElementValue::Unsigned(value);
+            self.record(position, format!("u({n_bits})"), recorded_value);
+        }
+        value
+    }
+
+    /// ue(v): unsigned integer 0-th order Exp-Golomb-coded syntax element.
+    pub fn read_unsigned_exp_golomb(&mut self) -> usize {
+        let position = self.position;
+        let value = self.next_unsigned_exp_golomb();
+        if self.syntax_elements.is_some() {
+            let recorded_value = SyntaxElementValue::Unsigned(value);
+            self.record(position, "ue(v)".to_string(), recorded_value);
+        }
+        value
+    }
+
+    /// se(v): signed integer 0-th order Exp-Golomb-coded syntax element.
+    pub fn read_signed_exp_golomb(&mut self) -> isize {
+        let position = self.position;
+        let k = self.next_unsigned_exp_golomb();
+        let value = if k % 2 == 1 {
+            k.div_ceil(2) as isize
+        } else {
+            -((k / 2) as isize)
+        };
+        if self.syntax_elements.is_some() {
+            let recorded_value = SyntaxElementValue::Signed(value);
+            self.record(position, "se(v)".to_string(), recorded_value);
+        }
+        value
+    }
+
+    pub fn is_byte_aligned(&self) -> bool {
+        self.bit_offset == 0
+    }
+
+    /// Skips the remaining bits of the current byte.
+    pub fn byte_align(&mut self) {
+        if self.bit_offset > 0 {
+            self.position += 8 - self.bit_offset;
+        }
+        self.bit_offset = 0;
+    }
+
+    /// more_rbsp_data() as specified in 7.2: true if there is more data before the
+    /// rbsp_stop_one_bit. Only the buffered part of the input is examined, which covers the whole
+    /// RBSP when reading from a slice.
+    pub fn more_rbsp_data(&mut self) -> bool {
+        let remaining_bits = if self.bit_offset > 0 {
+            self.buffer & (0xff >> self.bit_offset)
+        } else {
+            0
+        };
+        let next_bit = if self.bit_offset > 0 {
+            (self.buffer >> (7 - self.bit_offset)) & 1 > 0
+        } else {
+            false
+        };
+        let bytes = match self.input.fill_buf() {
+            Ok(bytes) => bytes,
+            Err(_) => return false,
+        };
+        let num_ones =
+            remaining_bits.count_ones() + bytes.iter().map(|byte| byte.count_ones()).sum::<u32>();
+        match num_ones {
+            0 => false,
+            1 => {
+                if self.bit_offset > 0 {
+                    !next_bit
+                } else {
+                    bytes[0] & 0x80 == 0
+                }
+            }
+            _ => true,
+        }
+    }
+
     pub fn read_to_vec<T: From<u8>>(&mut self, v: &mut Vec<T>) -> io::Result<usize> {
         let len = v.len();
         debug_eprintln!("len = {}", len);
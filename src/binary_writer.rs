@@ -24,6 +24,15 @@ impl<'a> BinaryWriter<'a> {
         })
     }
 
+    #[cfg(test)]
+    pub fn vec(v: &'a mut Vec<u8>) -> BinaryWriter<'a> {
+        BinaryWriter {
+            output: Box::new(v),
+            buf: 0,
+            index: 0,
+        }
+    }
+
     pub fn write_bit(&mut self, bit: bool) {
         self.buf = (self.buf << 1) | bit as u8;
         self.index += 1;
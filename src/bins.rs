@@ -1,5 +1,6 @@
 use std::cmp::PartialEq;
 
+#[derive(Clone)]
 pub struct Bins {
     pub head: u64,
     pub tail: Vec<u64>,
@@ -131,6 +132,14 @@ impl Bins {
         }
     }
 
+    /// Appends byte-aligned bins such as a substream of a tile.
+    pub fn append(&mut self, other: &Bins) {
+        debug_assert_eq!(other.num_bins % 8, 0);
+        for byte in other.bytes() {
+            self.push_bins_with_size(byte as u64, 8);
+        }
+    }
+
     #[inline(always)]
     pub fn bytes(&self) -> BinsByteIterator {
         BinsByteIterator {
@@ -2,6 +2,8 @@ use super::common::*;
 use super::ctu::*;
 use super::encoder_context::*;
 use super::intra_predictor::*;
+use super::motion_compensator::*;
+use super::motion_estimator::*;
 use super::quantizer::*;
 use super::slice_header::*;
 use super::transformer::*;
@@ -9,6 +11,8 @@ use std::sync::{Arc, Mutex};
 
 pub struct BlockSplitter {
     intra_predictor: IntraPredictor,
+    motion_compensator: MotionCompensator,
+    motion_estimator: MotionEstimator,
     transformer: Transformer,
     quantizer: Quantizer,
     lv_table: [i64; 1024],
@@ -53,6 +57,8 @@ impl BlockSplitter {
         }
         BlockSplitter {
             intra_predictor: IntraPredictor::new(),
+            motion_compensator: MotionCompensator::new(),
+            motion_estimator: MotionEstimator::new(),
             transformer: Transformer::new(),
             quantizer: Quantizer::new(ectx),
             lv_table,
@@ -80,7 +86,7 @@ impl BlockSplitter {
             // FIXME multiple transform units
             tt.tus[0].clone()
         };
-        let mut tu = tu.borrow_mut();
+        let mut tu = tu.lock().unwrap();
         let mut sad: usize = 0;
         for c_idx in 0..3 {
             if tu.is_component_active(c_idx) {
@@ -91,8 +97,8 @@ impl BlockSplitter {
                 let (tx, ty) = tu.get_component_pos(c_idx);
                 let (tw, th) = tu.get_component_size(c_idx);
                 // FIXME SIMD?
-                let pred_pixels = &tile.pred_pixels.borrow()[c_idx];
-                let original_pixels = &tile.original_pixels.borrow()[c_idx];
+                let pred_pixels = &tile.pred_pixels.lock().unwrap()[c_idx];
+                let original_pixels = &tile.original_pixels.lock().unwrap()[c_idx];
                 for y in ty..ty + th {
                     let pred_pixels = &pred_pixels[y][tx..];
                     let original_pixels = &original_pixels[y][tx..];
@@ -141,46 +147,24 @@ impl BlockSplitter {
                 tree_type,
             )
         };
-        let mut tu = tu.borrow_mut();
+        let mut tu = tu.lock().unwrap();
         let mut ssd: usize = 0;
         for c_idx in 0..3 {
             if tu.is_component_active(c_idx) {
                 self.intra_predictor
                     .predict(&mut tu, c_idx, sh.sps, sh.pps, ectx);
                 self.transformer
-                    .transform(&mut tu, c_idx, sh.sps, sh.ph.as_ref().unwrap(), ectx);
+                    .transform(&mut tu, c_idx, sh.sps, sh.picture_header(), ectx);
                 self.quantizer.quantize(&mut tu, c_idx, trellis, sh, ectx);
                 self.quantizer.dequantize(&mut tu, c_idx, sh, ectx);
                 self.transformer.inverse_transform(
                     &mut tu,
                     c_idx,
                     sh.sps,
-                    sh.ph.as_ref().unwrap(),
+                    sh.picture_header(),
                     ectx,
                 );
-                let tile = tu.get_tile();
-                let tile = &mut tile.lock().unwrap();
-                let (tx, ty) = tu.get_component_pos(c_idx);
-                let (tw, th) = tu.get_component_size(c_idx);
-                // FIXME SIMD?
-                let pred_pixels = &tile.pred_pixels.borrow()[c_idx];
-                let reconst_pixels = &mut tile.reconst_pixels.borrow_mut()[c_idx];
-                let original_pixels = &tile.original_pixels.borrow()[c_idx];
-                let it = &tu.itransformed_coeffs[c_idx];
-                for y in ty..ty + th {
-                    let pred_pixels = &pred_pixels[y][tx..];
-                    let reconst_pixels = &mut reconst_pixels[y][tx..];
-                    let original_pixels = &original_pixels[y][tx..];
-                    let it = &it[y - ty];
-                    for x in 0..tw {
-                        let pred = pred_pixels[x];
-                        let res = it[x];
-                        let rec = (pred as i16 + res).clamp(0, 255) as u8;
-                        reconst_pixels[x] = rec;
-                        let d = rec as i32 - original_pixels[x] as i32;
-                        ssd += (d * d) as usize;
-                    }
-                }
+                ssd += Self::reconstruct(&tu, c_idx);
             }
         }
 
@@ -281,38 +265,6 @@ impl BlockSplitter {
                 _ => 0.982_125_64,
             }
         };
-        let qp_div = if !sh.dep_quant_used_flag {
-            match ectx.extra_params.get("qp_div") {
-                Some(qp_div) => qp_div.parse::<f32>().unwrap(),
-                _ => 7.0,
-            }
-        } else if trellis {
-            match ectx.extra_params.get("qp_div_dq_trellis") {
-                Some(qp_div) => qp_div.parse::<f32>().unwrap(),
-                _ => 4.404_366_5,
-            }
-        } else {
-            match ectx.extra_params.get("qp_div_dq") {
-                Some(qp_div) => qp_div.parse::<f32>().unwrap(),
-                _ => 3.970_736,
-            }
-        };
-        let lambda_mul = if !sh.dep_quant_used_flag {
-            match ectx.extra_params.get("lambda_mul") {
-                Some(lambda_mul) => lambda_mul.parse::<f32>().unwrap(),
-                _ => 7.915_166,
-            }
-        } else if trellis {
-            match ectx.extra_params.get("lambda_mul_dq_trellis") {
-                Some(lambda_mul) => lambda_mul.parse::<f32>().unwrap(),
-                _ => 1.128_258_1,
-            }
-        } else {
-            match ectx.extra_params.get("lambda_mul_dq") {
-                Some(lambda_mul) => lambda_mul.parse::<f32>().unwrap(),
-                _ => 1.343_928_7,
-            }
-        };
         let cclm_pow = match ectx.extra_params.get("cclm_pow") {
             Some(cclm_pow) => cclm_pow.parse::<f32>().unwrap(),
             _ => 0.458_765_1,
@@ -404,6 +356,20 @@ impl BlockSplitter {
                 TreeType::DUAL_TREE_CHROMA => cclm_bits,
             }
         } * 16384.0) as i64;
+        let level = self.get_level(&tu, trellis, sh, ectx) + header_bits;
+        let lambda = Self::get_lambda(tu.qp, trellis, sh, ectx);
+        ssd as f32 + lambda * (level as f32 / 16384.0)
+    }
+
+    /// Returns the estimated bits of the quantized coefficients of a transform unit, which are
+    /// scaled by 16384.
+    fn get_level(
+        &self,
+        tu: &TransformUnit,
+        trellis: bool,
+        sh: &SliceHeader,
+        ectx: &EncoderContext,
+    ) -> i64 {
         let lv_table = if !sh.dep_quant_used_flag {
             &self.lv_table
         } else if trellis {
@@ -412,7 +378,7 @@ impl BlockSplitter {
             &self.lv_dq_table
         };
         let q_state_trans_table = &ectx.q_state_trans_table;
-        let level: i64 = if sh.dep_quant_used_flag {
+        if sh.dep_quant_used_flag {
             let mut sum = 0;
             for c_idx in 0..3 {
                 if !tu.is_component_active(c_idx) {
@@ -468,11 +434,199 @@ impl BlockSplitter {
                     })
                 })
                 .sum::<i64>()
-        } + header_bits;
-        let lambda = (2.0f32).powf(tu.qp as f32 / qp_div) * lambda_mul;
+        }
+    }
+
+    /// Returns the Lagrange multiplier of the rate-distortion costs.
+    fn get_lambda(qp: usize, trellis: bool, sh: &SliceHeader, ectx: &EncoderContext) -> f32 {
+        let qp_div = if !sh.dep_quant_used_flag {
+            match ectx.extra_params.get("qp_div") {
+                Some(qp_div) => qp_div.parse::<f32>().unwrap(),
+                _ => 7.0,
+            }
+        } else if trellis {
+            match ectx.extra_params.get("qp_div_dq_trellis") {
+                Some(qp_div) => qp_div.parse::<f32>().unwrap(),
+                _ => 4.404_366_5,
+            }
+        } else {
+            match ectx.extra_params.get("qp_div_dq") {
+                Some(qp_div) => qp_div.parse::<f32>().unwrap(),
+                _ => 3.970_736,
+            }
+        };
+        let lambda_mul = if !sh.dep_quant_used_flag {
+            match ectx.extra_params.get("lambda_mul") {
+                Some(lambda_mul) => lambda_mul.parse::<f32>().unwrap(),
+                _ => 7.915_166,
+            }
+        } else if trellis {
+            match ectx.extra_params.get("lambda_mul_dq_trellis") {
+                Some(lambda_mul) => lambda_mul.parse::<f32>().unwrap(),
+                _ => 1.128_258_1,
+            }
+        } else {
+            match ectx.extra_params.get("lambda_mul_dq") {
+                Some(lambda_mul) => lambda_mul.parse::<f32>().unwrap(),
+                _ => 1.343_928_7,
+            }
+        };
+        (2.0f32).powf(qp as f32 / qp_div) * lambda_mul
+    }
+
+    /// Reconstructs a component of a transform unit into the tile from the predicted samples and
+    /// the residuals, and returns the sum of squared errors from the original samples.
+    fn reconstruct(tu: &TransformUnit, c_idx: usize) -> usize {
+        let tile = tu.get_tile();
+        let tile = &mut tile.lock().unwrap();
+        let (tx, ty) = tu.get_component_pos(c_idx);
+        let (tw, th) = tu.get_component_size(c_idx);
+        // FIXME SIMD?
+        let pred_pixels = &tile.pred_pixels.lock().unwrap()[c_idx];
+        let reconst_pixels = &mut tile.reconst_pixels.lock().unwrap()[c_idx];
+        let original_pixels = &tile.original_pixels.lock().unwrap()[c_idx];
+        let it = &tu.itransformed_coeffs[c_idx];
+        let mut ssd = 0;
+        for y in ty..ty + th {
+            let pred_pixels = &pred_pixels[y][tx..];
+            let reconst_pixels = &mut reconst_pixels[y][tx..];
+            let original_pixels = &original_pixels[y][tx..];
+            let it = &it[y - ty];
+            for x in 0..tw {
+                let pred = pred_pixels[x];
+                let res = it[x];
+                let rec = (pred as i16 + res).clamp(0, 255) as u8;
+                reconst_pixels[x] = rec;
+                let d = rec as i32 - original_pixels[x] as i32;
+                ssd += (d * d) as usize;
+            }
+        }
+        ssd
+    }
+
+    pub fn get_inter_pred_cost(
+        &mut self,
+        motion_info: &MotionInfo,
+        mvd_bits: usize,
+        ct: &mut Arc<Mutex<CodingTree>>,
+        trellis: bool,
+        sh: &SliceHeader,
+        ectx: &mut EncoderContext,
+    ) -> f32 {
+        let tu = {
+            let cu = {
+                let ct = ct.lock().unwrap();
+                ct.cus[0].clone()
+            };
+            let cu = &mut cu.lock().unwrap();
+            cu.set_motion_info(Some(*motion_info));
+            let tt = cu.transform_tree.as_ref().unwrap();
+            let tt = tt.lock().unwrap();
+            // FIXME multiple transform units
+            tt.tus[0].clone()
+        };
+        let mut tu = tu.lock().unwrap();
+        let mut ssd: usize = 0;
+        for c_idx in 0..3 {
+            if tu.is_component_active(c_idx) {
+                self.motion_compensator
+                    .predict(&mut tu, c_idx, motion_info, sh, ectx);
+                self.transformer
+                    .transform(&mut tu, c_idx, sh.sps, sh.picture_header(), ectx);
+                self.quantizer.quantize(&mut tu, c_idx, trellis, sh, ectx);
+                self.quantizer.dequantize(&mut tu, c_idx, sh, ectx);
+                self.transformer.inverse_transform(
+                    &mut tu,
+                    c_idx,
+                    sh.sps,
+                    sh.picture_header(),
+                    ectx,
+                );
+                ssd += Self::reconstruct(&tu, c_idx);
+            }
+        }
+        // FIXME estimate header bits for coding units other than motion vector differences
+        let inter_header_bits = match ectx.extra_params.get("inter_header_bits") {
+            Some(inter_header_bits) => inter_header_bits.parse::<f32>().unwrap(),
+            _ => 3.0,
+        };
+        let header_bits = ((inter_header_bits + mvd_bits as f32) * 16384.0) as i64;
+        let level = self.get_level(&tu, trellis, sh, ectx) + header_bits;
+        let lambda = Self::get_lambda(tu.qp, trellis, sh, ectx);
         ssd as f32 + lambda * (level as f32 / 16384.0)
     }
 
+    /// Makes the CU of a coding tree inter predicted if the motion searched in the reference
+    /// picture costs less than intra_cost of its intra prediction, and returns the lower cost.
+    fn choose_inter_or_intra(
+        &mut self,
+        ct: &mut Arc<Mutex<CodingTree>>,
+        intra_cost: f32,
+        sh: &SliceHeader,
+        ectx: &mut EncoderContext,
+    ) -> f32 {
+        let cu = {
+            let ct = ct.lock().unwrap();
+            ct.cus[0].clone()
+        };
+        let searched = {
+            let cu = cu.lock().unwrap();
+            let qp = {
+                let tt = cu.transform_tree.as_ref().unwrap();
+                let tt = tt.lock().unwrap();
+                let tu = tt.tus[0].lock().unwrap();
+                tu.qp
+            };
+            let lambda = Self::get_lambda(qp, true, sh, ectx);
+            self.motion_estimator.search(&cu, lambda.sqrt(), sh, ectx)
+        };
+        let Some((motion_info, mvd_bits)) = searched else {
+            return intra_cost;
+        };
+        let intra_reconsts = Self::get_reconsts(ct);
+        let inter_cost = self.get_inter_pred_cost(&motion_info, mvd_bits, ct, true, sh, ectx);
+        if inter_cost < intra_cost {
+            inter_cost
+        } else {
+            cu.lock().unwrap().set_motion_info(None);
+            Self::set_reconsts(ct, &intra_reconsts);
+            intra_cost
+        }
+    }
+
+    /// Returns the reconstructed samples of the components of a coding tree of a single tree.
+    fn get_reconsts(ct: &Arc<Mutex<CodingTree>>) -> Vec<Vec2d<u8>> {
+        let ct = ct.lock().unwrap();
+        let tile = ct.tile.as_ref().unwrap();
+        let tile = tile.lock().unwrap();
+        let tile_reconsts = tile.reconst_pixels.lock().unwrap();
+        (0..3)
+            .map(|c_idx| {
+                let (cx, cy) = ct.get_component_pos(c_idx);
+                let (cw, ch) = ct.get_component_size(c_idx);
+                let mut reconst = vec2d![0; ch; cw];
+                for y in cy..cy + ch {
+                    reconst[y - cy].copy_from_slice(&tile_reconsts[c_idx][y][cx..cx + cw]);
+                }
+                reconst
+            })
+            .collect()
+    }
+
+    fn set_reconsts(ct: &Arc<Mutex<CodingTree>>, reconsts: &[Vec2d<u8>]) {
+        let ct = ct.lock().unwrap();
+        let tile = ct.tile.as_ref().unwrap();
+        let tile = tile.lock().unwrap();
+        let tile_reconsts = &mut tile.reconst_pixels.lock().unwrap();
+        for (c_idx, reconst) in reconsts.iter().enumerate() {
+            let (cx, cy) = ct.get_component_pos(c_idx);
+            let (cw, ch) = ct.get_component_size(c_idx);
+            for y in cy..cy + ch {
+                tile_reconsts[c_idx][y][cx..cx + cw].copy_from_slice(&reconst[y - cy]);
+            }
+        }
+    }
+
     pub fn get_chroma_intra_pred_aux_cost(
         &mut self,
         intra_pred_mode: IntraPredMode,
@@ -495,7 +649,7 @@ impl BlockSplitter {
             // FIXME multiple transform units
             tt.tus[0].clone()
         };
-        let mut tu = tu.borrow_mut();
+        let mut tu = tu.lock().unwrap();
         let mut sad: usize = 0;
         for c_idx in 1..3 {
             if tu.is_component_active(c_idx) {
@@ -505,8 +659,8 @@ impl BlockSplitter {
                 let tile = &mut tile.lock().unwrap();
                 let (tx, ty) = tu.get_component_pos(c_idx);
                 let (tw, th) = tu.get_component_size(c_idx);
-                let pred_pixels = &tile.pred_pixels.borrow()[c_idx];
-                let original_pixels = &tile.original_pixels.borrow()[c_idx];
+                let pred_pixels = &tile.pred_pixels.lock().unwrap()[c_idx];
+                let original_pixels = &tile.original_pixels.lock().unwrap()[c_idx];
                 for y in ty..ty + th {
                     let pred_pixels = &pred_pixels[y][tx..];
                     let original_pixels = &original_pixels[y][tx..];
@@ -548,46 +702,24 @@ impl BlockSplitter {
                 cu.get_cclm_mode_idx(),
             )
         };
-        let mut tu = tu.borrow_mut();
+        let mut tu = tu.lock().unwrap();
         let mut ssd: usize = 0;
         for c_idx in 1..3 {
             if tu.is_component_active(c_idx) {
                 self.intra_predictor
                     .predict(&mut tu, c_idx, sh.sps, sh.pps, ectx);
                 self.transformer
-                    .transform(&mut tu, c_idx, sh.sps, sh.ph.as_ref().unwrap(), ectx);
+                    .transform(&mut tu, c_idx, sh.sps, sh.picture_header(), ectx);
                 self.quantizer.quantize(&mut tu, c_idx, trellis, sh, ectx);
                 self.quantizer.dequantize(&mut tu, c_idx, sh, ectx);
                 self.transformer.inverse_transform(
                     &mut tu,
                     c_idx,
                     sh.sps,
-                    sh.ph.as_ref().unwrap(),
+                    sh.picture_header(),
                     ectx,
                 );
-                let tile = tu.get_tile();
-                let tile = &mut tile.lock().unwrap();
-                let (tx, ty) = tu.get_component_pos(c_idx);
-                let (tw, th) = tu.get_component_size(c_idx);
-                // FIXME SIMD?
-                let pred_pixels = &tile.pred_pixels.borrow()[c_idx];
-                let reconst_pixels = &mut tile.reconst_pixels.borrow_mut()[c_idx];
-                let original_pixels = &tile.original_pixels.borrow()[c_idx];
-                let it = &tu.itransformed_coeffs[c_idx];
-                for y in ty..ty + th {
-                    let pred_pixels = &pred_pixels[y][tx..];
-                    let reconst_pixels = &mut reconst_pixels[y][tx..];
-                    let original_pixels = &original_pixels[y][tx..];
-                    let it = &it[y - ty];
-                    for x in 0..tw {
-                        let pred = pred_pixels[x];
-                        let res = it[x];
-                        let rec = (pred as i16 + res).clamp(0, 255) as u8;
-                        reconst_pixels[x] = rec;
-                        let d = rec as i32 - original_pixels[x] as i32;
-                        ssd += (d * d) as usize;
-                    }
-                }
+                ssd += Self::reconstruct(&tu, c_idx);
             }
         }
 
@@ -813,7 +945,7 @@ impl BlockSplitter {
                             let (cx, cy) = ct.get_component_pos(c_idx);
                             let (cw, ch) = ct.get_component_size(c_idx);
                             let mut reconst = vec2d![0; ch; cw];
-                            let tile_reconst = &tile.reconst_pixels.borrow()[c_idx];
+                            let tile_reconst = &tile.reconst_pixels.lock().unwrap()[c_idx];
                             for y in cy..cy + ch {
                                 for x in cx..cx + cw {
                                     reconst[y - cy][x - cx] = tile_reconst[y][x];
@@ -830,7 +962,7 @@ impl BlockSplitter {
                         for c_idx in 1..3 {
                             let (cx, cy) = ct.get_component_pos(c_idx);
                             let (cw, ch) = ct.get_component_size(c_idx);
-                            let tile_reconst = &mut tile.reconst_pixels.borrow_mut()[c_idx];
+                            let tile_reconst = &mut tile.reconst_pixels.lock().unwrap()[c_idx];
                             for y in cy..cy + ch {
                                 for x in cx..cx + cw {
                                     tile_reconst[y][x] = cache[c_idx - 1][y - cy][x - cx];
@@ -884,96 +1016,113 @@ impl BlockSplitter {
                     current_cost
                 }
             } else {
-                let cand_modes = [0, 1, 2, 7, 13, 18, 23, 29, 34, 39, 45, 50, 55, 60, 66];
-                let cand_costs = cand_modes
-                    .iter()
-                    .map(|m| {
-                        let mode = num::FromPrimitive::from_usize(*m).unwrap();
-                        if mode as usize <= 1 {
-                            self.get_intra_pred_cost([mode; 3], ct, true, sh, ectx)
-                        } else {
-                            self.get_intra_pred_aux_cost([mode; 3], ct, sh, ectx)
-                        }
-                    })
-                    .collect::<Vec<f32>>();
-                let min_dir_cost = cand_costs[2..].iter().fold(f32::MAX, |m, v| v.min(m));
-                let min_dir_cost_idx = cand_costs[2..]
-                    .iter()
-                    .position(|x| x == &min_dir_cost)
-                    .unwrap()
-                    + 2;
-                let mut step_search = |current_mode: usize,
-                                       step: usize,
-                                       current_cost: f32,
-                                       aux: bool|
-                 -> (usize, f32) {
-                    let (mut current_mode, mut step, mut current_cost) = if aux {
-                        (current_mode, step, current_cost)
-                    } else {
-                        let current_cost = self.get_intra_pred_cost(
-                            [num::FromPrimitive::from_usize(current_mode).unwrap(); 3],
-                            ct,
-                            true,
-                            sh,
-                            ectx,
-                        );
-                        (current_mode, step, current_cost)
-                    };
-                    while step > 0 {
-                        let trellis = true;
-                        let cost0 = if current_mode < 2 + step {
-                            f32::MAX
-                        } else if aux {
-                            self.get_intra_pred_aux_cost(
-                                [num::FromPrimitive::from_usize(current_mode - step).unwrap(); 3],
-                                ct,
-                                sh,
-                                ectx,
-                            )
-                        } else {
-                            self.get_intra_pred_cost(
-                                [num::FromPrimitive::from_usize(current_mode - step).unwrap(); 3],
-                                ct,
-                                trellis,
-                                sh,
-                                ectx,
-                            )
-                        };
-                        let cost1 = if current_mode + step > 66 {
-                            f32::MAX
-                        } else if aux {
-                            self.get_intra_pred_aux_cost(
-                                [num::FromPrimitive::from_usize(current_mode + step).unwrap(); 3],
-                                ct,
-                                sh,
-                                ectx,
-                            )
+                let gdr_restricted = {
+                    let ct = ct.lock().unwrap();
+                    ectx.is_intra_pred_restricted_by_gdr(ct.x, ct.width, ct.height)
+                };
+                let (cand_modes, cand_costs) = if gdr_restricted {
+                    // modes which never refer to samples beyond the right of the block
+                    let cand_modes = [1, 18, 50];
+                    let cand_costs = cand_modes.map(|m| {
+                        let mode = num::FromPrimitive::from_usize(m).unwrap();
+                        self.get_intra_pred_cost([mode; 3], ct, true, sh, ectx)
+                    });
+                    (cand_modes, cand_costs)
+                } else {
+                    let cand_modes = [0, 1, 2, 7, 13, 18, 23, 29, 34, 39, 45, 50, 55, 60, 66];
+                    let cand_costs = cand_modes
+                        .iter()
+                        .map(|m| {
+                            let mode = num::FromPrimitive::from_usize(*m).unwrap();
+                            if mode as usize <= 1 {
+                                self.get_intra_pred_cost([mode; 3], ct, true, sh, ectx)
+                            } else {
+                                self.get_intra_pred_aux_cost([mode; 3], ct, sh, ectx)
+                            }
+                        })
+                        .collect::<Vec<f32>>();
+                    let min_dir_cost = cand_costs[2..].iter().fold(f32::MAX, |m, v| v.min(m));
+                    let min_dir_cost_idx = cand_costs[2..]
+                        .iter()
+                        .position(|x| x == &min_dir_cost)
+                        .unwrap()
+                        + 2;
+                    let mut step_search = |current_mode: usize,
+                                           step: usize,
+                                           current_cost: f32,
+                                           aux: bool|
+                     -> (usize, f32) {
+                        let (mut current_mode, mut step, mut current_cost) = if aux {
+                            (current_mode, step, current_cost)
                         } else {
-                            self.get_intra_pred_cost(
-                                [num::FromPrimitive::from_usize(current_mode + step).unwrap(); 3],
+                            let current_cost = self.get_intra_pred_cost(
+                                [num::FromPrimitive::from_usize(current_mode).unwrap(); 3],
                                 ct,
-                                trellis,
+                                true,
                                 sh,
                                 ectx,
-                            )
-                        };
-                        let min_cost = current_cost.min(cost0).min(cost1);
-                        (current_mode, current_cost) = if current_cost == min_cost {
-                            (current_mode, current_cost)
-                        } else if cost0 == min_cost {
-                            (current_mode - step, cost0)
-                        } else {
-                            (current_mode + step, cost1)
+                            );
+                            (current_mode, step, current_cost)
                         };
-                        step /= 2;
-                    }
-                    (current_mode, current_cost)
+                        while step > 0 {
+                            let trellis = true;
+                            let cost0 = if current_mode < 2 + step {
+                                f32::MAX
+                            } else if aux {
+                                self.get_intra_pred_aux_cost(
+                                    [num::FromPrimitive::from_usize(current_mode - step).unwrap();
+                                        3],
+                                    ct,
+                                    sh,
+                                    ectx,
+                                )
+                            } else {
+                                self.get_intra_pred_cost(
+                                    [num::FromPrimitive::from_usize(current_mode - step).unwrap();
+                                        3],
+                                    ct,
+                                    trellis,
+                                    sh,
+                                    ectx,
+                                )
+                            };
+                            let cost1 = if current_mode + step > 66 {
+                                f32::MAX
+                            } else if aux {
+                                self.get_intra_pred_aux_cost(
+                                    [num::FromPrimitive::from_usize(current_mode + step).unwrap();
+                                        3],
+                                    ct,
+                                    sh,
+                                    ectx,
+                                )
+                            } else {
+                                self.get_intra_pred_cost(
+                                    [num::FromPrimitive::from_usize(current_mode + step).unwrap();
+                                        3],
+                                    ct,
+                                    trellis,
+                                    sh,
+                                    ectx,
+                                )
+                            };
+                            let min_cost = current_cost.min(cost0).min(cost1);
+                            (current_mode, current_cost) = if current_cost == min_cost {
+                                (current_mode, current_cost)
+                            } else if cost0 == min_cost {
+                                (current_mode - step, cost0)
+                            } else {
+                                (current_mode + step, cost1)
+                            };
+                            step /= 2;
+                        }
+                        (current_mode, current_cost)
+                    };
+                    let (dir_mode, _dir_cost) =
+                        step_search(cand_modes[min_dir_cost_idx], 2, min_dir_cost, true);
+                    let (dir_mode, dir_cost) = step_search(dir_mode, 1, min_dir_cost, false);
+                    ([0, 1, dir_mode], [cand_costs[0], cand_costs[1], dir_cost])
                 };
-                let (dir_mode, _dir_cost) =
-                    step_search(cand_modes[min_dir_cost_idx], 2, min_dir_cost, true);
-                let (dir_mode, dir_cost) = step_search(dir_mode, 1, min_dir_cost, false);
-                let cand_modes = [0, 1, dir_mode];
-                let cand_costs = [cand_costs[0], cand_costs[1], dir_cost];
                 let mut min_cost = cand_costs.iter().fold(f32::MAX, |m, v| v.min(m));
                 let min_cost_idx = cand_costs.iter().position(|x| x == &min_cost).unwrap();
                 let cu = {
@@ -995,7 +1144,7 @@ impl BlockSplitter {
                         // FIXME multiple transform units
                         tt.tus[0].clone()
                     };
-                    let mut tu = tu.borrow_mut();
+                    let mut tu = tu.lock().unwrap();
                     if tu.is_component_active(c_idx) {
                         self.intra_predictor
                             .predict(&mut tu, c_idx, sh.sps, sh.pps, ectx);
@@ -1003,7 +1152,7 @@ impl BlockSplitter {
                             &mut tu,
                             c_idx,
                             sh.sps,
-                            sh.ph.as_ref().unwrap(),
+                            sh.picture_header(),
                             ectx,
                         );
                         self.quantizer.quantize(&mut tu, c_idx, true, sh, ectx);
@@ -1012,15 +1161,15 @@ impl BlockSplitter {
                             &mut tu,
                             c_idx,
                             sh.sps,
-                            sh.ph.as_ref().unwrap(),
+                            sh.picture_header(),
                             ectx,
                         );
                         let tile = tu.get_tile();
                         let tile = &mut tile.lock().unwrap();
                         let (tx, ty) = tu.get_component_pos(c_idx);
                         let (tw, th) = tu.get_component_size(c_idx);
-                        let pred_pixels = &tile.pred_pixels.borrow()[c_idx];
-                        let reconst_pixels = &mut tile.reconst_pixels.borrow_mut()[c_idx];
+                        let pred_pixels = &tile.pred_pixels.lock().unwrap()[c_idx];
+                        let reconst_pixels = &mut tile.reconst_pixels.lock().unwrap()[c_idx];
                         let it = &tu.itransformed_coeffs[c_idx];
                         for y in ty..ty + th {
                             let pred_pixels = &pred_pixels[y][tx..];
@@ -1040,8 +1189,11 @@ impl BlockSplitter {
                     let current_cost = self.get_chroma_intra_pred_cost(mode, ct, true, sh, ectx);
                     let cclm_lt_cost =
                         self.get_chroma_intra_pred_aux_cost(IntraPredMode::LT_CCLM, ct, sh, ectx);
-                    let cclm_t_cost =
-                        self.get_chroma_intra_pred_aux_cost(IntraPredMode::T_CCLM, ct, sh, ectx);
+                    let cclm_t_cost = if gdr_restricted {
+                        f32::MAX
+                    } else {
+                        self.get_chroma_intra_pred_aux_cost(IntraPredMode::T_CCLM, ct, sh, ectx)
+                    };
                     let cclm_l_cost =
                         self.get_chroma_intra_pred_aux_cost(IntraPredMode::L_CCLM, ct, sh, ectx);
                     let (cclm_mode, _cclm_cost) =
@@ -1070,10 +1222,13 @@ impl BlockSplitter {
                         let modes = [mode, cclm_mode, cclm_mode];
                         min_cost = self.get_intra_pred_cost(modes, ct, true, sh, ectx);
                     }
-                } else if cand_modes[min_cost_idx] <= 1 {
+                } else if gdr_restricted || cand_modes[min_cost_idx] <= 1 {
                     let modes = [mode; 3];
                     min_cost = self.get_intra_pred_cost(modes, ct, true, sh, ectx);
                 }
+                if sh.slice_type != SliceType::I && tree_type == TreeType::SINGLE_TREE {
+                    min_cost = self.choose_inter_or_intra(ct, min_cost, sh, ectx);
+                }
                 min_cost
             }
         } else {
@@ -1097,7 +1252,7 @@ impl BlockSplitter {
                     let (cx, cy) = ct.get_component_pos(c_idx);
                     let (cw, ch) = ct.get_component_size(c_idx);
                     let mut reconst = vec2d![0; ch; cw];
-                    let tile_reconst = &tile.reconst_pixels.borrow()[c_idx];
+                    let tile_reconst = &tile.reconst_pixels.lock().unwrap()[c_idx];
                     for y in cy..cy + ch {
                         for x in cx..cx + cw {
                             reconst[y - cy][x - cx] = tile_reconst[y][x];
@@ -1135,7 +1290,7 @@ impl BlockSplitter {
                     }
                     let (cx, cy) = ct.get_component_pos(c_idx);
                     let (cw, ch) = ct.get_component_size(c_idx);
-                    let tile_reconst = &mut tile.reconst_pixels.borrow_mut()[c_idx];
+                    let tile_reconst = &mut tile.reconst_pixels.lock().unwrap()[c_idx];
                     for y in cy..cy + ch {
                         for x in cx..cx + cw {
                             tile_reconst[y][x] = no_split_reconsts[c_idx][y - cy][x - cx];
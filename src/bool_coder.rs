@@ -10,8 +10,6 @@ use super::sps::*;
 use debug_print::*;
 use std::sync::{Arc, Mutex};
 
-// TODO enable parallel coding
-
 #[derive(Clone)]
 pub struct BoolCoder {
     pub cabac_p_state_idx: Vec<[Vec<[u16; 2]>; 3]>,
@@ -174,13 +172,14 @@ impl BoolCoder {
     pub fn flush_cabac_bin(&mut self, out_bins: &mut Bins, bin: bool) {
         // TODO reduce cost to check it everytime after initial bin
         if !self.cabac_first_bit_flag {
-            out_bins.push_bin(bin);
+            out_bins.push_bin_with_initial_check(bin);
         }
         self.cabac_first_bit_flag = false;
         // TODO optimize without loop
         //if self.cabac_bits_outstanding < 7 {
+        // out_bins is empty at the beginning of a substream of a tile
         while self.cabac_bits_outstanding > 0 {
-            out_bins.push_bin(!bin);
+            out_bins.push_bin_with_initial_check(!bin);
             self.cabac_bits_outstanding -= 1;
         }
         //} else {
@@ -191,7 +190,7 @@ impl BoolCoder {
 
     #[inline(always)]
     pub fn flush_cabac_trailing_bin(&mut self, out_bins: &mut Bins, bin: bool) {
-        out_bins.push_bin(bin);
+        out_bins.push_bin_with_initial_check(bin);
         while self.cabac_bits_outstanding > 0 {
             out_bins.push_bin(!bin);
             self.cabac_bits_outstanding -= 1;
@@ -1275,33 +1274,6 @@ impl BoolCoder {
         } {}
     }
 
-    pub fn encode_limited_kth_order_exp_golomb(
-        &self,
-        out_bins: &mut Bins,
-        symbol_val: usize,
-        k: usize,
-        max_pre_ext_len: usize,
-        trunc_suffix_len: usize,
-    ) {
-        let code_value = symbol_val >> k;
-        let mut pre_ext_len = 0;
-        while pre_ext_len < max_pre_ext_len && code_value > (2 << pre_ext_len) - 2 {
-            pre_ext_len += 1;
-            out_bins.push_bin(true);
-        }
-        let mut escape_length = if pre_ext_len == max_pre_ext_len {
-            trunc_suffix_len
-        } else {
-            out_bins.push_bin(false);
-            pre_ext_len + k
-        };
-        let symbol_val = symbol_val - (((1 << pre_ext_len) - 1) << k);
-        while escape_length > 0 {
-            escape_length -= 1;
-            out_bins.push_bin((symbol_val >> escape_length) & 1 == 1);
-        }
-    }
-
     pub fn encode_limited_kth_order_exp_golomb_to_vec(
         &self,
         symbol_val: usize,
@@ -1479,8 +1451,8 @@ impl BoolCoder {
         self.encode_trancated_binary(out_bins, palette_idx_idc, c_max);
     }
 
-    pub fn encode_abs_mvd_minus2(&mut self, out_bins: &mut Bins, abs_mvd: usize) {
-        self.encode_limited_kth_order_exp_golomb(out_bins, abs_mvd - 2, 1, 15, 17);
+    pub fn encode_abs_mvd_minus2(&mut self, out_bins: &mut Bins, abs_mvd_minus2: usize) {
+        self.encode_kth_order_exp_golomb(out_bins, abs_mvd_minus2, 1);
     }
 
     pub fn derive_context_and_bypass_flag_for_sb_coded_flag(
@@ -1544,12 +1516,7 @@ impl BoolCoder {
             ectx,
         );
         let (ctx_idx, bypass_flag) = match ctx_inc {
-            CtxInc::Number(ctx_inc) => {
-                let init_type = Self::get_init_type(sh);
-                let ctx_idx_offset = ctx_table[ctx as usize][0][0].len() * init_type;
-                let ctx_idx = ctx_idx_offset + ctx_inc;
-                (ctx_idx, false)
-            }
+            CtxInc::Number(ctx_inc) => (ctx_inc, false),
             CtxInc::Bypass => (0, true),
             CtxInc::Terminate => (0, false),
             _ => panic!(),
@@ -1562,7 +1529,7 @@ impl BoolCoder {
         previous_run_type: usize,
         previous_run_position: usize,
         cur_pos: usize,
-        sh: &SliceHeader,
+        _sh: &SliceHeader,
     ) -> (usize, bool) {
         let ctx_inc = self._derive_ctx_inc_for_run_copy_flag(
             previous_run_type,
@@ -1570,13 +1537,7 @@ impl BoolCoder {
             cur_pos,
         );
         let (ctx_idx, bypass_flag) = match ctx_inc {
-            CtxInc::Number(ctx_inc) => {
-                let init_type = Self::get_init_type(sh);
-                let ctx_idx_offset =
-                    ctx_table[CabacContext::RunCopyFlag as usize][0][0].len() * init_type;
-                let ctx_idx = ctx_idx_offset + ctx_inc;
-                (ctx_idx, false)
-            }
+            CtxInc::Number(ctx_inc) => (ctx_inc, false),
             CtxInc::Bypass => (0, true),
             CtxInc::Terminate => (0, false),
             _ => panic!(),
@@ -1608,13 +1569,7 @@ impl BoolCoder {
             ectx,
         );
         let (ctx_idx, bypass_flag) = match ctx_inc {
-            CtxInc::Number(ctx_inc) => {
-                let init_type = Self::get_init_type(sh);
-                let ctx_idx_offset =
-                    ctx_table[CabacContext::CoeffSignFlag as usize][0][0].len() * init_type;
-                let ctx_idx = ctx_idx_offset + ctx_inc;
-                (ctx_idx, false)
-            }
+            CtxInc::Number(ctx_inc) => (ctx_inc, false),
             CtxInc::Bypass => (0, true),
             CtxInc::Terminate => (0, false),
             _ => panic!(),
@@ -1628,7 +1583,7 @@ impl BoolCoder {
         x_c: usize,
         y_c: usize,
         c_idx: usize,
-        ctx: CabacContext,
+        _ctx: CabacContext,
         tu: &TransformUnit,
         sh: &SliceHeader,
         ectx: &EncoderContext,
@@ -1647,12 +1602,7 @@ impl BoolCoder {
             )
         };
         let (ctx_idx, bypass_flag) = match ctx_inc {
-            CtxInc::Number(ctx_inc) => {
-                let init_type = Self::get_init_type(sh);
-                let ctx_idx_offset = ctx_table[ctx as usize][0][0].len() * init_type;
-                let ctx_idx = ctx_idx_offset + ctx_inc;
-                (ctx_idx, false)
-            }
+            CtxInc::Number(ctx_inc) => (ctx_inc, false),
             CtxInc::Bypass => (0, true),
             CtxInc::Terminate => (0, false),
             _ => panic!(),
@@ -1663,21 +1613,16 @@ impl BoolCoder {
     pub fn _derive_context_and_bypass_flag_for_alf_ctb_filter_alt_idx(
         &self,
         ref_l: usize,
-        ctx: CabacContext,
+        _ctx: CabacContext,
         cu: Arc<Mutex<CodingUnit>>,
-        sh: &SliceHeader,
+        _sh: &SliceHeader,
     ) -> (usize, bool) {
         let ctx_inc = {
             let ref_idx = cu.lock().unwrap().ref_idx[ref_l];
             self._derive_ctx_inc_for_alf_ctb_filter_alt_idx(ref_idx)
         };
         let (ctx_idx, bypass_flag) = match ctx_inc {
-            CtxInc::Number(ctx_inc) => {
-                let init_type = Self::get_init_type(sh);
-                let ctx_idx_offset = ctx_table[ctx as usize][0][0].len() * init_type;
-                let ctx_idx = ctx_idx_offset + ctx_inc;
-                (ctx_idx, false)
-            }
+            CtxInc::Number(ctx_inc) => (ctx_inc, false),
             CtxInc::Bypass => (0, true),
             CtxInc::Terminate => (0, false),
             _ => panic!(),
@@ -1689,16 +1634,11 @@ impl BoolCoder {
         &self,
         bin_idx: usize,
         ctx: CabacContext,
-        sh: &SliceHeader,
+        _sh: &SliceHeader,
     ) -> (usize, bool) {
         let ctx_inc = CTX_INC_TABLE[ctx as usize][bin_idx.min(5)]; // FIXME no hard-coded magic number
         let (ctx_idx, bypass_flag) = match ctx_inc {
-            CtxInc::Number(ctx_inc) => {
-                let init_type = Self::get_init_type(sh);
-                let ctx_idx_offset = ctx_table[ctx as usize][0][0].len() * init_type;
-                let ctx_idx = ctx_idx_offset + ctx_inc;
-                (ctx_idx, false)
-            }
+            CtxInc::Number(ctx_inc) => (ctx_inc, false),
             CtxInc::Bypass => (0, true),
             CtxInc::Terminate => (0, false),
             _ => panic!(),
@@ -1807,12 +1747,7 @@ impl BoolCoder {
             _ => CTX_INC_TABLE[ctx as usize][bin_idx.min(5)], // FIXME no hard-coded magic number
         };
         let (ctx_idx, bypass_flag) = match ctx_inc {
-            CtxInc::Number(ctx_inc) => {
-                let init_type = Self::get_init_type(sh);
-                let ctx_idx_offset = ctx_table[ctx as usize][0][0].len() * init_type;
-                let ctx_idx = ctx_idx_offset + ctx_inc;
-                (ctx_idx, false)
-            }
+            CtxInc::Number(ctx_inc) => (ctx_inc, false),
             CtxInc::Bypass => (0, true),
             CtxInc::Terminate => (0, false),
             _ => panic!(),
@@ -1877,12 +1812,7 @@ impl BoolCoder {
             _ => CTX_INC_TABLE[ctx as usize][bin_idx.min(5)], // FIXME no hard-coded magic number
         };
         let (ctx_idx, bypass_flag) = match ctx_inc {
-            CtxInc::Number(ctx_inc) => {
-                let init_type = Self::get_init_type(sh);
-                let ctx_idx_offset = ctx_table[ctx as usize][0][0].len() * init_type;
-                let ctx_idx = ctx_idx_offset + ctx_inc;
-                (ctx_idx, false)
-            }
+            CtxInc::Number(ctx_inc) => (ctx_inc, false),
             CtxInc::Bypass => (0, true),
             CtxInc::Terminate => (0, false),
             _ => panic!(),
@@ -1896,14 +1826,14 @@ impl BoolCoder {
         c_idx: usize,
         ctx: CabacContext,
         tu: &TransformUnit,
-        sh: &SliceHeader,
+        _sh: &SliceHeader,
         ectx: &EncoderContext,
     ) -> (usize, bool) {
         let ctx_inc = match ctx {
             CabacContext::TuYCodedFlag => {
                 let prev_tu_cbf_y = match tu.prev_tu_in_cu() {
                     Some(prev_tu) => {
-                        let prev_tu = prev_tu.borrow();
+                        let prev_tu = prev_tu.lock().unwrap();
                         prev_tu.get_y_coded_flag() as usize
                     }
                     None => 0,
@@ -1936,12 +1866,7 @@ impl BoolCoder {
             _ => CTX_INC_TABLE[ctx as usize][bin_idx.min(5)], // FIXME no hard-coded magic number
         };
         let (ctx_idx, bypass_flag) = match ctx_inc {
-            CtxInc::Number(ctx_inc) => {
-                let init_type = Self::get_init_type(sh);
-                let ctx_idx_offset = ctx_table[ctx as usize][0][0].len() * init_type;
-                let ctx_idx = ctx_idx_offset + ctx_inc;
-                (ctx_idx, false)
-            }
+            CtxInc::Number(ctx_inc) => (ctx_inc, false),
             CtxInc::Bypass => (0, true),
             CtxInc::Terminate => (0, false),
             _ => panic!(),
@@ -1954,7 +1879,7 @@ impl BoolCoder {
         bin_idx: usize,
         c_idx: usize,
         tu: &TransformUnit,
-        sh: &SliceHeader,
+        _sh: &SliceHeader,
     ) -> (usize, bool) {
         let ctx_inc = self.derive_ctx_inc_for_last_sig_coeff_x_prefix(
             bin_idx,
@@ -1962,13 +1887,7 @@ impl BoolCoder {
             tu.get_log2_tb_size(c_idx).0,
         );
         let (ctx_idx, bypass_flag) = match ctx_inc {
-            CtxInc::Number(ctx_inc) => {
-                let init_type = Self::get_init_type(sh);
-                let ctx_idx_offset =
-                    ctx_table[CabacContext::LastSigCoeffXPrefix as usize][0][0].len() * init_type;
-                let ctx_idx = ctx_idx_offset + ctx_inc;
-                (ctx_idx, false)
-            }
+            CtxInc::Number(ctx_inc) => (ctx_inc, false),
             CtxInc::Bypass => (0, true),
             CtxInc::Terminate => (0, false),
             _ => panic!(),
@@ -1981,7 +1900,7 @@ impl BoolCoder {
         bin_idx: usize,
         c_idx: usize,
         tu: &TransformUnit,
-        sh: &SliceHeader,
+        _sh: &SliceHeader,
     ) -> (usize, bool) {
         let ctx_inc = self.derive_ctx_inc_for_last_sig_coeff_y_prefix(
             bin_idx,
@@ -1989,13 +1908,7 @@ impl BoolCoder {
             tu.get_log2_tb_size(c_idx).1,
         );
         let (ctx_idx, bypass_flag) = match ctx_inc {
-            CtxInc::Number(ctx_inc) => {
-                let init_type = Self::get_init_type(sh);
-                let ctx_idx_offset =
-                    ctx_table[CabacContext::LastSigCoeffYPrefix as usize][0][0].len() * init_type;
-                let ctx_idx = ctx_idx_offset + ctx_inc;
-                (ctx_idx, false)
-            }
+            CtxInc::Number(ctx_inc) => (ctx_inc, false),
             CtxInc::Bypass => (0, true),
             CtxInc::Terminate => (0, false),
             _ => panic!(),
@@ -2007,7 +1920,7 @@ impl BoolCoder {
         &self,
         bin_idx: usize,
         ctx: CabacContext,
-        sh: &SliceHeader,
+        _sh: &SliceHeader,
     ) -> (usize, bool) {
         let ctx_inc = match ctx {
             CabacContext::EndOfSliceOneBit
@@ -2016,12 +1929,7 @@ impl BoolCoder {
             _ => CTX_INC_TABLE[ctx as usize][bin_idx.min(5)], // FIXME no hard-coded magic number
         };
         let (ctx_idx, bypass_flag) = match ctx_inc {
-            CtxInc::Number(ctx_inc) => {
-                let init_type = Self::get_init_type(sh);
-                let ctx_idx_offset = ctx_table[ctx as usize][0][0].len() * init_type;
-                let ctx_idx = ctx_idx_offset + ctx_inc;
-                (ctx_idx, false)
-            }
+            CtxInc::Number(ctx_inc) => (ctx_inc, false),
             CtxInc::Bypass => (0, true),
             CtxInc::Terminate => (0, false),
             _ => panic!(),
@@ -2033,16 +1941,11 @@ impl BoolCoder {
         &self,
         bin_idx: usize,
         ctx: CabacContext,
-        sh: &SliceHeader,
+        _sh: &SliceHeader,
     ) -> (usize, bool) {
         let ctx_inc = CTX_INC_TABLE[ctx as usize][bin_idx.min(5)]; // FIXME no hard-coded magic number
         let (ctx_idx, bypass_flag) = match ctx_inc {
-            CtxInc::Number(ctx_inc) => {
-                let init_type = Self::get_init_type(sh);
-                let ctx_idx_offset = ctx_table[ctx as usize][0][0].len() * init_type;
-                let ctx_idx = ctx_idx_offset + ctx_inc;
-                (ctx_idx, false)
-            }
+            CtxInc::Number(ctx_inc) => (ctx_inc, false),
             CtxInc::Bypass => (0, true),
             //CtxInc::Terminate => (0, false),
             _ => panic!(),
@@ -2742,37 +2645,24 @@ impl BoolCoder {
                         + ctx_set_idx * 3,
                 )
             }
-            ctx @ CabacContext::NonInterFlag => {
-                let (cond_l, cond_a) = match ctx {
-                    CabacContext::PredModeFlag => {
-                        let left_ct = ct.left_ct();
-                        let left_ct = left_ct.as_ref().unwrap();
-                        let left_ct = left_ct.lock().unwrap();
-                        let left_cu = left_ct.cus[0].clone();
-                        let left_cu = left_cu.lock().unwrap();
-                        (
-                            // FIXME ch_type
-                            left_cu.mode_type == ModeType::MODE_INTRA,
-                            left_cu.mode_type == ModeType::MODE_INTRA,
-                        )
-                    }
-                    CabacContext::NonInterFlag => {
-                        let above_ct = ct.above_ct();
-                        let above_ct = above_ct.as_ref().unwrap();
-                        let above_ct = above_ct.lock().unwrap();
-                        let above_cu = above_ct.cus[0].clone();
-                        let above_cu = above_cu.lock().unwrap();
-                        (
-                            // FIXME ch_type
-                            above_cu.mode_type == ModeType::MODE_INTRA,
-                            above_cu.mode_type == ModeType::MODE_INTRA,
-                        )
-                    }
-                    _ => {
-                        panic!()
-                    }
+            CabacContext::NonInterFlag => {
+                let cond_l = available_l && {
+                    let left_ct = ct.left_ct();
+                    let left_ct = left_ct.as_ref().unwrap();
+                    let left_ct = left_ct.lock().unwrap();
+                    let left_cu = left_ct.cus[0].clone();
+                    let left_cu = left_cu.lock().unwrap();
+                    left_cu.pred_mode[0] == ModeType::MODE_INTRA
+                };
+                let cond_a = available_a && {
+                    let above_ct = ct.above_ct();
+                    let above_ct = above_ct.as_ref().unwrap();
+                    let above_ct = above_ct.lock().unwrap();
+                    let above_cu = above_ct.cus[0].clone();
+                    let above_cu = above_cu.lock().unwrap();
+                    above_cu.pred_mode[0] == ModeType::MODE_INTRA
                 };
-                CtxInc::Number(((cond_l && available_l) || (cond_a && available_a)) as usize)
+                CtxInc::Number((cond_l || cond_a) as usize)
             }
             _ => {
                 panic!()
@@ -2804,163 +2694,177 @@ impl BoolCoder {
             x0, y0, x_nb_a, y_nb_a, cb_width, cb_height, false, false, false, sps, pps,
         );
 
-        let ctx_inc = match cabac_context {
-            ctx @ (CabacContext::CuSkipFlag
-            | CabacContext::PredModeIbcFlag
-            | CabacContext::IntraMipFlag
-            | CabacContext::MergeSubblockFlag
-            | CabacContext::InterAffineFlag) => {
-                let (cond_l, cond_a, ctx_set_idx) = match ctx {
-                    CabacContext::CuSkipFlag => (
-                        if available_l {
-                            let left_ct = ct.left_ct();
-                            let left_ct = left_ct.as_ref().unwrap();
-                            let left_ct = left_ct.lock().unwrap();
-                            let left_cu = left_ct.cus[0].clone();
-                            let left_cu = left_cu.lock().unwrap();
-                            left_cu.skip_flag
-                        } else {
-                            false
-                        },
-                        if available_a {
-                            let above_ct = ct.above_ct();
-                            let above_ct = above_ct.as_ref().unwrap();
-                            let above_ct = above_ct.lock().unwrap();
-                            let above_cu = above_ct.cus[0].clone();
-                            let above_cu = above_cu.lock().unwrap();
-                            above_cu.skip_flag
-                        } else {
-                            false
-                        },
-                        0,
-                    ),
-                    CabacContext::PredModeIbcFlag => (
-                        if available_l {
-                            let left_ct = ct.left_ct();
-                            let left_ct = left_ct.as_ref().unwrap();
-                            let left_ct = left_ct.lock().unwrap();
-                            let left_cu = left_ct.cus[0].clone();
-                            let left_cu = left_cu.lock().unwrap();
-                            // FIXME ch_type
-                            left_cu.mode_type == ModeType::MODE_IBC
-                        } else {
-                            false
-                        },
-                        if available_a {
-                            let above_ct = ct.above_ct();
-                            let above_ct = above_ct.as_ref().unwrap();
-                            let above_ct = above_ct.lock().unwrap();
-                            let above_cu = above_ct.cus[0].clone();
-                            let above_cu = above_cu.lock().unwrap();
-                            // FIXME ch_type
-                            above_cu.mode_type == ModeType::MODE_IBC
-                        } else {
-                            false
-                        },
-                        0,
-                    ),
-                    CabacContext::IntraMipFlag => (
-                        if available_l {
-                            let left_ct = ct.left_ct();
-                            let left_ct = left_ct.as_ref().unwrap();
-                            let left_ct = left_ct.lock().unwrap();
-                            let left_cu = left_ct.cus[0].clone();
-                            let left_cu = left_cu.lock().unwrap();
-                            left_cu.intra_mip_flag
-                        } else {
-                            false
-                        },
-                        if available_a {
-                            let above_ct = ct.above_ct();
-                            let above_ct = above_ct.as_ref().unwrap();
-                            let above_ct = above_ct.lock().unwrap();
-                            let above_cu = above_ct.cus[0].clone();
-                            let above_cu = above_cu.lock().unwrap();
-                            above_cu.intra_mip_flag
-                        } else {
-                            false
-                        },
-                        0,
-                    ),
-                    CabacContext::MergeSubblockFlag => (
-                        if available_l {
-                            let left_ct = ct.left_ct();
-                            let left_ct = left_ct.as_ref().unwrap();
-                            let left_ct = left_ct.lock().unwrap();
-                            let left_cu = left_ct.cus[0].clone();
-                            let left_cu = left_cu.lock().unwrap();
-                            let merge_data = left_cu.merge_data.as_ref().unwrap();
-                            merge_data.merge_subblock_flag || left_cu.inter_affine_flag
-                        } else {
-                            false
-                        },
-                        if available_a {
-                            let above_ct = ct.above_ct();
-                            let above_ct = above_ct.as_ref().unwrap();
-                            let above_ct = above_ct.lock().unwrap();
-                            let above_cu = above_ct.cus[0].clone();
-                            let above_cu = above_cu.lock().unwrap();
-                            let merge_data = above_cu.merge_data.as_ref().unwrap();
-                            merge_data.merge_subblock_flag || above_cu.inter_affine_flag
-                        } else {
-                            false
-                        },
-                        0,
-                    ),
-                    CabacContext::InterAffineFlag => (
-                        if available_l {
-                            let left_ct = ct.left_ct();
-                            let left_ct = left_ct.as_ref().unwrap();
-                            let left_ct = left_ct.lock().unwrap();
-                            let left_cu = left_ct.cus[0].clone();
-                            let left_cu = left_cu.lock().unwrap();
-                            let merge_data = left_cu.merge_data.as_ref().unwrap();
-                            merge_data.merge_subblock_flag || left_cu.inter_affine_flag
-                        } else {
-                            false
-                        },
-                        if available_a {
-                            let above_ct = ct.above_ct();
-                            let above_ct = above_ct.as_ref().unwrap();
-                            let above_ct = above_ct.lock().unwrap();
-                            let above_cu = above_ct.cus[0].clone();
-                            let above_cu = above_cu.lock().unwrap();
-                            let merge_data = above_cu.merge_data.as_ref().unwrap();
-                            merge_data.merge_subblock_flag || above_cu.inter_affine_flag
-                        } else {
-                            false
-                        },
-                        0,
-                    ),
-                    _ => {
-                        panic!()
-                    }
-                };
-                CtxInc::Number(
-                    (cond_l && available_l) as usize
-                        + (cond_a && available_a) as usize
-                        + ctx_set_idx * 3,
-                )
-            }
-            CabacContext::PredModeFlag => {
-                let (cond_l, cond_a) = {
-                    let left_ct = ct.left_ct();
-                    let left_ct = left_ct.as_ref().unwrap();
-                    let left_ct = left_ct.lock().unwrap();
-                    let left_cu = left_ct.cus[0].clone();
-                    let left_cu = left_cu.lock().unwrap();
-                    (
-                        // FIXME ch_type
-                        left_cu.mode_type == ModeType::MODE_INTRA,
-                        left_cu.mode_type == ModeType::MODE_INTRA,
+        let ctx_inc =
+            match cabac_context {
+                ctx @ (CabacContext::CuSkipFlag
+                | CabacContext::PredModeIbcFlag
+                | CabacContext::IntraMipFlag
+                | CabacContext::MergeSubblockFlag
+                | CabacContext::InterAffineFlag) => {
+                    let (cond_l, cond_a, ctx_set_idx) =
+                        match ctx {
+                            CabacContext::CuSkipFlag => (
+                                if available_l {
+                                    let left_ct = ct.left_ct();
+                                    let left_ct = left_ct.as_ref().unwrap();
+                                    let left_ct = left_ct.lock().unwrap();
+                                    let left_cu = left_ct.cus[0].clone();
+                                    let left_cu = left_cu.lock().unwrap();
+                                    left_cu.skip_flag
+                                } else {
+                                    false
+                                },
+                                if available_a {
+                                    let above_ct = ct.above_ct();
+                                    let above_ct = above_ct.as_ref().unwrap();
+                                    let above_ct = above_ct.lock().unwrap();
+                                    let above_cu = above_ct.cus[0].clone();
+                                    let above_cu = above_cu.lock().unwrap();
+                                    above_cu.skip_flag
+                                } else {
+                                    false
+                                },
+                                0,
+                            ),
+                            CabacContext::PredModeIbcFlag => (
+                                if available_l {
+                                    let left_ct = ct.left_ct();
+                                    let left_ct = left_ct.as_ref().unwrap();
+                                    let left_ct = left_ct.lock().unwrap();
+                                    let left_cu = left_ct.cus[0].clone();
+                                    let left_cu = left_cu.lock().unwrap();
+                                    // FIXME ch_type
+                                    left_cu.mode_type == ModeType::MODE_IBC
+                                } else {
+                                    false
+                                },
+                                if available_a {
+                                    let above_ct = ct.above_ct();
+                                    let above_ct = above_ct.as_ref().unwrap();
+                                    let above_ct = above_ct.lock().unwrap();
+                                    let above_cu = above_ct.cus[0].clone();
+                                    let above_cu = above_cu.lock().unwrap();
+                                    // FIXME ch_type
+                                    above_cu.mode_type == ModeType::MODE_IBC
+                                } else {
+                                    false
+                                },
+                                0,
+                            ),
+                            CabacContext::IntraMipFlag => (
+                                if available_l {
+                                    let left_ct = ct.left_ct();
+                                    let left_ct = left_ct.as_ref().unwrap();
+                                    let left_ct = left_ct.lock().unwrap();
+                                    let left_cu = left_ct.cus[0].clone();
+                                    let left_cu = left_cu.lock().unwrap();
+                                    left_cu.intra_mip_flag
+                                } else {
+                                    false
+                                },
+                                if available_a {
+                                    let above_ct = ct.above_ct();
+                                    let above_ct = above_ct.as_ref().unwrap();
+                                    let above_ct = above_ct.lock().unwrap();
+                                    let above_cu = above_ct.cus[0].clone();
+                                    let above_cu = above_cu.lock().unwrap();
+                                    above_cu.intra_mip_flag
+                                } else {
+                                    false
+                                },
+                                0,
+                            ),
+                            CabacContext::MergeSubblockFlag => {
+                                (
+                                    if available_l {
+                                        let left_ct = ct.left_ct();
+                                        let left_ct = left_ct.as_ref().unwrap();
+                                        let left_ct = left_ct.lock().unwrap();
+                                        let left_cu = left_ct.cus[0].clone();
+                                        let left_cu = left_cu.lock().unwrap();
+                                        left_cu.merge_data.as_ref().is_some_and(|merge_data| {
+                                            merge_data.merge_subblock_flag
+                                        }) || left_cu.inter_affine_flag
+                                    } else {
+                                        false
+                                    },
+                                    if available_a {
+                                        let above_ct = ct.above_ct();
+                                        let above_ct = above_ct.as_ref().unwrap();
+                                        let above_ct = above_ct.lock().unwrap();
+                                        let above_cu = above_ct.cus[0].clone();
+                                        let above_cu = above_cu.lock().unwrap();
+                                        above_cu.merge_data.as_ref().is_some_and(|merge_data| {
+                                            merge_data.merge_subblock_flag
+                                        }) || above_cu.inter_affine_flag
+                                    } else {
+                                        false
+                                    },
+                                    0,
+                                )
+                            }
+                            CabacContext::InterAffineFlag => {
+                                (
+                                    if available_l {
+                                        let left_ct = ct.left_ct();
+                                        let left_ct = left_ct.as_ref().unwrap();
+                                        let left_ct = left_ct.lock().unwrap();
+                                        let left_cu = left_ct.cus[0].clone();
+                                        let left_cu = left_cu.lock().unwrap();
+                                        left_cu.merge_data.as_ref().is_some_and(|merge_data| {
+                                            merge_data.merge_subblock_flag
+                                        }) || left_cu.inter_affine_flag
+                                    } else {
+                                        false
+                                    },
+                                    if available_a {
+                                        let above_ct = ct.above_ct();
+                                        let above_ct = above_ct.as_ref().unwrap();
+                                        let above_ct = above_ct.lock().unwrap();
+                                        let above_cu = above_ct.cus[0].clone();
+                                        let above_cu = above_cu.lock().unwrap();
+                                        above_cu.merge_data.as_ref().is_some_and(|merge_data| {
+                                            merge_data.merge_subblock_flag
+                                        }) || above_cu.inter_affine_flag
+                                    } else {
+                                        false
+                                    },
+                                    0,
+                                )
+                            }
+                            _ => {
+                                panic!()
+                            }
+                        };
+                    CtxInc::Number(
+                        (cond_l && available_l) as usize
+                            + (cond_a && available_a) as usize
+                            + ctx_set_idx * 3,
                     )
-                };
-                CtxInc::Number(((cond_l && available_l) || (cond_a && available_a)) as usize)
-            }
-            _ => {
-                panic!()
-            }
-        };
+                }
+                CabacContext::PredModeFlag => {
+                    let cond_l = available_l && {
+                        let left_ct = ct.left_ct();
+                        let left_ct = left_ct.as_ref().unwrap();
+                        let left_ct = left_ct.lock().unwrap();
+                        let left_cu = left_ct.cus[0].clone();
+                        let left_cu = left_cu.lock().unwrap();
+                        left_cu.pred_mode[0] == ModeType::MODE_INTRA
+                    };
+                    let cond_a = available_a && {
+                        let above_ct = ct.above_ct();
+                        let above_ct = above_ct.as_ref().unwrap();
+                        let above_ct = above_ct.lock().unwrap();
+                        let above_cu = above_ct.cus[0].clone();
+                        let above_cu = above_cu.lock().unwrap();
+                        above_cu.pred_mode[0] == ModeType::MODE_INTRA
+                    };
+                    CtxInc::Number((cond_l || cond_a) as usize)
+                }
+                _ => {
+                    panic!()
+                }
+            };
         ctx_inc
     }
 }
@@ -1407,9 +1407,23 @@ pub const CTX_INC_TABLE: [[CtxInc; 6]; 110] = [
         CtxInc::Bypass,
     ],
     // mvp_l0_flag
-    [CtxInc::Invalid; 6],
+    [
+        CtxInc::Number(0),
+        CtxInc::NA,
+        CtxInc::NA,
+        CtxInc::NA,
+        CtxInc::NA,
+        CtxInc::NA,
+    ],
     // mvp_l1_flag
-    [CtxInc::Invalid; 6],
+    [
+        CtxInc::Number(0),
+        CtxInc::NA,
+        CtxInc::NA,
+        CtxInc::NA,
+        CtxInc::NA,
+        CtxInc::NA,
+    ],
     // amvr_flag
     [CtxInc::Invalid; 6],
     // amvr_precision_idx
@@ -0,0 +1,810 @@
+use super::analyzer::*;
+use super::binary_reader::*;
+use super::bool_coder::*;
+use super::cabac_contexts::*;
+use super::ctu::*;
+use super::encoder_context::*;
+use super::pps::*;
+use super::slice_header::*;
+use super::sps::*;
+use debug_print::*;
+use std::sync::{Arc, Mutex};
+
+/// CABAC parsing process (9.3) reading bins from slice data. Context variables are held by a
+/// BoolCoder, so that the context selection shared with the encoder is used as it is.
+pub struct CabacDecoder<'a, 'b> {
+    reader: &'a mut BinaryReader<'b>,
+    coder: BoolCoder,
+    num_bins: usize,
+    /// The name of the syntax element being decoded and the number of bins decoded before it
+    syntax_element: Option<(&'static str, usize)>,
+    trace: Option<String>,
+}
+
+impl<'a, 'b> CabacDecoder<'a, 'b> {
+    pub fn new(reader: &'a mut BinaryReader<'b>) -> CabacDecoder<'a, 'b> {
+        CabacDecoder {
+            reader,
+            coder: BoolCoder::new(),
+            num_bins: 0,
+            syntax_element: None,
+            trace: None,
+        }
+    }
+
+    /// Starts tracing the decoded syntax elements named by trace_syntax_element.
+    pub fn start_trace(&mut self) {
+        self.trace = Some(String::new());
+    }
+
+    /// Returns the lines traced so far, or None when not tracing.
+    pub fn take_trace(&mut self) -> Option<String> {
+        self.trace.as_mut().map(std::mem::take)
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// Appends a line such as the position of a block to the trace.
+    pub fn trace_line(&mut self, line: &str) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.push_str(line);
+            trace.push('\n');
+        }
+    }
+
+    /// Names the syntax element decoded next. The name is printed in debug builds, and the
+    /// syntax element is traced with its value and the number of its bins while tracing.
+    pub fn trace_syntax_element(&mut self, name: &'static str) {
+        debug_eprintln!("{}", name);
+        self.syntax_element = Some((name, self.num_bins));
+    }
+
+    fn traced_value(&mut self, value: usize) -> usize {
+        if let Some((name, num_bins)) = self.syntax_element.take() {
+            if self.trace.is_some() {
+                let line = format!(
+                    "{} ({} bins)",
+                    format_trace_line(name.trim(), "ae(v)", &value.to_string()),
+                    self.num_bins - num_bins
+                );
+                self.trace_line(&line);
+            }
+        }
+        value
+    }
+
+    fn traced_flag(&mut self, flag: bool) -> bool {
+        self.traced_value(flag as usize) != 0
+    }
+
+    /// Initializes context variables like the encoder does and then the arithmetic decoding
+    /// engine (9.3.2.5), which reads the first 9 bits of a substream.
+    pub fn init_cabac(
+        &mut self,
+        is_first_ctu_in_slice_or_tile: bool,
+        ctu: &CodingTreeUnit,
+        sps: &SequenceParameterSet,
+        pps: &PictureParameterSet,
+        ectx: Arc<Mutex<EncoderContext>>,
+    ) {
+        self.coder
+            .init_cabac(is_first_ctu_in_slice_or_tile, ctu, sps, pps, ectx);
+        self.coder.cabac_ivl_offset = self.reader.read_value(9) as u16;
+    }
+
+    #[inline(always)]
+    pub fn storage_ctx_table(&mut self) {
+        self.coder.storage_ctx_table();
+    }
+
+    /// Skips alignment_bit_equal_to_zero following the last bin of a substream. The arithmetic
+    /// decoding engine has already read alignment_bit_equal_to_one, which is the last bit written
+    /// by flushing the encoder.
+    pub fn byte_align(&mut self) {
+        self.reader.byte_align();
+    }
+
+    #[inline(always)]
+    fn renorm_cabac_decode_engine(&mut self) {
+        while self.coder.cabac_ivl_curr_range < 256 {
+            self.coder.cabac_ivl_curr_range <<= 1;
+            self.coder.cabac_ivl_offset =
+                (self.coder.cabac_ivl_offset << 1) | self.reader.read_bit() as u16;
+        }
+    }
+
+    pub fn decode_cabac_binary_decision(
+        &mut self,
+        ctx: CabacContext,
+        init_type: usize,
+        ctx_idx: usize,
+    ) -> bool {
+        let q_range_idx = self.coder.cabac_ivl_curr_range >> 5;
+        let p_state = self.coder.cabac_p_state_idx[ctx as usize][init_type][ctx_idx][1]
+            + 16 * self.coder.cabac_p_state_idx[ctx as usize][init_type][ctx_idx][0];
+        let val_mps = p_state >> 14;
+        let lps_range = ((q_range_idx
+            * ((if val_mps == 0 {
+                p_state
+            } else {
+                32767 - p_state
+            }) >> 9))
+            >> 1)
+            + 4;
+        self.coder.cabac_ivl_curr_range -= lps_range;
+        let bin_val = if self.coder.cabac_ivl_offset >= self.coder.cabac_ivl_curr_range {
+            self.coder.cabac_ivl_offset -= self.coder.cabac_ivl_curr_range;
+            self.coder.cabac_ivl_curr_range = lps_range;
+            val_mps == 0
+        } else {
+            val_mps == 1
+        };
+        self.renorm_cabac_decode_engine();
+        self.coder
+            .transition_cabac_state(bin_val, ctx, init_type, ctx_idx);
+        self.num_bins += 1;
+        bin_val
+    }
+
+    #[inline(always)]
+    pub fn decode_cabac_bypass(&mut self) -> bool {
+        self.num_bins += 1;
+        self.coder.cabac_ivl_offset =
+            (self.coder.cabac_ivl_offset << 1) | self.reader.read_bit() as u16;
+        if self.coder.cabac_ivl_offset >= self.coder.cabac_ivl_curr_range {
+            self.coder.cabac_ivl_offset -= self.coder.cabac_ivl_curr_range;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn decode_cabac_terminate(&mut self) -> bool {
+        self.num_bins += 1;
+        self.coder.cabac_ivl_curr_range -= 2;
+        if self.coder.cabac_ivl_offset >= self.coder.cabac_ivl_curr_range {
+            true
+        } else {
+            self.renorm_cabac_decode_engine();
+            false
+        }
+    }
+
+    #[inline(always)]
+    pub fn decode_arithmetic(
+        &mut self,
+        ctx: CabacContext,
+        ctx_idx: usize,
+        bypass_flag: bool,
+        init_type: usize,
+    ) -> bool {
+        let bin = if bypass_flag {
+            self.decode_cabac_bypass()
+        } else {
+            self.decode_cabac_binary_decision(ctx, init_type, ctx_idx)
+        };
+        debug_eprintln!("dcbin {:?}", bin);
+        bin
+    }
+
+    /// Inverse of the binarization process given by ctx_to_bin_process.
+    fn debinarize<F: FnMut(&mut Self, usize) -> bool>(
+        &mut self,
+        bin_process: &BinProcess,
+        next_bin: &mut F,
+    ) -> usize {
+        match bin_process {
+            BinProcess::FL(c_max) => self.decode_fixed_length(*c_max, 0, next_bin),
+            BinProcess::TB(c_max) => self.decode_trancated_binary(*c_max, next_bin),
+            BinProcess::TR(c_max, c_rice_param) => {
+                self.decode_trancated_rice(*c_max, *c_rice_param, 0, next_bin)
+                    .0
+            }
+            BinProcess::EG(k) => self.decode_kth_order_exp_golomb(*k, 0, next_bin).0,
+            _ => panic!(),
+        }
+    }
+
+    /// Returns the value and the number of bins read after bin_idx.
+    fn decode_fixed_length<F: FnMut(&mut Self, usize) -> bool>(
+        &mut self,
+        c_max: usize,
+        bin_idx: usize,
+        next_bin: &mut F,
+    ) -> usize {
+        let fixed_length = c_max.ilog2() as usize + 1;
+        (bin_idx..bin_idx + fixed_length).fold(0, |v, i| (v << 1) | next_bin(self, i) as usize)
+    }
+
+    fn decode_trancated_binary<F: FnMut(&mut Self, usize) -> bool>(
+        &mut self,
+        c_max: usize,
+        next_bin: &mut F,
+    ) -> usize {
+        let n = c_max + 1;
+        let k = n.ilog2() as usize;
+        let u = (1 << (k + 1)) - n;
+        let v = (0..k).fold(0, |v, i| (v << 1) | next_bin(self, i) as usize);
+        if v < u {
+            v
+        } else {
+            ((v << 1) | next_bin(self, k) as usize) - u
+        }
+    }
+
+    /// Returns the value and the index of the next bin.
+    fn decode_trancated_rice<F: FnMut(&mut Self, usize) -> bool>(
+        &mut self,
+        c_max: usize,
+        c_rice_param: usize,
+        bin_idx: usize,
+        next_bin: &mut F,
+    ) -> (usize, usize) {
+        let mut bin_idx = bin_idx;
+        let mut prefix_val = 0;
+        while prefix_val < c_max >> c_rice_param {
+            let bin = next_bin(self, bin_idx);
+            bin_idx += 1;
+            if !bin {
+                break;
+            }
+            prefix_val += 1;
+        }
+        let mut symbol_val = prefix_val << c_rice_param;
+        if symbol_val < c_max && c_rice_param > 0 {
+            symbol_val += self.decode_fixed_length((1 << c_rice_param) - 1, bin_idx, next_bin);
+            bin_idx += c_rice_param;
+        }
+        (symbol_val, bin_idx)
+    }
+
+    fn decode_kth_order_exp_golomb<F: FnMut(&mut Self, usize) -> bool>(
+        &mut self,
+        k: usize,
+        bin_idx: usize,
+        next_bin: &mut F,
+    ) -> (usize, usize) {
+        let mut bin_idx = bin_idx;
+        let mut k = k;
+        let mut symbol_val = 0;
+        while {
+            let bin = next_bin(self, bin_idx);
+            bin_idx += 1;
+            bin
+        } {
+            symbol_val += 1 << k;
+            k += 1;
+        }
+        if k > 0 {
+            symbol_val += self.decode_fixed_length((1 << k) - 1, bin_idx, next_bin);
+            bin_idx += k;
+        }
+        (symbol_val, bin_idx)
+    }
+
+    fn decode_limited_kth_order_exp_golomb<F: FnMut(&mut Self, usize) -> bool>(
+        &mut self,
+        k: usize,
+        max_pre_ext_len: usize,
+        trunc_suffix_len: usize,
+        bin_idx: usize,
+        next_bin: &mut F,
+    ) -> usize {
+        let mut bin_idx = bin_idx;
+        let mut pre_ext_len = 0;
+        while pre_ext_len < max_pre_ext_len && {
+            let bin = next_bin(self, bin_idx);
+            bin_idx += 1;
+            bin
+        } {
+            pre_ext_len += 1;
+        }
+        let escape_length = if pre_ext_len == max_pre_ext_len {
+            trunc_suffix_len
+        } else {
+            pre_ext_len + k
+        };
+        let symbol_val = if escape_length > 0 {
+            self.decode_fixed_length((1 << escape_length) - 1, bin_idx, next_bin)
+        } else {
+            0
+        };
+        symbol_val + (((1 << pre_ext_len) - 1) << k)
+    }
+
+    fn decode_intra_chroma_pred_mode<F: FnMut(&mut Self, usize) -> bool>(
+        &mut self,
+        next_bin: &mut F,
+    ) -> usize {
+        if next_bin(self, 0) {
+            self.decode_fixed_length(3, 1, next_bin)
+        } else {
+            4
+        }
+    }
+
+    fn decode_cu_qp_delta_abs<F: FnMut(&mut Self, usize) -> bool>(
+        &mut self,
+        next_bin: &mut F,
+    ) -> usize {
+        let (prefix_val, bin_idx) = self.decode_trancated_rice(5, 0, 0, next_bin);
+        if prefix_val > 4 {
+            prefix_val + self.decode_kth_order_exp_golomb(0, bin_idx, next_bin).0
+        } else {
+            prefix_val
+        }
+    }
+
+    /// Inverse of the binarization of abs_remainder and dec_abs_level (9.3.3.11, 9.3.3.12).
+    fn decode_abs_level_with_rice_parameter<F: FnMut(&mut Self, usize) -> bool>(
+        &mut self,
+        c_rice_param: usize,
+        next_bin: &mut F,
+    ) -> usize {
+        let c_max = 6 << c_rice_param;
+        let (prefix_val, bin_idx) = self.decode_trancated_rice(c_max, c_rice_param, 0, next_bin);
+        if prefix_val == c_max {
+            c_max
+                + self.decode_limited_kth_order_exp_golomb(
+                    c_rice_param + 1,
+                    11,
+                    15,
+                    bin_idx,
+                    next_bin,
+                )
+        } else {
+            prefix_val
+        }
+    }
+
+    pub fn decode_cabac_ct(
+        &mut self,
+        ctx: CabacContext,
+        ct: &CodingTree,
+        sh: &SliceHeader,
+        ectx: &mut EncoderContext,
+    ) -> usize {
+        debug_eprintln!("dc {}", ctx as usize);
+        let init_type = BoolCoder::get_init_type(sh);
+        let ectx = &*ectx;
+        let mut next_bin = |decoder: &mut Self, bin_idx: usize| {
+            let (ctx_idx, bypass_flag) = decoder
+                .coder
+                .derive_context_and_bypass_flag_ct(bin_idx, ctx, ct, sh, ectx);
+            decoder.decode_arithmetic(ctx, ctx_idx, bypass_flag, init_type)
+        };
+        let value = self.debinarize(&ctx_to_bin_process[ctx as usize], &mut next_bin);
+        self.traced_value(value)
+    }
+
+    pub fn decode_cabac_cu(
+        &mut self,
+        ctx: CabacContext,
+        cu: &CodingUnit,
+        sh: &SliceHeader,
+        ectx: &mut EncoderContext,
+    ) -> usize {
+        debug_eprintln!("dc {}", ctx as usize);
+        let init_type = BoolCoder::get_init_type(sh);
+        let ct = &cu.parent.lock().unwrap();
+        let ectx = &*ectx;
+        let mut next_bin = |decoder: &mut Self, bin_idx: usize| {
+            let (ctx_idx, bypass_flag) = decoder
+                .coder
+                .derive_context_and_bypass_flag_cu(bin_idx, ctx, ct, cu, sh, ectx);
+            decoder.decode_arithmetic(ctx, ctx_idx, bypass_flag, init_type)
+        };
+        let value = match ctx {
+            CabacContext::IntraMipMode => {
+                let c_max = if cu.width == 4 && cu.height == 4 {
+                    15
+                } else if cu.width == 4 || cu.height == 4 || (cu.width == 8 && cu.height == 8) {
+                    7
+                } else {
+                    5
+                };
+                self.decode_trancated_binary(c_max, &mut next_bin)
+            }
+            CabacContext::IntraChromaPredMode => self.decode_intra_chroma_pred_mode(&mut next_bin),
+            CabacContext::RefIdxL0 => {
+                let c_max = ectx.num_ref_idx_active[0] - 1;
+                self.decode_trancated_rice(c_max, 0, 0, &mut next_bin).0
+            }
+            CabacContext::RefIdxL1 => {
+                let c_max = ectx.num_ref_idx_active[1] - 1;
+                self.decode_trancated_rice(c_max, 0, 0, &mut next_bin).0
+            }
+            CabacContext::AbsMvd => self.decode_kth_order_exp_golomb(1, 0, &mut next_bin).0,
+            CabacContext::CuQpDeltaAbs => self.decode_cu_qp_delta_abs(&mut next_bin),
+            CabacContext::CuChromaQpOffsetIdx => {
+                let c_max = sh.pps.chroma_tool_offsets.chroma_qp_offset_list_len - 1;
+                self.decode_trancated_rice(c_max, 0, 0, &mut next_bin).0
+            }
+            _ => self.debinarize(&ctx_to_bin_process[ctx as usize], &mut next_bin),
+        };
+        self.traced_value(value)
+    }
+
+    pub fn decode_cabac_tu(
+        &mut self,
+        c_idx: usize,
+        ctx: CabacContext,
+        tu: &TransformUnit,
+        sh: &SliceHeader,
+        ectx: &mut EncoderContext,
+    ) -> usize {
+        debug_eprintln!("dc {}", ctx as usize);
+        let init_type = BoolCoder::get_init_type(sh);
+        let ectx = &*ectx;
+        let mut next_bin = |decoder: &mut Self, bin_idx: usize| {
+            let (ctx_idx, bypass_flag) = decoder
+                .coder
+                .derive_context_and_bypass_flag_tu(bin_idx, c_idx, ctx, tu, sh, ectx);
+            decoder.decode_arithmetic(ctx, ctx_idx, bypass_flag, init_type)
+        };
+        let value = match ctx {
+            CabacContext::CuQpDeltaAbs => self.decode_cu_qp_delta_abs(&mut next_bin),
+            CabacContext::CuChromaQpOffsetIdx => {
+                let c_max = sh.pps.chroma_tool_offsets.chroma_qp_offset_list_len - 1;
+                self.decode_trancated_rice(c_max, 0, 0, &mut next_bin).0
+            }
+            _ => self.debinarize(&ctx_to_bin_process[ctx as usize], &mut next_bin),
+        };
+        self.traced_value(value)
+    }
+
+    pub fn decode_cabac_last_sig_coeff_x_prefix(
+        &mut self,
+        tu: &TransformUnit,
+        c_idx: usize,
+        sh: &SliceHeader,
+    ) -> usize {
+        let init_type = BoolCoder::get_init_type(sh);
+        let c_max = (tu.get_log2_zo_tb_size(sh.sps, c_idx).0 << 1) - 1;
+        let mut next_bin = |decoder: &mut Self, bin_idx: usize| {
+            let (ctx_idx, bypass_flag) = decoder
+                .coder
+                .derive_context_and_bypass_flag_last_sig_coeff_x_prefix(bin_idx, c_idx, tu, sh);
+            decoder.decode_arithmetic(
+                CabacContext::LastSigCoeffXPrefix,
+                ctx_idx,
+                bypass_flag,
+                init_type,
+            )
+        };
+        let value = self.decode_trancated_rice(c_max, 0, 0, &mut next_bin).0;
+        self.traced_value(value)
+    }
+
+    pub fn decode_cabac_last_sig_coeff_y_prefix(
+        &mut self,
+        tu: &TransformUnit,
+        c_idx: usize,
+        sh: &SliceHeader,
+    ) -> usize {
+        let init_type = BoolCoder::get_init_type(sh);
+        let c_max = (tu.get_log2_zo_tb_size(sh.sps, c_idx).1 << 1) - 1;
+        let mut next_bin = |decoder: &mut Self, bin_idx: usize| {
+            let (ctx_idx, bypass_flag) = decoder
+                .coder
+                .derive_context_and_bypass_flag_last_sig_coeff_y_prefix(bin_idx, c_idx, tu, sh);
+            decoder.decode_arithmetic(
+                CabacContext::LastSigCoeffYPrefix,
+                ctx_idx,
+                bypass_flag,
+                init_type,
+            )
+        };
+        let value = self.decode_trancated_rice(c_max, 0, 0, &mut next_bin).0;
+        self.traced_value(value)
+    }
+
+    /// Decodes last_sig_coeff_x_suffix or last_sig_coeff_y_suffix.
+    pub fn decode_cabac_for_last_sig_coeff_suffix(
+        &mut self,
+        last_sig_coeff_prefix: usize,
+        ctx: CabacContext,
+        sh: &SliceHeader,
+    ) -> usize {
+        debug_eprintln!("dc {}", ctx as usize);
+        let init_type = BoolCoder::get_init_type(sh);
+        let c_max = (1 << ((last_sig_coeff_prefix >> 1) - 1)) - 1;
+        let mut next_bin = |decoder: &mut Self, bin_idx: usize| {
+            let (ctx_idx, bypass_flag) = decoder
+                .coder
+                .derive_context_and_bypass_flag(bin_idx, ctx, sh);
+            decoder.decode_arithmetic(ctx, ctx_idx, bypass_flag, init_type)
+        };
+        let value = self.decode_fixed_length(c_max, 0, &mut next_bin);
+        self.traced_value(value)
+    }
+
+    pub fn decode_cabac_for_sig_coeff_flag(
+        &mut self,
+        x_c: usize,
+        y_c: usize,
+        tu: &TransformUnit,
+        c_idx: usize,
+        sh: &SliceHeader,
+        ectx: &EncoderContext,
+    ) -> bool {
+        let ctx = CabacContext::SigCoeffFlag;
+        let init_type = BoolCoder::get_init_type(sh);
+        let (ctx_idx, bypass_flag) = self
+            .coder
+            .derive_context_and_bypass_flag_for_sig_coeff_flag(x_c, y_c, c_idx, ctx, tu, sh, ectx);
+        let flag = self.decode_arithmetic(ctx, ctx_idx, bypass_flag, init_type);
+        self.traced_flag(flag)
+    }
+
+    pub fn decode_cabac_for_sb_coded_flag(
+        &mut self,
+        tu: &TransformUnit,
+        c_idx: usize,
+        x_s: usize,
+        y_s: usize,
+        sh: &SliceHeader,
+    ) -> bool {
+        let init_type = BoolCoder::get_init_type(sh);
+        let (ctx_idx, bypass_flag) = self
+            .coder
+            .derive_context_and_bypass_flag_for_sb_coded_flag(tu, c_idx, x_s, y_s, sh);
+        let flag =
+            self.decode_arithmetic(CabacContext::SbCodedFlag, ctx_idx, bypass_flag, init_type);
+        self.traced_flag(flag)
+    }
+
+    pub fn decode_cabac_for_par_level_flag_and_abs_level_gtx_flag(
+        &mut self,
+        ctx: CabacContext,
+        abs_level_gtx_flag_j: usize,
+        tu: &TransformUnit,
+        c_idx: usize,
+        x_c: usize,
+        y_c: usize,
+        last_sig_coeff_pos: (usize, usize),
+        sh: &SliceHeader,
+        ectx: &EncoderContext,
+    ) -> bool {
+        let init_type = BoolCoder::get_init_type(sh);
+        let (ctx_idx, bypass_flag) = self
+            .coder
+            .derive_context_and_bypass_flag_for_par_level_flag_and_abs_level_gtx_flag(
+                ctx,
+                tu,
+                c_idx,
+                x_c,
+                y_c,
+                abs_level_gtx_flag_j,
+                last_sig_coeff_pos,
+                sh,
+                ectx,
+            );
+        let flag = self.decode_arithmetic(ctx, ctx_idx, bypass_flag, init_type);
+        self.traced_flag(flag)
+    }
+
+    pub fn decode_cabac_for_coeff_sign_flag(
+        &mut self,
+        last_scan_pos_pass1: isize,
+        coeff_sign_flag_n: usize,
+        tu: &TransformUnit,
+        c_idx: usize,
+        x_c: usize,
+        y_c: usize,
+        sh: &SliceHeader,
+        ectx: &EncoderContext,
+    ) -> bool {
+        let init_type = BoolCoder::get_init_type(sh);
+        let (ctx_idx, bypass_flag) = self
+            .coder
+            .derive_context_and_bypass_flag_for_coeff_sign_flag(
+                tu,
+                c_idx,
+                x_c,
+                y_c,
+                last_scan_pos_pass1,
+                coeff_sign_flag_n,
+                sh,
+                ectx,
+            );
+        let flag =
+            self.decode_arithmetic(CabacContext::CoeffSignFlag, ctx_idx, bypass_flag, init_type);
+        self.traced_flag(flag)
+    }
+
+    pub fn decode_cabac_for_abs_remainder(
+        &mut self,
+        x_c: usize,
+        y_c: usize,
+        abs_n: usize,
+        tu: &TransformUnit,
+        c_idx: usize,
+        sh: &SliceHeader,
+        ectx: &mut EncoderContext,
+    ) -> usize {
+        let ctx = CabacContext::AbsRemainder;
+        debug_eprintln!("dc {}", ctx as usize);
+        let init_type = BoolCoder::get_init_type(sh);
+        let c_rice_param = if tu.transform_skip_flag[c_idx] && !sh.ts_residual_coding_disabled_flag
+        {
+            1
+        } else {
+            let (log2_tb_width, log2_tb_height) = tu.get_log2_tb_size(c_idx);
+            self.coder.derive_rice_parameter(
+                4,
+                x_c,
+                y_c,
+                log2_tb_width,
+                log2_tb_height,
+                abs_n,
+                ectx,
+            )
+        };
+        let mut next_bin = |decoder: &mut Self, bin_idx: usize| {
+            let (ctx_idx, bypass_flag) = decoder
+                .coder
+                .derive_context_and_bypass_flag(bin_idx, ctx, sh);
+            decoder.decode_arithmetic(ctx, ctx_idx, bypass_flag, init_type)
+        };
+        let value = self.decode_abs_level_with_rice_parameter(c_rice_param, &mut next_bin);
+        self.traced_value(value)
+    }
+
+    /// Decodes dec_abs_level, where ectx.zero_pos[abs_n] is updated for mapping it to the
+    /// absolute level.
+    pub fn decode_cabac_for_dec_abs_level(
+        &mut self,
+        x_c: usize,
+        y_c: usize,
+        abs_n: usize,
+        tu: &TransformUnit,
+        c_idx: usize,
+        sh: &SliceHeader,
+        ectx: &mut EncoderContext,
+    ) -> usize {
+        let ctx = CabacContext::DecAbsLevel;
+        debug_eprintln!("dc {}", ctx as usize);
+        let init_type = BoolCoder::get_init_type(sh);
+        let (log2_tb_width, log2_tb_height) = tu.get_log2_tb_size(c_idx);
+        let c_rice_param = self.coder.derive_rice_parameter(
+            0,
+            x_c,
+            y_c,
+            log2_tb_width,
+            log2_tb_height,
+            abs_n,
+            ectx,
+        );
+        let mut next_bin = |decoder: &mut Self, bin_idx: usize| {
+            let (ctx_idx, bypass_flag) = decoder
+                .coder
+                .derive_context_and_bypass_flag(bin_idx, ctx, sh);
+            decoder.decode_arithmetic(ctx, ctx_idx, bypass_flag, init_type)
+        };
+        let value = self.decode_abs_level_with_rice_parameter(c_rice_param, &mut next_bin);
+        self.traced_value(value)
+    }
+
+    /// Decodes end_of_slice_one_bit, end_of_tile_one_bit or end_of_subset_one_bit.
+    pub fn decode_cabac_end_one_bit(&mut self) -> bool {
+        let bin = self.decode_cabac_terminate();
+        debug_eprintln!("dcbin {:?}", bin);
+        self.traced_flag(bin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bins::*;
+
+    #[test]
+    fn cabac_decoder_works() {
+        let ctx = CabacContext::SplitCuFlag;
+        let symbols = (0..1000)
+            .map(|i| (i * 7 + i / 3) % 5 < 3)
+            .collect::<Vec<bool>>();
+        let ectx = EncoderContext::new();
+        let mut coder = BoolCoder::new();
+        coder.init_ctx_table(&ectx);
+        coder.init_arithmetic_engine();
+        let mut bins = Bins::new();
+        for (i, &bin) in symbols.iter().enumerate() {
+            if i % 4 == 3 {
+                coder.encode_cabac_bypass(&mut bins, bin);
+            } else {
+                coder.encode_cabac_binary_decision(&mut bins, bin, ctx, 0, i % 3);
+            }
+        }
+        coder.encode_cabac_end_one_bit(&mut bins);
+        bins.byte_align();
+        let mut bytes = bins.bytes().collect::<Vec<u8>>();
+        bytes.push(0xa5);
+
+        let mut reader = BinaryReader::vec(&bytes).unwrap();
+        let mut decoder = CabacDecoder::new(&mut reader);
+        decoder.coder.init_ctx_table(&ectx);
+        decoder.coder.init_arithmetic_engine();
+        decoder.coder.cabac_ivl_offset = decoder.reader.read_value(9) as u16;
+        for (i, &bin) in symbols.iter().enumerate() {
+            let decoded = if i % 4 == 3 {
+                decoder.decode_cabac_bypass()
+            } else {
+                decoder.decode_cabac_binary_decision(ctx, 0, i % 3)
+            };
+            assert_eq!(decoded, bin);
+        }
+        assert!(decoder.decode_cabac_end_one_bit());
+        decoder.byte_align();
+        assert_eq!(decoder.reader.read_value(8), 0xa5);
+    }
+
+    #[test]
+    fn trace_works() {
+        let ectx = EncoderContext::new();
+        let mut coder = BoolCoder::new();
+        coder.init_ctx_table(&ectx);
+        coder.init_arithmetic_engine();
+        let mut bins = Bins::new();
+        coder.encode_cabac_end_one_bit(&mut bins);
+        bins.byte_align();
+        let bytes = bins.bytes().collect::<Vec<u8>>();
+
+        let mut reader = BinaryReader::vec(&bytes).unwrap();
+        let mut decoder = CabacDecoder::new(&mut reader);
+        decoder.coder.init_ctx_table(&ectx);
+        decoder.coder.init_arithmetic_engine();
+        decoder.coder.cabac_ivl_offset = decoder.reader.read_value(9) as u16;
+        decoder.start_trace();
+        decoder.trace_line("coding_tree_unit() pos=(0,0)");
+        decoder.trace_syntax_element("slice end_of_slice_one_bit ");
+        assert!(decoder.decode_cabac_end_one_bit());
+        assert_eq!(
+            decoder.take_trace().unwrap(),
+            format!(
+                "coding_tree_unit() pos=(0,0)\n{:<50} ae(v) : 1 (1 bins)\n",
+                "slice end_of_slice_one_bit"
+            )
+        );
+        assert_eq!(decoder.take_trace().unwrap(), "");
+    }
+
+    #[test]
+    fn debinarization_works() {
+        let coder = BoolCoder::new();
+        let mut bins = Bins::new();
+        let values = [0, 1, 5, 13, 26, 100, 1000];
+        for &v in values.iter() {
+            coder.encode_trancated_binary(&mut bins, v % 6, 5);
+            coder.encode_trancated_rice(&mut bins, v.min(12), 12, 2);
+            coder.encode_kth_order_exp_golomb(&mut bins, v, 1);
+            coder.encode_cu_qp_delta_abs(&mut bins, v % 64);
+            coder.encode_intra_chroma_pred_mode(&mut bins, v % 5);
+        }
+        let bits = bins.into_iter().collect::<Vec<bool>>();
+
+        let mut reader = BinaryReader::vec(&[]).unwrap();
+        let mut decoder = CabacDecoder::new(&mut reader);
+        let mut pos = 0;
+        let mut next_bin = |_: &mut CabacDecoder, _: usize| {
+            pos += 1;
+            bits[pos - 1]
+        };
+        for &v in values.iter() {
+            assert_eq!(decoder.decode_trancated_binary(5, &mut next_bin), v % 6);
+            assert_eq!(
+                decoder.decode_trancated_rice(12, 2, 0, &mut next_bin).0,
+                v.min(12)
+            );
+            assert_eq!(
+                decoder.decode_kth_order_exp_golomb(1, 0, &mut next_bin).0,
+                v
+            );
+            assert_eq!(decoder.decode_cu_qp_delta_abs(&mut next_bin), v % 64);
+            assert_eq!(decoder.decode_intra_chroma_pred_mode(&mut next_bin), v % 5);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 #![allow(non_camel_case_types, non_snake_case)]
 #[allow(unused_imports)]
 use num::{integer::Integer, FromPrimitive};
+use std::cell::RefCell;
 use std::ops::{Index, IndexMut};
 use std::sync::{Arc, Mutex};
 
@@ -14,6 +15,40 @@ macro_rules! hashmap {
     }};
 }
 
+/// Prints the name of the syntax element whose bins are pushed into bins next in debug builds,
+/// and records it together with the position of its first bin while a syntax element trace is
+/// taken on the current thread.
+#[macro_export]
+macro_rules! trace_syntax_element {
+    ($bins: expr, $name: literal) => {{
+        debug_eprint!($name);
+        $crate::common::record_syntax_element($name, $bins.num_bins);
+    }};
+}
+
+thread_local! {
+    static SYNTAX_ELEMENT_TRACE: RefCell<Option<Vec<(&'static str, usize)>>> = const { RefCell::new(None) };
+}
+
+/// Starts recording the syntax elements traced by trace_syntax_element! on the current thread.
+pub fn start_syntax_element_trace() {
+    SYNTAX_ELEMENT_TRACE.with(|trace| *trace.borrow_mut() = Some(vec![]));
+}
+
+/// Stops recording and returns the names of the traced syntax elements and the positions of their
+/// first bins, in the order they were coded.
+pub fn take_syntax_element_trace() -> Vec<(&'static str, usize)> {
+    SYNTAX_ELEMENT_TRACE.with(|trace| trace.borrow_mut().take().unwrap_or_default())
+}
+
+pub fn record_syntax_element(name: &'static str, position: usize) {
+    SYNTAX_ELEMENT_TRACE.with(|trace| {
+        if let Some(trace) = trace.borrow_mut().as_mut() {
+            trace.push((name, position));
+        }
+    });
+}
+
 pub type ArcMutex<T> = Arc<Mutex<T>>;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -25,6 +60,18 @@ pub enum ChromaFormat {
     YCbCr444 = 3,
 }
 
+impl ChromaFormat {
+    /// Returns the chroma format of a 2-bit chroma_format_idc.
+    pub fn from_idc(chroma_format_idc: usize) -> ChromaFormat {
+        match chroma_format_idc {
+            0 => ChromaFormat::Monochrome,
+            1 => ChromaFormat::YCbCr420,
+            2 => ChromaFormat::YCbCr422,
+            _ => ChromaFormat::YCbCr444,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum MttSplitMode {
@@ -242,6 +289,7 @@ pub enum TreeType {
     DUAL_TREE_CHROMA = 2,
 }
 
+#[derive(Clone)]
 pub struct WindowOffset {
     pub left_offset: isize,
     pub right_offset: isize,
@@ -273,17 +321,30 @@ pub struct Vec2d<T> {
     pub height: usize,
     pub width: usize,
     pub log2_stride: usize,
+    /// the position of the top-left element, by which elements are addressed
+    pub x_origin: usize,
+    pub y_origin: usize,
 }
 
 impl<T: Copy> Vec2d<T> {
     #[inline(always)]
     pub fn new(v: T, height: usize, width: usize) -> Vec2d<T> {
+        Self::new_at(v, 0, 0, height, width)
+    }
+
+    /// Creates a 2D vector covering width x height elements from (x, y), which are addressed by
+    /// their positions as if the vector covered the area from (0, 0). Rows only store the
+    /// covered elements, and the elements left of x in a row are those of the rows above.
+    #[inline(always)]
+    pub fn new_at(v: T, x: usize, y: usize, height: usize, width: usize) -> Vec2d<T> {
         let log2_stride = (width * 2 - 1).ilog2() as usize;
         Vec2d {
-            data: vec![v; height << log2_stride],
+            data: vec![v; (height << log2_stride) + x],
             height,
             width,
             log2_stride,
+            x_origin: x,
+            y_origin: y,
         }
     }
 
@@ -297,16 +358,16 @@ impl<T> Index<usize> for Vec2d<T> {
     type Output = [T];
     #[inline(always)]
     fn index(&self, index: usize) -> &Self::Output {
-        let offset = index << self.log2_stride;
-        &self.data[offset..offset + self.width]
+        let offset = (index - self.y_origin) << self.log2_stride;
+        &self.data[offset..offset + self.x_origin + self.width]
     }
 }
 
 impl<T> IndexMut<usize> for Vec2d<T> {
     #[inline(always)]
     fn index_mut(&mut self, index: usize) -> &mut [T] {
-        let offset = index << self.log2_stride;
-        &mut self.data[offset..offset + self.width]
+        let offset = (index - self.y_origin) << self.log2_stride;
+        &mut self.data[offset..offset + self.x_origin + self.width]
     }
 }
 
@@ -595,10 +656,117 @@ pub fn msum_8x8_i16_i32_le_i9_i17(v0: &[i16], v1: &[i32]) -> i32 {
     }
 }
 
+/// Filters 8 samples as dst[x] = sum(coeffs[i] * src[x + i * stride]). The sums of 8-bit samples
+/// weighted by the coefficients of the interpolation filters fit in 16 bits.
+#[inline(always)]
+pub fn filter_8_u8_i16(src: &[u8], stride: usize, coeffs: &[i16], dst: &mut [i16]) {
+    unsafe {
+        let mut h = _mm_setzero_si128();
+        for (i, &c) in coeffs.iter().enumerate() {
+            let s = _mm_loadl_epi64(src[i * stride..i * stride + 8].as_ptr() as *const _);
+            let s = _mm_cvtepu8_epi16(s);
+            h = _mm_add_epi16(h, _mm_mullo_epi16(s, _mm_set1_epi16(c)));
+        }
+        _mm_storeu_si128(dst[..8].as_mut_ptr() as *mut _, h);
+    }
+}
+
+/// Filters 16 samples as dst[x] = sum(coeffs[i] * src[x + i * stride]).
+#[inline(always)]
+pub fn filter_16_u8_i16(src: &[u8], stride: usize, coeffs: &[i16], dst: &mut [i16]) {
+    unsafe {
+        let mut h = _mm256_setzero_si256();
+        for (i, &c) in coeffs.iter().enumerate() {
+            let s = _mm_lddqu_si128(src[i * stride..i * stride + 16].as_ptr() as *const _);
+            let s = _mm256_cvtepu8_epi16(s);
+            h = _mm256_add_epi16(h, _mm256_mullo_epi16(s, _mm256_set1_epi16(c)));
+        }
+        _mm256_storeu_si256(dst[..16].as_mut_ptr() as *mut _, h);
+    }
+}
+
+/// Filters 8 samples as dst[x] = sum(coeffs[i] * src[x + i * stride]) >> shift, where the number
+/// of the coefficients is even and the sums are taken in 32 bits.
+#[inline(always)]
+pub fn filter_8_i16_i16(src: &[i16], stride: usize, coeffs: &[i16], shift: i32, dst: &mut [i16]) {
+    unsafe {
+        let mut lo = _mm_setzero_si128();
+        let mut hi = _mm_setzero_si128();
+        for (i, c) in coeffs.chunks(2).enumerate() {
+            let (i0, i1) = (i * 2 * stride, (i * 2 + 1) * stride);
+            let s0 = _mm_lddqu_si128(src[i0..i0 + 8].as_ptr() as *const _);
+            let s1 = _mm_lddqu_si128(src[i1..i1 + 8].as_ptr() as *const _);
+            let c = _mm_set1_epi32(((c[1] as i32) << 16) | (c[0] as u16 as i32));
+            lo = _mm_add_epi32(lo, _mm_madd_epi16(_mm_unpacklo_epi16(s0, s1), c));
+            hi = _mm_add_epi32(hi, _mm_madd_epi16(_mm_unpackhi_epi16(s0, s1), c));
+        }
+        let shift = _mm_cvtsi32_si128(shift);
+        let h = _mm_packs_epi32(_mm_sra_epi32(lo, shift), _mm_sra_epi32(hi, shift));
+        _mm_storeu_si128(dst[..8].as_mut_ptr() as *mut _, h);
+    }
+}
+
+/// Filters 16 samples as dst[x] = sum(coeffs[i] * src[x + i * stride]) >> shift.
+#[inline(always)]
+pub fn filter_16_i16_i16(src: &[i16], stride: usize, coeffs: &[i16], shift: i32, dst: &mut [i16]) {
+    unsafe {
+        let mut lo = _mm256_setzero_si256();
+        let mut hi = _mm256_setzero_si256();
+        for (i, c) in coeffs.chunks(2).enumerate() {
+            let (i0, i1) = (i * 2 * stride, (i * 2 + 1) * stride);
+            let s0 = _mm256_lddqu_si256(src[i0..i0 + 16].as_ptr() as *const _);
+            let s1 = _mm256_lddqu_si256(src[i1..i1 + 16].as_ptr() as *const _);
+            let c = _mm256_set1_epi32(((c[1] as i32) << 16) | (c[0] as u16 as i32));
+            lo = _mm256_add_epi32(lo, _mm256_madd_epi16(_mm256_unpacklo_epi16(s0, s1), c));
+            hi = _mm256_add_epi32(hi, _mm256_madd_epi16(_mm256_unpackhi_epi16(s0, s1), c));
+        }
+        // unpacking and packing within 128-bit lanes keep the order of the samples
+        let shift = _mm_cvtsi32_si128(shift);
+        let h = _mm256_packs_epi32(_mm256_sra_epi32(lo, shift), _mm256_sra_epi32(hi, shift));
+        _mm256_storeu_si256(dst[..16].as_mut_ptr() as *mut _, h);
+    }
+}
+
+/// Rounds the components of a motion vector to multiples of 1 << shift (8.5.2.14).
+pub fn round_mv(mv: [isize; 2], shift: usize) -> [isize; 2] {
+    let offset = (1 << shift) >> 1;
+    mv.map(|v| {
+        if v >= 0 {
+            ((v + offset) >> shift) << shift
+        } else {
+            -((-v + offset) >> shift) << shift
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use rand::{prelude::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn vec2d_new_at_works() {
+        let mut v = Vec2d::new_at(0u8, 24, 8, 3, 5);
+        for y in 8..11 {
+            for x in 24..29 {
+                v[y][x] = (y * 5 + x) as u8;
+            }
+        }
+        for y in 8..11 {
+            assert_eq!(v[y].len(), 29);
+            for x in 24..29 {
+                assert_eq!(v[y][x], (y * 5 + x) as u8);
+            }
+        }
+    }
+    #[test]
+    fn round_mv_works() {
+        assert_eq!(round_mv([6, -6], 2), [8, -8]);
+        assert_eq!(round_mv([5, -5], 2), [4, -4]);
+        assert_eq!(round_mv([-2, 2], 2), [-4, 4]);
+        assert_eq!(round_mv([-7, 7], 0), [-7, 7]);
+    }
+
     #[test]
     #[cfg(target_feature = "avx2")]
     fn msum_8_i16_le_i9_works() {
@@ -718,4 +886,52 @@ mod tests {
         let gt = (0..64).map(|i| v0[i] as i32 * v1[i]).sum();
         assert_eq!(dut, gt);
     }
+
+    #[test]
+    #[cfg(target_feature = "avx2")]
+    fn filter_16_u8_i16_works() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(2);
+        let mut src: Vec<u8> = vec![0; 32 * 8];
+        rng.fill(&mut src[..]);
+        let coeffs = [-1, 4, -11, 40, 40, -11, 4, -1];
+        for stride in [1, 32] {
+            let mut dut = [0; 16];
+            filter_16_u8_i16(&src, stride, &coeffs, &mut dut);
+            let mut dut8 = [0; 8];
+            filter_8_u8_i16(&src, stride, &coeffs, &mut dut8);
+            for x in 0..16 {
+                let gt: i32 = (0..8)
+                    .map(|i| coeffs[i] as i32 * src[x + i * stride] as i32)
+                    .sum();
+                assert_eq!(dut[x] as i32, gt);
+                if x < 8 {
+                    assert_eq!(dut8[x] as i32, gt);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_feature = "avx2")]
+    fn filter_16_i16_i16_works() {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(2);
+        let mut src: Vec<i16> = vec![0; 16 * 8];
+        rng.fill(&mut src[..]);
+        src.iter_mut().for_each(|x| *x %= 1 << 14);
+        let coeffs = [-4, 36, 36, -4];
+        let mut dut = [0; 16];
+        filter_16_i16_i16(&src, 16, &coeffs, 6, &mut dut);
+        let mut dut8 = [0; 8];
+        filter_8_i16_i16(&src, 16, &coeffs, 6, &mut dut8);
+        for x in 0..16 {
+            let gt: i32 = (0..4)
+                .map(|i| coeffs[i] as i32 * src[x + i * 16] as i32)
+                .sum::<i32>()
+                >> 6;
+            assert_eq!(dut[x] as i32, gt);
+            if x < 8 {
+                assert_eq!(dut8[x] as i32, gt);
+            }
+        }
+    }
 }
@@ -8,7 +8,6 @@ use super::tile::*;
 //use debug_print::*;
 use lazy_static::lazy_static;
 use std::cell::RefCell;
-use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
 lazy_static! {
@@ -140,7 +139,7 @@ pub struct CodingTreeUnit {
     pub width: usize,
     pub height: usize,
     pub pixels: Vec<Vec2d<u8>>,
-    pub sao: Rc<SAO>,
+    pub sao: Arc<SAO>,
     pub alf: ALF,
     pub dtiqs: Vec<Arc<Mutex<DTIQS>>>,
     pub ct: Vec<Arc<Mutex<CodingTree>>>,
@@ -177,7 +176,7 @@ impl CodingTreeUnit {
             width: 1 << log2_width,
             height: 1 << log2_height,
             pixels: vec![],
-            sao: Rc::new(SAO::new()),
+            sao: Arc::new(SAO::new()),
             alf: ALF::new(),
             dtiqs: vec![],
             ct: vec![],
@@ -187,32 +186,44 @@ impl CodingTreeUnit {
             width_tile,
             height_tile,
         }));
-        {
-            let ct = CodingTree::new_arc_mutex(
-                x,
-                y,
-                log2_width,
-                log2_height,
-                0,
-                0,
-                0,
-                0,
-                true,
-                true,
-                None,
-                Some(ctu.clone()),
-                fixed_qp,
-                MttSplitMode::SPLIT_NONE,
-                TreeType::SINGLE_TREE,
-                ModeType::MODE_TYPE_ALL,
-                tile,
-            );
-            let tmp = &mut ctu.lock().unwrap();
-            tmp.ct = vec![ct];
-        }
+        CodingTreeUnit::reset_ct(&ctu, fixed_qp);
         ctu
     }
 
+    /// Replaces the coding tree of a CTU with an unsplit one, so that the CTU can be coded again.
+    pub fn reset_ct(ctu: &Arc<Mutex<CodingTreeUnit>>, fixed_qp: Option<usize>) {
+        let (x, y, log2_width, log2_height, tile) = {
+            let ctu = ctu.lock().unwrap();
+            (
+                ctu.x,
+                ctu.y,
+                ctu.width.ilog2() as usize,
+                ctu.height.ilog2() as usize,
+                ctu.tile.clone(),
+            )
+        };
+        let ct = CodingTree::new_arc_mutex(
+            x,
+            y,
+            log2_width,
+            log2_height,
+            0,
+            0,
+            0,
+            0,
+            true,
+            true,
+            None,
+            Some(ctu.clone()),
+            fixed_qp,
+            MttSplitMode::SPLIT_NONE,
+            TreeType::SINGLE_TREE,
+            ModeType::MODE_TYPE_ALL,
+            tile,
+        );
+        ctu.lock().unwrap().ct = vec![ct];
+    }
+
     pub fn set_tile(&mut self, tile: Arc<Mutex<Tile>>) {
         self.tile = Some(tile.clone());
         for dtiq in self.dtiqs.iter() {
@@ -321,6 +332,35 @@ pub struct MvdCoding {
     pub sign_flag: [bool; 2],
 }
 
+impl MvdCoding {
+    pub fn new(mvd: [isize; 2]) -> MvdCoding {
+        MvdCoding {
+            abs_mvd_greater0_flag: [mvd[0] != 0, mvd[1] != 0],
+            abs_mvd_greater1_flag: [mvd[0].abs() > 1, mvd[1].abs() > 1],
+            abs_mvd: [mvd[0].unsigned_abs(), mvd[1].unsigned_abs()],
+            sign_flag: [mvd[0] < 0, mvd[1] < 0],
+        }
+    }
+
+    pub fn mvd(&self) -> [isize; 2] {
+        [0, 1].map(|i| {
+            if self.sign_flag[i] {
+                -(self.abs_mvd[i] as isize)
+            } else {
+                self.abs_mvd[i] as isize
+            }
+        })
+    }
+}
+
+/// Motion information of a block, whose motion vectors are in units of 1/16 luma samples.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct MotionInfo {
+    pub pred_flag: [bool; 2],
+    pub ref_idx: [usize; 2],
+    pub mv: [[isize; 2]; 2],
+}
+
 pub struct TransformUnit {
     pub qp: usize,
     pub cu_chroma_qp_offset_flag: bool,
@@ -378,7 +418,7 @@ impl TransformUnit {
         tree_type: TreeType,
         tile: Option<Arc<Mutex<Tile>>>,
         cu: Arc<Mutex<CodingUnit>>,
-    ) -> Rc<RefCell<TransformUnit>> {
+    ) -> Arc<Mutex<TransformUnit>> {
         let qp = if let Some(qp) = fixed_qp { qp } else { 26 };
         let luma_width = 1 << log2_tb_width;
         let luma_height = 1 << log2_tb_height;
@@ -446,7 +486,7 @@ impl TransformUnit {
                 vec2d![0; chroma_height; chroma_width],
             ],
         };
-        Rc::new(RefCell::new(TransformUnit {
+        Arc::new(Mutex::new(TransformUnit {
             width: luma_width,
             height: luma_height,
             qp,
@@ -643,6 +683,19 @@ impl TransformUnit {
         pps: &PictureParameterSet,
         ectx: &EncoderContext,
     ) -> isize {
+        let qp_y_pred = self.get_qp_y_pred(sps, pps, ectx);
+        let cu_qp_delta_val = self.qp as isize - ectx.qp_bd_offset - qp_y_pred as isize;
+        debug_assert!(cu_qp_delta_val == 0);
+        cu_qp_delta_val
+    }
+
+    /// Derives the predicted luma quantization parameter qPY_PRED of the quantization group (8.7.1).
+    pub fn get_qp_y_pred(
+        &self,
+        sps: &SequenceParameterSet,
+        pps: &PictureParameterSet,
+        ectx: &EncoderContext,
+    ) -> usize {
         let is_in_first_qg_in_slice_or_tile = self.is_in_first_qg_in_slice_or_tile(ectx);
         let x_qg = ectx.cu_qg_top_left_x;
         let y_qg = ectx.cu_qg_top_left_y;
@@ -703,17 +756,14 @@ impl TransformUnit {
             let above_qg_cu = above_qg_cu.lock().unwrap();
             above_qg_cu.qp_y
         };
-        let qp_y_pred = if available_a && is_in_first_qg_in_slice_or_tile {
+        if available_a && is_in_first_qg_in_slice_or_tile {
             let above_qg_cu = tile.get_cu(x_qg as isize, y_qg as isize - 1);
             let above_qg_cu = above_qg_cu.as_ref().unwrap();
             let above_qg_cu = above_qg_cu.lock().unwrap();
             above_qg_cu.qp_y
         } else {
             (qp_y_a + qp_y_b + 1) >> 1
-        };
-        let cu_qp_delta_val = self.qp as isize - ectx.qp_bd_offset - qp_y_pred as isize;
-        debug_assert!(cu_qp_delta_val == 0);
-        cu_qp_delta_val
+        }
     }
 
     #[inline(always)]
@@ -955,7 +1005,7 @@ impl TransformUnit {
     }
 
     #[inline(always)]
-    pub fn prev_tu_in_cu(&self) -> Option<Rc<RefCell<TransformUnit>>> {
+    pub fn prev_tu_in_cu(&self) -> Option<Arc<Mutex<TransformUnit>>> {
         let parent = self.parent.lock().unwrap();
         parent.prev_tu()
     }
@@ -967,7 +1017,7 @@ pub struct TransformTree {
     pub width: usize,
     pub height: usize,
     pub tts: Vec<Arc<Mutex<TransformTree>>>,
-    pub tus: Vec<Rc<RefCell<TransformUnit>>>,
+    pub tus: Vec<Arc<Mutex<TransformUnit>>>,
     pub parent: Option<Arc<Mutex<TransformTree>>>,
     pub part_idx: usize,
     pub cu: Arc<Mutex<CodingUnit>>,
@@ -1050,7 +1100,7 @@ impl TransformTree {
             tt.set_tile(tile.clone());
         }
         for tu in self.tus.iter() {
-            let mut tu = tu.borrow_mut();
+            let mut tu = tu.lock().unwrap();
             tu.set_tile(tile.clone());
         }
         let tile = tile.lock().unwrap();
@@ -1060,10 +1110,24 @@ impl TransformTree {
         self.height_tile = tile.num_ctu_rows << tile.log2_ctu_size;
     }
 
+    pub fn set_cu_pred_mode_flag(&mut self, pred_mode_flag: bool) {
+        if self.tts.is_empty() {
+            for tu in self.tus.iter() {
+                let tu = &mut tu.lock().unwrap();
+                tu.cu_pred_mode_flag = pred_mode_flag;
+            }
+        } else {
+            for tt in self.tts.iter() {
+                let tt = &mut tt.lock().unwrap();
+                tt.set_cu_pred_mode_flag(pred_mode_flag);
+            }
+        }
+    }
+
     pub fn set_cu_intra_pred_mode(&mut self, intra_pred_mode: [IntraPredMode; 3]) {
         if self.tts.is_empty() {
             for tu in self.tus.iter() {
-                let tu = &mut tu.borrow_mut();
+                let tu = &mut tu.lock().unwrap();
                 tu.cu_intra_pred_mode = intra_pred_mode;
             }
         } else {
@@ -1151,7 +1215,7 @@ impl TransformTree {
     }
 
     #[inline(always)]
-    pub fn first_tu(&self) -> Rc<RefCell<TransformUnit>> {
+    pub fn first_tu(&self) -> Arc<Mutex<TransformUnit>> {
         if !self.tts.is_empty() {
             let tt = self.tts[0].lock().unwrap();
             tt.first_tu()
@@ -1161,7 +1225,7 @@ impl TransformTree {
     }
 
     #[inline(always)]
-    pub fn last_tu(&self) -> Rc<RefCell<TransformUnit>> {
+    pub fn last_tu(&self) -> Arc<Mutex<TransformUnit>> {
         if !self.tts.is_empty() {
             let tt = self.tts.last().unwrap().lock().unwrap();
             tt.last_tu()
@@ -1171,7 +1235,7 @@ impl TransformTree {
     }
 
     #[inline(always)]
-    pub fn prev_tu(&self) -> Option<Rc<RefCell<TransformUnit>>> {
+    pub fn prev_tu(&self) -> Option<Arc<Mutex<TransformUnit>>> {
         match &self.parent {
             Some(parent) => {
                 let parent = parent.lock().unwrap();
@@ -1220,6 +1284,7 @@ pub struct CodingUnit {
     pub affine_type_flag: bool,
     pub sym_mvd_flag: bool,
     pub ref_idx: [usize; 2],
+    pub mv: [[isize; 2]; 2],
     pub amvr_flag: bool,
     pub bcw_idx: usize,
     pub coded_flag: bool,
@@ -1260,6 +1325,7 @@ impl CodingUnit {
         parent: Arc<Mutex<CodingTree>>,
         fixed_qp: Option<usize>,
         tree_type: TreeType,
+        mode_type: ModeType,
         tile: Option<Arc<Mutex<Tile>>>,
     ) -> Arc<Mutex<CodingUnit>> {
         let qp = if let Some(qp) = fixed_qp { qp } else { 26 };
@@ -1307,6 +1373,7 @@ impl CodingUnit {
             affine_type_flag: false,
             sym_mvd_flag: false,
             ref_idx: [0, 1],
+            mv: [[0; 2]; 2],
             amvr_flag: false,
             bcw_idx: 0,
             coded_flag: true,
@@ -1322,7 +1389,7 @@ impl CodingUnit {
             idx: 0,
             x,
             y,
-            mode_type: ModeType::MODE_INTRA,
+            mode_type,
             tree_type,
             parent,
             intra_pred_mode: [IntraPredMode::PLANAR; 3],
@@ -1380,6 +1447,135 @@ impl CodingUnit {
         tt.set_cu_intra_pred_mode(intra_pred_mode);
     }
 
+    /// Makes the CU inter predicted by the motion information, or intra predicted without it.
+    pub fn set_motion_info(&mut self, motion_info: Option<MotionInfo>) {
+        let pred_mode_flag = if let Some(motion_info) = motion_info {
+            self.pred_mode = [ModeType::MODE_INTER; 2];
+            self.inter_pred_idc = match motion_info.pred_flag {
+                [true, true] => InterPredMode::PRED_BI,
+                [false, true] => InterPredMode::PRED_L1,
+                _ => InterPredMode::PRED_L0,
+            } as usize;
+            self.ref_idx = motion_info.ref_idx;
+            self.mv = motion_info.mv;
+            false
+        } else {
+            self.pred_mode = [ModeType::MODE_INTRA; 2];
+            true
+        };
+        self.pred_mode_flag = pred_mode_flag;
+        let tt = self.transform_tree.as_ref().unwrap();
+        let tt = &mut tt.lock().unwrap();
+        tt.set_cu_pred_mode_flag(pred_mode_flag);
+    }
+
+    pub fn get_motion_info(&self) -> MotionInfo {
+        if self.pred_mode[0] != ModeType::MODE_INTER {
+            return MotionInfo::default();
+        }
+        let pred_flag = [
+            self.inter_pred_idc != InterPredMode::PRED_L1 as usize,
+            self.inter_pred_idc != InterPredMode::PRED_L0 as usize,
+        ];
+        MotionInfo {
+            pred_flag,
+            ref_idx: [0, 1].map(|i| if pred_flag[i] { self.ref_idx[i] } else { 0 }),
+            mv: [0, 1].map(|i| if pred_flag[i] { self.mv[i] } else { [0; 2] }),
+        }
+    }
+
+    /// Derives the candidate list of the luma motion vector predictors for the reference picture
+    /// RefPicList[lx][ref_idx] from the spatial neighbours and the history-based candidates,
+    /// rounded to the precision of the motion vector differences (8.5.2.8).
+    pub fn get_mvp_cand_list(
+        &self,
+        lx: usize,
+        ref_idx: usize,
+        sh: &SliceHeader,
+        ectx: &EncoderContext,
+    ) -> [[isize; 2]; 2] {
+        // AmvrShift without adaptive motion vector resolution
+        let amvr_shift = 2;
+        let ref_poc = ectx.ref_pictures[lx][ref_idx].pic_order_cnt;
+        // the motion vector of a candidate referring to the same picture in either list
+        let get_mv = |motion_info: &MotionInfo| {
+            [lx, 1 - lx].into_iter().find_map(|ly| {
+                (motion_info.pred_flag[ly]
+                    && ectx.ref_pictures[ly][motion_info.ref_idx[ly]].pic_order_cnt == ref_poc)
+                    .then(|| round_mv(motion_info.mv[ly], amvr_shift))
+            })
+        };
+        let is_above_right_available = self.is_above_right_available();
+        let is_below_left_available = self.is_below_left_available();
+        let tile = self.tile.as_ref().unwrap();
+        let tile = tile.lock().unwrap();
+        let get_spatial_mv = |neighbours: &[(isize, isize)]| {
+            neighbours.iter().find_map(|&(x_nb, y_nb)| {
+                let available = ectx.derive_neighbouring_block_availability(
+                    self.x,
+                    self.y,
+                    x_nb,
+                    y_nb,
+                    self.width,
+                    self.height,
+                    is_above_right_available,
+                    is_below_left_available,
+                    false,
+                    sh.sps,
+                    sh.pps,
+                );
+                if !available {
+                    return None;
+                }
+                let cu = tile.get_cu(x_nb, y_nb)?;
+                let cu = cu.lock().unwrap();
+                get_mv(&cu.get_motion_info())
+            })
+        };
+        let (x, y) = (self.x as isize, self.y as isize);
+        let (w, h) = (self.width as isize, self.height as isize);
+        let mv_a = get_spatial_mv(&[(x - 1, y + h), (x - 1, y + h - 1)]);
+        let mv_b = get_spatial_mv(&[(x + w, y - 1), (x + w - 1, y - 1), (x - 1, y - 1)]);
+        let mut cand_list = mv_a.into_iter().collect::<Vec<[isize; 2]>>();
+        if let Some(mv_b) = mv_b.filter(|&mv_b| mv_a != Some(mv_b)) {
+            cand_list.push(mv_b);
+        }
+        for motion_info in ectx.hmvp_cand_list.iter().rev().take(4) {
+            for ly in [lx, 1 - lx] {
+                if cand_list.len() < 2
+                    && motion_info.pred_flag[ly]
+                    && ectx.ref_pictures[ly][motion_info.ref_idx[ly]].pic_order_cnt == ref_poc
+                {
+                    cand_list.push(round_mv(motion_info.mv[ly], amvr_shift));
+                }
+            }
+        }
+        cand_list.resize(2, [0; 2]);
+        [cand_list[0], cand_list[1]]
+    }
+
+    /// Chooses the motion vector predictors giving the smallest motion vector differences, and
+    /// sets mvp_lX_flag and mvd_coding of the used lists (7.4.12.7).
+    pub fn set_mvd_coding(&mut self, sh: &SliceHeader, ectx: &EncoderContext) {
+        let motion_info = self.get_motion_info();
+        let mut mvd_coding = vec![vec![MvdCoding::new([0; 2])]; 2];
+        let mut mvp_flag = [false; 2];
+        for lx in 0..2 {
+            if !motion_info.pred_flag[lx] {
+                continue;
+            }
+            let mv = motion_info.mv[lx];
+            let mvp_cand_list = self.get_mvp_cand_list(lx, motion_info.ref_idx[lx], sh, ectx);
+            let mvds = mvp_cand_list.map(|mvp| [(mv[0] - mvp[0]) >> 2, (mv[1] - mvp[1]) >> 2]);
+            let cost = |mvd: [isize; 2]| mvd[0].abs() + mvd[1].abs();
+            mvp_flag[lx] = cost(mvds[1]) < cost(mvds[0]);
+            mvd_coding[lx][0] = MvdCoding::new(mvds[mvp_flag[lx] as usize]);
+        }
+        self.mvp_l0_flag = mvp_flag[0];
+        self.mvp_l1_flag = mvp_flag[1];
+        self.mvd_coding = mvd_coding;
+    }
+
     pub fn is_cclm_enabled(&self, sh: &SliceHeader, ectx: &EncoderContext) -> bool {
         // cross-component chroma intra prediction mode checking process (8.4.4)
         if sh.sps.cclm_enabled_flag {
@@ -1499,6 +1695,44 @@ impl CodingUnit {
         if self.intra_pred_mode[0] == IntraPredMode::PLANAR {
             return (true, 0, 0);
         }
+        let mut cand_mode_list = self.get_intra_luma_cand_mode_list();
+        let mode = self.intra_pred_mode[0] as usize;
+        if cand_mode_list.contains(&mode) {
+            let intra_luma_mpm_flag = true;
+            let intra_luma_mpm_idx = cand_mode_list.iter().position(|&x| x == mode).unwrap();
+            let intra_luma_mpm_remainer = 0;
+            (
+                intra_luma_mpm_flag,
+                intra_luma_mpm_idx,
+                intra_luma_mpm_remainer,
+            )
+        } else {
+            cand_mode_list.sort();
+            let intra_luma_mpm_flag = false;
+            let intra_luma_mpm_idx = 0;
+            let intra_luma_mpm_remainder = if mode > cand_mode_list[4] {
+                mode - 6
+            } else if mode > cand_mode_list[3] {
+                mode - 5
+            } else if mode > cand_mode_list[2] {
+                mode - 4
+            } else if mode > cand_mode_list[1] {
+                mode - 3
+            } else if mode > cand_mode_list[0] {
+                mode - 2
+            } else {
+                mode - 1
+            };
+            (
+                intra_luma_mpm_flag,
+                intra_luma_mpm_idx,
+                intra_luma_mpm_remainder,
+            )
+        }
+    }
+
+    /// Derives the candidate list of the most probable luma intra prediction modes (8.4.2).
+    pub fn get_intra_luma_cand_mode_list(&self) -> [usize; 5] {
         let tile = self.tile.as_ref().unwrap();
         let tile = tile.lock().unwrap();
         let left_cu = tile.get_cu(self.x as isize - 1, (self.y + self.height - 1) as isize);
@@ -1527,7 +1761,7 @@ impl CodingUnit {
         } else {
             IntraPredMode::PLANAR
         };
-        let mut cand_mode_list = if left_cand_pred_mode == above_cand_pred_mode
+        if left_cand_pred_mode == above_cand_pred_mode
             && left_cand_pred_mode as usize > IntraPredMode::DC as usize
         {
             let mode = left_cand_pred_mode as usize;
@@ -1598,39 +1832,6 @@ impl CodingUnit {
                 IntraPredMode::ANGULAR46 as usize,
                 IntraPredMode::ANGULAR54 as usize,
             ]
-        };
-        let mode = self.intra_pred_mode[0] as usize;
-        if cand_mode_list.contains(&mode) {
-            let intra_luma_mpm_flag = true;
-            let intra_luma_mpm_idx = cand_mode_list.iter().position(|&x| x == mode).unwrap();
-            let intra_luma_mpm_remainer = 0;
-            (
-                intra_luma_mpm_flag,
-                intra_luma_mpm_idx,
-                intra_luma_mpm_remainer,
-            )
-        } else {
-            cand_mode_list.sort();
-            let intra_luma_mpm_flag = false;
-            let intra_luma_mpm_idx = 0;
-            let intra_luma_mpm_remainder = if mode > cand_mode_list[4] {
-                mode - 6
-            } else if mode > cand_mode_list[3] {
-                mode - 5
-            } else if mode > cand_mode_list[2] {
-                mode - 4
-            } else if mode > cand_mode_list[1] {
-                mode - 3
-            } else if mode > cand_mode_list[0] {
-                mode - 2
-            } else {
-                mode - 1
-            };
-            (
-                intra_luma_mpm_flag,
-                intra_luma_mpm_idx,
-                intra_luma_mpm_remainder,
-            )
         }
     }
 
@@ -1892,6 +2093,7 @@ impl CodingTree {
                 parent,
                 fixed_qp,
                 tree_type,
+                mode_type,
                 tile,
             );
             let tmp = &mut ct.lock().unwrap();
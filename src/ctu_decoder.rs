@@ -0,0 +1,1483 @@
+use super::cabac_contexts::*;
+use super::cabac_decoder::*;
+use super::common::*;
+use super::ctu::*;
+use super::encoder_context::*;
+use super::intra_predictor::*;
+use super::motion_compensator::*;
+use super::quantizer::*;
+use super::slice_header::*;
+use super::transformer::*;
+use debug_print::*;
+use std::sync::{Arc, Mutex};
+
+/// Parses the coding tree of a CTU in the order CtuEncoder codes it, and reconstructs each coding
+/// unit into the pixel buffers of its tile. Only the tools the encoder uses in I and P slices are
+/// supported, and an error is returned when other ones are signalled.
+pub struct CtuDecoder<'a, 'b, 'c> {
+    decoder: &'a mut CabacDecoder<'b, 'c>,
+    encoder_context: Arc<Mutex<EncoderContext>>,
+}
+
+impl<'a, 'b, 'c> CtuDecoder<'a, 'b, 'c> {
+    pub fn new(
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        decoder: &'a mut CabacDecoder<'b, 'c>,
+    ) -> CtuDecoder<'a, 'b, 'c> {
+        CtuDecoder {
+            decoder,
+            encoder_context: encoder_context.clone(),
+        }
+    }
+
+    pub fn decode(
+        &mut self,
+        ctu: Arc<Mutex<CodingTreeUnit>>,
+        sh: &SliceHeader,
+    ) -> Result<(), String> {
+        debug_eprintln!("start ctu");
+        let ct = {
+            let ctu = ctu.lock().unwrap();
+            let ectx = &self.encoder_context;
+            let first = (ctu.x == ctu.x_tile && ctu.y == ctu.y_tile) || {
+                let ectx = ectx.lock().unwrap();
+                ectx.ctb_addr_in_rs == ectx.ctb_addr_in_curr_slice[0]
+            };
+            if first || (sh.sps.entropy_coding_sync_enabled_flag && ctu.x == ctu.x_tile) {
+                self.decoder
+                    .init_cabac(first, &ctu, sh.sps, sh.pps, ectx.clone());
+            }
+            if self.decoder.is_tracing() {
+                let line = format!("coding_tree_unit() pos=({},{})", ctu.x, ctu.y);
+                self.decoder.trace_line(&line);
+            }
+            ctu.ct[0].clone()
+        };
+        self.decode_coding_tree(ct, sh)?;
+        {
+            let ctu = ctu.lock().unwrap();
+            let ctb_to_tile_col_bd = {
+                let tile = ctu.tile.as_ref().unwrap();
+                let tile = tile.lock().unwrap();
+                tile.ctu_col
+            };
+            if sh.sps.entropy_coding_sync_enabled_flag && ctu.x / ctu.width == ctb_to_tile_col_bd {
+                self.decoder.storage_ctx_table();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn decode_coding_tree(
+        &mut self,
+        ct: Arc<Mutex<CodingTree>>,
+        sh: &SliceHeader,
+    ) -> Result<(), String> {
+        debug_eprintln!("start coding tree");
+        {
+            let ectx = &self.encoder_context;
+            let mut ectx = ectx.lock().unwrap();
+            let parent = ct.clone();
+            let ct = &mut ct.lock().unwrap();
+
+            let (
+                x,
+                y,
+                width,
+                height,
+                allow_split_bt_ver,
+                allow_split_bt_hor,
+                allow_split_tt_ver,
+                allow_split_tt_hor,
+                allow_split_qt,
+                qg_on_y,
+                qg_on_c,
+                cb_subdiv,
+            ) = (
+                ct.x,
+                ct.y,
+                ct.width,
+                ct.height,
+                ct.allow_split_bt(MttSplitMode::SPLIT_BT_VER, sh.pps, &ectx),
+                ct.allow_split_bt(MttSplitMode::SPLIT_BT_HOR, sh.pps, &ectx),
+                ct.allow_split_tt(MttSplitMode::SPLIT_TT_VER, sh.pps, &ectx),
+                ct.allow_split_tt(MttSplitMode::SPLIT_TT_HOR, sh.pps, &ectx),
+                ct.allow_split_qt(&ectx),
+                ct.qg_on_y,
+                ct.qg_on_c,
+                ct.get_cb_subdiv(),
+            );
+
+            let split_cu_flag = if (allow_split_bt_ver || allow_split_bt_hor || allow_split_qt)
+                && y + height <= sh.pps.pic_height_in_luma_samples
+            {
+                self.decoder.trace_syntax_element("ct split_cu_flag ");
+                self.decoder
+                    .decode_cabac_ct(CabacContext::SplitCuFlag, ct, sh, &mut ectx)
+                    != 0
+            } else {
+                x + width > sh.pps.pic_width_in_luma_samples
+                    || y + height > sh.pps.pic_height_in_luma_samples
+            };
+            if sh.pps.cu_qp_delta_enabled_flag && qg_on_y && cb_subdiv <= ectx.cu_qp_delta_sub_div {
+                ectx.is_cu_qp_delta_coded = false;
+                ectx.cu_qp_delta_val = 0;
+                ectx.cu_qg_top_left_x = x;
+                ectx.cu_qg_top_left_y = y;
+            }
+            if sh.cu_chroma_qp_offset_enabled_flag
+                && qg_on_c
+                && cb_subdiv <= ectx.cu_chroma_qp_offset_subdiv
+            {
+                ectx.is_cu_chroma_qp_offset_coded = false;
+                ectx.cu_qp_offset_cb = 0;
+                ectx.cu_qp_offset_cr = 0;
+                ectx.cu_qp_offset_cbcr = 0;
+            }
+            if split_cu_flag {
+                let split_qt_flag = if (allow_split_bt_ver
+                    || allow_split_bt_hor
+                    || allow_split_tt_ver
+                    || allow_split_tt_hor)
+                    && allow_split_qt
+                {
+                    self.decoder.trace_syntax_element("ct split_qt_flag ");
+                    self.decoder
+                        .decode_cabac_ct(CabacContext::SplitQtFlag, ct, sh, &mut ectx)
+                        != 0
+                } else {
+                    allow_split_qt
+                };
+                let split_mode = if split_qt_flag {
+                    MttSplitMode::SPLIT_QT
+                } else {
+                    let mtt_split_cu_vertical_flag = if allow_split_bt_ver
+                        || allow_split_bt_hor
+                        || allow_split_tt_ver
+                        || allow_split_tt_hor
+                    {
+                        self.decoder
+                            .trace_syntax_element("ct mtt_split_cu_vertical_flag ");
+                        self.decoder.decode_cabac_ct(
+                            CabacContext::MttSplitCuVerticalFlag,
+                            ct,
+                            sh,
+                            &mut ectx,
+                        ) != 0
+                    } else {
+                        !(allow_split_bt_hor || allow_split_tt_hor)
+                    };
+                    let mtt_split_cu_binary_flag = if (allow_split_bt_hor || allow_split_tt_hor)
+                        && (allow_split_bt_ver || allow_split_tt_ver)
+                    {
+                        self.decoder
+                            .trace_syntax_element("ct mtt_split_cu_binary_flag ");
+                        self.decoder.decode_cabac_ct(
+                            CabacContext::MttSplitCuBinaryFlag,
+                            ct,
+                            sh,
+                            &mut ectx,
+                        ) != 0
+                    } else if !allow_split_bt_ver && !allow_split_bt_hor {
+                        false
+                    } else if !allow_split_tt_ver && !allow_split_tt_hor {
+                        true
+                    } else if allow_split_bt_hor && allow_split_tt_ver {
+                        !mtt_split_cu_vertical_flag
+                    } else {
+                        mtt_split_cu_vertical_flag
+                    };
+                    match (mtt_split_cu_vertical_flag, mtt_split_cu_binary_flag) {
+                        (true, true) => MttSplitMode::SPLIT_BT_VER,
+                        (false, true) => MttSplitMode::SPLIT_BT_HOR,
+                        (true, false) => MttSplitMode::SPLIT_TT_VER,
+                        (false, false) => MttSplitMode::SPLIT_TT_HOR,
+                    }
+                };
+                ct.split(split_mode, parent, sh, &ectx);
+                ectx.mode_type_condition = ct.get_mode_type_condition(sh);
+                if ectx.mode_type_condition == 2 {
+                    return Err("non_inter_flag is not supported".to_string());
+                }
+            }
+        }
+        let cts = {
+            let ct = ct.lock().unwrap();
+            ct.cts.clone()
+        };
+        if !cts.is_empty() {
+            for ct in cts.iter() {
+                // coding trees outside the picture are not coded (7.3.11.4, 7.3.11.5)
+                let (x, y) = {
+                    let ct = ct.lock().unwrap();
+                    (ct.x, ct.y)
+                };
+                if x < sh.pps.pic_width_in_luma_samples && y < sh.pps.pic_height_in_luma_samples {
+                    self.decode_coding_tree(ct.clone(), sh)?;
+                }
+            }
+        } else {
+            let cus = {
+                let ct = ct.lock().unwrap();
+                ct.cus.clone()
+            };
+            for cu in cus.iter() {
+                self.decode_coding_unit(cu.clone(), sh)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn decode_coding_unit(
+        &mut self,
+        cu: Arc<Mutex<CodingUnit>>,
+        sh: &SliceHeader,
+    ) -> Result<(), String> {
+        debug_eprintln!("decode cu");
+
+        let (x, y, width, height, tree_type, transform_tree) = {
+            let cu = cu.lock().unwrap();
+            (
+                cu.x,
+                cu.y,
+                cu.width,
+                cu.height,
+                cu.tree_type,
+                cu.transform_tree.clone().unwrap(),
+            )
+        };
+        if self.decoder.is_tracing() {
+            let line = format!("coding_unit() pos=({x},{y}) size={width}x{height}");
+            self.decoder.trace_line(&line);
+        }
+        {
+            let cu = &mut cu.lock().unwrap();
+            let ectx = self.encoder_context.clone();
+            let mut ectx = ectx.lock().unwrap();
+            if width > ectx.max_tb_size_y || height > ectx.max_tb_size_y {
+                return Err(
+                    "coding units larger than the maximum transform size are not supported"
+                        .to_string(),
+                );
+            }
+            let mut pred_mode_flag = true;
+            if sh.slice_type != SliceType::I {
+                let is_4x4 = width == 4 && height == 4;
+                if tree_type != TreeType::DUAL_TREE_CHROMA
+                    && !is_4x4
+                    && cu.mode_type != ModeType::MODE_TYPE_INTRA
+                {
+                    self.decoder.trace_syntax_element("cu cu_skip_flag ");
+                    if self
+                        .decoder
+                        .decode_cabac_cu(CabacContext::CuSkipFlag, cu, sh, &mut ectx)
+                        != 0
+                    {
+                        return Err("skip mode is not supported".to_string());
+                    }
+                }
+                pred_mode_flag = if !is_4x4 && cu.mode_type == ModeType::MODE_TYPE_ALL {
+                    self.decoder.trace_syntax_element("cu pred_mode_flag ");
+                    self.decoder
+                        .decode_cabac_cu(CabacContext::PredModeFlag, cu, sh, &mut ectx)
+                        != 0
+                } else {
+                    is_4x4 || cu.mode_type == ModeType::MODE_TYPE_INTRA
+                };
+            }
+            if pred_mode_flag {
+                let mut intra_luma_pred_mode = IntraPredMode::PLANAR;
+                if tree_type == TreeType::SINGLE_TREE || tree_type == TreeType::DUAL_TREE_LUMA {
+                    if sh.sps.bdpcm_enabled_flag
+                        && width <= ectx.max_ts_size
+                        && height <= ectx.max_ts_size
+                    {
+                        self.decoder
+                            .trace_syntax_element("cu intra_bdpcm_luma_flag ");
+                        if self.decoder.decode_cabac_cu(
+                            CabacContext::IntraBdpcmLumaFlag,
+                            cu,
+                            sh,
+                            &mut ectx,
+                        ) != 0
+                        {
+                            return Err("BDPCM is not supported".to_string());
+                        }
+                    }
+                    if sh.sps.mip_enabled_flag {
+                        self.decoder.trace_syntax_element("cu intra_mip_flag ");
+                        if self.decoder.decode_cabac_cu(
+                            CabacContext::IntraMipFlag,
+                            cu,
+                            sh,
+                            &mut ectx,
+                        ) != 0
+                        {
+                            return Err(
+                                "matrix-based intra prediction is not supported".to_string()
+                            );
+                        }
+                    }
+                    if sh.sps.mrl_enabled_flag && y % ectx.ctb_size_y > 0 {
+                        self.decoder.trace_syntax_element("cu intra_luma_ref_idx ");
+                        if self.decoder.decode_cabac_cu(
+                            CabacContext::IntraLumaRefIdx,
+                            cu,
+                            sh,
+                            &mut ectx,
+                        ) != 0
+                        {
+                            return Err("multiple reference lines are not supported".to_string());
+                        }
+                    }
+                    if sh.sps.isp_enabled_flag
+                        && (width <= ectx.max_tb_size_y && height <= ectx.max_tb_size_y)
+                        && (width * height > ectx.min_tb_size_y * ectx.min_tb_size_y)
+                    {
+                        self.decoder
+                            .trace_syntax_element("cu intra_subpartitions_mode_flag ");
+                        if self.decoder.decode_cabac_cu(
+                            CabacContext::IntraSubpartitionsModeFlag,
+                            cu,
+                            sh,
+                            &mut ectx,
+                        ) != 0
+                        {
+                            return Err("intra sub-partitions are not supported".to_string());
+                        }
+                    }
+                    self.decoder.trace_syntax_element("cu intra_luma_mpm_flag ");
+                    let intra_luma_mpm_flag = self.decoder.decode_cabac_cu(
+                        CabacContext::IntraLumaMpmFlag,
+                        cu,
+                        sh,
+                        &mut ectx,
+                    ) != 0;
+                    let mode = if intra_luma_mpm_flag {
+                        self.decoder
+                            .trace_syntax_element("cu intra_luma_not_planar_flag ");
+                        if self.decoder.decode_cabac_cu(
+                            CabacContext::IntraLumaNotPlanarFlag,
+                            cu,
+                            sh,
+                            &mut ectx,
+                        ) != 0
+                        {
+                            self.decoder.trace_syntax_element("cu intra_luma_mpm_idx ");
+                            let intra_luma_mpm_idx = self.decoder.decode_cabac_cu(
+                                CabacContext::IntraLumaMpmIdx,
+                                cu,
+                                sh,
+                                &mut ectx,
+                            );
+                            cu.get_intra_luma_cand_mode_list()[intra_luma_mpm_idx]
+                        } else {
+                            IntraPredMode::PLANAR as usize
+                        }
+                    } else {
+                        self.decoder
+                            .trace_syntax_element("cu intra_luma_mpm_remainer ");
+                        let intra_luma_mpm_remainder = self.decoder.decode_cabac_cu(
+                            CabacContext::IntraLumaMpmRemainder,
+                            cu,
+                            sh,
+                            &mut ectx,
+                        );
+                        let mut cand_mode_list = cu.get_intra_luma_cand_mode_list();
+                        cand_mode_list.sort();
+                        let mut mode = intra_luma_mpm_remainder + 1;
+                        for cand_mode in cand_mode_list {
+                            if mode >= cand_mode {
+                                mode += 1;
+                            }
+                        }
+                        mode
+                    };
+                    intra_luma_pred_mode = num::FromPrimitive::from_usize(mode).unwrap();
+                }
+                let mut cclm_mode = None;
+                let mut intra_chroma_pred_mode = 4;
+                if (tree_type == TreeType::SINGLE_TREE || tree_type == TreeType::DUAL_TREE_CHROMA)
+                    && sh.sps.chroma_format != ChromaFormat::Monochrome
+                {
+                    if width / ectx.sub_width_c <= ectx.max_ts_size
+                        && height / ectx.sub_height_c <= ectx.max_ts_size
+                        && sh.sps.bdpcm_enabled_flag
+                    {
+                        self.decoder
+                            .trace_syntax_element("cu intra_bdpcm_chroma_flag ");
+                        if self.decoder.decode_cabac_cu(
+                            CabacContext::IntraBdpcmChromaFlag,
+                            cu,
+                            sh,
+                            &mut ectx,
+                        ) != 0
+                        {
+                            return Err("BDPCM is not supported".to_string());
+                        }
+                    }
+                    let cclm_mode_flag = if cu.is_cclm_enabled(sh, &ectx) {
+                        self.decoder.trace_syntax_element("cu cclm_mode_flag ");
+                        self.decoder
+                            .decode_cabac_cu(CabacContext::CclmModeFlag, cu, sh, &mut ectx)
+                            != 0
+                    } else {
+                        false
+                    };
+                    if cclm_mode_flag {
+                        self.decoder.trace_syntax_element("cu cclm_mode_idx ");
+                        let cclm_mode_idx = self.decoder.decode_cabac_cu(
+                            CabacContext::CclmModeIdx,
+                            cu,
+                            sh,
+                            &mut ectx,
+                        );
+                        cclm_mode = num::FromPrimitive::from_usize(
+                            IntraPredMode::LT_CCLM as usize + cclm_mode_idx,
+                        );
+                    } else {
+                        self.decoder
+                            .trace_syntax_element("cu intra_chroma_pred_mode ");
+                        intra_chroma_pred_mode = self.decoder.decode_cabac_cu(
+                            CabacContext::IntraChromaPredMode,
+                            cu,
+                            sh,
+                            &mut ectx,
+                        );
+                    }
+                }
+                // the chroma mode is derived from intra_chroma_pred_mode and the luma mode (8.4.3),
+                // and then set as the modes of the transform units in the same way as the encoder
+                let derive_chroma_pred_mode = |cu: &mut CodingUnit, luma_pred_mode| {
+                    cu.intra_chroma_pred_mode = intra_chroma_pred_mode;
+                    cu.set_intra_pred_mode([
+                        luma_pred_mode,
+                        IntraPredMode::PLANAR,
+                        IntraPredMode::PLANAR,
+                    ]);
+                    cu.intra_pred_mode[1]
+                };
+                match tree_type {
+                    TreeType::DUAL_TREE_LUMA => {
+                        cu.set_intra_pred_mode([intra_luma_pred_mode; 3]);
+                    }
+                    TreeType::DUAL_TREE_CHROMA => {
+                        let chroma_pred_mode = if let Some(cclm_mode) = cclm_mode {
+                            cclm_mode
+                        } else {
+                            // the luma mode is that of the luma coding unit at the center of the
+                            // chroma coding block
+                            let luma_cu = {
+                                let ct = cu.parent.lock().unwrap();
+                                let ct = ct.parent.as_ref().unwrap();
+                                let ct = ct.lock().unwrap();
+                                ct.get_cu(ct.x + ct.width / 2, ct.y + ct.height / 2)
+                            };
+                            let (luma_pred_mode, _) = {
+                                let luma_cu = luma_cu.as_ref().unwrap();
+                                let luma_cu = luma_cu.lock().unwrap();
+                                luma_cu.get_intra_chroma_pred_mode_and_mip_chroma_direct_mode_flag()
+                            };
+                            let chroma_pred_mode = derive_chroma_pred_mode(cu, luma_pred_mode);
+                            cu.intra_chroma_pred_mode = 4;
+                            chroma_pred_mode
+                        };
+                        cu.set_intra_pred_mode([chroma_pred_mode; 3]);
+                    }
+                    _ => {
+                        let chroma_pred_mode = if let Some(cclm_mode) = cclm_mode {
+                            cclm_mode
+                        } else {
+                            derive_chroma_pred_mode(cu, intra_luma_pred_mode)
+                        };
+                        cu.set_intra_pred_mode([
+                            intra_luma_pred_mode,
+                            chroma_pred_mode,
+                            chroma_pred_mode,
+                        ]);
+                    }
+                }
+            } else {
+                self.decode_motion_info(cu, sh, &mut ectx)?;
+                self.decoder.trace_syntax_element("cu cu_coded_flag ");
+                cu.coded_flag =
+                    self.decoder
+                        .decode_cabac_cu(CabacContext::CuCodedFlag, cu, sh, &mut ectx)
+                        != 0;
+            }
+            ectx.lfnst_dc_only = true;
+            ectx.lfnst_zero_out_sig_coeff_flag = true;
+            ectx.mts_dc_only = true;
+            ectx.mts_zero_out_sig_coeff_flag = true;
+            ectx.infer_tu_cbf_luma = true;
+        }
+        let tus = {
+            let tt = transform_tree.lock().unwrap();
+            tt.tus.clone()
+        };
+        if !cu.lock().unwrap().coded_flag {
+            for tu in tus.iter() {
+                self.reconstruct_transform_unit(tu.clone(), sh);
+            }
+            return Ok(());
+        }
+        for tu in tus.iter() {
+            self.decode_transform_unit(tu.clone(), sh)?;
+        }
+        let (lfnst_width, lfnst_height) = {
+            let ectx = &self.encoder_context;
+            let ectx = ectx.lock().unwrap();
+            if tree_type == TreeType::DUAL_TREE_CHROMA {
+                (width / ectx.sub_width_c, height / ectx.sub_height_c)
+            } else {
+                (width, height)
+            }
+        };
+        let (lfnst_not_ts_flag, transform_skip_flag) = {
+            let first_tu = tus[0].lock().unwrap();
+            (
+                (tree_type == TreeType::DUAL_TREE_CHROMA
+                    || !first_tu.get_y_coded_flag()
+                    || !first_tu.transform_skip_flag[0])
+                    && (tree_type == TreeType::DUAL_TREE_LUMA
+                        || ((!first_tu.get_cb_coded_flag() || !first_tu.transform_skip_flag[1])
+                            && (!first_tu.get_cr_coded_flag()
+                                || !first_tu.transform_skip_flag[2]))),
+                first_tu.transform_skip_flag[0],
+            )
+        };
+        {
+            let cu = &mut cu.lock().unwrap();
+            let ectx = &self.encoder_context;
+            let mut ectx = ectx.lock().unwrap();
+            if lfnst_width.min(lfnst_height) >= 4
+                && sh.sps.lfnst_enabled_flag
+                && cu.pred_mode[0] == ModeType::MODE_INTRA
+                && lfnst_not_ts_flag
+                && width.max(height) <= ectx.max_tb_size_y
+                && !ectx.lfnst_dc_only
+                && ectx.lfnst_zero_out_sig_coeff_flag
+            {
+                self.decoder.trace_syntax_element("cu lfnst_idx ");
+                cu.lfnst_idx =
+                    self.decoder
+                        .decode_cabac_cu(CabacContext::LfnstIdx, cu, sh, &mut ectx);
+                if cu.lfnst_idx != 0 {
+                    return Err("LFNST is not supported".to_string());
+                }
+            }
+            if tree_type != TreeType::DUAL_TREE_CHROMA
+                && cu.lfnst_idx == 0
+                && !transform_skip_flag
+                && width.max(height) <= 32
+                && !cu.sbt_flag
+                && ectx.mts_zero_out_sig_coeff_flag
+                && !ectx.mts_dc_only
+            {
+                self.decoder.trace_syntax_element("cu mts_idx ");
+                cu.mts_idx = self
+                    .decoder
+                    .decode_cabac_cu(CabacContext::MtsIdx, cu, sh, &mut ectx);
+                if cu.mts_idx != 0 {
+                    return Err("MTS is not supported".to_string());
+                }
+            }
+        }
+        for tu in tus.iter() {
+            self.reconstruct_transform_unit(tu.clone(), sh);
+        }
+        Ok(())
+    }
+
+    /// Decodes the syntax elements of the motion of an inter CU predicted from RefPicList[0] by
+    /// AMVP, and derives its motion vector (8.5.2.1).
+    fn decode_motion_info(
+        &mut self,
+        cu: &mut CodingUnit,
+        sh: &SliceHeader,
+        ectx: &mut EncoderContext,
+    ) -> Result<(), String> {
+        self.decoder.trace_syntax_element("cu general_merge_flag ");
+        if self
+            .decoder
+            .decode_cabac_cu(CabacContext::GeneralMergeFlag, cu, sh, ectx)
+            != 0
+        {
+            return Err("merge mode is not supported".to_string());
+        }
+        let ref_idx = if ectx.num_ref_idx_active[0] > 1 {
+            self.decoder.trace_syntax_element("cu ref_idx_l0 ");
+            self.decoder
+                .decode_cabac_cu(CabacContext::RefIdxL0, cu, sh, ectx)
+        } else {
+            0
+        };
+        let mvd = self.decode_mvd(cu, sh, ectx);
+        ectx.mvd_l0 = (mvd[0], mvd[1]);
+        self.decoder.trace_syntax_element("cu mvp_l0_flag ");
+        let mvp_l0_flag = self
+            .decoder
+            .decode_cabac_cu(CabacContext::MvpL0Flag, cu, sh, ectx);
+        let mvp = cu.get_mvp_cand_list(0, ref_idx, sh, ectx)[mvp_l0_flag];
+        // the motion vectors wrap around in 18 bits (8.5.2.8)
+        let mv = [0, 1].map(|i| {
+            let u = (mvp[i] + (mvd[i] << 2)).rem_euclid(1 << 18);
+            if u >= 1 << 17 {
+                u - (1 << 18)
+            } else {
+                u
+            }
+        });
+        let motion_info = MotionInfo {
+            pred_flag: [true, false],
+            ref_idx: [ref_idx, 0],
+            mv: [mv, [0; 2]],
+        };
+        cu.set_motion_info(Some(motion_info));
+        ectx.update_hmvp_cand_list(motion_info);
+        Ok(())
+    }
+
+    /// Decodes mvd_coding() and returns the motion vector difference (7.3.11.9).
+    fn decode_mvd(
+        &mut self,
+        cu: &CodingUnit,
+        sh: &SliceHeader,
+        ectx: &mut EncoderContext,
+    ) -> [isize; 2] {
+        let mut abs_mvd_greater0_flag = [false; 2];
+        for flag in abs_mvd_greater0_flag.iter_mut() {
+            self.decoder
+                .trace_syntax_element("mvd abs_mvd_greater0_flag ");
+            *flag = self
+                .decoder
+                .decode_cabac_cu(CabacContext::AbsMvdGreater0Flag, cu, sh, ectx)
+                != 0;
+        }
+        let mut abs_mvd_greater1_flag = [false; 2];
+        for i in 0..2 {
+            if abs_mvd_greater0_flag[i] {
+                self.decoder
+                    .trace_syntax_element("mvd abs_mvd_greater1_flag ");
+                abs_mvd_greater1_flag[i] =
+                    self.decoder
+                        .decode_cabac_cu(CabacContext::AbsMvdGreater1Flag, cu, sh, ectx)
+                        != 0;
+            }
+        }
+        let mut mvd = [0; 2];
+        for i in 0..2 {
+            if abs_mvd_greater0_flag[i] {
+                let abs_mvd = if abs_mvd_greater1_flag[i] {
+                    self.decoder.trace_syntax_element("mvd abs_mvd ");
+                    self.decoder
+                        .decode_cabac_cu(CabacContext::AbsMvd, cu, sh, ectx)
+                        + 2
+                } else {
+                    1
+                };
+                self.decoder.trace_syntax_element("mvd sign_flag ");
+                let sign_flag =
+                    self.decoder
+                        .decode_cabac_cu(CabacContext::MvdSignFlag, cu, sh, ectx)
+                        != 0;
+                mvd[i] = if sign_flag {
+                    -(abs_mvd as isize)
+                } else {
+                    abs_mvd as isize
+                };
+            }
+        }
+        mvd
+    }
+
+    pub fn decode_transform_unit(
+        &mut self,
+        tu: Arc<Mutex<TransformUnit>>,
+        sh: &SliceHeader,
+    ) -> Result<(), String> {
+        debug_eprintln!("start transform_unit");
+        let tu = &mut tu.lock().unwrap();
+
+        let (width, height, tree_type) = (tu.width, tu.height, tu.tree_type);
+        let (cb_width, cb_height) = {
+            let cu = tu.get_cu();
+            let cu = cu.lock().unwrap();
+            (cu.width, cu.height)
+        };
+        let (w_c, h_c, max_ts_size) = {
+            let ectx = &self.encoder_context;
+            let ectx = ectx.lock().unwrap();
+            (
+                width / ectx.sub_width_c,
+                height / ectx.sub_height_c,
+                ectx.max_ts_size,
+            )
+        };
+        let chroma_available = tree_type != TreeType::DUAL_TREE_LUMA
+            && sh.sps.chroma_format != ChromaFormat::Monochrome;
+        let (mut y_coded_flag, mut cb_coded_flag, mut cr_coded_flag) = (false, false, false);
+        {
+            let ectx = &self.encoder_context;
+            let mut ectx = ectx.lock().unwrap();
+            if (tree_type == TreeType::SINGLE_TREE || tree_type == TreeType::DUAL_TREE_CHROMA)
+                && sh.sps.chroma_format != ChromaFormat::Monochrome
+            {
+                self.decoder.trace_syntax_element("tu cb_coded_flag ");
+                cb_coded_flag =
+                    self.decoder
+                        .decode_cabac_tu(0, CabacContext::TuCbCodedFlag, tu, sh, &mut ectx)
+                        != 0;
+                // the context of tu_cr_coded_flag refers to tu_cb_coded_flag, which is derived
+                // from the coefficients of the transform unit
+                tu.quantized_transformed_coeffs[1][0][0] = cb_coded_flag as i16;
+                self.decoder.trace_syntax_element("tu cr_coded_flag ");
+                cr_coded_flag =
+                    self.decoder
+                        .decode_cabac_tu(0, CabacContext::TuCrCodedFlag, tu, sh, &mut ectx)
+                        != 0;
+                tu.quantized_transformed_coeffs[1][0][0] = 0;
+            }
+            if tree_type == TreeType::SINGLE_TREE || tree_type == TreeType::DUAL_TREE_LUMA {
+                // tu_y_coded_flag of an inter CU is inferred to be 1 unless the other flags of
+                // the CU are 0
+                y_coded_flag = if tu.cu_pred_mode_flag
+                    || (chroma_available && (cb_coded_flag || cr_coded_flag))
+                    || cb_width > ectx.max_tb_size_y
+                    || cb_height > ectx.max_tb_size_y
+                {
+                    self.decoder.trace_syntax_element("tu y_coded_flag ");
+                    self.decoder
+                        .decode_cabac_tu(0, CabacContext::TuYCodedFlag, tu, sh, &mut ectx)
+                        != 0
+                } else {
+                    true
+                };
+            }
+            if (cb_width > 64
+                || cb_height > 64
+                || y_coded_flag
+                || (chroma_available && (cb_coded_flag || cr_coded_flag)))
+                && tree_type != TreeType::DUAL_TREE_CHROMA
+                && sh.pps.cu_qp_delta_enabled_flag
+                && !ectx.is_cu_qp_delta_coded
+            {
+                self.decoder.trace_syntax_element("tu qp_delta_abs ");
+                let cu_qp_delta_abs =
+                    self.decoder
+                        .decode_cabac_tu(0, CabacContext::CuQpDeltaAbs, tu, sh, &mut ectx)
+                        as isize;
+                let cu_qp_delta_sign_flag = if cu_qp_delta_abs > 0 {
+                    self.decoder.trace_syntax_element("tu qp_delta_sign_flag ");
+                    self.decoder.decode_cabac_tu(
+                        0,
+                        CabacContext::CuQpDeltaSignFlag,
+                        tu,
+                        sh,
+                        &mut ectx,
+                    ) != 0
+                } else {
+                    false
+                };
+                ectx.is_cu_qp_delta_coded = true;
+                ectx.cu_qp_delta_val = cu_qp_delta_abs * (1 - 2 * cu_qp_delta_sign_flag as isize);
+            }
+            if (cb_width > 64
+                || cb_height > 64
+                || (chroma_available && (cb_coded_flag || cr_coded_flag)))
+                && tree_type != TreeType::DUAL_TREE_LUMA
+                && sh.cu_chroma_qp_offset_enabled_flag
+                && !ectx.is_cu_chroma_qp_offset_coded
+            {
+                self.decoder
+                    .trace_syntax_element("tu cu_chroma_qp_offset_flag ");
+                if self.decoder.decode_cabac_tu(
+                    0,
+                    CabacContext::CuChromaQpOffsetFlag,
+                    tu,
+                    sh,
+                    &mut ectx,
+                ) != 0
+                {
+                    return Err("chroma QP offset lists are not supported".to_string());
+                }
+            }
+            if sh.sps.joint_cbcr_enabled_flag
+                && (cb_coded_flag || cr_coded_flag)
+                && chroma_available
+            {
+                self.decoder
+                    .trace_syntax_element("tu joint_cbcr_residual_flag ");
+                if self.decoder.decode_cabac_tu(
+                    0,
+                    CabacContext::TuJointCbcrResidualFlag,
+                    tu,
+                    sh,
+                    &mut ectx,
+                ) != 0
+                {
+                    return Err("joint coding of chroma residuals is not supported".to_string());
+                }
+            }
+        }
+        let coded_flags = [y_coded_flag, cb_coded_flag, cr_coded_flag];
+        for (c_idx, &coded_flag) in coded_flags.iter().enumerate() {
+            if !coded_flag || !tu.is_component_active(c_idx) {
+                continue;
+            }
+            let (tb_width, tb_height) = if c_idx == 0 {
+                (width, height)
+            } else {
+                (w_c, h_c)
+            };
+            if sh.sps.transform_skip_enabled_flag
+                && !tu.cu_bdpcm_flag[c_idx]
+                && tb_width <= max_ts_size
+                && tb_height <= max_ts_size
+            {
+                let ectx = &self.encoder_context;
+                let mut ectx = ectx.lock().unwrap();
+                self.decoder.trace_syntax_element("tu transform_skip_flag ");
+                tu.transform_skip_flag[c_idx] = self.decoder.decode_cabac_tu(
+                    c_idx,
+                    CabacContext::TransformSkipFlag,
+                    tu,
+                    sh,
+                    &mut ectx,
+                ) != 0;
+            }
+            if !tu.transform_skip_flag[c_idx] || sh.ts_residual_coding_disabled_flag {
+                self.decode_residual(tu, c_idx, sh);
+            } else {
+                self.decode_residual_ts(tu, c_idx, sh);
+            }
+        }
+        debug_eprintln!("end transform_unit");
+        Ok(())
+    }
+
+    pub fn decode_residual(&mut self, tu: &mut TransformUnit, c_idx: usize, sh: &SliceHeader) {
+        debug_eprintln!("start decode_residual tu.x={}, tu.y={}", tu.x, tu.y);
+        let ectx = &self.encoder_context;
+        let mut ectx = ectx.lock().unwrap();
+
+        let ((mut log2_tb_width, mut log2_tb_height), transform_skip_flag) =
+            (tu.get_log2_tb_size(c_idx), tu.transform_skip_flag);
+
+        let (tw, th) = (1 << log2_tb_width, 1 << log2_tb_height);
+
+        for y in 0..th {
+            for x in 0..tw {
+                ectx.abs_level[y][x] = 0;
+                ectx.abs_level_pass1[y][x] = 0;
+                ectx.abs_level_pass2[y][x] = 0;
+            }
+        }
+
+        let (log2_zo_tb_width, log2_zo_tb_height) = tu.get_log2_zo_tb_size(sh.sps, c_idx);
+
+        let last_sig_coeff_x_prefix = if log2_tb_width > 0 {
+            self.decoder
+                .trace_syntax_element("res last_sig_coeff_x_prefix ");
+            self.decoder
+                .decode_cabac_last_sig_coeff_x_prefix(tu, c_idx, sh)
+        } else {
+            0
+        };
+        let last_sig_coeff_y_prefix = if log2_tb_height > 0 {
+            self.decoder
+                .trace_syntax_element("res last_sig_coeff_y_prefix ");
+            self.decoder
+                .decode_cabac_last_sig_coeff_y_prefix(tu, c_idx, sh)
+        } else {
+            0
+        };
+        let last_sig_coeff_x = if last_sig_coeff_x_prefix > 3 {
+            self.decoder
+                .trace_syntax_element("res last_sig_coeff_x_suffix ");
+            let last_sig_coeff_x_suffix = self.decoder.decode_cabac_for_last_sig_coeff_suffix(
+                last_sig_coeff_x_prefix,
+                CabacContext::LastSigCoeffXSuffix,
+                sh,
+            );
+            (1 << ((last_sig_coeff_x_prefix >> 1) - 1)) * (2 + (last_sig_coeff_x_prefix & 1))
+                + last_sig_coeff_x_suffix
+        } else {
+            last_sig_coeff_x_prefix
+        };
+        let last_sig_coeff_y = if last_sig_coeff_y_prefix > 3 {
+            self.decoder
+                .trace_syntax_element("res last_sig_coeff_y_suffix ");
+            let last_sig_coeff_y_suffix = self.decoder.decode_cabac_for_last_sig_coeff_suffix(
+                last_sig_coeff_y_prefix,
+                CabacContext::LastSigCoeffYSuffix,
+                sh,
+            );
+            (1 << ((last_sig_coeff_y_prefix >> 1) - 1)) * (2 + (last_sig_coeff_y_prefix & 1))
+                + last_sig_coeff_y_suffix
+        } else {
+            last_sig_coeff_y_prefix
+        };
+        debug_eprintln!(
+            "last_x={}, last_y={}, tu.x={}, tu.y={}, c_idx={}",
+            last_sig_coeff_x,
+            last_sig_coeff_y,
+            tu.x,
+            tu.y,
+            c_idx
+        );
+        ectx.last_significant_coeff_x = last_sig_coeff_x;
+        ectx.last_significant_coeff_y = last_sig_coeff_y;
+
+        (log2_tb_width, log2_tb_height) = (log2_zo_tb_width, log2_zo_tb_height);
+
+        let mut rem_bins_pass1 = ((1 << (log2_tb_width + log2_tb_height)) * 7) >> 2;
+        let (log2_sb_w, log2_sb_h) = tu.get_log2_zo_sb_size(sh.sps, c_idx);
+        let num_sb_coeff = 1 << (log2_sb_w + log2_sb_h);
+        let mut last_scan_pos = num_sb_coeff;
+        let mut last_subblock =
+            (1 << (log2_tb_width + log2_tb_height - (log2_sb_w + log2_sb_h))) - 1;
+        let (mut x_c, mut y_c);
+        let (mut x_s, mut y_s);
+        while {
+            if last_scan_pos == 0 {
+                last_scan_pos = num_sb_coeff;
+                last_subblock -= 1;
+            }
+            last_scan_pos -= 1;
+            (x_s, y_s) = DIAG_SCAN_ORDER[log2_tb_width - log2_sb_w][log2_tb_height - log2_sb_h]
+                [last_subblock];
+            x_c = (x_s << log2_sb_w) + DIAG_SCAN_ORDER[log2_sb_w][log2_sb_h][last_scan_pos].0;
+            y_c = (y_s << log2_sb_h) + DIAG_SCAN_ORDER[log2_sb_w][log2_sb_h][last_scan_pos].1;
+            x_c != last_sig_coeff_x || y_c != last_sig_coeff_y
+        } {}
+        if last_subblock == 0
+            && log2_tb_width >= 2
+            && log2_tb_height >= 2
+            && !transform_skip_flag[c_idx]
+            && last_scan_pos > 0
+        {
+            ectx.lfnst_dc_only = false;
+        }
+        if (last_subblock > 0 && log2_tb_width >= 2 && log2_tb_height >= 2)
+            || (last_scan_pos > 7
+                && (log2_tb_width == 2 || log2_tb_width == 3)
+                && log2_tb_width == log2_tb_height)
+        {
+            ectx.lfnst_zero_out_sig_coeff_flag = false;
+        }
+        if (last_subblock > 0 || last_scan_pos > 0) && c_idx == 0 {
+            ectx.mts_dc_only = false;
+        }
+        ectx.q_state = 0;
+        let last_sig_coeff_pos = (last_sig_coeff_x, last_sig_coeff_y);
+        let sb_order = &DIAG_SCAN_ORDER[log2_tb_width - log2_sb_w][log2_tb_height - log2_sb_h];
+        for i in (0..=last_subblock).rev() {
+            let start_q_state_sb = ectx.q_state;
+            (x_s, y_s) = sb_order[i];
+            let mut abs_levels = vec![0; num_sb_coeff];
+            let mut abs_level_gtx_flags1 = vec![false; num_sb_coeff];
+            let order = &DIAG_SCAN_ORDER[log2_sb_w][log2_sb_h];
+            let x_offset = x_s << log2_sb_w;
+            let y_offset = y_s << log2_sb_h;
+            let mut infer_sb_dc_sig_coeff_flag = false;
+            let sb_coded_flag = if i < last_subblock && i > 0 {
+                self.decoder.trace_syntax_element("res sb_coded_flag ");
+                let sb_coded_flag = self
+                    .decoder
+                    .decode_cabac_for_sb_coded_flag(tu, c_idx, x_s, y_s, sh);
+                infer_sb_dc_sig_coeff_flag = true;
+                sb_coded_flag
+            } else {
+                true
+            };
+            if sb_coded_flag && (x_s > 3 || y_s > 3) && c_idx == 0 {
+                ectx.mts_zero_out_sig_coeff_flag = false;
+            }
+            let mut first_sig_scan_pos_sb = num_sb_coeff;
+            let mut last_sig_scan_pos_sb: isize = -1;
+            let first_pos_mode0 = if i == last_subblock {
+                last_scan_pos
+            } else {
+                num_sb_coeff - 1
+            };
+            let mut first_pos_mode1 = first_pos_mode0 as isize;
+            for n in (0..=first_pos_mode0).rev() {
+                if rem_bins_pass1 < 4 {
+                    break;
+                }
+                x_c = x_offset + order[n].0;
+                y_c = y_offset + order[n].1;
+                let sig_coeff_flag = if sb_coded_flag
+                    && (n > 0 || !infer_sb_dc_sig_coeff_flag)
+                    && (x_c, y_c) != last_sig_coeff_pos
+                {
+                    self.decoder.trace_syntax_element("res sig_coeff_flag ");
+                    let sig_coeff_flag = self
+                        .decoder
+                        .decode_cabac_for_sig_coeff_flag(x_c, y_c, tu, c_idx, sh, &ectx);
+                    rem_bins_pass1 -= 1;
+                    if sig_coeff_flag {
+                        infer_sb_dc_sig_coeff_flag = false;
+                    }
+                    sig_coeff_flag
+                } else {
+                    (x_c, y_c) == last_sig_coeff_pos
+                        || (((x_c & ((1 << log2_sb_w) - 1), y_c & ((1 << log2_sb_h) - 1))
+                            == (0, 0))
+                            && infer_sb_dc_sig_coeff_flag
+                            && sb_coded_flag)
+                };
+                let (mut abs_level_gtx_flag0, mut abs_level_gtx_flag1, mut par_level_flag) =
+                    (false, false, false);
+                if sig_coeff_flag {
+                    self.decoder
+                        .trace_syntax_element("res abs_level_gtx_flag0 ");
+                    abs_level_gtx_flag0 = self
+                        .decoder
+                        .decode_cabac_for_par_level_flag_and_abs_level_gtx_flag(
+                            CabacContext::AbsLevelGtxFlag,
+                            0,
+                            tu,
+                            c_idx,
+                            x_c,
+                            y_c,
+                            last_sig_coeff_pos,
+                            sh,
+                            &ectx,
+                        );
+                    rem_bins_pass1 -= 1;
+                    if abs_level_gtx_flag0 {
+                        self.decoder.trace_syntax_element("res par_level_flag ");
+                        par_level_flag = self
+                            .decoder
+                            .decode_cabac_for_par_level_flag_and_abs_level_gtx_flag(
+                                CabacContext::ParLevelFlag,
+                                0,
+                                tu,
+                                c_idx,
+                                x_c,
+                                y_c,
+                                last_sig_coeff_pos,
+                                sh,
+                                &ectx,
+                            );
+                        rem_bins_pass1 -= 1;
+                        self.decoder
+                            .trace_syntax_element("res abs_level_gtx_flag1 ");
+                        abs_level_gtx_flag1 = self
+                            .decoder
+                            .decode_cabac_for_par_level_flag_and_abs_level_gtx_flag(
+                                CabacContext::AbsLevelGtxFlag,
+                                1,
+                                tu,
+                                c_idx,
+                                x_c,
+                                y_c,
+                                last_sig_coeff_pos,
+                                sh,
+                                &ectx,
+                            );
+                        rem_bins_pass1 -= 1;
+                    }
+                    if last_sig_scan_pos_sb == -1 {
+                        last_sig_scan_pos_sb = n as isize;
+                    }
+                    first_sig_scan_pos_sb = n;
+                    // the contexts of the following coefficients refer to the significance of
+                    // this one until its level is known
+                    tu.quantized_transformed_coeffs[c_idx][y_c][x_c] = 1;
+                }
+                let abs_level_pass1 = sig_coeff_flag as usize
+                    + par_level_flag as usize
+                    + abs_level_gtx_flag0 as usize
+                    + 2 * abs_level_gtx_flag1 as usize;
+                ectx.abs_level_pass1[y_c][x_c] = abs_level_pass1;
+                abs_levels[n] = abs_level_pass1;
+                abs_level_gtx_flags1[n] = abs_level_gtx_flag1;
+                if sh.dep_quant_used_flag {
+                    ectx.q_state = ectx.q_state_trans_table[ectx.q_state][abs_level_pass1 & 1];
+                }
+                first_pos_mode1 = n as isize - 1;
+            }
+            for n in (first_pos_mode1 + 1..=first_pos_mode0 as isize).rev() {
+                let n = n as usize;
+                x_c = x_offset + order[n].0;
+                y_c = y_offset + order[n].1;
+                let abs_remainder = if abs_level_gtx_flags1[n] {
+                    self.decoder.trace_syntax_element("res abs_remainder ");
+                    self.decoder
+                        .decode_cabac_for_abs_remainder(x_c, y_c, n, tu, c_idx, sh, &mut ectx)
+                } else {
+                    0
+                };
+                abs_levels[n] = ectx.abs_level_pass1[y_c][x_c] + 2 * abs_remainder;
+                ectx.abs_level[y_c][x_c] = abs_levels[n];
+            }
+            for n in (0..=first_pos_mode1).rev() {
+                let n = n as usize;
+                x_c = x_offset + order[n].0;
+                y_c = y_offset + order[n].1;
+                if sb_coded_flag {
+                    self.decoder.trace_syntax_element("res dec_abs_level ");
+                    let dec_abs_level = self
+                        .decoder
+                        .decode_cabac_for_dec_abs_level(x_c, y_c, n, tu, c_idx, sh, &mut ectx);
+                    let zero_pos = ectx.zero_pos[n];
+                    abs_levels[n] = if dec_abs_level == zero_pos {
+                        0
+                    } else if dec_abs_level < zero_pos {
+                        dec_abs_level + 1
+                    } else {
+                        dec_abs_level
+                    };
+                }
+                ectx.abs_level[y_c][x_c] = abs_levels[n];
+                if abs_levels[n] > 0 {
+                    if last_sig_scan_pos_sb == -1 {
+                        last_sig_scan_pos_sb = n as isize;
+                    }
+                    first_sig_scan_pos_sb = n;
+                }
+                if sh.dep_quant_used_flag {
+                    ectx.q_state = ectx.q_state_trans_table[ectx.q_state][abs_levels[n] & 1];
+                }
+            }
+            let sign_hidden_flag = sh.sign_data_hiding_used_flag
+                && last_sig_scan_pos_sb - first_sig_scan_pos_sb as isize > 3;
+            let mut coeff_sign_flags = vec![false; num_sb_coeff];
+            for n in (0..num_sb_coeff).rev() {
+                x_c = x_offset + order[n].0;
+                y_c = y_offset + order[n].1;
+                if abs_levels[n] > 0 && (!sign_hidden_flag || n != first_sig_scan_pos_sb) {
+                    self.decoder.trace_syntax_element("res coeff_sign_flag ");
+                    coeff_sign_flags[n] = self
+                        .decoder
+                        .decode_cabac_for_coeff_sign_flag(0, n, tu, c_idx, x_c, y_c, sh, &ectx);
+                }
+            }
+            // the sign of the first significant coefficient is hidden in the parity of the sum of
+            // the absolute levels (7.4.12.11)
+            let sum_abs_level = abs_levels.iter().sum::<usize>();
+            if sign_hidden_flag && sum_abs_level % 2 == 1 {
+                coeff_sign_flags[first_sig_scan_pos_sb] = true;
+            }
+            ectx.q_state = start_q_state_sb;
+            for n in (0..num_sb_coeff).rev() {
+                x_c = x_offset + order[n].0;
+                y_c = y_offset + order[n].1;
+                let abs_level = if sh.dep_quant_used_flag {
+                    let abs_level = if abs_levels[n] > 0 {
+                        2 * abs_levels[n] - (ectx.q_state > 1) as usize
+                    } else {
+                        0
+                    };
+                    ectx.q_state = ectx.q_state_trans_table[ectx.q_state][abs_levels[n] & 1];
+                    abs_level
+                } else {
+                    abs_levels[n]
+                } as i16;
+                tu.quantized_transformed_coeffs[c_idx][y_c][x_c] = if coeff_sign_flags[n] {
+                    -abs_level
+                } else {
+                    abs_level
+                };
+            }
+        }
+        debug_eprintln!("end decode_residual");
+    }
+
+    pub fn decode_residual_ts(&mut self, tu: &mut TransformUnit, c_idx: usize, sh: &SliceHeader) {
+        let ectx = &self.encoder_context;
+        let mut ectx = ectx.lock().unwrap();
+
+        let (log2_tb_width, log2_tb_height) = tu.get_log2_tb_size(c_idx);
+
+        let (tw, th) = (1 << log2_tb_width, 1 << log2_tb_height);
+
+        for y in 0..th {
+            for x in 0..tw {
+                ectx.abs_level[y][x] = 0;
+                ectx.abs_level_pass1[y][x] = 0;
+                ectx.abs_level_pass2[y][x] = 0;
+            }
+        }
+
+        let mut log2_sb_w = if log2_tb_width.min(log2_tb_height) < 2 {
+            1
+        } else {
+            2
+        };
+        let mut log2_sb_h = log2_sb_w;
+        if log2_tb_width + log2_tb_height > 3 {
+            if log2_tb_width < 2 {
+                log2_sb_w = log2_tb_width;
+                log2_sb_h = 4 - log2_sb_w;
+            } else if log2_tb_height < 2 {
+                log2_sb_h = log2_tb_height;
+                log2_sb_w = 4 - log2_sb_h;
+            }
+        }
+        let num_sb_coeff = 1 << (log2_sb_w + log2_sb_h);
+        let last_subblock = (1 << (log2_tb_width + log2_tb_height - (log2_sb_w + log2_sb_h))) - 1;
+        let mut infer_sb_cbf = true;
+        ectx.rem_ccbs = ((1 << (log2_tb_width + log2_tb_height)) * 7) >> 2;
+        let bdpcm_flag = tu.cu_bdpcm_flag[c_idx];
+        // the contexts in transform skip mode do not depend on the last significant coefficient
+        let last_sig_coeff_pos = (0, 0);
+        let sb_order = &DIAG_SCAN_ORDER[log2_tb_width - log2_sb_w][log2_tb_height - log2_sb_h];
+        let order = &DIAG_SCAN_ORDER[log2_sb_w][log2_sb_h];
+        for (i, &(x_s, y_s)) in sb_order.iter().enumerate().take(last_subblock + 1) {
+            let sb_coded_flag = if i != last_subblock || !infer_sb_cbf {
+                self.decoder.trace_syntax_element("res_ts sb_coded_flag ");
+                self.decoder
+                    .decode_cabac_for_sb_coded_flag(tu, c_idx, x_s, y_s, sh)
+            } else {
+                true
+            };
+            if sb_coded_flag && i < last_subblock {
+                infer_sb_cbf = false;
+            }
+            // first scan pass
+            let mut infer_sb_sig_coeff_flag = true;
+            let mut last_scan_pos_pass1 = -1;
+            for (n, &(x, y)) in order.iter().enumerate().take(num_sb_coeff) {
+                if ectx.rem_ccbs < 4 {
+                    break;
+                }
+                let x_c = (x_s << log2_sb_w) + x;
+                let y_c = (y_s << log2_sb_h) + y;
+                last_scan_pos_pass1 = n as isize;
+                let sig_coeff_flag =
+                    if sb_coded_flag && (n != num_sb_coeff - 1 || !infer_sb_sig_coeff_flag) {
+                        self.decoder.trace_syntax_element("res_ts sig_coeff_flag ");
+                        let sig_coeff_flag = self
+                            .decoder
+                            .decode_cabac_for_sig_coeff_flag(x_c, y_c, tu, c_idx, sh, &ectx);
+                        ectx.rem_ccbs -= 1;
+                        if sig_coeff_flag {
+                            infer_sb_sig_coeff_flag = false;
+                        }
+                        sig_coeff_flag
+                    } else {
+                        sb_coded_flag
+                    };
+                ectx.coeff_sign_level[x_c][y_c] = 0;
+                let (mut abs_level_gtx_flag0, mut par_level_flag) = (false, false);
+                if sig_coeff_flag {
+                    // the contexts of the following coefficients refer to the significance of
+                    // this one until its level is known
+                    tu.quantized_transformed_coeffs[c_idx][y_c][x_c] = 1;
+                    self.decoder.trace_syntax_element("res_ts coeff_sign_flag ");
+                    let coeff_sign_flag = self.decoder.decode_cabac_for_coeff_sign_flag(
+                        last_scan_pos_pass1,
+                        n,
+                        tu,
+                        c_idx,
+                        x_c,
+                        y_c,
+                        sh,
+                        &ectx,
+                    );
+                    ectx.rem_ccbs -= 1;
+                    ectx.coeff_sign_level[x_c][y_c] = if coeff_sign_flag { -1 } else { 1 };
+                    self.decoder
+                        .trace_syntax_element("res_ts abs_level_gtx_flag ");
+                    abs_level_gtx_flag0 = self
+                        .decoder
+                        .decode_cabac_for_par_level_flag_and_abs_level_gtx_flag(
+                            CabacContext::AbsLevelGtxFlag,
+                            0,
+                            tu,
+                            c_idx,
+                            x_c,
+                            y_c,
+                            last_sig_coeff_pos,
+                            sh,
+                            &ectx,
+                        );
+                    ectx.rem_ccbs -= 1;
+                    if abs_level_gtx_flag0 {
+                        self.decoder.trace_syntax_element("res_ts par_level_flag ");
+                        par_level_flag = self
+                            .decoder
+                            .decode_cabac_for_par_level_flag_and_abs_level_gtx_flag(
+                                CabacContext::ParLevelFlag,
+                                0,
+                                tu,
+                                c_idx,
+                                x_c,
+                                y_c,
+                                last_sig_coeff_pos,
+                                sh,
+                                &ectx,
+                            );
+                        ectx.rem_ccbs -= 1;
+                    }
+                }
+                ectx.abs_level_pass1[y_c][x_c] = sig_coeff_flag as usize
+                    + par_level_flag as usize
+                    + abs_level_gtx_flag0 as usize;
+            }
+            // greater than X scan pass (num_gt_x_flags=5)
+            let mut last_scan_pos_pass2: isize = -1;
+            for (n, &(x, y)) in order.iter().enumerate().take(num_sb_coeff) {
+                if ectx.rem_ccbs < 4 {
+                    break;
+                }
+                let x_c = (x_s << log2_sb_w) + x;
+                let y_c = (y_s << log2_sb_h) + y;
+                ectx.abs_level_pass2[y_c][x_c] = ectx.abs_level_pass1[y_c][x_c];
+                let mut abs_level_gtx_flag_jm1 = ectx.abs_level_pass1[y_c][x_c] > 1;
+                for j in 1..5 {
+                    let mut abs_level_gtx_flag_j = false;
+                    if abs_level_gtx_flag_jm1 {
+                        self.decoder
+                            .trace_syntax_element("res_ts abs_level_gtx_flag ");
+                        abs_level_gtx_flag_j = self
+                            .decoder
+                            .decode_cabac_for_par_level_flag_and_abs_level_gtx_flag(
+                                CabacContext::AbsLevelGtxFlag,
+                                j,
+                                tu,
+                                c_idx,
+                                x_c,
+                                y_c,
+                                last_sig_coeff_pos,
+                                sh,
+                                &ectx,
+                            );
+                        ectx.rem_ccbs -= 1;
+                    }
+                    ectx.abs_level_pass2[y_c][x_c] += 2 * abs_level_gtx_flag_j as usize;
+                    abs_level_gtx_flag_jm1 = abs_level_gtx_flag_j;
+                }
+                last_scan_pos_pass2 = n as isize;
+            }
+            // remainder scan pass
+            for (n, &(x, y)) in order.iter().enumerate().take(num_sb_coeff) {
+                let x_c = (x_s << log2_sb_w) + x;
+                let y_c = (y_s << log2_sb_h) + y;
+                let abs_remainder = if (n as isize <= last_scan_pos_pass2
+                    && ectx.abs_level_pass2[y_c][x_c] >= 10)
+                    || (n as isize > last_scan_pos_pass2
+                        && n as isize <= last_scan_pos_pass1
+                        && ectx.abs_level_pass1[y_c][x_c] >= 2)
+                    || (n as isize > last_scan_pos_pass1 && sb_coded_flag)
+                {
+                    self.decoder.trace_syntax_element("res_ts abs_remainder ");
+                    self.decoder
+                        .decode_cabac_for_abs_remainder(x_c, y_c, n, tu, c_idx, sh, &mut ectx)
+                } else {
+                    0
+                };
+                let (abs_level, coeff_sign_level) = if n as isize <= last_scan_pos_pass2 {
+                    (
+                        ectx.abs_level_pass2[y_c][x_c] + 2 * abs_remainder,
+                        ectx.coeff_sign_level[x_c][y_c],
+                    )
+                } else if n as isize <= last_scan_pos_pass1 {
+                    (
+                        ectx.abs_level_pass1[y_c][x_c] + 2 * abs_remainder,
+                        ectx.coeff_sign_level[x_c][y_c],
+                    )
+                } else if abs_remainder > 0 {
+                    self.decoder.trace_syntax_element("res_ts coeff_sign_flag ");
+                    let coeff_sign_flag = self.decoder.decode_cabac_for_coeff_sign_flag(
+                        last_scan_pos_pass1,
+                        n,
+                        tu,
+                        c_idx,
+                        x_c,
+                        y_c,
+                        sh,
+                        &ectx,
+                    );
+                    (abs_remainder, if coeff_sign_flag { -1 } else { 1 })
+                } else {
+                    (0, 0)
+                };
+                let mut abs_level = abs_level as i16;
+                // the level is coded relative to the larger one of the left and above
+                // coefficients (7.4.12.12)
+                if !bdpcm_flag && n as isize <= last_scan_pos_pass1 {
+                    let abs_left_coeff = if x_c > 0 {
+                        tu.quantized_transformed_coeffs[c_idx][y_c][x_c - 1].abs()
+                    } else {
+                        0
+                    };
+                    let abs_above_coeff = if y_c > 0 {
+                        tu.quantized_transformed_coeffs[c_idx][y_c - 1][x_c].abs()
+                    } else {
+                        0
+                    };
+                    let pred_coeff = abs_left_coeff.max(abs_above_coeff);
+                    if abs_level == 1 && pred_coeff > 0 {
+                        abs_level = pred_coeff;
+                    } else if abs_level > 0 && abs_level <= pred_coeff {
+                        abs_level -= 1;
+                    }
+                }
+                tu.quantized_transformed_coeffs[c_idx][y_c][x_c] =
+                    abs_level * coeff_sign_level as i16;
+            }
+        }
+    }
+
+    /// Reconstructs the components of a transform unit by adding the residuals to the intra
+    /// prediction, in the same way as the encoder does.
+    pub fn reconstruct_transform_unit(&mut self, tu: Arc<Mutex<TransformUnit>>, sh: &SliceHeader) {
+        let tu = &mut tu.lock().unwrap();
+        let ectx = &self.encoder_context;
+        let ectx = &mut ectx.lock().unwrap();
+        if sh.pps.cu_qp_delta_enabled_flag {
+            tu.qp = (tu.get_qp_y_pred(sh.sps, sh.pps, ectx) as isize
+                + ectx.cu_qp_delta_val
+                + ectx.qp_bd_offset) as usize;
+            let cu = tu.get_cu();
+            let cu = &mut cu.lock().unwrap();
+            cu.qp_y = tu.qp;
+        }
+        let motion_info = if tu.cu_pred_mode_flag {
+            None
+        } else {
+            let cu = tu.get_cu();
+            let cu = cu.lock().unwrap();
+            Some(cu.get_motion_info())
+        };
+        let mut intra_predictor = IntraPredictor::new();
+        let mut motion_compensator = MotionCompensator::new();
+        let mut transformer = Transformer::new();
+        let mut quantizer = Quantizer::new(ectx);
+        for c_idx in 0..3 {
+            if tu.is_component_active(c_idx) {
+                if let Some(motion_info) = &motion_info {
+                    motion_compensator.predict(tu, c_idx, motion_info, sh, ectx);
+                } else {
+                    intra_predictor.predict(tu, c_idx, sh.sps, sh.pps, ectx);
+                }
+                quantizer.dequantize(tu, c_idx, sh, ectx);
+                transformer.inverse_transform(tu, c_idx, sh.sps, sh.picture_header(), ectx);
+                let tile = tu.get_tile();
+                let tile = &mut tile.lock().unwrap();
+                let (tx, ty) = tu.get_component_pos(c_idx);
+                let (tw, th) = tu.get_component_size(c_idx);
+                let pred_pixels = &tile.pred_pixels.lock().unwrap()[c_idx];
+                let reconst_pixels = &mut tile.reconst_pixels.lock().unwrap()[c_idx];
+                for y in ty..ty + th {
+                    let pred_pixels = &pred_pixels[y];
+                    let reconst_pixels = &mut reconst_pixels[y];
+                    let it = &tu.itransformed_coeffs[c_idx][y - ty];
+                    for x in tx..tx + tw {
+                        let pred = pred_pixels[x];
+                        let res = it[x - tx];
+                        let rec = (pred as i16 + res).clamp(0, 255) as u8;
+                        reconst_pixels[x] = rec;
+                    }
+                }
+            }
+        }
+
+        ectx.qp_y = tu.qp;
+    }
+}
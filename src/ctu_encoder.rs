@@ -6,12 +6,11 @@ use super::common::*;
 use super::ctu::*;
 use super::encoder_context::*;
 use super::intra_predictor::*;
+use super::motion_compensator::*;
 use super::quantizer::*;
 use super::slice_header::*;
 use super::transformer::*;
 use debug_print::*;
-use std::cell::RefCell;
-use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
 pub struct CtuEncoder<'a> {
@@ -35,8 +34,11 @@ impl<'a> CtuEncoder<'a> {
         {
             let mut ct = {
                 let ctu = ctu.lock().unwrap();
-                let first = ctu.x == 0 && ctu.y == 0;
                 let ectx = &self.encoder_context;
+                let first = (ctu.x == ctu.x_tile && ctu.y == ctu.y_tile) || {
+                    let ectx = ectx.lock().unwrap();
+                    ectx.ctb_addr_in_rs == ectx.ctb_addr_in_curr_slice[0]
+                };
                 //// FIXME
                 //let ectx = &mut ectx.lock().unwrap();
                 //ectx.cu_qg_top_left_x = x;
@@ -424,7 +426,14 @@ impl<'a> CtuEncoder<'a> {
         };
         if !cts.is_empty() {
             for ct in cts.iter() {
-                self.encode_coding_tree(bins, ctu.clone(), ct.clone(), sh);
+                // coding trees outside the picture are not coded (7.3.11.4, 7.3.11.5)
+                let (x, y) = {
+                    let ct = ct.lock().unwrap();
+                    (ct.x, ct.y)
+                };
+                if x < sh.pps.pic_width_in_luma_samples && y < sh.pps.pic_height_in_luma_samples {
+                    self.encode_coding_tree(bins, ctu.clone(), ct.clone(), sh);
+                }
             }
         } else {
             let cus = {
@@ -445,6 +454,36 @@ impl<'a> CtuEncoder<'a> {
     ) {
         debug_eprintln!("encode cu");
 
+        // inter predicted CUs are reconstructed first, since cu_coded_flag depends on their
+        // residuals
+        let motion_info = {
+            let cu = &mut cu.lock().unwrap();
+            (cu.pred_mode[0] == ModeType::MODE_INTER && cu.tree_type != TreeType::DUAL_TREE_CHROMA)
+                .then(|| {
+                    let ectx = self.encoder_context.lock().unwrap();
+                    cu.set_mvd_coding(sh, &ectx);
+                    cu.get_motion_info()
+                })
+        };
+        if let Some(motion_info) = &motion_info {
+            let mut tts = vec![cu.lock().unwrap().transform_tree.clone().unwrap()];
+            let mut coded_flag = false;
+            while let Some(tt) = tts.pop() {
+                let tus = {
+                    let tt = tt.lock().unwrap();
+                    tts.extend(tt.tts.iter().cloned());
+                    tt.tus.clone()
+                };
+                for tu in tus.iter() {
+                    let tu = &mut tu.lock().unwrap();
+                    self.reconstruct_transform_unit(tu, Some(motion_info), sh);
+                    coded_flag |=
+                        tu.get_y_coded_flag() || tu.get_cb_coded_flag() || tu.get_cr_coded_flag();
+                }
+            }
+            cu.lock().unwrap().coded_flag = coded_flag;
+        }
+
         let (
             x,
             y,
@@ -878,7 +917,7 @@ impl<'a> CtuEncoder<'a> {
             } else if tree_type != TreeType::DUAL_TREE_CHROMA {
                 let ectx = self.encoder_context.clone();
                 let mut ectx = ectx.lock().unwrap();
-                if skip_flag {
+                if !skip_flag {
                     debug_eprintln!("cu general_merge_flag ");
                     self.coder.encode_cabac_cu(
                         bins,
@@ -892,7 +931,9 @@ impl<'a> CtuEncoder<'a> {
                 if general_merge_flag {
                     // TODO encode_merge_data
                 } else if pred_mode[ch_type] == ModeType::MODE_IBC {
-                    self.encode_mvd(bins, &mvd_coding[0][0], cu, sh);
+                    self.encode_mvd(bins, &mvd_coding[0][0], cu, sh, &mut ectx);
+                    let mvd = mvd_coding[0][0].mvd();
+                    ectx.mvd_l0 = (mvd[0], mvd[1]);
                     if ectx.max_num_ibc_merge_cand > 1 {
                         debug_eprintln!("cu mvp_l0_flag ");
                         self.coder.encode_cabac_cu(
@@ -950,7 +991,7 @@ impl<'a> CtuEncoder<'a> {
                         }
                     }
                     if sh.sps.smvd_enabled_flag
-                        && !sh.ph.as_ref().unwrap().mvd_l1_zero_flag
+                        && !sh.picture_header().mvd_l1_zero_flag
                         && inter_pred_idc == InterPredMode::PRED_BI as usize
                         && !inter_affine_flag
                         && ectx.ref_idx_sym_l0 > -1
@@ -978,12 +1019,14 @@ impl<'a> CtuEncoder<'a> {
                                 &mut ectx,
                             );
                         }
-                        self.encode_mvd(bins, &mvd_coding[0][0], cu, sh);
+                        self.encode_mvd(bins, &mvd_coding[0][0], cu, sh, &mut ectx);
+                        let mvd = mvd_coding[0][0].mvd();
+                        ectx.mvd_l0 = (mvd[0], mvd[1]);
                         if ectx.motion_model_idc > 0 {
-                            self.encode_mvd(bins, &mvd_coding[0][1], cu, sh);
+                            self.encode_mvd(bins, &mvd_coding[0][1], cu, sh, &mut ectx);
                         }
                         if ectx.motion_model_idc > 1 {
-                            self.encode_mvd(bins, &mvd_coding[0][2], cu, sh);
+                            self.encode_mvd(bins, &mvd_coding[0][2], cu, sh, &mut ectx);
                         }
                         debug_eprintln!("cu mvp_l0_flag ");
                         self.coder.encode_cabac_cu(
@@ -1009,7 +1052,7 @@ impl<'a> CtuEncoder<'a> {
                                 &mut ectx,
                             );
                         }
-                        if sh.ph.as_ref().unwrap().mvd_l1_zero_flag
+                        if sh.picture_header().mvd_l1_zero_flag
                             && inter_pred_idc == InterPredMode::PRED_BI as usize
                         {
                             ectx.mvd_l1 = (0, 0);
@@ -1018,13 +1061,15 @@ impl<'a> CtuEncoder<'a> {
                             if sym_mvd_flag {
                                 ectx.mvd_l1 = (-ectx.mvd_l0.0, -ectx.mvd_l0.1);
                             } else {
-                                self.encode_mvd(bins, &mvd_coding[1][0], cu, sh);
+                                self.encode_mvd(bins, &mvd_coding[1][0], cu, sh, &mut ectx);
+                                let mvd = mvd_coding[1][0].mvd();
+                                ectx.mvd_l1 = (mvd[0], mvd[1]);
                             }
                             if ectx.motion_model_idc > 0 {
-                                self.encode_mvd(bins, &mvd_coding[1][1], cu, sh);
+                                self.encode_mvd(bins, &mvd_coding[1][1], cu, sh, &mut ectx);
                             }
                             if ectx.motion_model_idc > 1 {
-                                self.encode_mvd(bins, &mvd_coding[1][2], cu, sh);
+                                self.encode_mvd(bins, &mvd_coding[1][2], cu, sh, &mut ectx);
                             }
                         }
                         debug_eprintln!("cu mvp_l1_flag ");
@@ -1140,9 +1185,9 @@ impl<'a> CtuEncoder<'a> {
                 let ectx = &self.encoder_context;
                 let mut ectx = ectx.lock().unwrap();
                 //if ectx.cu_pred_mode[ch_type][x][y] == ModeType::MODE_INTRA
-                if pred_mode[ch_type] == ModeType::MODE_INTRA
+                if pred_mode[ch_type] == ModeType::MODE_INTER
                     && sh.sps.sbt_enabled_flag
-                    && !merge_data.as_ref().unwrap().ciip_flag
+                    && merge_data.as_ref().is_none_or(|m| !m.ciip_flag)
                     && width <= ectx.max_tb_size_y
                     && height <= ectx.max_tb_size_y
                 {
@@ -1253,7 +1298,7 @@ impl<'a> CtuEncoder<'a> {
                     let tt = tt.lock().unwrap();
                     tt.first_tu()
                 };
-                let first_tu = first_tu.borrow();
+                let first_tu = first_tu.lock().unwrap();
                 (tree_type == TreeType::DUAL_TREE_CHROMA
                     || !first_tu.get_y_coded_flag()
                     || !first_tu.transform_skip_flag[0])
@@ -1293,7 +1338,7 @@ impl<'a> CtuEncoder<'a> {
                     let tt = transform_tree.as_ref().unwrap();
                     let tt = tt.lock().unwrap();
                     let first_tu = tt.first_tu();
-                    let first_tu = first_tu.borrow();
+                    let first_tu = first_tu.lock().unwrap();
                     first_tu.transform_skip_flag[0]
                 };
                 if tree_type != TreeType::DUAL_TREE_CHROMA
@@ -1318,6 +1363,10 @@ impl<'a> CtuEncoder<'a> {
                 }
             }
         }
+        if let Some(motion_info) = motion_info {
+            let mut ectx = self.encoder_context.lock().unwrap();
+            ectx.update_hmvp_cand_list(motion_info);
+        }
     }
 
     // TODO encode_palette_coding
@@ -1329,9 +1378,8 @@ impl<'a> CtuEncoder<'a> {
         mvd: &MvdCoding,
         cu: &CodingUnit,
         sh: &SliceHeader,
+        ectx: &mut EncoderContext,
     ) {
-        let ectx = &self.encoder_context;
-        let mut ectx = ectx.lock().unwrap();
         for i in 0..=1 {
             debug_eprintln!("mvd abs_mvd_greater0_flag ");
             self.coder.encode_cabac_cu(
@@ -1340,7 +1388,7 @@ impl<'a> CtuEncoder<'a> {
                 CabacContext::AbsMvdGreater0Flag,
                 cu,
                 sh,
-                &mut ectx,
+                ectx,
             );
         }
         for i in 0..=1 {
@@ -1352,7 +1400,7 @@ impl<'a> CtuEncoder<'a> {
                     CabacContext::AbsMvdGreater1Flag,
                     cu,
                     sh,
-                    &mut ectx,
+                    ectx,
                 );
             }
         }
@@ -1366,7 +1414,7 @@ impl<'a> CtuEncoder<'a> {
                         CabacContext::AbsMvd,
                         cu,
                         sh,
-                        &mut ectx,
+                        ectx,
                     );
                 }
                 debug_eprintln!("mvd sign_flag ");
@@ -1376,7 +1424,7 @@ impl<'a> CtuEncoder<'a> {
                     CabacContext::MvdSignFlag,
                     cu,
                     sh,
-                    &mut ectx,
+                    ectx,
                 );
             }
         }
@@ -1411,56 +1459,72 @@ impl<'a> CtuEncoder<'a> {
         debug_eprintln!("end transform_tree");
     }
 
+    /// Predicts the samples of a transform unit by intra prediction or by motion compensation
+    /// with the motion information, and reconstructs them from the quantized residuals.
+    fn reconstruct_transform_unit(
+        &mut self,
+        tu: &mut TransformUnit,
+        motion_info: Option<&MotionInfo>,
+        sh: &SliceHeader,
+    ) {
+        let mut intra_predictor = IntraPredictor::new();
+        let mut motion_compensator = MotionCompensator::new();
+        let ectx = &self.encoder_context;
+        let ectx = &mut ectx.lock().unwrap();
+        let mut transformer = Transformer::new();
+        let mut quantizer = Quantizer::new(ectx);
+        for c_idx in 0..3 {
+            if tu.is_component_active(c_idx) {
+                if let Some(motion_info) = motion_info {
+                    motion_compensator.predict(tu, c_idx, motion_info, sh, ectx);
+                } else {
+                    intra_predictor.predict(tu, c_idx, sh.sps, sh.pps, ectx);
+                    ectx.enable_print = false;
+                }
+                transformer.transform(tu, c_idx, sh.sps, sh.picture_header(), ectx);
+                quantizer.quantize(tu, c_idx, true, sh, ectx);
+                quantizer.dequantize(tu, c_idx, sh, ectx);
+                transformer.inverse_transform(tu, c_idx, sh.sps, sh.picture_header(), ectx);
+                let tile = tu.get_tile();
+                let tile = &mut tile.lock().unwrap();
+                let (tx, ty) = tu.get_component_pos(c_idx);
+                let (tw, th) = tu.get_component_size(c_idx);
+                let pred_pixels = &tile.pred_pixels.lock().unwrap()[c_idx];
+                let reconst_pixels = &mut tile.reconst_pixels.lock().unwrap()[c_idx];
+                for y in ty..ty + th {
+                    let pred_pixels = &pred_pixels[y];
+                    let reconst_pixels = &mut reconst_pixels[y];
+                    let it = &tu.itransformed_coeffs[c_idx][y - ty];
+                    for x in tx..tx + tw {
+                        let pred = pred_pixels[x];
+                        let res = it[x - tx];
+                        let rec = (pred as i16 + res).clamp(0, 255) as u8;
+                        reconst_pixels[x] = rec;
+                    }
+                }
+            }
+        }
+
+        ectx.qp_y = tu.qp;
+    }
+
     pub fn encode_transform_unit(
         &mut self,
         bins: &mut Bins,
-        tu: Rc<RefCell<TransformUnit>>,
+        tu: Arc<Mutex<TransformUnit>>,
         sh: &SliceHeader,
     ) {
         debug_eprintln!("start transform_unit");
         {
-            let tu = &mut tu.borrow_mut();
+            let tu = &mut tu.lock().unwrap();
             // FIXME should not be here
-            let pred_mode_flag = tu.cu_pred_mode_flag;
-            let mut intra_predictor = IntraPredictor::new();
-            let ectx = &self.encoder_context;
-            let ectx = &mut ectx.lock().unwrap();
-            let mut transformer = Transformer::new();
-            let mut quantizer = Quantizer::new(ectx);
-            for c_idx in 0..3 {
-                if tu.is_component_active(c_idx) {
-                    if pred_mode_flag {
-                        intra_predictor.predict(tu, c_idx, sh.sps, sh.pps, ectx);
-                        ectx.enable_print = false;
-                    }
-                    transformer.transform(tu, c_idx, sh.sps, sh.ph.as_ref().unwrap(), ectx);
-                    quantizer.quantize(tu, c_idx, true, sh, ectx);
-                    quantizer.dequantize(tu, c_idx, sh, ectx);
-                    transformer.inverse_transform(tu, c_idx, sh.sps, sh.ph.as_ref().unwrap(), ectx);
-                    let tile = tu.get_tile();
-                    let tile = &mut tile.lock().unwrap();
-                    let (tx, ty) = tu.get_component_pos(c_idx);
-                    let (tw, th) = tu.get_component_size(c_idx);
-                    let pred_pixels = &tile.pred_pixels.borrow()[c_idx];
-                    let reconst_pixels = &mut tile.reconst_pixels.borrow_mut()[c_idx];
-                    for y in ty..ty + th {
-                        let pred_pixels = &pred_pixels[y];
-                        let reconst_pixels = &mut reconst_pixels[y];
-                        let it = &tu.itransformed_coeffs[c_idx][y - ty];
-                        for x in tx..tx + tw {
-                            let pred = pred_pixels[x];
-                            let res = it[x - tx];
-                            let rec = (pred as i16 + res).clamp(0, 255) as u8;
-                            reconst_pixels[x] = rec;
-                        }
-                    }
-                }
+            // inter predicted TUs are reconstructed before cu_coded_flag
+            if tu.cu_pred_mode_flag {
+                self.reconstruct_transform_unit(tu, None, sh);
             }
-
-            ectx.qp_y = tu.qp;
         }
 
-        let tu = &tu.borrow();
+        let tu = &tu.lock().unwrap();
 
         let (
             width,
@@ -2611,7 +2675,7 @@ impl<'a> CtuEncoder<'a> {
     pub fn encode_sao(
         &mut self,
         bins: &mut Bins,
-        sao: Rc<SAO>,
+        sao: Arc<SAO>,
         sh: &SliceHeader,
         rx: usize,
         ry: usize,
@@ -2632,8 +2696,8 @@ impl<'a> CtuEncoder<'a> {
             }
         }
         if ry > 0 && !sao.merge_left_flag {
-            let up_ctb_available =
-                ry != ectx.ctb_to_tile_col_bd[ry] && !ectx.first_ctb_row_in_slice;
+            let up_ctb_available = ry != ectx.ctb_to_tile_row_bd[ry]
+                && ectx.is_ctb_in_curr_slice[(ry - 1) * ectx.pic_width_in_ctbs_y + rx];
             if up_ctb_available {
                 debug_eprintln!("sao alf_sao_merge_up_flag ");
                 self.coder.encode_cabac_ctu(
@@ -0,0 +1,394 @@
+use super::analyzer::*;
+use super::aps::*;
+use super::binary_reader::*;
+use super::binary_writer::*;
+use super::cabac_decoder::*;
+use super::common::*;
+use super::ctu::*;
+use super::ctu_decoder::*;
+use super::dpb::*;
+use super::encoder_context::*;
+use super::extractor::*;
+use super::nal::*;
+use super::ph_parser::*;
+use super::picture::*;
+use super::picture_header::*;
+use super::pps::*;
+use super::pps_parser::*;
+use super::reference_picture::*;
+use super::sh_parser::*;
+use super::slice_header::*;
+use super::sps::*;
+use super::sps_parser::*;
+use super::vps_parser::*;
+use debug_print::*;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// Decodes a byte stream of intra pictures and P pictures referring to the previously decoded
+/// pictures, and writes the reconstructed pictures into writer in the same format as --reconst,
+/// returning the number of decoded pictures. Only the coding tools
+/// used by the encoder are supported, and an error is returned when others are found. The coded
+/// syntax elements are written into trace_writer when it is given, where those of parameter sets
+/// and headers are named as in the traces of the header encoders, and those of slice data as in
+/// the traces of the CTU encoder.
+pub fn decode_byte_stream(
+    reader: &mut BinaryReader,
+    mut writer: Option<&mut BinaryWriter>,
+    mut trace_writer: Option<&mut BinaryWriter>,
+) -> Result<usize, String> {
+    let mut analyzer = trace_writer.as_ref().map(|_| StreamAnalyzer::new());
+    let ectx = Arc::new(Mutex::new(EncoderContext::new()));
+    let aps = [
+        AdaptationParameterSet::new_alf(0),
+        AdaptationParameterSet::new_lmcs(0),
+        AdaptationParameterSet::new_sl(0),
+    ];
+    let mut sps: Option<SequenceParameterSet> = None;
+    let mut pps: Option<PictureParameterSet> = None;
+    let mut ph: Option<PictureHeader> = None;
+    let mut starts_picture = false;
+    let mut picture: Option<Picture> = None;
+    let mut num_pictures = 0;
+    let mut poc = 0;
+    let mut prev_tid0_poc = 0;
+    let mut picture_temporal_id = 0;
+    // decoded pictures which are referred to by the RPLs of the current picture
+    let mut dpb = DecodedPictureBuffer::new(&DpbParameter::new());
+    for nal_unit in read_byte_stream_nal_units(reader) {
+        let (_, nal_unit_type, temporal_id) = read_nal_unit_header(&nal_unit)?;
+        if let (Some(analyzer), Some(trace_writer)) = (analyzer.as_mut(), trace_writer.as_mut()) {
+            let trace = analyzer.analyze_nal_unit(&nal_unit)?.to_trace();
+            trace_writer
+                .write_all(trace.as_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+        let rbsp = remove_emulation_prevention_bytes(&nal_unit);
+        let mut rbsp_reader = BinaryReader::vec(&rbsp).unwrap();
+        match nal_unit_type {
+            NALUnitType::VPS_NUT => {
+                let vps = VpsParser::new(&ectx, &mut rbsp_reader).parse();
+                ectx.lock().unwrap().update_from_vps(&vps);
+            }
+            NALUnitType::SPS_NUT => {
+                let parsed_sps = SpsParser::new(&ectx, &mut rbsp_reader).parse();
+                check_sps(&parsed_sps)?;
+                ectx.lock().unwrap().update_from_sps(&parsed_sps);
+                let dpb_parameter = &parsed_sps.dpb_parameters[parsed_sps.max_sublayers - 1];
+                dpb.max_num_pictures = dpb_parameter.max_dec_pic_buffering + 1;
+                sps = Some(parsed_sps);
+            }
+            NALUnitType::PPS_NUT => {
+                let sps = sps.as_ref().ok_or("PPS before SPS")?;
+                let parsed_pps = PpsParser::new(&ectx, &mut rbsp_reader).parse(sps);
+                ectx.lock()
+                    .unwrap()
+                    .update_from_sps_and_pps(sps, &parsed_pps);
+                pps = Some(parsed_pps);
+            }
+            NALUnitType::PH_NUT => {
+                let (sps, pps) = get_active_parameter_sets(&sps, &pps)?;
+                let parsed_ph = PhParser::new(&ectx, &mut rbsp_reader).parse(sps, pps);
+                ectx.lock().unwrap().update_from_ph(&parsed_ph, sps, pps);
+                ph = Some(parsed_ph);
+                starts_picture = true;
+            }
+            _ => {}
+        }
+        if (nal_unit_type as usize) < NALUnitType::OPI_NUT as usize {
+            let (sps, pps) = get_active_parameter_sets(&sps, &pps)?;
+            let sh = ShParser::new(&ectx, &mut rbsp_reader).parse(
+                nal_unit_type,
+                sps,
+                pps,
+                [&aps[0], &aps[1], &aps[2]],
+                ph.as_ref(),
+            );
+            if sh.ph_in_sh.is_some() {
+                // the variables of the slice depend on those of the picture header
+                let mut ectx = ectx.lock().unwrap();
+                ectx.update_from_ph(sh.picture_header(), sps, pps);
+                ectx.update_from_sh(&sh, pps);
+            }
+            check_slice_header(&sh)?;
+            if starts_picture || sh.ph_in_sh.is_some() {
+                if let Some(picture) = &picture {
+                    if let Some(writer) = writer.as_mut() {
+                        write_picture(picture, writer)?;
+                    }
+                    dpb.push(get_reference_picture(picture, poc, picture_temporal_id))?;
+                    num_pictures += 1;
+                }
+                let ph = sh.picture_header();
+                poc = derive_pic_order_cnt(
+                    ph.pic_order_cnt_lsb,
+                    1 << sps.log2_max_pic_order_cnt_lsb,
+                    prev_tid0_poc,
+                    num_pictures == 0
+                        || nal_unit_type == NALUnitType::IDR_W_RADL
+                        || nal_unit_type == NALUnitType::IDR_N_LP,
+                );
+                if temporal_id == 0
+                    && !ph.non_ref_pic_flag
+                    && nal_unit_type != NALUnitType::RADL_NUT
+                    && nal_unit_type != NALUnitType::RASL_NUT
+                {
+                    prev_tid0_poc = poc;
+                }
+                picture_temporal_id = temporal_id;
+                // pictures which are not included in the RPLs are marked as unused for reference
+                if nal_unit_type == NALUnitType::IDR_W_RADL
+                    || nal_unit_type == NALUnitType::IDR_N_LP
+                {
+                    dpb.clear();
+                } else {
+                    let ectx = ectx.lock().unwrap();
+                    dpb.mark(
+                        poc,
+                        &sh.ref_pic_lists,
+                        &ectx.rpls_idx,
+                        ectx.max_pic_order_cnt_lsb,
+                    )?;
+                }
+                let mut new_picture = Picture::new(
+                    pps.pic_width_in_luma_samples,
+                    pps.pic_height_in_luma_samples,
+                    None,
+                );
+                new_picture.init_ctus(sps.log2_ctu_size);
+                {
+                    let ectx = ectx.lock().unwrap();
+                    new_picture.init_tiles(
+                        ectx.tile_col_bd_val[..ectx.num_tile_columns].to_vec(),
+                        ectx.tile_row_bd_val[..ectx.num_tile_rows].to_vec(),
+                    );
+                }
+                picture = Some(new_picture);
+                starts_picture = false;
+            }
+            let picture = picture.as_ref().ok_or("slice before picture header")?;
+            {
+                let ectx = &mut ectx.lock().unwrap();
+                ectx.pic_order_cnt_val = poc;
+                ectx.ref_pictures = if sh.slice_type == SliceType::I {
+                    [vec![], vec![]]
+                } else {
+                    dpb.get_ref_pictures(
+                        poc,
+                        &sh.ref_pic_lists,
+                        &ectx.rpls_idx,
+                        ectx.max_pic_order_cnt_lsb,
+                    )?
+                };
+            }
+            let mut decoder = CabacDecoder::new(&mut rbsp_reader);
+            if trace_writer.is_some() {
+                decoder.start_trace();
+            }
+            let result = decode_slice_data(&mut decoder, picture, &sh, &ectx);
+            if let (Some(trace), Some(trace_writer)) = (decoder.take_trace(), trace_writer.as_mut())
+            {
+                trace_writer
+                    .write_all(format!("slice_data() POC={poc}\n{trace}").as_bytes())
+                    .map_err(|e| e.to_string())?;
+            }
+            result?;
+        }
+    }
+    if let Some(picture) = &picture {
+        if let Some(writer) = writer.as_mut() {
+            write_picture(picture, writer)?;
+        }
+        num_pictures += 1;
+    }
+    if let Some(writer) = writer {
+        writer.flush().map_err(|e| e.to_string())?;
+    }
+    if let Some(trace_writer) = trace_writer {
+        trace_writer.flush().map_err(|e| e.to_string())?;
+    }
+    Ok(num_pictures)
+}
+
+/// Derives PicOrderCntVal from ph_pic_order_cnt_lsb as specified in 8.3.1, where prev_tid0_poc is
+/// that of the previous picture with TemporalId equal to 0 which is not a RASL, RADL or SLNR
+/// picture, and the MSB is 0 for a CLVSS picture.
+fn derive_pic_order_cnt(
+    pic_order_cnt_lsb: usize,
+    max_pic_order_cnt_lsb: usize,
+    prev_tid0_poc: isize,
+    is_clvss: bool,
+) -> isize {
+    let pic_order_cnt_lsb = pic_order_cnt_lsb as isize;
+    let max_pic_order_cnt_lsb = max_pic_order_cnt_lsb as isize;
+    let pic_order_cnt_msb = if is_clvss {
+        0
+    } else {
+        let prev_pic_order_cnt_lsb = prev_tid0_poc & (max_pic_order_cnt_lsb - 1);
+        let prev_pic_order_cnt_msb = prev_tid0_poc - prev_pic_order_cnt_lsb;
+        if pic_order_cnt_lsb < prev_pic_order_cnt_lsb
+            && prev_pic_order_cnt_lsb - pic_order_cnt_lsb >= max_pic_order_cnt_lsb / 2
+        {
+            prev_pic_order_cnt_msb + max_pic_order_cnt_lsb
+        } else if pic_order_cnt_lsb > prev_pic_order_cnt_lsb
+            && pic_order_cnt_lsb - prev_pic_order_cnt_lsb > max_pic_order_cnt_lsb / 2
+        {
+            prev_pic_order_cnt_msb - max_pic_order_cnt_lsb
+        } else {
+            prev_pic_order_cnt_msb
+        }
+    };
+    pic_order_cnt_msb + pic_order_cnt_lsb
+}
+
+fn check_sps(sps: &SequenceParameterSet) -> Result<(), String> {
+    if sps.chroma_format != ChromaFormat::YCbCr420 {
+        return Err("chroma formats other than 4:2:0 are not supported".to_string());
+    }
+    if sps.bitdepth != 8 {
+        return Err("bit depths other than 8 are not supported".to_string());
+    }
+    if sps.partition_constraints.qtbtt_dual_tree_intra_flag {
+        return Err("dual trees are not supported".to_string());
+    }
+    if sps.palette_enabled_flag || sps.ibc_enabled_flag || sps.act_enabled_flag {
+        return Err("palette, IBC and ACT are not supported".to_string());
+    }
+    if sps.temporal_mvp_enabled_flag
+        || sps.amvr_enabled_flag
+        || sps.smvd_enabled_flag
+        || sps.affine_enabled_flag
+        || sps.bcw_enabled_flag
+        || sps.sbt_enabled_flag
+    {
+        return Err("inter coding tools other than AMVP are not supported".to_string());
+    }
+    Ok(())
+}
+
+fn check_slice_header(sh: &SliceHeader) -> Result<(), String> {
+    if sh.slice_type == SliceType::B {
+        return Err("B slices are not supported".to_string());
+    }
+    if sh.sao_luma_used_flag || sh.sao_chroma_used_flag || sh.alf_enabled_flag {
+        return Err("SAO and ALF are not supported".to_string());
+    }
+    if sh.lmcs_used_flag {
+        return Err("LMCS is not supported".to_string());
+    }
+    if !sh.deblocking_filter_disabled_flag {
+        return Err("the deblocking filter is not supported".to_string());
+    }
+    Ok(())
+}
+
+/// Decodes the CTUs of a slice in the order they are coded, where the arithmetic decoder is
+/// re-initialized at the start of each tile and, with WPP, of each CTU row in the tile.
+fn decode_slice_data(
+    decoder: &mut CabacDecoder,
+    picture: &Picture,
+    sh: &SliceHeader,
+    encoder_context: &Arc<Mutex<EncoderContext>>,
+) -> Result<(), String> {
+    let (ctb_addrs, pic_width_in_ctbs_y, slice_qp_y) = {
+        let ectx = encoder_context.lock().unwrap();
+        (
+            ectx.ctb_addr_in_curr_slice[..ectx.num_ctus_in_curr_slice].to_vec(),
+            ectx.pic_width_in_ctbs_y,
+            ectx.slice_qp_y,
+        )
+    };
+    let ctus = picture.ctus.lock().unwrap().clone();
+    let get_ctu = |ctb_addr: usize| {
+        ctus[ctb_addr / pic_width_in_ctbs_y][ctb_addr % pic_width_in_ctbs_y].clone()
+    };
+    // the start of the tile containing a CTU and whether the CTU is in the last column of the tile
+    let get_tile_pos = |ctu: &ArcMutex<CodingTreeUnit>| {
+        let ctu = ctu.lock().unwrap();
+        let tile = ctu.tile.as_ref().unwrap();
+        let tile = tile.lock().unwrap();
+        (
+            (tile.ctu_col, tile.ctu_row),
+            ctu.x / ctu.width == tile.ctu_col + tile.num_ctu_cols - 1,
+        )
+    };
+    for (i, &ctb_addr) in ctb_addrs.iter().enumerate() {
+        let ctu = get_ctu(ctb_addr);
+        CodingTreeUnit::reset_ct(&ctu, Some(slice_qp_y as usize));
+        {
+            let ctu = ctu.lock().unwrap();
+            let mut ectx = encoder_context.lock().unwrap();
+            ectx.ctb_addr_x = ctu.x >> ectx.ctb_log2_size_y;
+            ectx.ctb_addr_y = ctu.y >> ectx.ctb_log2_size_y;
+            ectx.ctb_addr_in_rs = ectx.ctb_addr_y * ectx.pic_width_in_ctbs_y + ectx.ctb_addr_x;
+            if ctu.x == ctu.x_tile {
+                ectx.num_hmvp_cand = 0;
+                ectx.hmvp_cand_list.clear();
+                ectx.num_hmvp_ibc_cand = 0;
+                ectx.reset_ibc_buf = true;
+            }
+        }
+        debug_eprintln!("ctu {}", ctb_addr);
+        CtuDecoder::new(encoder_context, decoder).decode(ctu.clone(), sh)?;
+        if i == ctb_addrs.len() - 1 {
+            decoder.trace_syntax_element("slice end_of_slice_one_bit ");
+            if !decoder.decode_cabac_end_one_bit() {
+                return Err("end_of_slice_one_bit must be 1".to_string());
+            }
+            continue;
+        }
+        let (tile_pos, is_last_ctu_col_in_tile) = get_tile_pos(&ctu);
+        if !is_last_ctu_col_in_tile {
+            continue;
+        }
+        if get_tile_pos(&get_ctu(ctb_addrs[i + 1])).0 != tile_pos {
+            decoder.trace_syntax_element("slice end_of_tile_one_bit ");
+            if !decoder.decode_cabac_end_one_bit() {
+                return Err("end_of_tile_one_bit must be 1".to_string());
+            }
+        } else if sh.sps.entropy_coding_sync_enabled_flag {
+            decoder.trace_syntax_element("slice end_of_subset_one_bit ");
+            if !decoder.decode_cabac_end_one_bit() {
+                return Err("end_of_subset_one_bit must be 1".to_string());
+            }
+        } else {
+            continue;
+        }
+        decoder.byte_align();
+    }
+    Ok(())
+}
+
+/// Returns the decoded picture to be kept for the following pictures.
+fn get_reference_picture(picture: &Picture, poc: isize, temporal_id: usize) -> ReferencePicture {
+    ReferencePicture {
+        pic_order_cnt: poc,
+        temporal_id,
+        width: picture.width,
+        pixels: Arc::new(picture.get_reconst_pixels()),
+        clean_area_bd_x: picture.width,
+    }
+}
+
+fn write_picture(picture: &Picture, writer: &mut BinaryWriter) -> Result<(), String> {
+    for component_pixels in &picture.get_reconst_pixels() {
+        writer
+            .write_all(&component_pixels[..])
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derive_pic_order_cnt_works() {
+        assert_eq!(derive_pic_order_cnt(3, 16, 14, true), 3);
+        assert_eq!(derive_pic_order_cnt(15, 16, 14, false), 15);
+        assert_eq!(derive_pic_order_cnt(2, 16, 14, false), 18);
+        assert_eq!(derive_pic_order_cnt(14, 16, 18, false), 14);
+        assert_eq!(derive_pic_order_cnt(12, 16, 34, false), 28);
+    }
+}
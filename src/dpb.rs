@@ -1,16 +1,18 @@
 use super::common::*;
+use super::reference_picture::*;
 
 pub struct DpbParameter {
-    pub max_tid: usize,
+    /// dpb_max_dec_pic_buffering_minus1, with which the DPB holds max_dec_pic_buffering + 1
+    /// pictures
     pub max_dec_pic_buffering: usize,
     pub max_num_reorder_pics: usize,
+    /// dpb_max_latency_increase_plus1
     pub max_latency_increase: usize,
 }
 
 impl DpbParameter {
     pub fn new() -> DpbParameter {
         DpbParameter {
-            max_tid: 1,
             max_dec_pic_buffering: 8,
             max_num_reorder_pics: 4,
             max_latency_increase: 1,
@@ -43,3 +45,268 @@ impl OlsDpbParameter {
         }
     }
 }
+
+/// Marking of a picture in the DPB (8.3.3).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReferenceMarking {
+    ShortTerm,
+    LongTerm,
+    Unused,
+}
+
+pub struct DecodedPicture {
+    pub picture: ReferencePicture,
+    pub marking: ReferenceMarking,
+}
+
+/// Decoded picture buffer keeping the reconstructed pictures which the following pictures may
+/// refer to, whose markings follow the RPLs of the current picture.
+pub struct DecodedPictureBuffer {
+    pub pictures: Vec<DecodedPicture>,
+    /// the maximum number of the pictures in the DPB including the current picture
+    pub max_num_pictures: usize,
+}
+
+impl DecodedPictureBuffer {
+    pub fn new(dpb_parameter: &DpbParameter) -> DecodedPictureBuffer {
+        DecodedPictureBuffer {
+            pictures: vec![],
+            max_num_pictures: dpb_parameter.max_dec_pic_buffering + 1,
+        }
+    }
+
+    /// Removes all the pictures, which are marked as unused for reference by an IDR picture.
+    pub fn clear(&mut self) {
+        self.pictures.clear();
+    }
+
+    /// Returns the POCs of the pictures referred to by the entries of an RPL of the current
+    /// picture with its structure, with whether they are long-term, where None is the POC of a
+    /// long-term entry which doesn't exist in the DPB (8.3.2).
+    fn get_entry_pocs(
+        &self,
+        poc: isize,
+        rpl: &RefPicList,
+        rpls: &RefPicListStruct,
+        max_pic_order_cnt_lsb: usize,
+    ) -> Result<Vec<(Option<isize>, bool)>, String> {
+        let max_pic_order_cnt_lsb = max_pic_order_cnt_lsb as isize;
+        let mut st_delta_pocs = rpls.delta_pocs().into_iter();
+        let mut j = 0;
+        let mut delta_poc_msb_cycle_lt = 0;
+        let mut entry_pocs = vec![];
+        for i in 0..rpls.num_ref_entries {
+            if rpls.inter_layer_ref_pic_flag[i] {
+                return Err("inter-layer reference pictures are not supported".to_string());
+            }
+            if rpls.st_ref_pic_flag[i] {
+                entry_pocs.push((Some(poc + st_delta_pocs.next().unwrap()), false));
+                continue;
+            }
+            let poc_lsb_lt = if rpls.ltrp_in_header_flag {
+                rpl.poc_lsb_lt[j]
+            } else {
+                rpls.rpls_poc_lsb_lt[i]
+            } as isize;
+            // DeltaPocMsbCycleLt accumulates the cycles of the entries of a list
+            delta_poc_msb_cycle_lt += rpl.delta_poc_msb_cycle_lt[j] as isize;
+            let entry_poc = if rpl.delta_poc_msb_cycle_present_flag[j] {
+                let full_poc_lt = poc
+                    - delta_poc_msb_cycle_lt * max_pic_order_cnt_lsb
+                    - (poc & (max_pic_order_cnt_lsb - 1))
+                    + poc_lsb_lt;
+                self.pictures
+                    .iter()
+                    .find(|p| p.picture.pic_order_cnt == full_poc_lt)
+            } else {
+                self.pictures
+                    .iter()
+                    .find(|p| p.picture.pic_order_cnt & (max_pic_order_cnt_lsb - 1) == poc_lsb_lt)
+            }
+            .map(|p| p.picture.pic_order_cnt);
+            entry_pocs.push((entry_poc, true));
+            j += 1;
+        }
+        Ok(entry_pocs)
+    }
+
+    /// Marks the pictures referred to by the RPLs of the current picture as short-term or
+    /// long-term reference pictures and the others as unused for reference, and removes the
+    /// unused ones, which are not output separately from decoding (8.3.2, C.3.2).
+    pub fn mark(
+        &mut self,
+        poc: isize,
+        ref_pic_lists: &[RefPicList],
+        rpls_idx: &[usize],
+        max_pic_order_cnt_lsb: usize,
+    ) -> Result<(), String> {
+        for decoded in self.pictures.iter_mut() {
+            decoded.marking = ReferenceMarking::Unused;
+        }
+        for i in 0..2 {
+            let rpl = &ref_pic_lists[i];
+            let rpls = &rpl.ref_pic_list_structs[rpls_idx[i]];
+            for (entry_poc, is_long_term) in
+                self.get_entry_pocs(poc, rpl, rpls, max_pic_order_cnt_lsb)?
+            {
+                let decoded = self
+                    .pictures
+                    .iter_mut()
+                    .find(|p| Some(p.picture.pic_order_cnt) == entry_poc);
+                if let Some(decoded) = decoded {
+                    // a picture referred to by both short-term and long-term entries is
+                    // long-term
+                    if is_long_term || decoded.marking == ReferenceMarking::Unused {
+                        decoded.marking = if is_long_term {
+                            ReferenceMarking::LongTerm
+                        } else {
+                            ReferenceMarking::ShortTerm
+                        };
+                    }
+                }
+            }
+        }
+        self.pictures
+            .retain(|decoded| decoded.marking != ReferenceMarking::Unused);
+        Ok(())
+    }
+
+    /// Constructs RefPicList[0] and RefPicList[1] of the current picture from the entries of its
+    /// RPLs, where the entries referring to missing pictures are errors (8.3.2).
+    pub fn get_ref_pictures(
+        &self,
+        poc: isize,
+        ref_pic_lists: &[RefPicList],
+        rpls_idx: &[usize],
+        max_pic_order_cnt_lsb: usize,
+    ) -> Result<[Vec<ReferencePicture>; 2], String> {
+        let get_ref_picture_list = |i: usize| {
+            let rpl = &ref_pic_lists[i];
+            let rpls = &rpl.ref_pic_list_structs[rpls_idx[i]];
+            self.get_entry_pocs(poc, rpl, rpls, max_pic_order_cnt_lsb)?
+                .into_iter()
+                .map(|(entry_poc, _)| {
+                    self.pictures
+                        .iter()
+                        .find(|p| Some(p.picture.pic_order_cnt) == entry_poc)
+                        .map(|p| p.picture.clone())
+                        .ok_or("no reference picture of an RPL entry in the DPB".to_string())
+                })
+                .collect::<Result<Vec<ReferencePicture>, String>>()
+        };
+        Ok([get_ref_picture_list(0)?, get_ref_picture_list(1)?])
+    }
+
+    /// Returns the nearest short-term reference picture of each sublayer lower than
+    /// max_temporal_id in descending order of POC, of which the current picture may keep as many
+    /// as the DPB can hold with itself.
+    pub fn get_nearest_pictures(&self, max_temporal_id: usize) -> Vec<ReferencePicture> {
+        let mut nearest_pictures: Vec<ReferencePicture> = vec![];
+        for decoded in self.pictures.iter() {
+            let picture = &decoded.picture;
+            if decoded.marking != ReferenceMarking::ShortTerm
+                || picture.temporal_id >= max_temporal_id
+            {
+                continue;
+            }
+            match nearest_pictures
+                .iter_mut()
+                .find(|nearest| nearest.temporal_id == picture.temporal_id)
+            {
+                Some(nearest) if nearest.pic_order_cnt < picture.pic_order_cnt => {
+                    *nearest = picture.clone()
+                }
+                Some(_) => {}
+                None => nearest_pictures.push(picture.clone()),
+            }
+        }
+        nearest_pictures.sort_by_key(|picture| -picture.pic_order_cnt);
+        nearest_pictures.truncate(self.max_num_pictures - 1);
+        nearest_pictures
+    }
+
+    /// Stores the current picture after decoding as a short-term reference picture (C.3.4).
+    pub fn push(&mut self, picture: ReferencePicture) -> Result<(), String> {
+        if self.pictures.len() >= self.max_num_pictures {
+            return Err(format!(
+                "DPB of {} pictures overflows",
+                self.max_num_pictures
+            ));
+        }
+        self.pictures.push(DecodedPicture {
+            picture,
+            marking: ReferenceMarking::ShortTerm,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn new_picture(pic_order_cnt: isize, temporal_id: usize) -> ReferencePicture {
+        ReferencePicture {
+            pic_order_cnt,
+            temporal_id,
+            width: 0,
+            pixels: Arc::new(vec![]),
+            clean_area_bd_x: 0,
+        }
+    }
+
+    #[test]
+    fn dpb_mark_works() {
+        let mut dpb = DecodedPictureBuffer::new(&DpbParameter::new());
+        for (poc, temporal_id) in [(0, 0), (4, 0), (2, 1), (1, 2)] {
+            dpb.push(new_picture(poc, temporal_id)).unwrap();
+        }
+        let nearest_pocs = |dpb: &DecodedPictureBuffer, max_temporal_id| {
+            dpb.get_nearest_pictures(max_temporal_id)
+                .iter()
+                .map(|p| p.pic_order_cnt)
+                .collect::<Vec<isize>>()
+        };
+        assert_eq!(nearest_pocs(&dpb, 1), vec![4]);
+        assert_eq!(nearest_pocs(&dpb, 3), vec![4, 2, 1]);
+
+        // the long-term entry refers to POC 0 by its LSBs
+        let mut rpls = RefPicListStruct::new_st(&[-1, 1]);
+        rpls.num_ref_entries += 1;
+        rpls.inter_layer_ref_pic_flag.push(false);
+        rpls.st_ref_pic_flag.push(false);
+        rpls.rpls_poc_lsb_lt.push(0);
+        let rpl = RefPicList::new_explicit(0, rpls);
+        let ref_pic_lists = [rpl.clone(), rpl];
+        dpb.mark(3, &ref_pic_lists, &[1, 1], 16).unwrap();
+        let markings = dpb
+            .pictures
+            .iter()
+            .map(|p| (p.picture.pic_order_cnt, p.marking))
+            .collect::<Vec<(isize, ReferenceMarking)>>();
+        assert_eq!(
+            markings,
+            vec![
+                (0, ReferenceMarking::LongTerm),
+                (4, ReferenceMarking::ShortTerm),
+                (2, ReferenceMarking::ShortTerm),
+            ]
+        );
+        assert_eq!(nearest_pocs(&dpb, 3), vec![4, 2]);
+        let ref_pictures = dpb
+            .get_ref_pictures(3, &ref_pic_lists, &[1, 1], 16)
+            .unwrap();
+        let ref_pocs = ref_pictures[0]
+            .iter()
+            .map(|p| p.pic_order_cnt)
+            .collect::<Vec<isize>>();
+        assert_eq!(ref_pocs, vec![2, 4, 0]);
+
+        dpb.clear();
+        for poc in 0..9 {
+            dpb.push(new_picture(poc, 0)).unwrap();
+        }
+        assert!(dpb.push(new_picture(9, 0)).is_err());
+    }
+}
@@ -34,13 +34,13 @@ impl<'a> DpbpEncoder<'a> {
             max_sublayers - 1
         };
         for dpbp in dpb_parameters.iter().take(max_sublayers).skip(l) {
-            debug_eprint!("dpbp.max_dec_pic_buffering ");
+            trace_syntax_element!(bins, "dpbp.max_dec_pic_buffering ");
             self.coder
                 .encode_unsigned_exp_golomb(bins, dpbp.max_dec_pic_buffering as u64);
-            debug_eprint!("dpbp.max_num_reorder_pics ");
+            trace_syntax_element!(bins, "dpbp.max_num_reorder_pics ");
             self.coder
                 .encode_unsigned_exp_golomb(bins, dpbp.max_num_reorder_pics as u64);
-            debug_eprint!("dpbp.max_latency_increase ");
+            trace_syntax_element!(bins, "dpbp.max_latency_increase ");
             self.coder
                 .encode_unsigned_exp_golomb(bins, dpbp.max_latency_increase as u64);
         }
@@ -0,0 +1,44 @@
+use super::binary_reader::*;
+use super::dpb::*;
+use super::encoder_context::*;
+use std::sync::{Arc, Mutex};
+
+pub struct DpbpParser<'a, 'b> {
+    reader: &'a mut BinaryReader<'b>,
+    _encoder_context: Arc<Mutex<EncoderContext>>,
+}
+
+impl<'a, 'b> DpbpParser<'a, 'b> {
+    pub fn new(
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        reader: &'a mut BinaryReader<'b>,
+    ) -> DpbpParser<'a, 'b> {
+        DpbpParser {
+            reader,
+            _encoder_context: encoder_context.clone(),
+        }
+    }
+
+    /// Parses dpb_parameters( ). When the sublayer information is not signalled, the parameters
+    /// of the highest sublayer are copied to the lower ones.
+    pub fn parse(&mut self, max_sublayers: usize, sublayer_info_flag: bool) -> Vec<DpbParameter> {
+        let l = if sublayer_info_flag {
+            0
+        } else {
+            max_sublayers - 1
+        };
+        let mut dpb_parameters: Vec<DpbParameter> =
+            (0..max_sublayers).map(|_| DpbParameter::new()).collect();
+        for dpbp in dpb_parameters.iter_mut().take(max_sublayers).skip(l) {
+            dpbp.max_dec_pic_buffering = self.reader.read_unsigned_exp_golomb();
+            dpbp.max_num_reorder_pics = self.reader.read_unsigned_exp_golomb();
+            dpbp.max_latency_increase = self.reader.read_unsigned_exp_golomb();
+        }
+        for i in 0..l {
+            dpb_parameters[i].max_dec_pic_buffering = dpb_parameters[l].max_dec_pic_buffering;
+            dpb_parameters[i].max_num_reorder_pics = dpb_parameters[l].max_num_reorder_pics;
+            dpb_parameters[i].max_latency_increase = dpb_parameters[l].max_latency_increase;
+        }
+        dpb_parameters
+    }
+}
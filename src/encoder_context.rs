@@ -1,4 +1,5 @@
 use super::common::*;
+use super::ctu::*;
 use super::picture_header::*;
 use super::pps::*;
 use super::reference_picture::*;
@@ -8,6 +9,9 @@ use super::vps::*;
 use debug_print::*;
 use std::collections::HashMap;
 
+pub const MAX_NUM_HMVP_CAND: usize = 5;
+
+#[derive(Clone)]
 pub struct EncoderContext {
     pub vps_num_dpb_params: usize,
     pub input_picture_width: usize,
@@ -75,16 +79,25 @@ pub struct EncoderContext {
     pub slice_top_left_tile_idx: Vec<usize>,
     pub num_slices_in_tile: Vec<usize>,
     pub ctb_addr_in_slice: Vec<Vec<usize>>,
+    pub subpic_idx_for_slice: Vec<usize>,
+    pub subpic_level_slice_idx: Vec<usize>,
+    pub col_width_val: Vec<usize>,
     pub row_height_val: Vec<usize>,
+    pub tile_col_bd_val: Vec<usize>,
+    pub tile_row_bd_val: Vec<usize>,
     pub num_ltrp_entries: Vec<Vec<usize>>,
     pub rpls_idx: Vec<usize>,
-    pub first_ctb_row_in_slice: bool,
     pub ctb_addr_in_rs: usize,
     pub ctb_addr_in_curr_slice: Vec<usize>,
+    /// whether each CTB in raster scan is in the current slice
+    pub is_ctb_in_curr_slice: Vec<bool>,
     pub ctb_addr_x: usize,
     pub ctb_addr_y: usize,
     pub ctb_to_tile_col_bd: Vec<usize>,
+    pub ctb_to_tile_row_bd: Vec<usize>,
     pub num_hmvp_cand: usize,
+    /// history-based motion vector predictor candidates (8.5.2.16)
+    pub hmvp_cand_list: Vec<MotionInfo>,
     pub num_hmvp_ibc_cand: usize,
     pub reset_ibc_buf: bool,
     pub ctb_log2_size_y: usize,
@@ -179,6 +192,13 @@ pub struct EncoderContext {
     pub max_split_depth: usize,
     pub extra_params: HashMap<String, String>,
     pub enable_print: bool,
+    /// x coordinate of the boundary between the clean area and the dirty area while refreshing
+    /// a picture by GDR
+    pub gdr_clean_area_bd_x: Option<usize>,
+    /// PicOrderCntVal of the current picture
+    pub pic_order_cnt_val: isize,
+    /// pictures referred to by the entries of RefPicList[0] and RefPicList[1]
+    pub ref_pictures: [Vec<ReferencePicture>; 2],
 }
 
 impl EncoderContext {
@@ -248,16 +268,23 @@ impl EncoderContext {
             slice_top_left_tile_idx: vec![],
             num_slices_in_tile: vec![],
             ctb_addr_in_slice: vec![],
+            subpic_idx_for_slice: vec![],
+            subpic_level_slice_idx: vec![],
+            col_width_val: vec![],
             row_height_val: vec![],
+            tile_col_bd_val: vec![],
+            tile_row_bd_val: vec![],
             num_ltrp_entries: vec![],
             rpls_idx: vec![],
-            first_ctb_row_in_slice: false,
             ctb_addr_in_rs: 0,
             ctb_addr_in_curr_slice: vec![],
+            is_ctb_in_curr_slice: vec![],
             ctb_addr_x: 0,
             ctb_addr_y: 0,
             ctb_to_tile_col_bd: vec![],
+            ctb_to_tile_row_bd: vec![],
             num_hmvp_cand: 0,
+            hmvp_cand_list: vec![],
             num_hmvp_ibc_cand: 0,
             reset_ibc_buf: false,
             ctb_log2_size_y: 0,
@@ -354,6 +381,9 @@ impl EncoderContext {
             max_split_depth: 0,
             extra_params: hashmap![],
             enable_print: false,
+            gdr_clean_area_bd_x: None,
+            pic_order_cnt_val: 0,
+            ref_pictures: [vec![], vec![]],
         }
     }
 
@@ -671,15 +701,17 @@ impl EncoderContext {
         pps: &PictureParameterSet,
     ) {
         if let Some(sps_subpic_info) = &sps.subpic_info {
-            for i in 0..sps_subpic_info.num_subpics {
-                if sps_subpic_info.subpic_id_mapping_present_flag {
-                    self.subpic_id_val[i] = if pps.subpic_id_mapping_present_flag {
+            self.subpic_id_val = (0..sps_subpic_info.num_subpics)
+                .map(|i| {
+                    if !sps_subpic_info.subpic_id_mapping_explicitly_signalled_flag {
+                        i
+                    } else if pps.subpic_id_mapping_present_flag {
                         pps.subpic_id[i]
                     } else {
                         sps_subpic_info.subpic_id[i]
-                    };
-                }
-            }
+                    }
+                })
+                .collect();
         }
         //else {
         //panic!();
@@ -715,24 +747,266 @@ impl EncoderContext {
                 * (pps.scaling_window.bottom_offset + pps.scaling_window.top_offset))
             as usize;
 
+        // CTB raster scanning, tile scanning and subpicture scanning processes (6.5.1)
+        (self.col_width_val, self.row_height_val) = if pps.no_pic_partition_flag {
+            (
+                vec![self.pic_width_in_ctbs_y],
+                vec![self.pic_height_in_ctbs_y],
+            )
+        } else {
+            (
+                derive_tile_sizes(&pps.tile_column_widths, self.pic_width_in_ctbs_y),
+                derive_tile_sizes(&pps.tile_column_heights, self.pic_height_in_ctbs_y),
+            )
+        };
+        self.num_tile_columns = self.col_width_val.len();
+        self.num_tile_rows = self.row_height_val.len();
         self.num_tiles_in_pic = self.num_tile_columns * self.num_tile_rows;
+        self.tile_col_bd_val = vec![0];
+        for i in 0..self.num_tile_columns {
+            self.tile_col_bd_val
+                .push(self.tile_col_bd_val[i] + self.col_width_val[i]);
+        }
+        self.tile_row_bd_val = vec![0];
+        for j in 0..self.num_tile_rows {
+            self.tile_row_bd_val
+                .push(self.tile_row_bd_val[j] + self.row_height_val[j]);
+        }
+        self.ctb_to_tile_col_bd = vec![0; self.pic_width_in_ctbs_y + 1];
+        let mut tile_x = 0;
+        for ctb_addr_x in 0..=self.pic_width_in_ctbs_y {
+            if ctb_addr_x == self.tile_col_bd_val[tile_x + 1] {
+                tile_x += 1;
+            }
+            self.ctb_to_tile_col_bd[ctb_addr_x] = self.tile_col_bd_val[tile_x];
+        }
+        self.ctb_to_tile_row_bd = vec![0; self.pic_height_in_ctbs_y + 1];
+        let mut tile_y = 0;
+        for ctb_addr_y in 0..=self.pic_height_in_ctbs_y {
+            if ctb_addr_y == self.tile_row_bd_val[tile_y + 1] {
+                tile_y += 1;
+            }
+            self.ctb_to_tile_row_bd[ctb_addr_y] = self.tile_row_bd_val[tile_y];
+        }
+        self.derive_rect_slices(sps, pps);
+    }
+
+    fn add_ctbs_to_slice(
+        &self,
+        ctb_addr_in_slice: &mut Vec<usize>,
+        start_x: usize,
+        stop_x: usize,
+        start_y: usize,
+        stop_y: usize,
+    ) {
+        for ctb_y in start_y..stop_y {
+            for ctb_x in start_x..stop_x {
+                ctb_addr_in_slice.push(ctb_y * self.pic_width_in_ctbs_y + ctb_x);
+            }
+        }
+    }
+
+    fn add_tile_ctbs_to_slice(&self, ctb_addr_in_slice: &mut Vec<usize>, tile_idx: usize) {
+        let tile_x = tile_idx % self.num_tile_columns;
+        let tile_y = tile_idx / self.num_tile_columns;
+        self.add_ctbs_to_slice(
+            ctb_addr_in_slice,
+            self.tile_col_bd_val[tile_x],
+            self.tile_col_bd_val[tile_x + 1],
+            self.tile_row_bd_val[tile_y],
+            self.tile_row_bd_val[tile_y + 1],
+        );
+    }
+
+    /// Derives the layout of rectangular slices (6.5.1). Raster-scan slices are derived from
+    /// each slice header instead.
+    fn derive_rect_slices(&mut self, sps: &SequenceParameterSet, pps: &PictureParameterSet) {
+        let partition_parameters = &pps.partition_parameters;
+        self.slice_top_left_tile_idx = vec![];
+        self.num_slices_in_tile = vec![];
+        self.ctb_addr_in_slice = vec![];
+        if !pps.no_pic_partition_flag && !partition_parameters.rect_slice_flag {
+            return;
+        }
+        let subpic_rects = match &sps.subpic_info {
+            Some(info) => (0..info.num_subpics)
+                .map(|i| {
+                    (
+                        info.subpic_ctu_top_left_xs[i],
+                        info.subpic_ctu_top_left_ys[i],
+                        info.subpic_widths[i],
+                        info.subpic_heights[i],
+                    )
+                })
+                .collect(),
+            None => vec![(0, 0, self.pic_width_in_ctbs_y, self.pic_height_in_ctbs_y)],
+        };
+        if pps.no_pic_partition_flag || partition_parameters.single_slice_per_subpic_flag {
+            for &(x, y, width, height) in subpic_rects.iter() {
+                let mut ctb_addr_in_slice = vec![];
+                for tile_idx in 0..self.num_tiles_in_pic {
+                    let tile_x = tile_idx % self.num_tile_columns;
+                    let tile_y = tile_idx / self.num_tile_columns;
+                    if self.tile_row_bd_val[tile_y] >= y
+                        && self.tile_row_bd_val[tile_y + 1] <= y + height
+                        && self.tile_col_bd_val[tile_x] >= x
+                        && self.tile_col_bd_val[tile_x + 1] <= x + width
+                    {
+                        self.add_tile_ctbs_to_slice(&mut ctb_addr_in_slice, tile_idx);
+                    }
+                }
+                // the slice consists of CTU rows in a tile
+                if ctb_addr_in_slice.is_empty() {
+                    self.add_ctbs_to_slice(&mut ctb_addr_in_slice, x, x + width, y, y + height);
+                }
+                self.ctb_addr_in_slice.push(ctb_addr_in_slice);
+            }
+        } else {
+            let num_slices_in_pic = partition_parameters.num_slices_in_pic;
+            let mut slice_width_in_tiles = vec![1; num_slices_in_pic];
+            let mut slice_height_in_tiles = vec![1; num_slices_in_pic];
+            self.slice_top_left_tile_idx = vec![0; num_slices_in_pic];
+            self.num_slices_in_tile = vec![1; num_slices_in_pic];
+            self.ctb_addr_in_slice = vec![vec![]; num_slices_in_pic];
+            let mut tile_idx = 0;
+            let mut i = 0;
+            while i < num_slices_in_pic {
+                let slice = &partition_parameters.slices[i];
+                self.slice_top_left_tile_idx[i] = tile_idx;
+                let tile_x = tile_idx % self.num_tile_columns;
+                let tile_y = tile_idx / self.num_tile_columns;
+                if i < num_slices_in_pic - 1 {
+                    slice_width_in_tiles[i] = slice.slice_width_in_tiles;
+                    slice_height_in_tiles[i] = slice.slice_height_in_tiles;
+                } else {
+                    slice_width_in_tiles[i] = self.num_tile_columns - tile_x;
+                    slice_height_in_tiles[i] = self.num_tile_rows - tile_y;
+                }
+                if slice_width_in_tiles[i] == 1 && slice_height_in_tiles[i] == 1 {
+                    let slice_heights_in_ctus =
+                        if i == num_slices_in_pic - 1 || slice.num_exp_slices_in_tile == 0 {
+                            vec![self.row_height_val[tile_y]]
+                        } else {
+                            derive_tile_sizes(
+                                &slice.exp_slice_height_in_ctus,
+                                self.row_height_val[tile_y],
+                            )
+                        };
+                    self.num_slices_in_tile[i] = slice_heights_in_ctus.len();
+                    let mut ctb_y = self.tile_row_bd_val[tile_y];
+                    for (j, slice_height_in_ctus) in slice_heights_in_ctus.into_iter().enumerate() {
+                        let mut ctb_addr_in_slice = vec![];
+                        self.add_ctbs_to_slice(
+                            &mut ctb_addr_in_slice,
+                            self.tile_col_bd_val[tile_x],
+                            self.tile_col_bd_val[tile_x + 1],
+                            ctb_y,
+                            ctb_y + slice_height_in_ctus,
+                        );
+                        self.ctb_addr_in_slice[i + j] = ctb_addr_in_slice;
+                        self.slice_top_left_tile_idx[i + j] = tile_idx;
+                        ctb_y += slice_height_in_ctus;
+                    }
+                    i += self.num_slices_in_tile[i] - 1;
+                } else {
+                    let mut ctb_addr_in_slice = vec![];
+                    for j in 0..slice_height_in_tiles[i] {
+                        for k in 0..slice_width_in_tiles[i] {
+                            self.add_tile_ctbs_to_slice(
+                                &mut ctb_addr_in_slice,
+                                tile_idx + j * self.num_tile_columns + k,
+                            );
+                        }
+                    }
+                    self.ctb_addr_in_slice[i] = ctb_addr_in_slice;
+                }
+                if i < num_slices_in_pic - 1 {
+                    if partition_parameters.tile_idx_delta_present_flag {
+                        tile_idx = (tile_idx as isize
+                            + partition_parameters.slices[i].tile_idx_delta_val)
+                            as usize;
+                    } else {
+                        tile_idx += slice_width_in_tiles[i];
+                        if tile_idx % self.num_tile_columns == 0 {
+                            tile_idx += (slice_height_in_tiles[i] - 1) * self.num_tile_columns;
+                        }
+                    }
+                }
+                i += 1;
+            }
+        }
+        self.num_ctus_in_slice = self.ctb_addr_in_slice.iter().map(|v| v.len()).collect();
+        self.num_slices_in_subpic = vec![0; subpic_rects.len()];
+        self.subpic_idx_for_slice = vec![0; self.ctb_addr_in_slice.len()];
+        self.subpic_level_slice_idx = vec![0; self.ctb_addr_in_slice.len()];
+        for (i, &(x, y, width, height)) in subpic_rects.iter().enumerate() {
+            for j in 0..self.ctb_addr_in_slice.len() {
+                let pos_x = self.ctb_addr_in_slice[j][0] % self.pic_width_in_ctbs_y;
+                let pos_y = self.ctb_addr_in_slice[j][0] / self.pic_width_in_ctbs_y;
+                if (x..x + width).contains(&pos_x) && (y..y + height).contains(&pos_y) {
+                    self.subpic_idx_for_slice[j] = i;
+                    self.subpic_level_slice_idx[j] = self.num_slices_in_subpic[i];
+                    self.num_slices_in_subpic[i] += 1;
+                }
+            }
+        }
     }
 
-    pub fn update_from_ph(&mut self, ph: &PictureHeader, pps: &PictureParameterSet) {
+    pub fn update_from_ph(
+        &mut self,
+        ph: &PictureHeader,
+        sps: &SequenceParameterSet,
+        pps: &PictureParameterSet,
+    ) {
         if pps.partition_parameters.qp_delta_info_in_ph_flag {
             self.slice_qp_y = pps.init_qp + ph.qp_delta;
             self.qp_y = self.slice_qp_y as usize;
         }
+        if pps.partition_parameters.rpl_info_in_ph_flag {
+            self.update_from_ref_pic_lists(&ph.ref_pic_lists, sps);
+        }
     }
 
     pub fn update_from_sh(&mut self, sh: &SliceHeader, pps: &PictureParameterSet) {
+        if sh.sps.subpic_info.is_some() {
+            self.curr_subpic_idx = self
+                .subpic_id_val
+                .iter()
+                .position(|&subpic_id| subpic_id == sh.subpic_id)
+                .unwrap();
+        }
+        self.ctb_addr_in_curr_slice =
+            if pps.partition_parameters.rect_slice_flag || pps.no_pic_partition_flag {
+                let curr_slice_idx = (0..self.ctb_addr_in_slice.len())
+                    .find(|&i| {
+                        self.subpic_idx_for_slice[i] == self.curr_subpic_idx
+                            && self.subpic_level_slice_idx[i] == sh.slice_address
+                    })
+                    .unwrap();
+                self.ctb_addr_in_slice[curr_slice_idx].clone()
+            } else {
+                let mut ctb_addr_in_curr_slice = vec![];
+                for tile_idx in sh.slice_address..sh.slice_address + sh.num_tiles_in_slice {
+                    self.add_tile_ctbs_to_slice(&mut ctb_addr_in_curr_slice, tile_idx);
+                }
+                ctb_addr_in_curr_slice
+            };
+        self.num_ctus_in_curr_slice = self.ctb_addr_in_curr_slice.len();
+        self.is_ctb_in_curr_slice = vec![false; self.pic_size_in_ctbs_y];
+        for &ctb_addr in self.ctb_addr_in_curr_slice.iter() {
+            self.is_ctb_in_curr_slice[ctb_addr] = true;
+        }
         if !pps.partition_parameters.qp_delta_info_in_ph_flag {
             self.slice_qp_y = pps.init_qp + sh.qp_delta;
             self.qp_y = self.slice_qp_y as usize;
         }
+        if !pps.partition_parameters.rpl_info_in_ph_flag {
+            self.update_from_ref_pic_lists(&sh.ref_pic_lists, sh.sps);
+        }
+        self.num_ref_idx_active = [0, 1].map(|i| sh.num_ref_idx_active.get(i).map_or(0, |&n| n));
         if sh.slice_type == SliceType::I {
             self.min_qt_log2_size_y = self.min_cb_log2_size_y
-                + match &sh.ph.as_ref().unwrap().partition_constraints {
+                + match &sh.picture_header().partition_constraints {
                     Some(pc) => pc.log2_diff_min_qt_min_cb_intra_slice_luma,
                     None => {
                         sh.sps
@@ -741,7 +1015,7 @@ impl EncoderContext {
                     }
                 };
             self.min_qt_log2_size_c = self.min_cb_log2_size_y
-                + match &sh.ph.as_ref().unwrap().partition_constraints {
+                + match &sh.picture_header().partition_constraints {
                     Some(pc) => pc.log2_diff_min_qt_min_cb_intra_slice_chroma,
                     None => {
                         sh.sps
@@ -751,7 +1025,7 @@ impl EncoderContext {
                 };
             self.max_bt_size_y = 1
                 << (self.min_qt_log2_size_y
-                    + match &sh.ph.as_ref().unwrap().partition_constraints {
+                    + match &sh.picture_header().partition_constraints {
                         Some(pc) => pc.log2_diff_max_bt_min_qt_intra_slice_luma,
                         None => {
                             sh.sps
@@ -761,7 +1035,7 @@ impl EncoderContext {
                     });
             self.max_bt_size_c = 1
                 << (self.min_qt_log2_size_c
-                    + match &sh.ph.as_ref().unwrap().partition_constraints {
+                    + match &sh.picture_header().partition_constraints {
                         Some(pc) => pc.log2_diff_max_bt_min_qt_intra_slice_chroma,
                         None => {
                             sh.sps
@@ -771,7 +1045,7 @@ impl EncoderContext {
                     });
             self.max_tt_size_y = 1
                 << (self.min_qt_log2_size_y
-                    + match &sh.ph.as_ref().unwrap().partition_constraints {
+                    + match &sh.picture_header().partition_constraints {
                         Some(pc) => pc.log2_diff_max_tt_min_qt_intra_slice_luma,
                         None => {
                             sh.sps
@@ -781,7 +1055,7 @@ impl EncoderContext {
                     });
             self.max_tt_size_c = 1
                 << (self.min_qt_log2_size_c
-                    + match &sh.ph.as_ref().unwrap().partition_constraints {
+                    + match &sh.picture_header().partition_constraints {
                         Some(pc) => pc.log2_diff_max_tt_min_qt_intra_slice_chroma,
                         None => {
                             sh.sps
@@ -789,7 +1063,7 @@ impl EncoderContext {
                                 .log2_diff_max_tt_min_qt_intra_slice_chroma
                         }
                     });
-            self.max_mtt_depth_y = match &sh.ph.as_ref().unwrap().partition_constraints {
+            self.max_mtt_depth_y = match &sh.picture_header().partition_constraints {
                 Some(pc) => pc.max_mtt_hierarchy_depth_intra_slice_luma,
                 None => {
                     sh.sps
@@ -797,7 +1071,7 @@ impl EncoderContext {
                         .max_mtt_hierarchy_depth_intra_slice_luma
                 }
             };
-            self.max_mtt_depth_c = match &sh.ph.as_ref().unwrap().partition_constraints {
+            self.max_mtt_depth_c = match &sh.picture_header().partition_constraints {
                 Some(pc) => pc.max_mtt_hierarchy_depth_intra_slice_chroma,
                 None => {
                     sh.sps
@@ -805,11 +1079,11 @@ impl EncoderContext {
                         .max_mtt_hierarchy_depth_intra_slice_chroma
                 }
             };
-            self.cu_qp_delta_sub_div = match &sh.ph.as_ref().unwrap().partition_constraints {
+            self.cu_qp_delta_sub_div = match &sh.picture_header().partition_constraints {
                 Some(pc) => pc.cu_qp_delta_subdiv_intra_slice,
                 None => sh.sps.partition_constraints.cu_qp_delta_subdiv_intra_slice,
             };
-            self.cu_chroma_qp_offset_subdiv = match &sh.ph.as_ref().unwrap().partition_constraints {
+            self.cu_chroma_qp_offset_subdiv = match &sh.picture_header().partition_constraints {
                 Some(pc) => pc.cu_chroma_qp_offset_subdiv_intra_slice,
                 None => {
                     sh.sps
@@ -819,7 +1093,7 @@ impl EncoderContext {
             };
         } else {
             self.min_qt_log2_size_y = self.min_cb_log2_size_y
-                + match &sh.ph.as_ref().unwrap().partition_constraints {
+                + match &sh.picture_header().partition_constraints {
                     Some(pc) => pc.log2_diff_min_qt_min_cb_inter_slice,
                     None => {
                         sh.sps
@@ -828,7 +1102,7 @@ impl EncoderContext {
                     }
                 };
             self.min_qt_log2_size_c = self.min_cb_log2_size_y
-                + match &sh.ph.as_ref().unwrap().partition_constraints {
+                + match &sh.picture_header().partition_constraints {
                     Some(pc) => pc.log2_diff_min_qt_min_cb_inter_slice,
                     None => {
                         sh.sps
@@ -838,7 +1112,7 @@ impl EncoderContext {
                 };
             self.max_bt_size_y = 1
                 << (self.min_qt_log2_size_y
-                    + match &sh.ph.as_ref().unwrap().partition_constraints {
+                    + match &sh.picture_header().partition_constraints {
                         Some(pc) => pc.log2_diff_max_bt_min_qt_inter_slice,
                         None => {
                             sh.sps
@@ -848,7 +1122,7 @@ impl EncoderContext {
                     });
             self.max_bt_size_c = 1
                 << (self.min_qt_log2_size_c
-                    + match &sh.ph.as_ref().unwrap().partition_constraints {
+                    + match &sh.picture_header().partition_constraints {
                         Some(pc) => pc.log2_diff_max_bt_min_qt_inter_slice,
                         None => {
                             sh.sps
@@ -858,7 +1132,7 @@ impl EncoderContext {
                     });
             self.max_tt_size_y = 1
                 << (self.min_qt_log2_size_y
-                    + match &sh.ph.as_ref().unwrap().partition_constraints {
+                    + match &sh.picture_header().partition_constraints {
                         Some(pc) => pc.log2_diff_max_tt_min_qt_inter_slice,
                         None => {
                             sh.sps
@@ -868,7 +1142,7 @@ impl EncoderContext {
                     });
             self.max_tt_size_c = 1
                 << (self.min_qt_log2_size_c
-                    + match &sh.ph.as_ref().unwrap().partition_constraints {
+                    + match &sh.picture_header().partition_constraints {
                         Some(pc) => pc.log2_diff_max_tt_min_qt_inter_slice,
                         None => {
                             sh.sps
@@ -876,7 +1150,7 @@ impl EncoderContext {
                                 .log2_diff_max_tt_min_qt_inter_slice
                         }
                     });
-            self.max_mtt_depth_y = match &sh.ph.as_ref().unwrap().partition_constraints {
+            self.max_mtt_depth_y = match &sh.picture_header().partition_constraints {
                 Some(pc) => pc.max_mtt_hierarchy_depth_inter_slice,
                 None => {
                     sh.sps
@@ -884,7 +1158,7 @@ impl EncoderContext {
                         .max_mtt_hierarchy_depth_inter_slice
                 }
             };
-            self.max_mtt_depth_c = match &sh.ph.as_ref().unwrap().partition_constraints {
+            self.max_mtt_depth_c = match &sh.picture_header().partition_constraints {
                 Some(pc) => pc.max_mtt_hierarchy_depth_inter_slice,
                 None => {
                     sh.sps
@@ -892,11 +1166,11 @@ impl EncoderContext {
                         .max_mtt_hierarchy_depth_inter_slice
                 }
             };
-            self.cu_qp_delta_sub_div = match &sh.ph.as_ref().unwrap().partition_constraints {
+            self.cu_qp_delta_sub_div = match &sh.picture_header().partition_constraints {
                 Some(pc) => pc.cu_qp_delta_subdiv_inter_slice,
                 None => sh.sps.partition_constraints.cu_qp_delta_subdiv_inter_slice,
             };
-            self.cu_chroma_qp_offset_subdiv = match &sh.ph.as_ref().unwrap().partition_constraints {
+            self.cu_chroma_qp_offset_subdiv = match &sh.picture_header().partition_constraints {
                 Some(pc) => pc.cu_chroma_qp_offset_subdiv_inter_slice,
                 None => {
                     sh.sps
@@ -912,7 +1186,58 @@ impl EncoderContext {
         assert!(self.slice_qp_y >= -self.qp_bd_offset && self.slice_qp_y <= 63);
     }
 
-    pub fn _update_from_ref_pic_lists(&mut self, _ref_pic_lists: Vec<RefPicList>) {}
+    pub fn update_from_ref_pic_lists(
+        &mut self,
+        ref_pic_lists: &[RefPicList; 2],
+        sps: &SequenceParameterSet,
+    ) {
+        self.rpls_idx = (0..2)
+            .map(|i| {
+                if ref_pic_lists[i].rpl_sps_flag {
+                    ref_pic_lists[i].rpl_idx
+                } else {
+                    sps.ref_pic_lists[i].num_ref_pic_list
+                }
+            })
+            .collect();
+        self.num_ltrp_entries = ref_pic_lists
+            .iter()
+            .map(|rpl| {
+                rpl.ref_pic_list_structs
+                    .iter()
+                    .map(|rpls| rpls.num_ltrp_entries())
+                    .collect()
+            })
+            .collect();
+    }
+
+    /// Adds the motion information of an inter CU to the history-based motion vector predictor
+    /// candidate list, from which an identical candidate or the oldest one is removed (8.5.2.16).
+    pub fn update_hmvp_cand_list(&mut self, motion_info: MotionInfo) {
+        if let Some(idx) = self.hmvp_cand_list.iter().position(|&c| c == motion_info) {
+            self.hmvp_cand_list.remove(idx);
+        } else if self.hmvp_cand_list.len() == MAX_NUM_HMVP_CAND {
+            self.hmvp_cand_list.remove(0);
+        }
+        self.hmvp_cand_list.push(motion_info);
+        self.num_hmvp_cand = self.hmvp_cand_list.len();
+    }
+
+    /// Returns true if a block in the clean area could refer to reference samples in the dirty
+    /// area by intra prediction.
+    pub fn is_intra_pred_restricted_by_gdr(&self, x: usize, width: usize, height: usize) -> bool {
+        match self.gdr_clean_area_bd_x {
+            Some(bd_x) => x < bd_x && bd_x < x + 2 * width.max(height),
+            None => false,
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_in_same_tile(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> bool {
+        let log2 = self.ctb_log2_size_y;
+        self.ctb_to_tile_col_bd[x0 >> log2] == self.ctb_to_tile_col_bd[x1 >> log2]
+            && self.ctb_to_tile_row_bd[y0 >> log2] == self.ctb_to_tile_row_bd[y1 >> log2]
+    }
 
     #[inline(always)]
     pub fn derive_neighbouring_block_availability(
@@ -929,8 +1254,6 @@ impl EncoderContext {
         sps: &SequenceParameterSet,
         pps: &PictureParameterSet,
     ) -> bool {
-        let is_in_same_slice = true; // FIXME
-        let is_in_same_tile = true; // FIXME
         let mut available_n = x_nb_y >= 0
             && y_nb_y >= 0
             && x_nb_y < pps.pic_width_in_luma_samples as isize
@@ -941,8 +1264,10 @@ impl EncoderContext {
             //&& self.is_available[c_idx][x_nb_y as usize][y_nb_y as usize]
             && (x_nb_y<x_curr as isize + width as isize || is_above_right_available)
             && (y_nb_y<y_curr as isize + height as isize || is_below_left_available)
-            && is_in_same_slice
-            && is_in_same_tile
+            && self.is_ctb_in_curr_slice[((y_nb_y as usize) >> self.ctb_log2_size_y)
+                * self.pic_width_in_ctbs_y
+                + ((x_nb_y as usize) >> self.ctb_log2_size_y)]
+            && self.is_in_same_tile(x_curr, y_curr, x_nb_y as usize, y_nb_y as usize)
             && (!sps.entropy_coding_sync_enabled_flag
                 || (x_nb_y >> self.ctb_log2_size_y)
                     < (x_curr >> self.ctb_log2_size_y) as isize + 1);
@@ -1059,3 +1384,30 @@ impl EncoderContext {
             && (cb_width * cb_height != 64 || mode_type != ModeType::MODE_TYPE_INTER)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_intra_pred_restricted_by_gdr_works() {
+        // clean area boundary, x, width, height, restricted
+        let cases = [
+            (None, 16, 32, 32, false),
+            (Some(64), 0, 32, 32, false),
+            (Some(64), 16, 32, 32, true),
+            (Some(64), 48, 8, 16, true),
+            (Some(64), 56, 4, 4, false),
+            (Some(64), 60, 4, 4, true),
+            (Some(64), 64, 32, 32, false),
+        ];
+        let mut ectx = EncoderContext::new();
+        for (bd_x, x, width, height, restricted) in cases {
+            ectx.gdr_clean_area_bd_x = bd_x;
+            assert_eq!(
+                ectx.is_intra_pred_restricted_by_gdr(x, width, height),
+                restricted
+            );
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use super::aps::*;
+use super::binary_reader::*;
+use super::binary_writer::*;
+use super::bool_coder::*;
+use super::encoder_context::*;
+use super::nal::*;
+use super::opi_encoder::*;
+use super::ph_parser::*;
+use super::picture_header::*;
+use super::pps::*;
+use super::pps_parser::*;
+use super::sh_parser::*;
+use super::sps::*;
+use super::sps_parser::*;
+use super::vps_parser::*;
+use std::sync::{Arc, Mutex};
+
+/// Extracts the temporal sub-bitstream whose TemporalIds are less than or equal to max_tid from a
+/// byte stream. An OPI NAL unit telling the highest TemporalId left is put at the beginning of
+/// each IRAP or GDR AU when sublayers signalled in the SPS are dropped, replacing the existing one.
+pub fn extract_temporal_sublayers(
+    reader: &mut BinaryReader,
+    writer: &mut BinaryWriter,
+    max_tid: usize,
+) -> Result<(), String> {
+    let ectx = Arc::new(Mutex::new(EncoderContext::new()));
+    let mut coder = BoolCoder::new();
+    let opi = OperatingPointInformation::new(None, Some(max_tid));
+    let opi_bits = {
+        let mut opi_encoder = OpiEncoder::new(&ectx, &mut coder);
+        opi_encoder.encode(&opi)
+    };
+
+    // parameter sets and headers are parsed to find the first VCL NAL unit of each picture
+    let parsed_ectx = Arc::new(Mutex::new(EncoderContext::new()));
+    let aps = [
+        AdaptationParameterSet::new_alf(0),
+        AdaptationParameterSet::new_lmcs(0),
+        AdaptationParameterSet::new_sl(0),
+    ];
+    let mut sps: Option<SequenceParameterSet> = None;
+    let mut pps: Option<PictureParameterSet> = None;
+    let mut ph: Option<PictureHeader> = None;
+    let mut starts_picture = false;
+    // non-VCL NAL units are held until the first VCL NAL unit of their AU is found
+    let mut pending_nal_units: Vec<Vec<u8>> = vec![];
+    for nal_unit in read_byte_stream_nal_units(reader) {
+        let (nuh_layer_id, nal_unit_type, nuh_temporal_id) = read_nal_unit_header(&nal_unit)?;
+        if nuh_temporal_id > max_tid || nal_unit_type == NALUnitType::OPI_NUT {
+            continue;
+        }
+        let rbsp = remove_emulation_prevention_bytes(&nal_unit);
+        let mut rbsp_reader = BinaryReader::vec(&rbsp).unwrap();
+        match nal_unit_type {
+            NALUnitType::VPS_NUT => {
+                let vps = VpsParser::new(&parsed_ectx, &mut rbsp_reader).parse();
+                parsed_ectx.lock().unwrap().update_from_vps(&vps);
+            }
+            NALUnitType::SPS_NUT => {
+                let parsed_sps = SpsParser::new(&parsed_ectx, &mut rbsp_reader).parse();
+                parsed_ectx.lock().unwrap().update_from_sps(&parsed_sps);
+                sps = Some(parsed_sps);
+            }
+            NALUnitType::PPS_NUT => {
+                let sps = sps.as_ref().ok_or("PPS before SPS")?;
+                let parsed_pps = PpsParser::new(&parsed_ectx, &mut rbsp_reader).parse(sps);
+                parsed_ectx
+                    .lock()
+                    .unwrap()
+                    .update_from_sps_and_pps(sps, &parsed_pps);
+                pps = Some(parsed_pps);
+            }
+            NALUnitType::PH_NUT => {
+                let (sps, pps) = get_active_parameter_sets(&sps, &pps)?;
+                let parsed_ph = PhParser::new(&parsed_ectx, &mut rbsp_reader).parse(sps, pps);
+                parsed_ectx
+                    .lock()
+                    .unwrap()
+                    .update_from_ph(&parsed_ph, sps, pps);
+                ph = Some(parsed_ph);
+                starts_picture = true;
+            }
+            _ => {}
+        }
+        if (nal_unit_type as usize) < NALUnitType::OPI_NUT as usize {
+            let picture_header_in_slice_header = {
+                let (sps, pps) = get_active_parameter_sets(&sps, &pps)?;
+                let mut sh_parser = ShParser::new(&parsed_ectx, &mut rbsp_reader);
+                let sh = sh_parser.parse(
+                    nal_unit_type,
+                    sps,
+                    pps,
+                    [&aps[0], &aps[1], &aps[2]],
+                    ph.as_ref(),
+                );
+                sh.ph_in_sh.is_some()
+            };
+            let is_first_vcl_nal_unit = starts_picture || picture_header_in_slice_header;
+            starts_picture = false;
+            let is_irap_or_gdr = (NALUnitType::IDR_W_RADL as usize..=NALUnitType::GDR_NUT as usize)
+                .contains(&(nal_unit_type as usize));
+            if is_first_vcl_nal_unit
+                && is_irap_or_gdr
+                && max_tid + 1 < sps.as_ref().unwrap().max_sublayers
+            {
+                write_byte_stream_nal_unit_bits(
+                    nuh_layer_id,
+                    NALUnitType::OPI_NUT,
+                    0,
+                    &opi_bits,
+                    writer,
+                );
+            }
+            for pending_nal_unit in pending_nal_units.drain(..) {
+                write_byte_stream_nal_unit_bytes(&pending_nal_unit, writer);
+            }
+            write_byte_stream_nal_unit_bytes(&nal_unit, writer);
+        } else {
+            pending_nal_units.push(nal_unit);
+        }
+    }
+    for pending_nal_unit in pending_nal_units.drain(..) {
+        write_byte_stream_nal_unit_bytes(&pending_nal_unit, writer);
+    }
+    Ok(())
+}
+
+pub(crate) fn get_active_parameter_sets<'a>(
+    sps: &'a Option<SequenceParameterSet>,
+    pps: &'a Option<PictureParameterSet>,
+) -> Result<(&'a SequenceParameterSet, &'a PictureParameterSet), String> {
+    match (sps, pps) {
+        (Some(sps), Some(pps)) => Ok((sps, pps)),
+        _ => Err("picture header or slice before parameter sets".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bins::*;
+    use crate::ph_encoder::*;
+    use crate::picture::*;
+    use crate::pps_encoder::*;
+    use crate::slice::*;
+    use crate::slice_encoder::*;
+    use crate::slice_header::*;
+    use crate::sps_encoder::*;
+
+    /// Encodes a stream of intra pictures with the given TemporalIds, where an IDR picture is put
+    /// every 4 pictures.
+    fn encode_stream(temporal_ids: &[usize], ph_in_sh: bool) -> Vec<u8> {
+        let sps = SequenceParameterSet::new(0, 0, 128, 64, 8, 3);
+        let pps = PictureParameterSet::new(0, &sps, None);
+        let aps = [
+            AdaptationParameterSet::new_alf(0),
+            AdaptationParameterSet::new_lmcs(0),
+            AdaptationParameterSet::new_sl(0),
+        ];
+        let mut ectx = EncoderContext::new();
+        ectx.update_from_sps(&sps);
+        ectx.update_from_sps_and_pps(&sps, &pps);
+        let ectx = Arc::new(Mutex::new(ectx));
+        let mut coder = BoolCoder::new();
+        let mut stream = vec![];
+        {
+            let mut writer = BinaryWriter::vec(&mut stream);
+            let sps_bits = SpsEncoder::new(&ectx, &mut coder).encode(&sps);
+            write_byte_stream_nal_unit_bits(0, NALUnitType::SPS_NUT, 0, &sps_bits, &mut writer);
+            let pps_bits = PpsEncoder::new(&ectx, &mut coder).encode(&pps);
+            write_byte_stream_nal_unit_bits(0, NALUnitType::PPS_NUT, 0, &pps_bits, &mut writer);
+            for (poc, &tid) in temporal_ids.iter().enumerate() {
+                let (picture_type, nal_unit_type) = if poc % 4 == 0 {
+                    (PictureType::IRAP_IDR, NALUnitType::IDR_N_LP)
+                } else {
+                    (PictureType::Trailing, NALUnitType::TRAIL_NUT)
+                };
+                let ph = PictureHeader::new(&pps, picture_type, true, poc);
+                ectx.lock().unwrap().update_from_ph(&ph, &sps, &pps);
+                if !ph_in_sh {
+                    let mut bins = Bins::new();
+                    PhEncoder::new(&ectx, &mut coder).encode(&mut bins, &ph, &sps, &pps);
+                    let bits = bins.into_iter().collect::<Vec<bool>>();
+                    write_byte_stream_nal_unit_bits(
+                        0,
+                        NALUnitType::PH_NUT,
+                        tid,
+                        &bits,
+                        &mut writer,
+                    );
+                }
+                let mut sh = {
+                    let ectx = ectx.lock().unwrap();
+                    let ph = if ph_in_sh { None } else { Some(&ph) };
+                    SliceHeader::new(&sps, &pps, [&aps[0], &aps[1], &aps[2]], ph, None, &ectx)
+                };
+                if ph_in_sh {
+                    sh.ph_in_sh = Some(PictureHeader::new(&pps, picture_type, true, poc));
+                }
+                ectx.lock().unwrap().update_from_sh(&sh, &pps);
+                let slice = Slice {
+                    slice_struct: SliceStruct::Rectangle {
+                        tile_col: 0,
+                        tile_row: 0,
+                        num_tile_cols: 1,
+                        num_tile_rows: 1,
+                    },
+                    nal_unit_type,
+                    tiles: Arc::new(Mutex::new(vec![])),
+                };
+                let mut bins = Bins::new();
+                SliceEncoder::new(&ectx, &mut coder).encode_sh(&mut bins, &sh, &slice);
+                let bits = bins.into_iter().collect::<Vec<bool>>();
+                write_byte_stream_nal_unit_bits(0, nal_unit_type, tid, &bits, &mut writer);
+            }
+        }
+        stream
+    }
+
+    /// Returns the NAL unit types and TemporalIds of the NAL units in a stream, together with the
+    /// opi_htid of each OPI NAL unit.
+    fn read_stream(stream: &[u8]) -> Vec<(NALUnitType, usize, Option<usize>)> {
+        let mut reader = BinaryReader::vec(stream).unwrap();
+        read_byte_stream_nal_units(&mut reader)
+            .iter()
+            .map(|nal_unit| {
+                let (_, nal_unit_type, tid) = read_nal_unit_header(nal_unit).unwrap();
+                let opi_htid = if nal_unit_type == NALUnitType::OPI_NUT {
+                    let rbsp = remove_emulation_prevention_bytes(nal_unit);
+                    let mut rbsp_reader = BinaryReader::vec(&rbsp).unwrap();
+                    // opi_ols_info_present_flag and opi_htid_info_present_flag
+                    assert_eq!(rbsp_reader.read_value(2), 1);
+                    // opi_htid_plus1
+                    Some(rbsp_reader.read_value(3) - 1)
+                } else {
+                    None
+                };
+                (nal_unit_type, tid, opi_htid)
+            })
+            .collect()
+    }
+
+    fn extract(stream: &[u8], max_tid: usize) -> Vec<u8> {
+        let mut reader = BinaryReader::vec(stream).unwrap();
+        let mut extracted = vec![];
+        extract_temporal_sublayers(&mut reader, &mut BinaryWriter::vec(&mut extracted), max_tid)
+            .unwrap();
+        extracted
+    }
+
+    fn check_extraction(ph_in_sh: bool) {
+        let temporal_ids = [0, 2, 1, 2, 0, 2, 1, 2];
+        let stream = encode_stream(&temporal_ids, ph_in_sh);
+        let nal_units = read_stream(&stream);
+        for max_tid in 0..3 {
+            let extracted = read_stream(&extract(&stream, max_tid));
+            let (opi_nal_units, other_nal_units): (Vec<_>, Vec<_>) = extracted
+                .iter()
+                .enumerate()
+                .partition(|(_, (nal_unit_type, _, _))| *nal_unit_type == NALUnitType::OPI_NUT);
+            let expected_nal_units = nal_units
+                .iter()
+                .filter(|(_, tid, _)| *tid <= max_tid)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                other_nal_units
+                    .iter()
+                    .map(|(_, nal_unit)| *nal_unit)
+                    .collect::<Vec<_>>(),
+                expected_nal_units
+            );
+            // an OPI NAL unit leads the AU of each IDR picture when sublayers are dropped
+            assert_eq!(opi_nal_units.len(), if max_tid < 2 { 2 } else { 0 });
+            for (i, opi) in opi_nal_units {
+                assert_eq!(*opi, (NALUnitType::OPI_NUT, 0, Some(max_tid)));
+                let first_vcl_nal_unit_type = extracted[i + 1..]
+                    .iter()
+                    .map(|(nal_unit_type, _, _)| *nal_unit_type)
+                    .find(|&nal_unit_type| {
+                        (nal_unit_type as usize) < NALUnitType::OPI_NUT as usize
+                    });
+                assert_eq!(first_vcl_nal_unit_type, Some(NALUnitType::IDR_N_LP));
+                assert!(i == 0 || (extracted[i - 1].0 as usize) < NALUnitType::OPI_NUT as usize);
+            }
+        }
+    }
+
+    #[test]
+    fn extract_temporal_sublayers_works() {
+        check_extraction(false);
+    }
+
+    #[test]
+    fn extract_temporal_sublayers_with_ph_in_sh_works() {
+        check_extraction(true);
+    }
+
+    #[test]
+    fn extract_temporal_sublayers_rejects_broken_nal_unit_header() {
+        // forbidden_zero_bit equal to 1
+        let stream = [0, 0, 0, 1, 0x80, 0x01, 0x00];
+        let mut reader = BinaryReader::vec(&stream).unwrap();
+        let mut extracted = vec![];
+        let mut writer = BinaryWriter::vec(&mut extracted);
+        assert!(extract_temporal_sublayers(&mut reader, &mut writer, 0).is_err());
+    }
+}
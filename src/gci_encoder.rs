@@ -22,7 +22,7 @@ impl<'a> GCIEncoder<'a> {
     }
 
     pub fn encode(&mut self, bins: &mut Bins, gci: &Option<GeneralConstraintsInfo>) {
-        debug_eprint!("gci.present ");
+        trace_syntax_element!(bins, "gci.present ");
         bins.push_bin(gci.is_some());
         if let Some(gci) = gci {
             // general
@@ -0,0 +1,115 @@
+use super::binary_reader::*;
+use super::encoder_context::*;
+use super::gci::*;
+use std::sync::{Arc, Mutex};
+
+pub struct GCIParser<'a, 'b> {
+    reader: &'a mut BinaryReader<'b>,
+    _encoder_context: Arc<Mutex<EncoderContext>>,
+}
+
+impl<'a, 'b> GCIParser<'a, 'b> {
+    pub fn new(
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        reader: &'a mut BinaryReader<'b>,
+    ) -> GCIParser<'a, 'b> {
+        GCIParser {
+            reader,
+            _encoder_context: encoder_context.clone(),
+        }
+    }
+
+    pub fn parse(&mut self) -> Option<GeneralConstraintsInfo> {
+        let present = self.reader.read_bit();
+        let gci = if present {
+            // fields are evaluated in order, which is the order they are signalled in
+            let mut gci = GeneralConstraintsInfo {
+                // general
+                intra_only_constraint_flag: self.reader.read_bit(),
+                all_layers_independent_constraint_flag: self.reader.read_bit(),
+                one_au_only_constraint_flag: self.reader.read_bit(),
+                // picture format
+                sixteen_minus_max_bitdepth_constraint_idc: self.reader.read_value(4),
+                three_minus_max_chroma_format_constraint_idc: self.reader.read_value(2),
+                // NAL unit type related
+                no_mixed_nalu_types_in_pic_constraint_flag: self.reader.read_bit(),
+                no_trail_constraint_flag: self.reader.read_bit(),
+                no_stsa_constraint_flag: self.reader.read_bit(),
+                no_rasl_constraint_flag: self.reader.read_bit(),
+                no_radl_constraint_flag: self.reader.read_bit(),
+                no_idr_constraint_flag: self.reader.read_bit(),
+                no_cra_constraint_flag: self.reader.read_bit(),
+                no_gdr_constraint_flag: self.reader.read_bit(),
+                no_aps_constraint_flag: self.reader.read_bit(),
+                no_idr_rpl_constraint_flag: self.reader.read_bit(),
+                // tile, slice, subpicture partitioning
+                one_tile_per_pic_constraint_flag: self.reader.read_bit(),
+                pic_header_in_slice_header_constraint_flag: self.reader.read_bit(),
+                one_slice_per_pic_constraint_flag: self.reader.read_bit(),
+                no_rectangular_slice_constraint_flag: self.reader.read_bit(),
+                one_slice_per_subpic_constraint_flag: self.reader.read_bit(),
+                no_subpic_info_constraint_flag: self.reader.read_bit(),
+                // CTU and block partitioning
+                three_minus_max_log2_ctu_size_constraint_idc: self.reader.read_value(2),
+                no_partition_constraints_override_constraint_flag: self.reader.read_bit(),
+                no_mtt_constraint_flag: self.reader.read_bit(),
+                no_qtbtt_dual_tree_intra_constraint_flag: self.reader.read_bit(),
+                // intra
+                no_palette_constraint_flag: self.reader.read_bit(),
+                no_ibc_constraint_flag: self.reader.read_bit(),
+                no_isp_constraint_flag: self.reader.read_bit(),
+                no_mrl_constraint_flag: self.reader.read_bit(),
+                no_mip_constraint_flag: self.reader.read_bit(),
+                no_cclm_constraint_flag: self.reader.read_bit(),
+                // inter
+                no_ref_pic_resampling_constraint_flag: self.reader.read_bit(),
+                no_res_change_in_clvs_constraint_flag: self.reader.read_bit(),
+                no_weighted_prediction_constraint_flag: self.reader.read_bit(),
+                no_ref_wraparound_constraint_flag: self.reader.read_bit(),
+                no_temporal_mvp_constraint_flag: self.reader.read_bit(),
+                no_sbtmvp_constraint_flag: self.reader.read_bit(),
+                no_amvr_constraint_flag: self.reader.read_bit(),
+                no_bdof_constraint_flag: self.reader.read_bit(),
+                no_smvd_constraint_flag: self.reader.read_bit(),
+                no_dmvr_constraint_flag: self.reader.read_bit(),
+                no_mmvd_constraint_flag: self.reader.read_bit(),
+                no_affine_motion_constraint_flag: self.reader.read_bit(),
+                no_prof_constraint_flag: self.reader.read_bit(),
+                no_bcw_constraint_flag: self.reader.read_bit(),
+                no_ciip_constraint_flag: self.reader.read_bit(),
+                no_gpm_constraint_flag: self.reader.read_bit(),
+                // transform, quantization, residual
+                no_luma_transform_size_64_constraint_flag: self.reader.read_bit(),
+                no_transform_skip_constraint_flag: self.reader.read_bit(),
+                no_bdpcm_constraint_flag: self.reader.read_bit(),
+                no_mts_constraint_flag: self.reader.read_bit(),
+                no_lfnst_constraint_flag: self.reader.read_bit(),
+                no_joint_cbcr_constraint_flag: self.reader.read_bit(),
+                no_sbt_constraint_flag: self.reader.read_bit(),
+                no_act_constraint_flag: self.reader.read_bit(),
+                no_explicit_scaling_list_constraint_flag: self.reader.read_bit(),
+                no_dep_quant_constraint_flag: self.reader.read_bit(),
+                no_sign_data_hiding_constraint_flag: self.reader.read_bit(),
+                no_cu_qp_delta_constraint_flag: self.reader.read_bit(),
+                no_chroma_qp_offset_constraint_flag: self.reader.read_bit(),
+                // loop filter
+                no_sao_constraint_flag: self.reader.read_bit(),
+                no_alf_constraint_flag: self.reader.read_bit(),
+                no_ccalf_constraint_flag: self.reader.read_bit(),
+                no_lmcs_constraint_flag: self.reader.read_bit(),
+                no_ladf_constraint_flag: self.reader.read_bit(),
+                no_virtual_boundaries_constraint_flag: self.reader.read_bit(),
+                num_reserved_bits: 0,
+            };
+            gci.num_reserved_bits = self.reader.read_value(8);
+            for _ in 0..gci.num_reserved_bits {
+                let _reserved_zero_bit = self.reader.read_bit();
+            }
+            Some(gci)
+        } else {
+            None
+        };
+        self.reader.byte_align();
+        gci
+    }
+}
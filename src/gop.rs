@@ -0,0 +1,145 @@
+use super::common::*;
+use super::picture::*;
+
+// mean absolute luma difference from the previous picture regarded as a scene change
+const SCENE_CUT_THRESHOLD: usize = 32;
+
+pub struct GopController {
+    /// max interval between IRAP pictures
+    pub keyint: usize,
+    /// min interval between IRAP pictures inserted at scene changes
+    pub min_keyint: usize,
+    /// code IRAP pictures except the first one as CRA pictures
+    pub open_gop: bool,
+    /// number of pictures over which a GDR picture refreshes the entire picture, or None to
+    /// insert IRAP pictures instead of GDR pictures
+    pub gdr_period: Option<usize>,
+    /// number of temporal sublayers in the dyadic temporal hierarchy
+    pub num_temporal_layers: usize,
+    pub num_pictures_since_irap: Option<usize>,
+    pub num_pictures_since_gdr: Option<usize>,
+    pub picture_order_count: usize,
+    prev_luma: Option<Vec2d<u8>>,
+}
+
+impl GopController {
+    pub fn new(
+        keyint: usize,
+        min_keyint: usize,
+        open_gop: bool,
+        gdr_period: Option<usize>,
+        num_temporal_layers: usize,
+    ) -> GopController {
+        GopController {
+            keyint,
+            min_keyint,
+            open_gop,
+            gdr_period,
+            num_temporal_layers,
+            num_pictures_since_irap: None,
+            num_pictures_since_gdr: None,
+            picture_order_count: 0,
+            prev_luma: None,
+        }
+    }
+
+    /// Decides the type, the picture order count and the TemporalId of the next picture in coding
+    /// order.
+    pub fn next_picture(&mut self, picture: &Picture) -> (PictureType, usize, usize) {
+        // the first picture and pictures at scene changes are always coded as IRAP pictures
+        let (is_periodic, needs_irap) = match self.num_pictures_since_irap {
+            None => (false, true),
+            Some(n) => (
+                n + 1 >= self.keyint,
+                n + 1 >= self.min_keyint && self.is_scene_cut(picture),
+            ),
+        };
+        self.prev_luma = Some(picture.pixels[0].clone());
+
+        let picture_type = if needs_irap || (is_periodic && self.gdr_period.is_none()) {
+            if self.open_gop && self.num_pictures_since_irap.is_some() {
+                PictureType::IRAP_CRA
+            } else {
+                PictureType::IRAP_IDR
+            }
+        } else if is_periodic {
+            PictureType::GDR
+        } else {
+            PictureType::Trailing
+        };
+        match picture_type {
+            PictureType::IRAP_IDR => {
+                self.picture_order_count = 0;
+            }
+            _ => {
+                self.picture_order_count += 1;
+            }
+        }
+        self.num_pictures_since_irap = match picture_type {
+            PictureType::Trailing => self.num_pictures_since_irap.map(|n| n + 1),
+            _ => Some(0),
+        };
+        self.num_pictures_since_gdr = match picture_type {
+            PictureType::GDR => Some(0),
+            PictureType::Trailing => self
+                .num_pictures_since_gdr
+                .map(|n| n + 1)
+                .filter(|&n| n < self.gdr_period.unwrap()),
+            _ => None,
+        };
+        let temporal_id = self.get_temporal_id(self.num_pictures_since_irap.unwrap());
+        // pictures in a sublayer only refer to pictures in lower sublayers, so each of them can be
+        // a switching point to its sublayer
+        let picture_type = if temporal_id > 0 {
+            PictureType::STLA
+        } else {
+            picture_type
+        };
+        (picture_type, self.picture_order_count, temporal_id)
+    }
+
+    fn get_temporal_id(&self, num_pictures_since_irap: usize) -> usize {
+        let period = 1 << (self.num_temporal_layers - 1);
+        let idx = num_pictures_since_irap % period;
+        if idx == 0 {
+            0
+        } else {
+            self.num_temporal_layers - 1 - idx.trailing_zeros() as usize
+        }
+    }
+
+    /// POC distance from the current GDR picture to its recovery point picture.
+    pub fn recovery_poc_cnt(&self) -> usize {
+        self.gdr_period.map_or(0, |period| period - 1)
+    }
+
+    /// Returns the x coordinate of the boundary between the clean and dirty areas of the current
+    /// picture while refreshing, where the refreshed area grows by CTU columns.
+    pub fn gdr_clean_area_bd_x(&self, picture_width: usize, log2_ctu_size: usize) -> Option<usize> {
+        let n = self.num_pictures_since_gdr?;
+        let period = self.gdr_period.unwrap();
+        let num_ctu_cols = picture_width.div_ceil(1 << log2_ctu_size);
+        let bd_x = ((n + 1) * num_ctu_cols).div_ceil(period) << log2_ctu_size;
+        if bd_x < picture_width {
+            Some(bd_x)
+        } else {
+            None
+        }
+    }
+
+    fn is_scene_cut(&self, picture: &Picture) -> bool {
+        let prev_luma = match &self.prev_luma {
+            Some(prev_luma) => prev_luma,
+            None => return false,
+        };
+        let luma = &picture.pixels[0];
+        let mut sad = 0;
+        for y in (0..luma.height).step_by(2) {
+            for x in (0..luma.width).step_by(2) {
+                sad += (luma[y][x] as isize - prev_luma[y][x] as isize).unsigned_abs();
+            }
+        }
+        let num_samples = luma.height.div_ceil(2) * luma.width.div_ceil(2);
+        sad > SCENE_CUT_THRESHOLD * num_samples
+    }
+}
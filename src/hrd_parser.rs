@@ -0,0 +1,139 @@
+use super::binary_reader::*;
+use super::encoder_context::*;
+use super::timing_hrd::*;
+use std::sync::{Arc, Mutex};
+
+pub struct HrdParser<'a, 'b> {
+    reader: &'a mut BinaryReader<'b>,
+    _encoder_context: Arc<Mutex<EncoderContext>>,
+}
+
+impl<'a, 'b> HrdParser<'a, 'b> {
+    pub fn new(
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        reader: &'a mut BinaryReader<'b>,
+    ) -> HrdParser<'a, 'b> {
+        HrdParser {
+            reader,
+            _encoder_context: encoder_context.clone(),
+        }
+    }
+
+    pub fn parse_general_timing_hrd_parameters(&mut self) -> GeneralTimingHrdParameters {
+        let mut hrd_params = GeneralTimingHrdParameters {
+            num_units_in_tick: self.reader.read_value(32),
+            time_scale: self.reader.read_value(32),
+            general_nal_hrd_params_present_flag: self.reader.read_bit(),
+            general_vcl_hrd_params_present_flag: self.reader.read_bit(),
+            general_same_pic_timing_in_all_ols_flag: false,
+            general_du_hrd_params_present_flag: false,
+            tick_divisor: 2,
+            bit_rate_scale: 0,
+            cpb_size_scale: 0,
+            cpb_size_du_scale: 0,
+            hrd_cpb_cnt: 0,
+        };
+        if hrd_params.general_nal_hrd_params_present_flag
+            || hrd_params.general_vcl_hrd_params_present_flag
+        {
+            hrd_params.general_same_pic_timing_in_all_ols_flag = self.reader.read_bit();
+            hrd_params.general_du_hrd_params_present_flag = self.reader.read_bit();
+            if hrd_params.general_du_hrd_params_present_flag {
+                hrd_params.tick_divisor = self.reader.read_value(8) + 2;
+            }
+            hrd_params.bit_rate_scale = self.reader.read_value(4);
+            hrd_params.cpb_size_scale = self.reader.read_value(4);
+            if hrd_params.general_du_hrd_params_present_flag {
+                hrd_params.cpb_size_du_scale = self.reader.read_value(4);
+            }
+            hrd_params.hrd_cpb_cnt = self.reader.read_unsigned_exp_golomb();
+        }
+        hrd_params
+    }
+
+    /// Parses ols_timing_hrd_parameters( ) into `ols_hrd_params`, which is indexed by sublayer
+    /// and grown as needed.
+    pub fn parse_ols_timing_hrd_parameters(
+        &mut self,
+        ols_hrd_params: &mut Vec<OlsTimingHrdParameter>,
+        general_hrd_params: &GeneralTimingHrdParameters,
+        first_sublayer: usize,
+        max_sublayers_val: usize,
+    ) {
+        while ols_hrd_params.len() < max_sublayers_val + 1 {
+            ols_hrd_params.push(OlsTimingHrdParameter {
+                fixed_pic_rate_general_flag: false,
+                fixed_pic_rate_within_cvs_flag: false,
+                low_delay_hrd_flag: false,
+                elemental_duration_in_tc: 0,
+                sublayer_hrd_parameters: SublayerHrdParameter {
+                    bit_rate_value: vec![],
+                    cpb_size_value: vec![],
+                    cpb_size_du_value: vec![],
+                    bit_rate_du_value: vec![],
+                    cbr_flag: vec![],
+                },
+            });
+        }
+        for param in ols_hrd_params
+            .iter_mut()
+            .take(max_sublayers_val + 1)
+            .skip(first_sublayer)
+        {
+            param.fixed_pic_rate_general_flag = self.reader.read_bit();
+            // inferred to be equal to 1 when fixed_pic_rate_general_flag is equal to 1
+            param.fixed_pic_rate_within_cvs_flag = if !param.fixed_pic_rate_general_flag {
+                self.reader.read_bit()
+            } else {
+                true
+            };
+            if param.fixed_pic_rate_within_cvs_flag {
+                param.elemental_duration_in_tc = self.reader.read_unsigned_exp_golomb();
+            } else if general_hrd_params.general_du_hrd_params_present_flag
+                && general_hrd_params.hrd_cpb_cnt == 1
+            {
+                param.low_delay_hrd_flag = self.reader.read_bit();
+            }
+            // the encoder signals the same parameters for NAL and VCL HRD
+            if general_hrd_params.general_nal_hrd_params_present_flag {
+                param.sublayer_hrd_parameters =
+                    self.parse_sublayer_hrd_parameters(general_hrd_params);
+            }
+            if general_hrd_params.general_vcl_hrd_params_present_flag {
+                param.sublayer_hrd_parameters =
+                    self.parse_sublayer_hrd_parameters(general_hrd_params);
+            }
+        }
+    }
+
+    pub fn parse_sublayer_hrd_parameters(
+        &mut self,
+        general_hrd_params: &GeneralTimingHrdParameters,
+    ) -> SublayerHrdParameter {
+        let mut hrd_params = SublayerHrdParameter {
+            bit_rate_value: vec![],
+            cpb_size_value: vec![],
+            cpb_size_du_value: vec![],
+            bit_rate_du_value: vec![],
+            cbr_flag: vec![],
+        };
+        for _ in 0..general_hrd_params.hrd_cpb_cnt {
+            hrd_params
+                .bit_rate_value
+                .push(self.reader.read_unsigned_exp_golomb());
+            hrd_params
+                .cpb_size_value
+                .push(self.reader.read_unsigned_exp_golomb());
+            if general_hrd_params.general_du_hrd_params_present_flag {
+                hrd_params
+                    .cpb_size_du_value
+                    .push(self.reader.read_unsigned_exp_golomb());
+                hrd_params
+                    .bit_rate_du_value
+                    .push(self.reader.read_unsigned_exp_golomb());
+            }
+            hrd_params.cbr_flag.push(self.reader.read_bit());
+        }
+        hrd_params
+    }
+}
@@ -70,8 +70,9 @@ impl IntraPredictor {
         };
         let tile = tu.get_tile();
         let tile = &mut tile.lock().unwrap();
-        let pred_pixels = &mut tile.pred_pixels.borrow_mut()[c_idx];
-        let reconst_pixels = &tile.reconst_pixels.borrow()[c_idx];
+        let pred_pixels = &mut tile.pred_pixels.lock().unwrap()[c_idx];
+        let tile_reconst_pixels = &tile.reconst_pixels.lock().unwrap();
+        let reconst_pixels = &tile_reconst_pixels[c_idx];
         match intra_pred_mode {
             IntraPredMode::PLANAR => {
                 self.predict_planar(
@@ -111,22 +112,14 @@ impl IntraPredictor {
                         ectx,
                     );
                 } else {
-                    self.predict_cclm(
-                        tu,
-                        c_idx,
-                        pred_pixels,
-                        &tile.reconst_pixels.borrow(),
-                        sps,
-                        pps,
-                        ectx,
-                    );
+                    self.predict_cclm(tu, c_idx, pred_pixels, tile_reconst_pixels, sps, pps, ectx);
                 }
             }
         }
         let (tw, th) = tu.get_component_size(c_idx);
         let (tx, ty) = tu.get_component_pos(c_idx);
-        let original_pixels = &tile.original_pixels.borrow()[c_idx];
-        let residual_pixels = &mut tile.residual_pixels.borrow_mut()[c_idx];
+        let original_pixels = &tile.original_pixels.lock().unwrap()[c_idx];
+        let residual_pixels = &mut tile.residual_pixels.lock().unwrap()[c_idx];
         let residuals = &mut tu.residuals[c_idx];
         for y in ty..ty + th {
             let residuals = &mut residuals[y - ty];
@@ -231,11 +224,6 @@ impl IntraPredictor {
         let ex = ref_w as isize - 1;
         let y_nb_cmp = y_tb_cmp as isize + ay;
         let y_nb_y = y_nb_cmp << chroma_shift;
-        let reconst_pixels = if y_nb_cmp >= 0 {
-            &tile_reconst_pixels[y_nb_cmp as usize]
-        } else {
-            &tile_reconst_pixels[0]
-        };
         for x in sx..=ex {
             let x_nb_cmp = x_tb_cmp as isize + x;
             let (x_tb_y, y_tb_y) = tu.get_component_pos(0);
@@ -256,7 +244,8 @@ impl IntraPredictor {
                 );
             }
             if available {
-                above_ref_samples[(x - sx) as usize] = reconst_pixels[x_nb_cmp as usize] as i16;
+                above_ref_samples[(x - sx) as usize] =
+                    tile_reconst_pixels[y_nb_cmp as usize][x_nb_cmp as usize] as i16;
             }
         }
 
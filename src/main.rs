@@ -4,6 +4,7 @@
 extern crate num;
 #[macro_use]
 extern crate num_derive;
+mod analyzer;
 mod aps;
 mod aps_encoder;
 mod binary_reader;
@@ -14,36 +15,55 @@ mod common;
 mod bins;
 mod block_splitter;
 mod bool_coder;
+mod cabac_decoder;
 mod ctu;
+mod ctu_decoder;
 mod ctu_encoder;
+mod decoder;
 mod dpb;
 mod dpbp_encoder;
+mod dpbp_parser;
 mod encoder_context;
+mod extractor;
 mod gci;
 mod gci_encoder;
+mod gci_parser;
+mod gop;
 mod hrd_encoder;
+mod hrd_parser;
 mod intra_predictor;
+mod motion_compensator;
+mod motion_estimator;
 mod nal;
+mod opi_encoder;
 mod partition;
 mod ph_encoder;
+mod ph_parser;
 mod picture;
 mod picture_header;
 mod pps;
 mod pps_encoder;
+mod pps_parser;
 mod pred_weight_table;
 mod ptl;
 mod ptl_encoder;
+mod ptl_parser;
 mod pwt_encoder;
+mod pwt_parser;
 mod quantizer;
 mod reference_picture;
 mod rpl_encoder;
+mod rpl_parser;
+mod sh_parser;
 mod slice;
 mod slice_encoder;
 mod slice_header;
 mod slice_splitter;
 mod sps;
 mod sps_encoder;
+mod sps_parser;
 mod subpicture;
+mod subpicture_rewriter;
 mod subpicture_splitter;
 mod tile;
 mod tile_splitter;
@@ -52,6 +72,8 @@ mod transformer;
 mod virtual_boundary;
 mod vps;
 mod vps_encoder;
+mod vps_parser;
+use analyzer::*;
 use aps::*;
 //use aps_encoder::*;
 use binary_reader::BinaryReader;
@@ -62,13 +84,19 @@ use clap::Parser;
 use colored::*;
 use common::*;
 use debug_print::*;
+use decoder::*;
+use dpb::*;
 use encoder_context::*;
+use extractor::*;
+use gop::*;
 use nal::*;
 use ph_encoder::*;
-use picture::Picture;
+use picture::{Picture, PictureType};
 use picture_header::*;
 use pps::*;
 use pps_encoder::*;
+use reference_picture::*;
+use slice::*;
 use slice_encoder::*;
 use slice_header::*;
 use slice_splitter::*;
@@ -77,8 +105,10 @@ use sps_encoder::*;
 use std::io::{self, Write};
 use std::process;
 use std::sync::{Arc, Mutex};
+use subpicture_rewriter::*;
 use subpicture_splitter::*;
 use tile_splitter::*;
+use virtual_boundary::*;
 use vps::*;
 use vps_encoder::*;
 
@@ -94,6 +124,9 @@ struct Args {
     /// Path to reconstructed frames
     #[clap(short, long)]
     reconst: Option<String>,
+    /// Path to trace file of coded syntax elements, written by decoding the output bitstream
+    #[clap(long)]
+    trace_file: Option<String>,
     /// Input video resolution (WIDTHxHEIGHT)
     #[clap(long)]
     input_size: String,
@@ -106,6 +139,48 @@ struct Args {
     /// Fixed quantization parameter for entire video stream
     #[clap(long)]
     qp: Option<usize>,
+    /// Max interval between IRAP pictures (1 makes every picture an IRAP picture)
+    #[clap(long, default_value_t = 1)]
+    keyint: usize,
+    /// Min interval between IRAP pictures inserted at scene changes (defaults to keyint)
+    #[clap(long)]
+    min_keyint: Option<usize>,
+    /// Use CRA pictures for IRAP pictures except the first one (open GOP)
+    #[clap(long)]
+    open_gop: bool,
+    /// Refresh pictures gradually over N pictures by GDR instead of inserting IRAP pictures periodically
+    #[clap(long)]
+    gdr_period: Option<usize>,
+    /// Number of temporal sublayers in a dyadic hierarchy (1 to 7)
+    #[clap(long, default_value_t = 1)]
+    temporal_layers: usize,
+    /// Number of uniformly spaced tile columns and rows (COLSxROWS)
+    #[clap(long)]
+    tiles: Option<String>,
+    /// Tile column widths in CTUs (W1[,W2,...]), where the last width is repeated to cover the rest
+    #[clap(long)]
+    tile_column_widths: Option<String>,
+    /// Tile row heights in CTUs (H1[,H2,...]), where the last height is repeated to cover the rest
+    #[clap(long)]
+    tile_row_heights: Option<String>,
+    /// Code CTU rows in parallel by wavefront parallel processing
+    #[clap(long)]
+    wpp: bool,
+    /// Number of slices, each of which has consecutive tiles in raster scan
+    #[clap(long)]
+    slices: Option<usize>,
+    /// Put each tile into its own rectangular slice
+    #[clap(long)]
+    slice_per_tile: bool,
+    /// Split each tile into rectangular slices of N CTU rows
+    #[clap(long)]
+    slice_ctu_rows: Option<usize>,
+    /// Max size in bytes of slice NAL units, where slices in each tile are ended at CTU rows during encoding
+    #[clap(long)]
+    max_slice_bytes: Option<usize>,
+    /// Number of subpicture columns and rows in a uniform grid over tiles (COLSxROWS), where each subpicture is a single slice coded independently
+    #[clap(long)]
+    subpics: Option<String>,
     /// Max split depth of coding trees to search
     #[clap(long, default_value_t = 3)]
     max_split_depth: usize,
@@ -114,7 +189,282 @@ struct Args {
     extra_params: Option<String>,
 }
 
+/// Extracts temporal sublayers or a subpicture from a bitstream
+#[derive(Parser, Debug)]
+#[clap(name = "extract", bin_name = "wrenc extract")]
+struct ExtractArgs {
+    /// Path to input bitstream
+    #[clap(short, long)]
+    input: String,
+    /// Path to output bitstream
+    #[clap(short, long)]
+    output: String,
+    /// Highest TemporalId to keep
+    #[clap(long)]
+    max_tid: Option<usize>,
+    /// Index of the subpicture to extract as a standalone bitstream
+    #[clap(long)]
+    subpic_idx: Option<usize>,
+}
+
+/// Merges pictures of bitstreams with subpictures into pictures
+#[derive(Parser, Debug)]
+#[clap(name = "merge", bin_name = "wrenc merge")]
+struct MergeArgs {
+    /// Paths to input bitstreams, whose pictures are placed in raster scan order of the grid
+    #[clap(short, long, required = true, multiple_occurrences = true)]
+    input: Vec<String>,
+    /// Path to output bitstream
+    #[clap(short, long)]
+    output: String,
+    /// Number of columns and rows of input pictures (COLSxROWS), where all inputs are put in a row by default
+    #[clap(long)]
+    grid: Option<String>,
+}
+
+/// Decodes a bitstream of intra pictures into reconstructed pictures
+#[derive(Parser, Debug)]
+#[clap(name = "decode", bin_name = "wrenc decode")]
+struct DecodeArgs {
+    /// Path to input bitstream
+    #[clap(short, long)]
+    input: String,
+    /// Path to output reconstructed pictures
+    #[clap(short, long)]
+    output: String,
+    /// Path to trace file of coded syntax elements
+    #[clap(long)]
+    trace_file: Option<String>,
+}
+
+/// Lists the NAL units of a bitstream with their parameter sets and headers, and the bits of each
+/// picture
+#[derive(Parser, Debug)]
+#[clap(name = "analyze", bin_name = "wrenc analyze")]
+struct AnalyzeArgs {
+    /// Path to input bitstream
+    input: String,
+    /// Output in JSON
+    #[clap(long)]
+    json: bool,
+}
+
+fn extract(args: ExtractArgs) {
+    match (args.max_tid, args.subpic_idx) {
+        (Some(max_tid), None) if max_tid > 6 => {
+            eprintln!("{}: Invalid max-tid: {}", "error".red(), max_tid);
+            process::exit(0);
+        }
+        (Some(_), Some(_)) => {
+            eprintln!(
+                "{}: max-tid and subpic-idx cannot be used together",
+                "error".red()
+            );
+            process::exit(0);
+        }
+        (None, None) => {
+            eprintln!(
+                "{}: either max-tid or subpic-idx is required",
+                "error".red()
+            );
+            process::exit(0);
+        }
+        _ => {}
+    }
+
+    let stdin = io::stdin();
+    let mut reader = if args.input == *"-" {
+        BinaryReader::standard(&stdin)
+    } else {
+        match BinaryReader::file(args.input) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("{}: failed to open input file: {}", "error".red(), e);
+                process::exit(0);
+            }
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut writer = if args.output == *"-" {
+        BinaryWriter::standard(&stdout)
+    } else {
+        match BinaryWriter::file(args.output) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("{}: failed to open output file: {}", "error".red(), e);
+                process::exit(0);
+            }
+        }
+    };
+
+    if let Some(max_tid) = args.max_tid {
+        if let Err(e) = extract_temporal_sublayers(&mut reader, &mut writer, max_tid) {
+            eprintln!("{}: {}", "error".red(), e);
+            process::exit(0);
+        }
+    } else if let Some(subpic_idx) = args.subpic_idx {
+        if let Err(e) = extract_subpicture(&mut reader, &mut writer, subpic_idx) {
+            eprintln!("{}: {}", "error".red(), e);
+            process::exit(0);
+        }
+    }
+}
+
+fn merge(args: MergeArgs) {
+    let num_inputs = args.input.len();
+    let num_cols = match &args.grid {
+        Some(grid) => {
+            let grid_size = grid
+                .split('x')
+                .map(|x| x.parse::<usize>())
+                .collect::<Vec<Result<usize, std::num::ParseIntError>>>();
+            match grid_size[..] {
+                [Ok(num_cols), Ok(num_rows)] if num_cols * num_rows == num_inputs => num_cols,
+                _ => {
+                    eprintln!(
+                        "{}: Invalid grid for {} inputs: {}",
+                        "error".red(),
+                        num_inputs,
+                        grid
+                    );
+                    process::exit(0);
+                }
+            }
+        }
+        None => num_inputs,
+    };
+
+    let stdin = io::stdin();
+    let mut readers = args
+        .input
+        .iter()
+        .map(|input| {
+            if input == "-" {
+                BinaryReader::standard(&stdin)
+            } else {
+                match BinaryReader::file(input.clone()) {
+                    Ok(f) => f,
+                    Err(e) => {
+                        eprintln!("{}: failed to open input file: {}", "error".red(), e);
+                        process::exit(0);
+                    }
+                }
+            }
+        })
+        .collect::<Vec<BinaryReader>>();
+
+    let stdout = io::stdout();
+    let mut writer = if args.output == *"-" {
+        BinaryWriter::standard(&stdout)
+    } else {
+        match BinaryWriter::file(args.output) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("{}: failed to open output file: {}", "error".red(), e);
+                process::exit(0);
+            }
+        }
+    };
+
+    if let Err(e) = merge_subpictures(&mut readers, &mut writer, num_cols) {
+        eprintln!("{}: {}", "error".red(), e);
+        process::exit(0);
+    }
+}
+
+fn decode(args: DecodeArgs) {
+    let stdin = io::stdin();
+    let mut reader = if args.input == *"-" {
+        BinaryReader::standard(&stdin)
+    } else {
+        match BinaryReader::file(args.input) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("{}: failed to open input file: {}", "error".red(), e);
+                process::exit(0);
+            }
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut writer = if args.output == *"-" {
+        BinaryWriter::standard(&stdout)
+    } else {
+        match BinaryWriter::file(args.output) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("{}: failed to open output file: {}", "error".red(), e);
+                process::exit(0);
+            }
+        }
+    };
+
+    let mut trace_writer = open_trace_writer(args.trace_file);
+
+    if let Err(e) = decode_byte_stream(&mut reader, Some(&mut writer), trace_writer.as_mut()) {
+        eprintln!("{}: {}", "error".red(), e);
+        process::exit(0);
+    }
+}
+
+fn open_trace_writer<'a>(trace_file: Option<String>) -> Option<BinaryWriter<'a>> {
+    trace_file.map(|path| match BinaryWriter::file(path) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("{}: failed to open trace file: {}", "error".red(), e);
+            process::exit(0);
+        }
+    })
+}
+
+fn analyze(args: AnalyzeArgs) {
+    let stdin = io::stdin();
+    let mut reader = if args.input == *"-" {
+        BinaryReader::standard(&stdin)
+    } else {
+        match BinaryReader::file(args.input) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("{}: failed to open input file: {}", "error".red(), e);
+                process::exit(0);
+            }
+        }
+    };
+
+    match analyze_byte_stream(&mut reader) {
+        Ok(analysis) => {
+            if args.json {
+                print!("{}", analysis.to_json());
+            } else {
+                print!("{}", analysis.to_text());
+            }
+        }
+        Err(e) => {
+            eprintln!("{}: {}", "error".red(), e);
+            process::exit(0);
+        }
+    }
+}
+
 fn main() {
+    if std::env::args().nth(1).as_deref() == Some("extract") {
+        extract(ExtractArgs::parse_from(std::env::args().skip(1)));
+        return;
+    }
+    if std::env::args().nth(1).as_deref() == Some("merge") {
+        merge(MergeArgs::parse_from(std::env::args().skip(1)));
+        return;
+    }
+    if std::env::args().nth(1).as_deref() == Some("decode") {
+        decode(DecodeArgs::parse_from(std::env::args().skip(1)));
+        return;
+    }
+    if std::env::args().nth(1).as_deref() == Some("analyze") {
+        analyze(AnalyzeArgs::parse_from(std::env::args().skip(1)));
+        return;
+    }
+
     let args = Args::parse();
 
     let mut ectx = EncoderContext::new();
@@ -135,6 +485,7 @@ fn main() {
 
     // initialize binary writer
     let stdout = io::stdout();
+    let output_path = args.output.clone();
     let mut writer = if args.output == *"-" {
         BinaryWriter::standard(&stdout)
     } else {
@@ -160,6 +511,16 @@ fn main() {
         None
     };
 
+    // initialize trace writer, where the trace is written by decoding the output bitstream
+    if args.trace_file.is_some() && output_path == *"-" {
+        eprintln!(
+            "{}: --trace-file requires the output bitstream to be a file",
+            "error".red()
+        );
+        process::exit(0);
+    }
+    let mut trace_writer = open_trace_writer(args.trace_file);
+
     let input_size = args
         .input_size
         .split('x')
@@ -199,6 +560,177 @@ fn main() {
 
     ectx.max_split_depth = args.max_split_depth;
 
+    if args.keyint == 0 {
+        eprintln!("{}: Invalid keyint: {}", "error".red(), args.keyint);
+        process::exit(0);
+    }
+    let min_keyint = args.min_keyint.unwrap_or(args.keyint);
+    if min_keyint == 0 || min_keyint > args.keyint {
+        eprintln!("{}: Invalid min-keyint: {}", "error".red(), min_keyint);
+        process::exit(0);
+    }
+    if let Some(gdr_period) = args.gdr_period {
+        if gdr_period == 0 || gdr_period > args.keyint || gdr_period > 1 << 15 {
+            eprintln!("{}: Invalid gdr-period: {}", "error".red(), gdr_period);
+            process::exit(0);
+        }
+    }
+    if args.temporal_layers == 0 || args.temporal_layers > 7 {
+        eprintln!(
+            "{}: Invalid temporal-layers: {}",
+            "error".red(),
+            args.temporal_layers
+        );
+        process::exit(0);
+    }
+    // all sublayers signalled in the VPS and the SPS appear only when the dyadic hierarchy fits
+    // in an IRAP period
+    if args.keyint < 1 << (args.temporal_layers - 1) {
+        eprintln!(
+            "{}: temporal-layers {} requires keyint of at least {}",
+            "error".red(),
+            args.temporal_layers,
+            1 << (args.temporal_layers - 1)
+        );
+        process::exit(0);
+    }
+    let mut gop_controller = GopController::new(
+        args.keyint,
+        min_keyint,
+        args.open_gop,
+        args.gdr_period,
+        args.temporal_layers,
+    );
+
+    let num_subpics = args.subpics.as_ref().map(|subpics| {
+        let num_subpics = subpics
+            .split('x')
+            .map(|x| x.parse::<usize>())
+            .collect::<Vec<Result<usize, std::num::ParseIntError>>>();
+        match num_subpics[..] {
+            [Ok(num_subpic_cols), Ok(num_subpic_rows)]
+                if num_subpic_cols > 0 && num_subpic_rows > 0 =>
+            {
+                (num_subpic_cols, num_subpic_rows)
+            }
+            _ => {
+                eprintln!("{}: Invalid subpics: {}", "error".red(), subpics);
+                process::exit(0);
+            }
+        }
+    });
+    if args.gdr_period.is_some() && num_subpics.is_some() {
+        // the refresh column is signalled as a virtual boundary in picture headers, which does not
+        // fit subpictures extracted from the pictures
+        eprintln!(
+            "{}: gdr-period and subpics cannot be used together",
+            "error".red()
+        );
+        process::exit(0);
+    }
+
+    let tile_splitter: Box<dyn TileSplitter> = if let Some(tiles) = &args.tiles {
+        if args.tile_column_widths.is_some() || args.tile_row_heights.is_some() {
+            eprintln!(
+                "{}: tiles cannot be used with tile-column-widths or tile-row-heights",
+                "error".red()
+            );
+            process::exit(0);
+        }
+        let num_tiles = tiles
+            .split('x')
+            .map(|x| x.parse::<usize>())
+            .collect::<Vec<Result<usize, std::num::ParseIntError>>>();
+        if let [Ok(num_tile_cols), Ok(num_tile_rows)] = num_tiles[..] {
+            if num_tile_cols == 0 || num_tile_rows == 0 {
+                eprintln!("{}: Invalid tiles: {}", "error".red(), tiles);
+                process::exit(0);
+            }
+            Box::new(UniformTileSplitter {
+                num_tile_cols,
+                num_tile_rows,
+            })
+        } else {
+            eprintln!("{}: Invalid tiles: {}", "error".red(), tiles);
+            process::exit(0);
+        }
+    } else if args.tile_column_widths.is_some() || args.tile_row_heights.is_some() {
+        let parse_tile_sizes = |name: &str, sizes: &Option<String>| match sizes {
+            Some(sizes) => {
+                let parsed = sizes
+                    .split(',')
+                    .map(|x| x.parse::<usize>())
+                    .collect::<Result<Vec<usize>, std::num::ParseIntError>>();
+                match parsed {
+                    Ok(parsed) if !parsed.contains(&0) => parsed,
+                    _ => {
+                        eprintln!("{}: Invalid {}: {}", "error".red(), name, sizes);
+                        process::exit(0);
+                    }
+                }
+            }
+            None => vec![],
+        };
+        Box::new(ExplicitTileSplitter {
+            tile_column_widths: parse_tile_sizes("tile-column-widths", &args.tile_column_widths),
+            tile_row_heights: parse_tile_sizes("tile-row-heights", &args.tile_row_heights),
+        })
+    } else if let Some((num_subpic_cols, num_subpic_rows)) = num_subpics {
+        Box::new(UniformTileSplitter {
+            num_tile_cols: num_subpic_cols,
+            num_tile_rows: num_subpic_rows,
+        })
+    } else {
+        Box::new(UnitTileSplitter {})
+    };
+
+    if [
+        args.slices.is_some(),
+        args.slice_per_tile,
+        args.slice_ctu_rows.is_some(),
+        args.max_slice_bytes.is_some(),
+        args.subpics.is_some(),
+    ]
+    .iter()
+    .filter(|&&x| x)
+    .count()
+        > 1
+    {
+        eprintln!(
+            "{}: slices, slice-per-tile, slice-ctu-rows, max-slice-bytes and subpics cannot be used together",
+            "error".red()
+        );
+        process::exit(0);
+    }
+    let slice_splitter: Box<dyn SliceSplitter> = if let Some(num_slices) = args.slices {
+        if num_slices == 0 {
+            eprintln!("{}: slices must be greater than 0", "error".red());
+            process::exit(0);
+        }
+        Box::new(RasterSliceSplitter { num_slices })
+    } else if args.slice_per_tile {
+        Box::new(TileSliceSplitter {})
+    } else if let Some(num_ctu_rows) = args.slice_ctu_rows {
+        if num_ctu_rows == 0 {
+            eprintln!("{}: slice-ctu-rows must be greater than 0", "error".red());
+            process::exit(0);
+        }
+        Box::new(CtuRowSliceSplitter { num_ctu_rows })
+    } else if let Some(max_slice_bytes) = args.max_slice_bytes {
+        if max_slice_bytes == 0 {
+            eprintln!("{}: max-slice-bytes must be greater than 0", "error".red());
+            process::exit(0);
+        }
+        Box::new(MaxBytesSliceSplitter { max_slice_bytes })
+    } else if let Some((num_subpic_cols, num_subpic_rows)) = num_subpics {
+        Box::new(SubpictureSliceSplitter {
+            num_subpic_cols,
+            num_subpic_rows,
+        })
+    } else {
+        Box::new(UnitSliceSplitter {})
+    };
+
     if let Some(extra_params) = args.extra_params {
         for param in extra_params.split(',') {
             let param = param.split('=').collect::<Vec<&str>>();
@@ -220,44 +752,143 @@ fn main() {
     let mut coder = BoolCoder::new();
     let ectx = Arc::new(Mutex::new(ectx));
 
-    let vps = VideoParameterSet::new(8, output_width, output_height, 8, ChromaFormat::YCbCr420);
+    let vps = VideoParameterSet::new(
+        8,
+        output_width,
+        output_height,
+        8,
+        ChromaFormat::YCbCr420,
+        args.temporal_layers,
+    );
     vps.validate();
-    {
+    let vps_bits = {
         {
             let ectx = &mut ectx.lock().unwrap();
             ectx.update_from_vps(&vps);
         }
         let mut vps_encoder = VpsEncoder::new(&ectx, &mut coder);
-        let vps_bits = vps_encoder.encode(&vps);
-        debug_eprintln!("vps bits {}", vps_bits.len());
-        write_byte_stream_nal_unit_bits(1, NALUnitType::VPS_NUT, 0, &vps_bits, &mut writer);
-    }
-    debug_eprintln!("vps end");
+        vps_encoder.encode(&vps)
+    };
+    debug_eprintln!("vps bits {}", vps_bits.len());
 
-    let sps = SequenceParameterSet::new(1, 8, output_width, output_height, 8);
-    {
+    let mut sps =
+        SequenceParameterSet::new(1, 8, output_width, output_height, 8, args.temporal_layers);
+    if let Some(gdr_period) = args.gdr_period {
+        sps.gdr_enabled_flag = true;
+        // in-loop filters do not cross the boundary between the clean and dirty areas while
+        // refreshing, which is signalled as a virtual boundary in picture headers
+        sps.virtual_boundaries_enabled_flag = true;
+        // ph_recovery_poc_cnt shall be less than MaxPicOrderCntLsb
+        sps.log2_max_pic_order_cnt_lsb = sps
+            .log2_max_pic_order_cnt_lsb
+            .max(gdr_period.ilog2() as usize + 1);
+    }
+    // the POC of a picture is derived from the previous TemporalId 0 picture, which can be
+    // 2^(temporal_layers - 1) pictures away, and the distance shall be less than half of
+    // MaxPicOrderCntLsb
+    sps.log2_max_pic_order_cnt_lsb = sps.log2_max_pic_order_cnt_lsb.max(args.temporal_layers + 1);
+    let num_ctu_cols = output_width.div_ceil(1 << sps.log2_ctu_size);
+    let num_ctu_rows = output_height.div_ceil(1 << sps.log2_ctu_size);
+    let (tile_ctu_cols, tile_ctu_rows) =
+        tile_splitter.get_ctu_cols_and_rows(num_ctu_cols, num_ctu_rows);
+    let tile_row_heights = tile_ctu_rows
+        .iter()
+        .zip(tile_ctu_rows.iter().skip(1).chain([num_ctu_rows].iter()))
+        .map(|(top, bottom)| bottom - top)
+        .collect::<Vec<usize>>();
+    if let Some(num_slices) = args.slices {
+        let num_tiles = tile_ctu_cols.len() * tile_ctu_rows.len();
+        if num_slices > num_tiles {
+            eprintln!(
+                "{}: slices ({}) must not exceed the number of tiles ({})",
+                "error".red(),
+                num_slices,
+                num_tiles
+            );
+            process::exit(0);
+        }
+    }
+    if let Some((num_subpic_cols, num_subpic_rows)) = num_subpics {
+        if num_subpic_cols > tile_ctu_cols.len() || num_subpic_rows > tile_ctu_rows.len() {
+            eprintln!(
+                "{}: subpics ({}x{}) must not exceed the number of tile columns and rows ({}x{})",
+                "error".red(),
+                num_subpic_cols,
+                num_subpic_rows,
+                tile_ctu_cols.len(),
+                tile_ctu_rows.len()
+            );
+            process::exit(0);
+        }
+    }
+    let slice_types = slice_splitter.get_slice_types(tile_ctu_cols.len(), &tile_row_heights);
+    if num_subpics.is_some() {
+        let subpic_rects = slice_types
+            .iter()
+            .map(|slice_struct| match *slice_struct {
+                SliceStruct::Rectangle {
+                    tile_col,
+                    tile_row,
+                    num_tile_cols,
+                    num_tile_rows,
+                } => {
+                    let x = tile_ctu_cols[tile_col];
+                    let y = tile_ctu_rows[tile_row];
+                    let right = *tile_ctu_cols
+                        .get(tile_col + num_tile_cols)
+                        .unwrap_or(&num_ctu_cols);
+                    let bottom = *tile_ctu_rows
+                        .get(tile_row + num_tile_rows)
+                        .unwrap_or(&num_ctu_rows);
+                    (x, y, right - x, bottom - y)
+                }
+                _ => unreachable!(),
+            })
+            .collect::<Vec<(usize, usize, usize, usize)>>();
+        sps.set_subpics(&subpic_rects);
+    }
+    sps.entropy_coding_sync_enabled_flag = args.wpp;
+    // tiles and CTU rows with WPP are coded in parallel, so entry points to them are signalled
+    // in slice headers
+    sps.entry_point_offsets_present_flag =
+        tile_ctu_cols.len() * tile_ctu_rows.len() > 1 || args.wpp;
+    let sps_bits = {
         {
             let ectx = &mut ectx.lock().unwrap();
             ectx.update_from_sps(&sps);
         }
         let mut sps_encoder = SpsEncoder::new(&ectx, &mut coder);
-        let sps_bits = sps_encoder.encode(&sps);
-        debug_eprintln!("sps bits {}", sps_bits.len());
-        write_byte_stream_nal_unit_bits(9, NALUnitType::SPS_NUT, 0, &sps_bits, &mut writer);
-    }
-    debug_eprintln!("sps end");
+        sps_encoder.encode(&sps)
+    };
+    debug_eprintln!("sps bits {}", sps_bits.len());
 
-    let pps = PictureParameterSet::new(1, &sps, fixed_qp.map(|x| x as isize));
-    {
+    let mut pps = PictureParameterSet::new(1, &sps, fixed_qp.map(|x| x as isize));
+    pps.set_tiles(
+        sps.log2_ctu_size,
+        &tile_ctu_cols,
+        &tile_ctu_rows,
+        num_ctu_cols,
+        num_ctu_rows,
+    );
+    // each subpicture is a single slice as pps_single_slice_per_subpic_flag is set with tiles
+    if num_subpics.is_none() {
+        if let Err(e) = pps.set_slices(&slice_types, tile_ctu_cols.len(), &tile_row_heights) {
+            eprintln!("{}: {}", "error".red(), e);
+            process::exit(0);
+        }
+    }
+    let pps_bits = {
         {
             let ectx = &mut ectx.lock().unwrap();
             ectx.update_from_sps_and_pps(&sps, &pps);
         }
         let mut pps_encoder = PpsEncoder::new(&ectx, &mut coder);
-        let pps_bits = pps_encoder.encode(&pps);
-        write_byte_stream_nal_unit_bits(9, NALUnitType::PPS_NUT, 0, &pps_bits, &mut writer);
-    }
-    debug_eprintln!("pps end");
+        pps_encoder.encode(&pps)
+    };
+    debug_eprintln!("pps bits {}", pps_bits.len());
+    let mut last_pps_bits = pps_bits.clone();
+    // reconstructed pictures which the following pictures may refer to
+    let mut dpb = DecodedPictureBuffer::new(&sps.dpb_parameters[sps.max_sublayers - 1]);
 
     let alf_aps = AdaptationParameterSet::new_alf(1);
     let lmcs_aps = AdaptationParameterSet::new_lmcs(2);
@@ -291,30 +922,7 @@ fn main() {
     }
     debug_eprintln!("aps end");
 
-    for picture_index in 0..args.num_pictures {
-        //eprintln!("picture #{}", picture_index);
-        let intra = true;
-        let ph = PictureHeader::new(&pps, intra, picture_index);
-        let nuh_layer_id = 9;
-        {
-            {
-                let ectx = &mut ectx.lock().unwrap();
-                ectx.update_from_ph(&ph, &pps);
-            }
-            let mut ph_encoder = PhEncoder::new(&ectx, &mut coder);
-            let mut ph_bins = Bins::new();
-            ph_encoder.encode(&mut ph_bins, &ph, &sps, &pps);
-            let ph_bins = ph_bins.into_iter().collect();
-            let nuh_temporal_id = 0;
-            write_byte_stream_nal_unit_bits(
-                nuh_layer_id,
-                NALUnitType::PH_NUT,
-                nuh_temporal_id,
-                &ph_bins,
-                &mut writer,
-            );
-        }
-
+    for _ in 0..args.num_pictures {
         let mut picture = Picture::new(output_width, output_height, fixed_qp);
         {
             let mut luma = vec![0; output_height * output_width];
@@ -349,47 +957,209 @@ fn main() {
             }
         }
 
-        let default_log2_ctu_size = 5;
-        picture.init_ctus(default_log2_ctu_size);
-        let (ctu_cols, ctu_rows) = UnitTileSplitter {}.get_ctu_cols_and_rows(&picture);
+        let (picture_type, picture_order_count, nuh_temporal_id) =
+            gop_controller.next_picture(&picture);
+        picture.picture_type = picture_type;
+        picture.picture_order_count = picture_order_count;
+        let nal_unit_type = picture_type.nal_unit_type();
+        let nuh_layer_id = 9;
+
+        if picture_type.is_irap() {
+            dpb.clear();
+        } else if picture_type == PictureType::GDR {
+            // the clean area of a refreshing picture never refers to the preceding pictures
+            for decoded in dpb.pictures.iter_mut() {
+                decoded.picture.clean_area_bd_x = 0;
+            }
+        }
+        // a picture keeps the nearest picture of each of its sublayer or lower ones in its RPLs,
+        // and a switching point to a sublayer only those of the lower ones, with the nearest one
+        // first
+        let ref_pictures = dpb.get_nearest_pictures(nuh_temporal_id.max(1));
+        let intra = ref_pictures.is_empty();
+        let pic_order_cnt_lsb = picture_order_count % ectx.lock().unwrap().max_pic_order_cnt_lsb;
+        let mut ph = PictureHeader::new(&pps, picture_type, intra, pic_order_cnt_lsb);
+        if picture_type == PictureType::GDR {
+            ph.recovery_poc_cnt = gop_controller.recovery_poc_cnt();
+        }
+        let gdr_clean_area_bd_x =
+            gop_controller.gdr_clean_area_bd_x(output_width, sps.log2_ctu_size);
+        if let Some(bd_x) = gdr_clean_area_bd_x {
+            ph.virtual_boundary = VirtualBoundaryParameters::new_vertical(bd_x);
+        }
+        {
+            let ectx = &mut ectx.lock().unwrap();
+            ectx.update_from_ph(&ph, &sps, &pps);
+            ectx.gdr_clean_area_bd_x = gdr_clean_area_bd_x;
+            ectx.pic_order_cnt_val = picture_order_count as isize;
+            ectx.ref_pictures = [ref_pictures.clone(), ref_pictures];
+            // the pictures in the DPB are marked by the RPLs written in the slice headers, and
+            // the inter search refers to their entries
+            let ref_pic_lists = SliceHeader::get_ref_pic_lists(Some(&ph), ectx);
+            ectx.update_from_ref_pic_lists(&ref_pic_lists, &sps);
+            let poc = ectx.pic_order_cnt_val;
+            let max_pic_order_cnt_lsb = ectx.max_pic_order_cnt_lsb;
+            let ref_pictures = dpb
+                .mark(poc, &ref_pic_lists, &ectx.rpls_idx, max_pic_order_cnt_lsb)
+                .and_then(|_| {
+                    dpb.get_ref_pictures(poc, &ref_pic_lists, &ectx.rpls_idx, max_pic_order_cnt_lsb)
+                });
+            match ref_pictures {
+                Ok(ref_pictures) => ectx.ref_pictures = ref_pictures,
+                Err(e) => panic!("{e}"),
+            }
+        }
+
+        picture.init_ctus(sps.log2_ctu_size);
         debug_eprintln!("pre init tiles");
-        picture.init_tiles(ctu_cols, ctu_rows);
-        let slice_types = UnitSliceSplitter {}.get_slice_types(&picture);
-        picture.init_slices(slice_types, NALUnitType::IDR_W_RADL);
-        let slice_index_groups =
-            UnitSubpictureSplitter {}.get_subpicture_slice_index_groups(&picture);
+        picture.init_tiles(tile_ctu_cols.clone(), tile_ctu_rows.clone());
+        picture.init_slices(slice_types.clone(), nal_unit_type);
+        let subpicture_splitter: Box<dyn SubpictureSplitter> = if num_subpics.is_some() {
+            Box::new(SlicePerSubpictureSplitter {})
+        } else {
+            Box::new(UnitSubpictureSplitter {})
+        };
+        let slice_index_groups = subpicture_splitter.get_subpicture_slice_index_groups(&picture);
         picture.init_subpictures(slice_index_groups);
 
-        let slices = picture.slices.lock().unwrap();
-        for slice in slices.iter() {
-            let sh = {
-                let ectx = &ectx.lock().unwrap();
-                SliceHeader::new(
-                    &sps,
-                    &pps,
-                    [&alf_aps, &lmcs_aps, &sl_aps],
-                    Some(&ph),
-                    fixed_qp.map(|x| x as isize),
-                    ectx,
-                )
-            };
+        let new_sh = |slice: &Slice, slice_idx: usize| {
+            let ectx = &mut ectx.lock().unwrap();
+            let mut sh = SliceHeader::new(
+                &sps,
+                &pps,
+                [&alf_aps, &lmcs_aps, &sl_aps],
+                Some(&ph),
+                fixed_qp.map(|x| x as isize),
+                ectx,
+            );
+            sh.set_slice_address(slice, slice_idx, ectx);
+            ectx.update_from_sh(&sh, &pps);
+            sh
+        };
+        let num_tile_cols = tile_ctu_cols.len();
+        let mut slice_bins = vec![];
+        let slices = picture.slices.lock().unwrap().clone();
+        // with slices ended during encoding, the slices in the PPS are those of the picture
+        let (picture_pps, picture_pps_bits) = if let Some(max_slice_bytes) =
+            slice_splitter.get_max_slice_bytes()
+        {
+            let mut slice_types = vec![];
+            let mut slice_substreams = vec![];
+            for (slice_idx, slice) in slices.iter().enumerate() {
+                let slice = slice.lock().unwrap();
+                let sh = new_sh(&slice, slice_idx);
+                let (tile_col, tile_row) = match slice.slice_struct {
+                    SliceStruct::Rectangle {
+                        tile_col, tile_row, ..
+                    } => (tile_col, tile_row),
+                    _ => unreachable!(),
+                };
+                let (slice_heights, substreams): (Vec<usize>, Vec<Vec<Bins>>) =
+                    SliceEncoder::new(&ectx, &mut coder)
+                        .encode_tile_within_bytes(&slice, &sh, max_slice_bytes)
+                        .into_iter()
+                        .unzip();
+                slice_types.extend(get_tile_slice_types(tile_col, tile_row, &slice_heights));
+                slice_substreams.extend(substreams);
+            }
+            let mut picture_pps = pps.clone();
+            if let Err(e) = picture_pps.set_slices(&slice_types, num_tile_cols, &tile_row_heights) {
+                panic!("{e}");
+            }
             {
                 let ectx = &mut ectx.lock().unwrap();
-                ectx.update_from_sh(&sh, &pps);
+                ectx.update_from_sps_and_pps(&sps, &picture_pps);
             }
-            let mut slice_encoder = SliceEncoder::new(&ectx, &mut coder);
-            let slice = slice.lock().unwrap();
-            let slice_bins = slice_encoder.encode(&slice, &sh);
-            write_byte_stream_nal_unit_bins(
+            picture.init_slices(slice_types, nal_unit_type);
+            let slices = picture.slices.lock().unwrap().clone();
+            for (slice_idx, (slice, substreams)) in slices.iter().zip(slice_substreams).enumerate()
+            {
+                let slice = slice.lock().unwrap();
+                let mut sh = {
+                    let ectx = &mut ectx.lock().unwrap();
+                    let mut sh = SliceHeader::new(
+                        &sps,
+                        &picture_pps,
+                        [&alf_aps, &lmcs_aps, &sl_aps],
+                        Some(&ph),
+                        fixed_qp.map(|x| x as isize),
+                        ectx,
+                    );
+                    sh.set_slice_address(&slice, slice_idx, ectx);
+                    ectx.update_from_sh(&sh, &picture_pps);
+                    sh
+                };
+                slice_bins.push(SliceEncoder::new(&ectx, &mut coder).encode_substreams(
+                    &slice,
+                    &mut sh,
+                    &[substreams],
+                ));
+            }
+            let picture_pps_bits = PpsEncoder::new(&ectx, &mut coder).encode(&picture_pps);
+            (picture_pps, picture_pps_bits)
+        } else {
+            for (slice_idx, slice) in slices.iter().enumerate() {
+                let slice = slice.lock().unwrap();
+                let mut sh = new_sh(&slice, slice_idx);
+                let substreams = SliceEncoder::new(&ectx, &mut coder).encode_tiles(&slice, &sh);
+                slice_bins.push(SliceEncoder::new(&ectx, &mut coder).encode_substreams(
+                    &slice,
+                    &mut sh,
+                    &substreams,
+                ));
+            }
+            (pps.clone(), pps_bits.clone())
+        };
+
+        // parameter sets are repeated at each IRAP or GDR picture so that decoding can start from
+        // it, and the PPS is also sent when it changes
+        if picture_type.is_irap() || picture_type == PictureType::GDR {
+            write_byte_stream_nal_unit_bits(1, NALUnitType::VPS_NUT, 0, &vps_bits, &mut writer);
+            write_byte_stream_nal_unit_bits(
                 nuh_layer_id,
-                NALUnitType::IDR_W_RADL,
+                NALUnitType::SPS_NUT,
                 0,
-                &slice_bins,
+                &sps_bits,
+                &mut writer,
+            );
+        }
+        if picture_type.is_irap()
+            || picture_type == PictureType::GDR
+            || picture_pps_bits != last_pps_bits
+        {
+            write_byte_stream_nal_unit_bits(
+                nuh_layer_id,
+                NALUnitType::PPS_NUT,
+                0,
+                &picture_pps_bits,
+                &mut writer,
+            );
+            last_pps_bits = picture_pps_bits;
+        }
+        {
+            let mut ph_encoder = PhEncoder::new(&ectx, &mut coder);
+            let mut ph_bins = Bins::new();
+            ph_encoder.encode(&mut ph_bins, &ph, &sps, &picture_pps);
+            let ph_bins = ph_bins.into_iter().collect();
+            write_byte_stream_nal_unit_bits(
+                nuh_layer_id,
+                NALUnitType::PH_NUT,
+                nuh_temporal_id,
+                &ph_bins,
                 &mut writer,
             );
         }
+        for bins in slice_bins.iter() {
+            write_byte_stream_nal_unit_bins(
+                nuh_layer_id,
+                nal_unit_type,
+                nuh_temporal_id,
+                bins,
+                &mut writer,
+            );
+        }
+        let reconst_pixels = picture.get_reconst_pixels();
         if let Some(ref mut reconst_writer) = reconst_writer {
-            let reconst_pixels = picture.get_reconst_pixels();
             for component_pixels in &reconst_pixels {
                 if let Err(e) = reconst_writer.write(&component_pixels[..]) {
                     panic!("{e}");
@@ -399,5 +1169,28 @@ fn main() {
                 panic!("{e}");
             }
         }
+        let reference_picture = ReferencePicture {
+            pic_order_cnt: picture_order_count as isize,
+            temporal_id: nuh_temporal_id,
+            width: picture.width,
+            pixels: Arc::new(reconst_pixels),
+            clean_area_bd_x: gdr_clean_area_bd_x.unwrap_or(picture.width),
+        };
+        if let Err(e) = dpb.push(reference_picture) {
+            panic!("{e}");
+        }
+    }
+
+    if let Some(ref mut trace_writer) = trace_writer {
+        if let Err(e) = writer.flush() {
+            panic!("{e}");
+        }
+        let result = BinaryReader::file(output_path)
+            .map_err(|e| e.to_string())
+            .and_then(|mut reader| decode_byte_stream(&mut reader, None, Some(trace_writer)));
+        if let Err(e) = result {
+            eprintln!("{}: failed to write trace file: {}", "error".red(), e);
+            process::exit(0);
+        }
     }
 }
@@ -0,0 +1,440 @@
+use super::common::*;
+use super::ctu::*;
+use super::encoder_context::*;
+use super::slice_header::*;
+
+/// Coefficients of the 8-tap luma interpolation filter for each 1/16 fractional sample position
+/// (Table 27).
+pub const LUMA_FILTER: [[i16; 8]; 16] = [
+    [0, 0, 0, 64, 0, 0, 0, 0],
+    [0, 1, -3, 63, 4, -2, 1, 0],
+    [-1, 2, -5, 62, 8, -3, 1, 0],
+    [-1, 3, -8, 60, 13, -4, 1, 0],
+    [-1, 4, -10, 58, 17, -5, 1, 0],
+    [-1, 4, -11, 52, 26, -8, 3, -1],
+    [-1, 3, -9, 47, 31, -10, 4, -1],
+    [-1, 4, -11, 45, 34, -10, 4, -1],
+    [-1, 4, -11, 40, 40, -11, 4, -1],
+    [-1, 4, -10, 34, 45, -11, 4, -1],
+    [-1, 4, -10, 31, 47, -9, 3, -1],
+    [-1, 3, -8, 26, 52, -11, 4, -1],
+    [0, 1, -5, 17, 58, -10, 4, -1],
+    [0, 1, -4, 13, 60, -8, 3, -1],
+    [0, 1, -3, 8, 62, -5, 2, -1],
+    [0, 1, -2, 4, 63, -3, 1, 0],
+];
+
+/// Coefficients of the 6-tap luma interpolation filter for the 4x4 subblocks of affine motion,
+/// padded to 8 taps (Table 28).
+#[allow(dead_code)]
+pub const LUMA_AFFINE_FILTER: [[i16; 8]; 16] = [
+    [0, 0, 0, 64, 0, 0, 0, 0],
+    [0, 1, -3, 63, 4, -2, 1, 0],
+    [0, 1, -5, 62, 8, -3, 1, 0],
+    [0, 2, -8, 60, 13, -4, 1, 0],
+    [0, 3, -10, 58, 17, -5, 1, 0],
+    [0, 3, -11, 52, 26, -8, 2, 0],
+    [0, 2, -9, 47, 31, -10, 3, 0],
+    [0, 3, -11, 45, 34, -10, 3, 0],
+    [0, 3, -11, 40, 40, -11, 3, 0],
+    [0, 3, -10, 34, 45, -11, 3, 0],
+    [0, 3, -10, 31, 47, -9, 2, 0],
+    [0, 2, -8, 26, 52, -11, 3, 0],
+    [0, 1, -5, 17, 58, -10, 3, 0],
+    [0, 1, -4, 13, 60, -8, 2, 0],
+    [0, 1, -3, 8, 62, -5, 1, 0],
+    [0, 1, -2, 4, 63, -3, 1, 0],
+];
+
+/// Maximum width and height of the blocks to be predicted.
+const MAX_BLOCK_SIZE: usize = 128;
+
+pub struct MotionCompensator {
+    /// reference samples of a block extended by the taps of the interpolation filters
+    ref_block: Vec2d<u8>,
+    /// reference samples filtered horizontally
+    tmp: Vec2d<i16>,
+    /// predicted samples of each reference picture list in the intermediate 14-bit precision
+    preds: [Vec2d<i16>; 2],
+}
+
+impl MotionCompensator {
+    pub fn new() -> MotionCompensator {
+        MotionCompensator {
+            ref_block: vec2d![0; MAX_BLOCK_SIZE + 7; MAX_BLOCK_SIZE + 7],
+            tmp: vec2d![0; MAX_BLOCK_SIZE + 7; MAX_BLOCK_SIZE],
+            preds: [
+                vec2d![0; MAX_BLOCK_SIZE; MAX_BLOCK_SIZE],
+                vec2d![0; MAX_BLOCK_SIZE; MAX_BLOCK_SIZE],
+            ],
+        }
+    }
+
+    /// Returns the leftmost, topmost, rightmost and bottommost positions of the reference samples
+    /// of a component, which are clipped to the subpicture treated as a picture or to the picture
+    /// (8.5.6.3.2).
+    pub fn get_ref_sample_bounds(
+        c_idx: usize,
+        sh: &SliceHeader,
+        ectx: &EncoderContext,
+    ) -> (isize, isize, isize, isize) {
+        let (pic_width, pic_height) = (
+            sh.pps.pic_width_in_luma_samples,
+            sh.pps.pic_height_in_luma_samples,
+        );
+        let (left, top, right, bottom) = match &sh.sps.subpic_info {
+            Some(info) if info.subpic_treated_as_pic_flags[ectx.curr_subpic_idx] => {
+                let i = ectx.curr_subpic_idx;
+                let ctb_size_y = ectx.ctb_size_y;
+                (
+                    info.subpic_ctu_top_left_xs[i] * ctb_size_y,
+                    info.subpic_ctu_top_left_ys[i] * ctb_size_y,
+                    ((info.subpic_ctu_top_left_xs[i] + info.subpic_widths[i]) * ctb_size_y)
+                        .min(pic_width),
+                    ((info.subpic_ctu_top_left_ys[i] + info.subpic_heights[i]) * ctb_size_y)
+                        .min(pic_height),
+                )
+            }
+            _ => (0, 0, pic_width, pic_height),
+        };
+        let (sub_width, sub_height) = if c_idx == 0 {
+            (1, 1)
+        } else {
+            (ectx.sub_width_c, ectx.sub_height_c)
+        };
+        (
+            (left / sub_width) as isize,
+            (top / sub_height) as isize,
+            (right / sub_width) as isize - 1,
+            (bottom / sub_height) as isize - 1,
+        )
+    }
+
+    /// Fills the reference block with the reference samples from (x_int, y_int) extended by the
+    /// taps of the interpolation filters, where the positions are clipped to the bounds.
+    #[allow(clippy::too_many_arguments)]
+    fn fill_ref_block(
+        &mut self,
+        x_int: isize,
+        y_int: isize,
+        width: usize,
+        height: usize,
+        ref_pixels: &[u8],
+        ref_stride: usize,
+        bounds: (isize, isize, isize, isize),
+    ) {
+        let (left, top, right, bottom) = bounds;
+        let is_inside = x_int >= left && x_int + width as isize - 1 <= right;
+        for y in 0..height {
+            let y_ref = (y_int + y as isize).clamp(top, bottom) as usize;
+            let ref_row = &ref_pixels[y_ref * ref_stride..(y_ref + 1) * ref_stride];
+            let block_row = &mut self.ref_block[y][..width];
+            if is_inside {
+                block_row.copy_from_slice(&ref_row[x_int as usize..x_int as usize + width]);
+            } else {
+                for (x, sample) in block_row.iter_mut().enumerate() {
+                    *sample = ref_row[(x_int + x as isize).clamp(left, right) as usize];
+                }
+            }
+        }
+    }
+
+    /// Interpolates the samples of a block of a component, whose reference samples start from
+    /// (x_int, y_int) and are displaced by the fractional positions (x_frac, y_frac), into the
+    /// prediction of a reference picture list in the intermediate 14-bit precision (8.5.6.3.2,
+    /// 8.5.6.3.4). The fractional positions are in units of 1/16 luma samples or 1/32 chroma
+    /// samples.
+    #[allow(clippy::too_many_arguments)]
+    pub fn interpolate(
+        &mut self,
+        lx: usize,
+        c_idx: usize,
+        (x_int, y_int): (isize, isize),
+        (x_frac, y_frac): (usize, usize),
+        (width, height): (usize, usize),
+        ref_pixels: &[u8],
+        ref_stride: usize,
+        bounds: (isize, isize, isize, isize),
+    ) {
+        let coeffs = |frac: usize| -> [i16; 8] {
+            if c_idx == 0 {
+                LUMA_FILTER[frac]
+            } else {
+                let c = F_C[frac];
+                [c[0], c[1], c[2], c[3], 0, 0, 0, 0].map(|c| c as i16)
+            }
+        };
+        let num_taps = if c_idx == 0 { 8 } else { 4 };
+        let (h_coeffs, v_coeffs) = (coeffs(x_frac), coeffs(y_frac));
+        let (h_coeffs, v_coeffs) = (&h_coeffs[..num_taps], &v_coeffs[..num_taps]);
+        // the taps before the sample at the integer position
+        let offset = num_taps / 2 - 1;
+        self.fill_ref_block(
+            x_int - offset as isize,
+            y_int - offset as isize,
+            width + num_taps - 1,
+            height + num_taps - 1,
+            ref_pixels,
+            ref_stride,
+            bounds,
+        );
+        let use_avx2 = is_x86_feature_detected!("avx2");
+        let ref_block = &self.ref_block;
+        let ref_block_stride = 1 << ref_block.log2_stride;
+        let tmp_stride = 1 << self.tmp.log2_stride;
+        let pred = &mut self.preds[lx];
+        // filters the samples from src with the stride between the taps, with SIMD for each 16 or
+        // 8 samples if available
+        macro_rules! filter {
+            ($src: expr, $stride: expr, $coeffs: expr, $dst: expr, $filter_16: expr, $filter_8: expr, $shift: expr) => {{
+                let (src, dst) = ($src, $dst);
+                let mut x = 0;
+                if use_avx2 {
+                    while x + 16 <= width {
+                        $filter_16(&src[x..], $stride, $coeffs, &mut dst[x..]);
+                        x += 16;
+                    }
+                    if x + 8 <= width {
+                        $filter_8(&src[x..], $stride, $coeffs, &mut dst[x..]);
+                        x += 8;
+                    }
+                }
+                for x in x..width {
+                    let sum = $coeffs
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &c)| c as i32 * src[x + i * $stride] as i32)
+                        .sum::<i32>();
+                    dst[x] = (sum >> $shift) as i16;
+                }
+            }};
+        }
+        // 8-bit samples are filtered without the right shift by Min(4, BitDepth - 8) and the
+        // intermediate samples are right shifted by 6
+        match (x_frac, y_frac) {
+            (0, 0) => {
+                for y in 0..height {
+                    let block_row = &ref_block[y + offset][offset..offset + width];
+                    for (p, &r) in pred[y][..width].iter_mut().zip(block_row) {
+                        *p = (r as i16) << 6;
+                    }
+                }
+            }
+            (_, 0) => {
+                for y in 0..height {
+                    filter!(
+                        &ref_block[y + offset],
+                        1,
+                        h_coeffs,
+                        &mut pred[y],
+                        filter_16_u8_i16,
+                        filter_8_u8_i16,
+                        0
+                    );
+                }
+            }
+            (0, _) => {
+                for y in 0..height {
+                    let start = (y << ref_block.log2_stride) + offset;
+                    filter!(
+                        &ref_block.data[start..],
+                        ref_block_stride,
+                        v_coeffs,
+                        &mut pred[y],
+                        filter_16_u8_i16,
+                        filter_8_u8_i16,
+                        0
+                    );
+                }
+            }
+            _ => {
+                let tmp = &mut self.tmp;
+                for y in 0..height + num_taps - 1 {
+                    filter!(
+                        &ref_block[y],
+                        1,
+                        h_coeffs,
+                        &mut tmp[y],
+                        filter_16_u8_i16,
+                        filter_8_u8_i16,
+                        0
+                    );
+                }
+                for y in 0..height {
+                    let start = y << tmp.log2_stride;
+                    filter!(
+                        &tmp.data[start..],
+                        tmp_stride,
+                        v_coeffs,
+                        &mut pred[y],
+                        |src, stride, coeffs, dst| filter_16_i16_i16(src, stride, coeffs, 6, dst),
+                        |src, stride, coeffs, dst| filter_8_i16_i16(src, stride, coeffs, 6, dst),
+                        6
+                    );
+                }
+            }
+        }
+    }
+
+    /// Writes the samples of a row of the prediction, which are those of the interpolated
+    /// prediction of a reference picture list or the average of the both, by the default
+    /// weighted sample prediction (8.5.6.6.2).
+    pub fn weight_row(&self, pred_flag: [bool; 2], y: usize, dst: &mut [u8]) {
+        // shift1 = 14 - bitDepth and shift2 = 15 - bitDepth
+        if pred_flag[0] && pred_flag[1] {
+            let (pred0, pred1) = (&self.preds[0][y], &self.preds[1][y]);
+            for (x, d) in dst.iter_mut().enumerate() {
+                *d = ((pred0[x] as i32 + pred1[x] as i32 + 64) >> 7).clamp(0, 255) as u8;
+            }
+        } else {
+            let pred = &self.preds[if pred_flag[0] { 0 } else { 1 }][y];
+            for (x, d) in dst.iter_mut().enumerate() {
+                *d = ((pred[x] as i32 + 32) >> 6).clamp(0, 255) as u8;
+            }
+        }
+    }
+
+    /// Predicts the samples of a component of a transform unit from the reference pictures of
+    /// the motion information, and derives the residuals from them (8.5.6.3).
+    pub fn predict(
+        &mut self,
+        tu: &mut TransformUnit,
+        c_idx: usize,
+        motion_info: &MotionInfo,
+        sh: &SliceHeader,
+        ectx: &EncoderContext,
+    ) {
+        let (tx, ty) = tu.get_component_pos(c_idx);
+        let (tw, th) = tu.get_component_size(c_idx);
+        let bounds = Self::get_ref_sample_bounds(c_idx, sh, ectx);
+        let (sub_width, sub_height) = if c_idx == 0 {
+            (1, 1)
+        } else {
+            (ectx.sub_width_c, ectx.sub_height_c)
+        };
+        for lx in 0..2 {
+            if !motion_info.pred_flag[lx] {
+                continue;
+            }
+            let ref_picture = &ectx.ref_pictures[lx][motion_info.ref_idx[lx]];
+            // luma motion vectors are in units of 1/16 samples, and chroma ones derived from them
+            // are in units of 1/32 samples (8.5.2.13)
+            let mv = motion_info.mv[lx];
+            let (mv, shift) = if c_idx == 0 {
+                (mv, 4)
+            } else {
+                (
+                    [
+                        mv[0] * 2 / sub_width as isize,
+                        mv[1] * 2 / sub_height as isize,
+                    ],
+                    5,
+                )
+            };
+            let mask = (1 << shift) - 1;
+            self.interpolate(
+                lx,
+                c_idx,
+                (
+                    tx as isize + (mv[0] >> shift),
+                    ty as isize + (mv[1] >> shift),
+                ),
+                ((mv[0] & mask) as usize, (mv[1] & mask) as usize),
+                (tw, th),
+                &ref_picture.pixels[c_idx],
+                ref_picture.width / sub_width,
+                bounds,
+            );
+        }
+        let tile = tu.get_tile();
+        let tile = tile.lock().unwrap();
+        let pred_pixels = &mut tile.pred_pixels.lock().unwrap()[c_idx];
+        for y in 0..th {
+            self.weight_row(
+                motion_info.pred_flag,
+                y,
+                &mut pred_pixels[ty + y][tx..tx + tw],
+            );
+        }
+        let original_pixels = &tile.original_pixels.lock().unwrap()[c_idx];
+        let residual_pixels = &mut tile.residual_pixels.lock().unwrap()[c_idx];
+        let residuals = &mut tu.residuals[c_idx];
+        for y in ty..ty + th {
+            let residuals = &mut residuals[y - ty];
+            let original_pixels = &original_pixels[y][tx..];
+            let pred_pixels = &pred_pixels[y][tx..];
+            let residual_pixels = &mut residual_pixels[y][tx..];
+            for x in 0..tw {
+                let residual = original_pixels[x] as i16 - pred_pixels[x] as i16;
+                residual_pixels[x] = residual;
+                residuals[x] = residual;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolation_filters_are_normalized_and_symmetric() {
+        for filter in [&LUMA_FILTER, &LUMA_AFFINE_FILTER] {
+            for frac in 0..16 {
+                assert_eq!(filter[frac].iter().sum::<i16>(), 64);
+                let mut mirrored = filter[(16 - frac) % 16];
+                mirrored.reverse();
+                // the filter of the integer position is not symmetric by the padding
+                if frac != 0 {
+                    assert_eq!(filter[frac], mirrored);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn interpolate_works() {
+        let mut mc = MotionCompensator::new();
+        let (width, height) = (24, 4);
+        let ref_pixels: Vec<u8> = (0..width * height).map(|i| (i * 7 % 251) as u8).collect();
+        let bounds = (0, 0, width as isize - 1, height as isize - 1);
+        for (x_frac, y_frac) in [(0, 0), (5, 0), (0, 8), (3, 13)] {
+            mc.interpolate(
+                0,
+                0,
+                (2, 1),
+                (x_frac, y_frac),
+                (17, 2),
+                &ref_pixels,
+                width,
+                bounds,
+            );
+            let sample = |x: isize, y: isize| {
+                ref_pixels[y.clamp(0, height as isize - 1) as usize * width
+                    + x.clamp(0, width as isize - 1) as usize] as i32
+            };
+            for y in 0..2 {
+                for x in 0..17 {
+                    let filter = |x: isize, y: isize| {
+                        (0..8)
+                            .map(|i| LUMA_FILTER[x_frac][i] as i32 * sample(x + i as isize - 3, y))
+                            .sum::<i32>()
+                    };
+                    let (x_ref, y_ref) = (2 + x as isize, 1 + y as isize);
+                    let gt = if y_frac == 0 {
+                        filter(x_ref, y_ref)
+                    } else {
+                        (0..8)
+                            .map(|i| {
+                                LUMA_FILTER[y_frac][i] as i32
+                                    * filter(x_ref, y_ref + i as isize - 3)
+                            })
+                            .sum::<i32>()
+                            >> 6
+                    };
+                    assert_eq!(mc.preds[0][y][x] as i32, gt);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use super::common::*;
+use super::ctu::*;
+use super::encoder_context::*;
+use super::motion_compensator::*;
+use super::reference_picture::*;
+use super::slice_header::*;
+
+/// Number of samples by which reference blocks may lie outside the reference picture.
+const MAX_OUTSIDE_SAMPLES: isize = 8;
+
+pub struct MotionEstimator {
+    orig: Vec2d<u8>,
+    motion_compensator: MotionCompensator,
+}
+
+impl MotionEstimator {
+    pub fn new() -> MotionEstimator {
+        MotionEstimator {
+            orig: vec2d![0; 128; 128],
+            motion_compensator: MotionCompensator::new(),
+        }
+    }
+
+    /// Returns the approximate number of bins of a component of a motion vector difference in
+    /// units of 1/4 luma samples (7.3.11.9).
+    pub fn get_mvd_bits(mvd: isize) -> usize {
+        match mvd.unsigned_abs() {
+            0 => 1,
+            1 => 3,
+            abs_mvd => {
+                // abs_mvd_minus2 is coded by the first order Exp-Golomb code
+                let mut v = abs_mvd - 2;
+                let mut k = 1;
+                while v >= 1 << k {
+                    v -= 1 << k;
+                    k += 1;
+                }
+                3 + 2 * k
+            }
+        }
+    }
+
+    fn get_sad(
+        &self,
+        x_ref: isize,
+        y_ref: isize,
+        width: usize,
+        height: usize,
+        ref_picture: &ReferencePicture,
+        bounds: (isize, isize, isize, isize),
+    ) -> usize {
+        let (left, top, right, bottom) = bounds;
+        let ref_pixels = &ref_picture.pixels[0];
+        let stride = ref_picture.width;
+        let is_inside = x_ref >= left && x_ref + width as isize - 1 <= right;
+        let mut sad = 0;
+        for y in 0..height {
+            let y_ref = (y_ref + y as isize).clamp(top, bottom) as usize;
+            let ref_row = &ref_pixels[y_ref * stride..(y_ref + 1) * stride];
+            let orig_row = &self.orig[y][..width];
+            sad += if is_inside {
+                ref_row[x_ref as usize..x_ref as usize + width]
+                    .iter()
+                    .zip(orig_row)
+                    .map(|(&r, &o)| r.abs_diff(o) as usize)
+                    .sum::<usize>()
+            } else {
+                orig_row
+                    .iter()
+                    .enumerate()
+                    .map(|(x, &o)| {
+                        let r = ref_row[(x_ref + x as isize).clamp(left, right) as usize];
+                        r.abs_diff(o) as usize
+                    })
+                    .sum::<usize>()
+            };
+        }
+        sad
+    }
+
+    /// Returns the SAD of the luma samples predicted by a motion vector in units of 1/16 samples.
+    fn get_subpel_sad(
+        &mut self,
+        cu: &CodingUnit,
+        mv: [isize; 2],
+        ref_picture: &ReferencePicture,
+        bounds: (isize, isize, isize, isize),
+    ) -> usize {
+        let (width, height) = (cu.width, cu.height);
+        self.motion_compensator.interpolate(
+            0,
+            0,
+            (cu.x as isize + (mv[0] >> 4), cu.y as isize + (mv[1] >> 4)),
+            ((mv[0] & 15) as usize, (mv[1] & 15) as usize),
+            (width, height),
+            &ref_picture.pixels[0],
+            ref_picture.width,
+            bounds,
+        );
+        let mut pred_row = [0; 128];
+        let mut sad = 0;
+        for y in 0..height {
+            let pred_row = &mut pred_row[..width];
+            self.motion_compensator
+                .weight_row([true, false], y, pred_row);
+            sad += pred_row
+                .iter()
+                .zip(&self.orig[y][..width])
+                .map(|(&p, &o)| p.abs_diff(o) as usize)
+                .sum::<usize>();
+        }
+        sad
+    }
+
+    /// Searches the motion vector of the first reference picture which minimizes the sum of the
+    /// SAD of the luma samples and the weighted bits of the motion vector difference, by integer
+    /// sample search followed by half and quarter sample refinement. Returns the motion and the
+    /// bits of its motion vector difference.
+    pub fn search(
+        &mut self,
+        cu: &CodingUnit,
+        lambda: f32,
+        sh: &SliceHeader,
+        ectx: &EncoderContext,
+    ) -> Option<(MotionInfo, usize)> {
+        let (lx, ref_idx) = (0, 0);
+        let ref_picture = &ectx.ref_pictures[lx][ref_idx];
+        let (x, y) = (cu.x as isize, cu.y as isize);
+        let (width, height) = (cu.width, cu.height);
+        let bounds = MotionCompensator::get_ref_sample_bounds(0, sh, ectx);
+        let (left, top, right, bottom) = bounds;
+        let mut x_ref_max = right + 1 - width as isize + MAX_OUTSIDE_SAMPLES;
+        // blocks in the clean area of a picture refer only to the clean area of a picture being
+        // refreshed by GDR, including the samples right of the blocks used by the interpolation
+        // filters
+        let is_clean = ectx.gdr_clean_area_bd_x.is_none_or(|bd_x| cu.x < bd_x);
+        let clean_area_bd_x = if is_clean && ref_picture.clean_area_bd_x < ref_picture.width {
+            if ref_picture.clean_area_bd_x == 0 {
+                return None;
+            }
+            let clean_area_bd_x = ref_picture.clean_area_bd_x as isize;
+            x_ref_max = x_ref_max.min(clean_area_bd_x - width as isize);
+            Some(clean_area_bd_x)
+        } else {
+            None
+        };
+        let x_ref_min = left - MAX_OUTSIDE_SAMPLES;
+        let y_ref_min = top - MAX_OUTSIDE_SAMPLES;
+        let y_ref_max = bottom + 1 - height as isize + MAX_OUTSIDE_SAMPLES;
+        // ranges of displacements in units of luma samples
+        let (dx_min, dx_max) = (x_ref_min - x, x_ref_max - x);
+        let (dy_min, dy_max) = (y_ref_min - y, y_ref_max - y);
+        if dx_min > dx_max || dy_min > dy_max {
+            return None;
+        }
+
+        {
+            let tile = cu.tile.as_ref().unwrap();
+            let tile = tile.lock().unwrap();
+            let original_pixels = &tile.original_pixels.lock().unwrap()[0];
+            for dy in 0..height {
+                self.orig[dy][..width]
+                    .copy_from_slice(&original_pixels[cu.y + dy][cu.x..cu.x + width]);
+            }
+        }
+        let mvp_cand_list = cu.get_mvp_cand_list(lx, ref_idx, sh, ectx);
+        let get_bits = |mv: [isize; 2]| {
+            mvp_cand_list
+                .iter()
+                .map(|mvp| {
+                    Self::get_mvd_bits((mv[0] - mvp[0]) >> 2)
+                        + Self::get_mvd_bits((mv[1] - mvp[1]) >> 2)
+                })
+                .min()
+                .unwrap()
+        };
+        let is_valid = |mv: [isize; 2]| {
+            let (x_int, y_int) = (x + (mv[0] >> 4), y + (mv[1] >> 4));
+            let is_inside = (x_ref_min..=x_ref_max).contains(&x_int)
+                && (y_ref_min..=y_ref_max).contains(&y_int);
+            // the interpolation filters of luma and chroma samples refer up to 4 luma samples
+            // right of the blocks unless chroma samples are at integer positions
+            is_inside
+                && clean_area_bd_x.is_none_or(|bd_x| {
+                    x_int + width as isize + if mv[0] & 31 == 0 { 0 } else { 4 } <= bd_x
+                })
+        };
+        let get_cost = |d: (isize, isize)| -> f32 {
+            if !is_valid([d.0 * 16, d.1 * 16]) {
+                return f32::MAX;
+            }
+            let sad = self.get_sad(x + d.0, y + d.1, width, height, ref_picture, bounds);
+            sad as f32 + lambda * get_bits([d.0 * 16, d.1 * 16]) as f32
+        };
+        let clamp = |d: (isize, isize)| (d.0.clamp(dx_min, dx_max), d.1.clamp(dy_min, dy_max));
+
+        // starts from the predictors and the zero motion vector
+        let mut start_cands = vec![(0, 0)];
+        start_cands.extend(
+            mvp_cand_list
+                .iter()
+                .chain(ectx.hmvp_cand_list.iter().filter_map(|c| {
+                    (c.pred_flag[lx] && c.ref_idx[lx] == ref_idx).then_some(&c.mv[lx])
+                }))
+                .map(|mv| {
+                    let mv = round_mv(*mv, 4);
+                    (mv[0] >> 4, mv[1] >> 4)
+                }),
+        );
+        let (mut best, mut best_cost) = ((0, 0), f32::MAX);
+        for d in start_cands.into_iter().map(clamp) {
+            let cost = get_cost(d);
+            if cost < best_cost {
+                (best, best_cost) = (d, cost);
+            }
+        }
+
+        // full search around the best start
+        let center = best;
+        for dy in -2..=2 {
+            for dx in -2..=2 {
+                let d = clamp((center.0 + dx, center.1 + dy));
+                if d != best {
+                    let cost = get_cost(d);
+                    if cost < best_cost {
+                        (best, best_cost) = (d, cost);
+                    }
+                }
+            }
+        }
+
+        // diamond search with decreasing steps
+        for step in [4, 2, 1] {
+            loop {
+                let center = best;
+                for (dx, dy) in [(-step, 0), (step, 0), (0, -step), (0, step)] {
+                    let d = clamp((center.0 + dx, center.1 + dy));
+                    if d != center {
+                        let cost = get_cost(d);
+                        if cost < best_cost {
+                            (best, best_cost) = (d, cost);
+                        }
+                    }
+                }
+                if best == center {
+                    break;
+                }
+            }
+        }
+
+        if best_cost == f32::MAX {
+            return None;
+        }
+
+        // half and quarter sample refinement around the best integer motion vector
+        let mut best_mv = [best.0 * 16, best.1 * 16];
+        for step in [8, 4] {
+            let center = best_mv;
+            for (dx, dy) in [
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ] {
+                let mv = [center[0] + dx * step, center[1] + dy * step];
+                if is_valid(mv) {
+                    let sad = self.get_subpel_sad(cu, mv, ref_picture, bounds);
+                    let cost = sad as f32 + lambda * get_bits(mv) as f32;
+                    if cost < best_cost {
+                        (best_mv, best_cost) = (mv, cost);
+                    }
+                }
+            }
+        }
+
+        let mut mv = [[0; 2]; 2];
+        mv[lx] = best_mv;
+        let mut pred_flag = [false; 2];
+        pred_flag[lx] = true;
+        let motion_info = MotionInfo {
+            pred_flag,
+            ref_idx: [ref_idx; 2],
+            mv,
+        };
+        Some((motion_info, get_bits(best_mv)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_mvd_bits_works() {
+        assert_eq!(MotionEstimator::get_mvd_bits(0), 1);
+        assert_eq!(MotionEstimator::get_mvd_bits(-1), 3);
+        assert_eq!(MotionEstimator::get_mvd_bits(2), 5);
+        assert_eq!(MotionEstimator::get_mvd_bits(3), 5);
+        assert_eq!(MotionEstimator::get_mvd_bits(-4), 7);
+        assert_eq!(MotionEstimator::get_mvd_bits(7), 7);
+        assert_eq!(MotionEstimator::get_mvd_bits(8), 9);
+    }
+}
@@ -1,8 +1,9 @@
+use super::binary_reader::*;
 use super::binary_writer::*;
 use super::bins::*;
 use super::ptl::*;
 use debug_print::*;
-use std::io::Write;
+use std::io::{Read, Write};
 
 #[allow(non_camel_case_types)]
 #[allow(dead_code)]
@@ -42,6 +43,46 @@ pub enum NALUnitType {
     UNSPEC_31 = 31,      // Unspecified non VCL NAL unit types
 }
 
+impl NALUnitType {
+    /// Returns the NAL unit type of a 5-bit nal_unit_type.
+    pub fn from_value(nal_unit_type: usize) -> NALUnitType {
+        match nal_unit_type {
+            0 => NALUnitType::TRAIL_NUT,
+            1 => NALUnitType::STSA_NUT,
+            2 => NALUnitType::RADL_NUT,
+            3 => NALUnitType::RASL_NUT,
+            4 => NALUnitType::RSV_VCL_4,
+            5 => NALUnitType::RSV_VCL_5,
+            6 => NALUnitType::RSV_VCL_6,
+            7 => NALUnitType::IDR_W_RADL,
+            8 => NALUnitType::IDR_N_LP,
+            9 => NALUnitType::CRA_NUT,
+            10 => NALUnitType::GDR_NUT,
+            11 => NALUnitType::RSV_IRAP_11,
+            12 => NALUnitType::OPI_NUT,
+            13 => NALUnitType::DCI_NUT,
+            14 => NALUnitType::VPS_NUT,
+            15 => NALUnitType::SPS_NUT,
+            16 => NALUnitType::PPS_NUT,
+            17 => NALUnitType::PREFIX_APS_NUT,
+            18 => NALUnitType::SUFFIX_APS_NUT,
+            19 => NALUnitType::PH_NUT,
+            20 => NALUnitType::AUD_NUT,
+            21 => NALUnitType::EOS_NUT,
+            22 => NALUnitType::EOB_NUT,
+            23 => NALUnitType::PREFIX_SEI_NUT,
+            24 => NALUnitType::SUFFIX_SEI_NUT,
+            25 => NALUnitType::FD_NUT,
+            26 => NALUnitType::RSV_NVCL_26,
+            27 => NALUnitType::RSV_NVCL_27,
+            28 => NALUnitType::UNSPEC_28,
+            29 => NALUnitType::UNSPEC_29,
+            30 => NALUnitType::UNSPEC_30,
+            _ => NALUnitType::UNSPEC_31,
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum RBSP {
@@ -68,11 +109,20 @@ pub struct DecodingCapabilityInformation {
     dci_extension_flags: Vec<bool>,
 }
 
-#[allow(dead_code)]
 pub struct OperatingPointInformation {
-    opi_ols_idx: Option<usize>,
-    opi_htid: Option<usize>,
-    opi_extension_data: Vec<bool>,
+    pub opi_ols_idx: Option<usize>,
+    pub opi_htid: Option<usize>,
+    pub opi_extension_data: Vec<bool>,
+}
+
+impl OperatingPointInformation {
+    pub fn new(opi_ols_idx: Option<usize>, opi_htid: Option<usize>) -> OperatingPointInformation {
+        OperatingPointInformation {
+            opi_ols_idx,
+            opi_htid,
+            opi_extension_data: vec![],
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -297,3 +347,133 @@ pub fn write_nal_unit_bins(
         idx += 1;
     }
 }
+
+/// Counts emulation_prevention_three_bytes inserted into bytes by write_nal_unit_bins, assuming
+/// that the byte before them is not zero, as the last byte of a substream or a slice header is.
+pub fn count_emulation_prevention_bytes(bytes: &[u8]) -> usize {
+    let mut num_bytes = 0;
+    let mut idx = 0;
+    while idx + 2 < bytes.len() {
+        if bytes[idx] == 0 && bytes[idx + 1] == 0 && bytes[idx + 2] <= 3 {
+            num_bytes += 1;
+            idx += 2;
+        } else {
+            idx += 1;
+        }
+    }
+    num_bytes
+}
+
+/// Returns the size in bytes of a NAL unit written by write_nal_unit_bins with bins as its payload.
+pub fn get_nal_unit_size(bins: &Bins) -> usize {
+    let bytes: Vec<u8> = bins.bytes().collect();
+    // 2 bytes of nal_unit_header, while the last 3 bytes are not checked for emulation
+    2 + bytes.len() + count_emulation_prevention_bytes(&bytes[..bytes.len().saturating_sub(1)])
+}
+
+pub fn write_byte_stream_nal_unit_bytes(nal_unit: &[u8], writer: &mut BinaryWriter) {
+    // no leading_zero_8bits
+    let header_bytes: [u8; 3] = [0, 0, 0];
+    if let Err(e) = writer.write(&header_bytes) {
+        panic!("{e}");
+    }
+    let start_code_prefix_one_3bytes: [u8; 3] = [0, 0, 1];
+    if let Err(e) = writer.write(&start_code_prefix_one_3bytes) {
+        panic!("{e}");
+    }
+    // nal_unit is already emulation-prevented
+    if let Err(e) = writer.write(nal_unit) {
+        panic!("{e}");
+    }
+    if let Err(e) = writer.flush() {
+        panic!("{e}");
+    }
+}
+
+/// Splits a byte stream into NAL units, each of which starts with its NAL unit header and keeps
+/// its emulation prevention bytes.
+pub fn read_byte_stream_nal_units(reader: &mut BinaryReader) -> Vec<Vec<u8>> {
+    let mut bytes = vec![];
+    if let Err(e) = reader.read_to_end(&mut bytes) {
+        panic!("{e}");
+    }
+    let mut nal_units = vec![];
+    let mut start = None;
+    let mut idx = 0;
+    while idx + 2 < bytes.len() {
+        if bytes[idx] == 0 && bytes[idx + 1] == 0 && bytes[idx + 2] == 1 {
+            if let Some(start) = start {
+                nal_units.push(bytes[start..idx].to_vec());
+            }
+            idx += 3;
+            start = Some(idx);
+        } else {
+            idx += 1;
+        }
+    }
+    if let Some(start) = start {
+        nal_units.push(bytes[start..].to_vec());
+    }
+    // zero_byte and trailing_zero_8bits are not a part of NAL units, and a NAL unit never ends
+    // with a zero byte since it ends with rbsp_stop_one_bit or cabac_zero_word
+    for nal_unit in nal_units.iter_mut() {
+        while nal_unit.last() == Some(&0) {
+            nal_unit.pop();
+        }
+    }
+    nal_units
+}
+
+/// Returns the RBSP of a NAL unit by removing its NAL unit header and emulation prevention bytes.
+pub fn remove_emulation_prevention_bytes(nal_unit: &[u8]) -> Vec<u8> {
+    let mut rbsp = vec![];
+    let mut num_zeros = 0;
+    for &byte in &nal_unit[2..] {
+        if num_zeros >= 2 && byte == 3 {
+            num_zeros = 0;
+            continue;
+        }
+        num_zeros = if byte == 0 { num_zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    rbsp
+}
+
+/// Writes bits as a NAL unit in a byte stream and returns the RBSP read back from the stream.
+#[cfg(test)]
+pub fn write_and_read_rbsp(nal_unit_type: NALUnitType, bits: &Vec<bool>) -> Vec<u8> {
+    let mut stream = vec![];
+    write_byte_stream_nal_unit_bits(
+        0,
+        nal_unit_type,
+        0,
+        bits,
+        &mut BinaryWriter::vec(&mut stream),
+    );
+    let mut reader = BinaryReader::vec(&stream).unwrap();
+    let nal_units = read_byte_stream_nal_units(&mut reader);
+    assert_eq!(nal_units.len(), 1);
+    assert_eq!(
+        read_nal_unit_header(&nal_units[0]),
+        Ok((0, nal_unit_type, 0))
+    );
+    remove_emulation_prevention_bytes(&nal_units[0])
+}
+
+/// Reads nal_unit_header() of a NAL unit and returns nuh_layer_id, nal_unit_type and TemporalId.
+pub fn read_nal_unit_header(nal_unit: &[u8]) -> Result<(usize, NALUnitType, usize), String> {
+    if nal_unit.len() < 2 {
+        return Err("NAL unit shorter than its header".to_string());
+    }
+    let forbidden_zero_bit = nal_unit[0] >> 7;
+    if forbidden_zero_bit != 0 {
+        return Err("forbidden_zero_bit is not equal to 0".to_string());
+    }
+    let nuh_layer_id = (nal_unit[0] & 0x3f) as usize;
+    let nal_unit_type = NALUnitType::from_value((nal_unit[1] >> 3) as usize);
+    let nuh_temporal_id_plus1 = (nal_unit[1] & 0x7) as usize;
+    if nuh_temporal_id_plus1 == 0 {
+        return Err("nuh_temporal_id_plus1 is equal to 0".to_string());
+    }
+    Ok((nuh_layer_id, nal_unit_type, nuh_temporal_id_plus1 - 1))
+}
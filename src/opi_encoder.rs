@@ -0,0 +1,51 @@
+use super::bins::*;
+use super::bool_coder::*;
+use super::encoder_context::*;
+use super::nal::*;
+use debug_print::*;
+use std::sync::{Arc, Mutex};
+
+pub struct OpiEncoder<'a> {
+    coder: &'a mut BoolCoder,
+    _encoder_context: Arc<Mutex<EncoderContext>>,
+}
+
+impl<'a> OpiEncoder<'a> {
+    pub fn new(
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        coder: &'a mut BoolCoder,
+    ) -> OpiEncoder<'a> {
+        OpiEncoder {
+            coder,
+            _encoder_context: encoder_context.clone(),
+        }
+    }
+
+    pub fn encode(&mut self, opi: &OperatingPointInformation) -> Vec<bool> {
+        let mut bins = Bins::new();
+        trace_syntax_element!(bins, "opi.ols_info_present_flag ");
+        bins.push_initial_bin(opi.opi_ols_idx.is_some());
+        trace_syntax_element!(bins, "opi.htid_info_present_flag ");
+        bins.push_bin(opi.opi_htid.is_some());
+        if let Some(opi_ols_idx) = opi.opi_ols_idx {
+            trace_syntax_element!(bins, "opi.ols_idx ");
+            self.coder
+                .encode_unsigned_exp_golomb(&mut bins, opi_ols_idx as u64);
+        }
+        if let Some(opi_htid) = opi.opi_htid {
+            trace_syntax_element!(bins, "opi.htid_plus1 ");
+            bins.push_bins_with_size(opi_htid as u64 + 1, 3);
+        }
+        trace_syntax_element!(bins, "opi.extension_flag ");
+        bins.push_bin(!opi.opi_extension_data.is_empty());
+        for i in 0..opi.opi_extension_data.len() {
+            trace_syntax_element!(bins, "opi.extension_data_flag ");
+            bins.push_bin(opi.opi_extension_data[i]);
+        }
+        let rbsp_stop_one_bit = true;
+        trace_syntax_element!(bins, "rbsp_stop_one_bit ");
+        bins.push_bin(rbsp_stop_one_bit);
+        bins.byte_align();
+        bins.into_iter().collect()
+    }
+}
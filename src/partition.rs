@@ -1,3 +1,4 @@
+#[derive(Clone)]
 pub struct PartitionConstraints {
     pub log2_diff_min_qt_min_cb_intra_slice_luma: usize,
     pub max_mtt_hierarchy_depth_intra_slice_luma: usize,
@@ -0,0 +1,395 @@
+use super::binary_reader::*;
+use super::common::*;
+use super::encoder_context::*;
+use super::picture::*;
+use super::picture_header::*;
+use super::pps::*;
+use super::pwt_parser::*;
+use super::rpl_parser::*;
+use super::sps::*;
+use std::sync::{Arc, Mutex};
+
+pub struct PhParser<'a, 'b> {
+    reader: &'a mut BinaryReader<'b>,
+    encoder_context: Arc<Mutex<EncoderContext>>,
+}
+
+impl<'a, 'b> PhParser<'a, 'b> {
+    pub fn new(
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        reader: &'a mut BinaryReader<'b>,
+    ) -> PhParser<'a, 'b> {
+        PhParser {
+            reader,
+            encoder_context: encoder_context.clone(),
+        }
+    }
+
+    /// Parses picture_header_structure( ) followed by the trailing bits written by PhEncoder. The
+    /// caller is expected to call update_from_ph afterwards.
+    pub fn parse(
+        &mut self,
+        sps: &SequenceParameterSet,
+        pps: &PictureParameterSet,
+    ) -> PictureHeader {
+        let gdr_or_irap_pic_flag = self.reader.read_bit();
+        let non_ref_pic_flag = self.reader.read_bit();
+        let gdr_pic_flag = if gdr_or_irap_pic_flag {
+            self.reader.read_bit()
+        } else {
+            false
+        };
+        let inter_slice_allowed_flag = self.reader.read_bit();
+        let mut ph = PictureHeader::new(pps, PictureType::Trailing, !inter_slice_allowed_flag, 0);
+        ph.gdr_or_irap_pic_flag = gdr_or_irap_pic_flag;
+        ph.non_ref_pic_flag = non_ref_pic_flag;
+        ph.gdr_pic_flag = gdr_pic_flag;
+        ph.intra_slice_allowed_flag = if inter_slice_allowed_flag {
+            self.reader.read_bit()
+        } else {
+            true
+        };
+        ph.pic_parameter_set_id = self.reader.read_unsigned_exp_golomb();
+        assert_eq!(ph.pic_parameter_set_id, pps.id);
+        ph.pic_order_cnt_lsb = self.reader.read_value(sps.log2_max_pic_order_cnt_lsb);
+        if ph.gdr_pic_flag {
+            ph.recovery_poc_cnt = self.reader.read_unsigned_exp_golomb();
+        }
+        let num_extra_ph_bits = self.encoder_context.lock().unwrap().num_extra_ph_bits;
+        ph.extra_bit = (0..num_extra_ph_bits)
+            .map(|_| self.reader.read_bit())
+            .collect();
+        if sps.poc_msb_cycle_flag {
+            ph.poc_msb_cycle_val = self.reader.read_value(sps.poc_msb_cycle_len);
+        }
+        if sps.alf_enabled_flag && pps.partition_parameters.alf_info_in_ph_flag {
+            ph.alf_enabled_flag = self.reader.read_bit();
+            if ph.alf_enabled_flag {
+                let alf_info = &mut ph.alf_info;
+                alf_info.num_alf_aps_ids_luma = self.reader.read_value(3);
+                alf_info.aps_id_luma = (0..alf_info.num_alf_aps_ids_luma)
+                    .map(|_| self.reader.read_value(3))
+                    .collect();
+                if sps.chroma_format != ChromaFormat::Monochrome {
+                    alf_info.cb_enabled_flag = self.reader.read_bit();
+                    alf_info.cr_enabled_flag = self.reader.read_bit();
+                }
+                if alf_info.cb_enabled_flag || alf_info.cr_enabled_flag {
+                    alf_info.aps_id_chroma = self.reader.read_value(3);
+                }
+                if sps.ccalf_enabled_flag {
+                    alf_info.cc_cb_enabled_flag = self.reader.read_bit();
+                    if alf_info.cc_cb_enabled_flag {
+                        alf_info.cc_cb_aps_id = self.reader.read_value(3);
+                    }
+                    alf_info.cc_cr_enabled_flag = self.reader.read_bit();
+                    if alf_info.cc_cr_enabled_flag {
+                        alf_info.cc_cr_aps_id = self.reader.read_value(3);
+                    }
+                }
+            }
+        }
+        if sps.lmcs_enabled_flag {
+            ph.lmcs_enabled_flag = self.reader.read_bit();
+            if ph.lmcs_enabled_flag {
+                ph.lmcs_aps_id = self.reader.read_value(2);
+                if sps.chroma_format != ChromaFormat::Monochrome {
+                    ph.chroma_residual_scale_flag = self.reader.read_bit();
+                }
+            }
+        }
+        if sps.explicit_scaling_list_enabled_flag {
+            ph.explicit_scaling_list_enabled_flag = self.reader.read_bit();
+            if ph.explicit_scaling_list_enabled_flag {
+                ph.scaling_list_aps_id = self.reader.read_value(3);
+            }
+        }
+        if sps.virtual_boundaries_enabled_flag
+            && !sps
+                .virtual_boundary_parameters
+                .virtual_boundaries_present_flag
+        {
+            let vb = &mut ph.virtual_boundary;
+            vb.virtual_boundaries_present_flag = self.reader.read_bit();
+            if vb.virtual_boundaries_present_flag {
+                vb.num_ver_virtual_boundaries = self.reader.read_unsigned_exp_golomb();
+                vb.virtual_boundary_pos_xs = (0..vb.num_ver_virtual_boundaries)
+                    .map(|_| self.reader.read_unsigned_exp_golomb() + 1)
+                    .collect();
+                vb.num_hor_virtual_boundaries = self.reader.read_unsigned_exp_golomb();
+                vb.virtual_boundary_pos_ys = (0..vb.num_hor_virtual_boundaries)
+                    .map(|_| self.reader.read_unsigned_exp_golomb() + 1)
+                    .collect();
+            }
+        }
+        if pps.output_flag_present_flag && !ph.non_ref_pic_flag {
+            ph.pic_output_flag = self.reader.read_bit();
+        }
+        if pps.partition_parameters.rpl_info_in_ph_flag {
+            let ectx = self.encoder_context.clone();
+            let mut rpl_parser = RefPicListStructParser::new(&ectx, self.reader);
+            ph.ref_pic_lists = rpl_parser.parse(sps, pps);
+        }
+        if sps.partition_constraints_override_enabled_flag {
+            ph.partition_constraints_override_flag = self.reader.read_bit();
+        }
+        if ph.partition_constraints_override_flag {
+            ph.partition_constraints = Some(sps.partition_constraints.clone());
+        }
+        if ph.intra_slice_allowed_flag {
+            if let Some(pc) = ph.partition_constraints.as_mut() {
+                pc.log2_diff_min_qt_min_cb_intra_slice_luma =
+                    self.reader.read_unsigned_exp_golomb();
+                pc.max_mtt_hierarchy_depth_intra_slice_luma =
+                    self.reader.read_unsigned_exp_golomb();
+                if pc.max_mtt_hierarchy_depth_intra_slice_luma != 0 {
+                    pc.log2_diff_max_bt_min_qt_intra_slice_luma =
+                        self.reader.read_unsigned_exp_golomb();
+                    pc.log2_diff_max_tt_min_qt_intra_slice_luma =
+                        self.reader.read_unsigned_exp_golomb();
+                }
+                if sps.partition_constraints.qtbtt_dual_tree_intra_flag {
+                    pc.log2_diff_min_qt_min_cb_intra_slice_chroma =
+                        self.reader.read_unsigned_exp_golomb();
+                    pc.max_mtt_hierarchy_depth_intra_slice_chroma =
+                        self.reader.read_unsigned_exp_golomb();
+                    if pc.max_mtt_hierarchy_depth_intra_slice_chroma != 0 {
+                        pc.log2_diff_max_bt_min_qt_intra_slice_chroma =
+                            self.reader.read_unsigned_exp_golomb();
+                        pc.log2_diff_max_tt_min_qt_intra_slice_chroma =
+                            self.reader.read_unsigned_exp_golomb();
+                    }
+                }
+            }
+            if pps.cu_qp_delta_enabled_flag {
+                ph.cu_qp_delta_subdiv_intra_slice = self.reader.read_unsigned_exp_golomb();
+            }
+            if pps
+                .chroma_tool_offsets
+                .cu_chroma_qp_offset_list_enabled_flag
+            {
+                ph.cu_chroma_qp_offset_subdiv_intra_slice = self.reader.read_unsigned_exp_golomb();
+            }
+        }
+        if ph.inter_slice_allowed_flag {
+            if let Some(pc) = ph.partition_constraints.as_mut() {
+                pc.log2_diff_min_qt_min_cb_inter_slice = self.reader.read_unsigned_exp_golomb();
+                pc.max_mtt_hierarchy_depth_inter_slice = self.reader.read_unsigned_exp_golomb();
+                if pc.max_mtt_hierarchy_depth_inter_slice != 0 {
+                    pc.log2_diff_max_bt_min_qt_inter_slice = self.reader.read_unsigned_exp_golomb();
+                    pc.log2_diff_max_tt_min_qt_inter_slice = self.reader.read_unsigned_exp_golomb();
+                }
+            }
+            if pps.cu_qp_delta_enabled_flag {
+                ph.cu_qp_delta_subdiv_inter_slice = self.reader.read_unsigned_exp_golomb();
+            }
+            if pps
+                .chroma_tool_offsets
+                .cu_chroma_qp_offset_list_enabled_flag
+            {
+                ph.cu_chroma_qp_offset_subdiv_inter_slice = self.reader.read_unsigned_exp_golomb();
+            }
+            if sps.temporal_mvp_enabled_flag {
+                ph.temporal_mvp_enabled_flag = self.reader.read_bit();
+            }
+            let rpls_idx = self.encoder_context.lock().unwrap().rpls_idx.clone();
+            let num_ref_entries =
+                |i: usize| ph.ref_pic_lists[i].ref_pic_list_structs[rpls_idx[i]].num_ref_entries;
+            if ph.temporal_mvp_enabled_flag && pps.partition_parameters.rpl_info_in_ph_flag {
+                let (num_ref_entries_l0, num_ref_entries_l1) =
+                    (num_ref_entries(0), num_ref_entries(1));
+                ph.collocated_from_l0_flag = if num_ref_entries_l1 > 0 {
+                    self.reader.read_bit()
+                } else {
+                    true
+                };
+                if (ph.collocated_from_l0_flag && num_ref_entries_l0 > 1)
+                    || (!ph.collocated_from_l0_flag && num_ref_entries_l1 > 1)
+                {
+                    ph.collocated_ref_idx = self.reader.read_unsigned_exp_golomb();
+                }
+            }
+            if sps.mmvd_fullpel_only_enabled_flag {
+                // the encoder writes ph_mmvd_fullpel_only_flag as ue(v)
+                ph.mmvd_fullpel_only_flag = self.reader.read_unsigned_exp_golomb() != 0;
+            }
+            let presence_flag =
+                !pps.partition_parameters.rpl_info_in_ph_flag || num_ref_entries(1) > 0;
+            if presence_flag {
+                ph.mvd_l1_zero_flag = self.reader.read_bit();
+                if sps.bdof_control_present_in_ph_flag {
+                    ph.bdof_disabled_flag = self.reader.read_bit();
+                }
+                if sps.dmvr_control_present_in_ph_flag {
+                    ph.dmvr_disabled_flag = self.reader.read_bit();
+                }
+            }
+            if (pps.weighted_pred_flag || pps.weighted_bipred_flag)
+                && pps.partition_parameters.wp_info_in_ph_flag
+            {
+                let ectx = self.encoder_context.clone();
+                let mut pwt_parser = PredWeightTableParser::new(&ectx, self.reader);
+                ph.pred_weight_table = Some(pwt_parser.parse(sps, pps, &ph.ref_pic_lists));
+            }
+        }
+        if pps.partition_parameters.qp_delta_info_in_ph_flag {
+            ph.qp_delta = self.reader.read_signed_exp_golomb();
+        }
+        self.encoder_context.lock().unwrap().slice_qp_y = pps.init_qp + ph.qp_delta;
+        if sps.joint_cbcr_enabled_flag {
+            ph.joint_cbcr_sign_flag = self.reader.read_bit();
+        }
+        if sps.sao_enabled_flag && pps.partition_parameters.sao_info_in_ph_flag {
+            ph.sao_luma_enabled_flag = self.reader.read_bit();
+            if sps.chroma_format != ChromaFormat::Monochrome {
+                ph.sao_chroma_enabled_flag = self.reader.read_bit();
+            }
+        }
+        let dfc = &pps.deblocking_filter_control;
+        ph.deblocking_filter_disabled_flag = dfc.deblocking_filter_disabled_flag;
+        ph.luma_beta_offset = dfc.luma_beta_offset;
+        ph.luma_tc_offset = dfc.luma_tc_offset;
+        ph.cb_beta_offset = dfc.cb_beta_offset;
+        ph.cb_tc_offset = dfc.cb_tc_offset;
+        ph.cr_beta_offset = dfc.cr_beta_offset;
+        ph.cr_tc_offset = dfc.cr_tc_offset;
+        if dfc.dbf_info_in_ph_flag {
+            ph.deblocking_params_present_flag = self.reader.read_bit();
+            if ph.deblocking_params_present_flag {
+                ph.deblocking_filter_disabled_flag = if !dfc.deblocking_filter_disabled_flag {
+                    self.reader.read_bit()
+                } else {
+                    false
+                };
+                if !ph.deblocking_filter_disabled_flag {
+                    ph.luma_beta_offset = self.reader.read_signed_exp_golomb() * 2;
+                    ph.luma_tc_offset = self.reader.read_signed_exp_golomb() * 2;
+                    if pps.chroma_tool_offsets_present_flag {
+                        ph.cb_beta_offset = self.reader.read_signed_exp_golomb() * 2;
+                        ph.cb_tc_offset = self.reader.read_signed_exp_golomb() * 2;
+                        ph.cr_beta_offset = self.reader.read_signed_exp_golomb() * 2;
+                        ph.cr_tc_offset = self.reader.read_signed_exp_golomb() * 2;
+                    } else {
+                        ph.cb_beta_offset = ph.luma_beta_offset;
+                        ph.cb_tc_offset = ph.luma_tc_offset;
+                        ph.cr_beta_offset = ph.luma_beta_offset;
+                        ph.cr_tc_offset = ph.luma_tc_offset;
+                    }
+                }
+            }
+        }
+        if pps.picture_header_extension_present_flag {
+            let len = self.reader.read_unsigned_exp_golomb();
+            ph.extension_data_byte = (0..len).map(|_| self.reader.read_value(8)).collect();
+        }
+
+        let rbsp_stop_one_bit = self.reader.read_bit();
+        assert!(rbsp_stop_one_bit);
+        self.reader.byte_align();
+        ph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bins::*;
+    use crate::bool_coder::*;
+    use crate::nal::*;
+    use crate::ph_encoder::*;
+    use crate::virtual_boundary::*;
+
+    fn round_trip(
+        ph: &PictureHeader,
+        sps: &SequenceParameterSet,
+        pps: &PictureParameterSet,
+    ) -> PictureHeader {
+        let new_encoder_context = || {
+            let mut ectx = EncoderContext::new();
+            ectx.update_from_sps(sps);
+            ectx.update_from_sps_and_pps(sps, pps);
+            Arc::new(Mutex::new(ectx))
+        };
+        let encode = |ectx: &Arc<Mutex<EncoderContext>>, ph: &PictureHeader| {
+            ectx.lock().unwrap().update_from_ph(ph, sps, pps);
+            let mut coder = BoolCoder::new();
+            let mut bins = Bins::new();
+            PhEncoder::new(ectx, &mut coder).encode(&mut bins, ph, sps, pps);
+            bins.into_iter().collect::<Vec<bool>>()
+        };
+        let bits = encode(&new_encoder_context(), ph);
+
+        let rbsp = write_and_read_rbsp(NALUnitType::PH_NUT, &bits);
+        let mut reader = BinaryReader::vec(&rbsp).unwrap();
+        let parsed_ectx = new_encoder_context();
+        let parsed = PhParser::new(&parsed_ectx, &mut reader).parse(sps, pps);
+        assert_eq!(encode(&parsed_ectx, &parsed), bits);
+        parsed
+    }
+
+    #[test]
+    fn ph_round_trip_works() {
+        let sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 1);
+        let pps = PictureParameterSet::new(1, &sps, None);
+        let ph = PictureHeader::new(&pps, PictureType::IRAP_IDR, true, 0);
+        let parsed = round_trip(&ph, &sps, &pps);
+        assert!(parsed.gdr_or_irap_pic_flag);
+        assert!(!parsed.inter_slice_allowed_flag);
+        assert_eq!(parsed.pic_parameter_set_id, 1);
+    }
+
+    #[test]
+    fn ph_with_gdr_and_deblocking_round_trip_works() {
+        let mut sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 1);
+        sps.gdr_enabled_flag = true;
+        sps.virtual_boundaries_enabled_flag = true;
+        let mut pps = PictureParameterSet::new(1, &sps, None);
+        pps.deblocking_filter_control.dbf_info_in_ph_flag = true;
+        pps.deblocking_filter_control
+            .deblocking_filter_disabled_flag = false;
+        let mut ph = PictureHeader::new(&pps, PictureType::GDR, true, 3);
+        ph.recovery_poc_cnt = 7;
+        ph.deblocking_params_present_flag = true;
+        ph.deblocking_filter_disabled_flag = false;
+        ph.luma_beta_offset = 2;
+        ph.luma_tc_offset = -4;
+        ph.virtual_boundary = VirtualBoundaryParameters::new_vertical(64);
+        let parsed = round_trip(&ph, &sps, &pps);
+        assert!(parsed.gdr_pic_flag);
+        assert_eq!(parsed.pic_order_cnt_lsb, 3);
+        assert_eq!(parsed.recovery_poc_cnt, 7);
+        assert_eq!(parsed.luma_tc_offset, -4);
+        assert_eq!(parsed.cr_beta_offset, 2);
+        assert!(parsed.virtual_boundary.virtual_boundaries_present_flag);
+        assert_eq!(parsed.virtual_boundary.virtual_boundary_pos_xs, vec![8]);
+        assert_eq!(parsed.virtual_boundary.num_hor_virtual_boundaries, 0);
+    }
+
+    #[test]
+    fn ph_with_rpl_and_partition_constraints_round_trip_works() {
+        let mut sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 1);
+        sps.temporal_mvp_enabled_flag = true;
+        sps.partition_constraints_override_enabled_flag = true;
+        let mut pps = PictureParameterSet::new(1, &sps, None);
+        pps.partition_parameters.rpl_info_in_ph_flag = true;
+        let mut ph = PictureHeader::new(&pps, PictureType::Trailing, false, 5);
+        ph.partition_constraints_override_flag = true;
+        let mut partition_constraints = sps.partition_constraints.clone();
+        partition_constraints.max_mtt_hierarchy_depth_inter_slice = 0;
+        ph.partition_constraints = Some(partition_constraints);
+        ph.temporal_mvp_enabled_flag = true;
+        ph.collocated_ref_idx = 1;
+        let parsed = round_trip(&ph, &sps, &pps);
+        assert!(parsed.inter_slice_allowed_flag);
+        assert!(parsed.ref_pic_lists[1].rpl_sps_flag);
+        assert!(!parsed.collocated_from_l0_flag);
+        assert_eq!(parsed.collocated_ref_idx, 1);
+        assert_eq!(
+            parsed
+                .partition_constraints
+                .unwrap()
+                .max_mtt_hierarchy_depth_inter_slice,
+            0
+        );
+    }
+}
@@ -10,6 +10,7 @@ use std::sync::{Arc, Mutex};
 #[allow(non_camel_case_types)]
 #[allow(clippy::upper_case_acronyms)]
 #[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum PictureType {
     IRAP_IDR, // Intra Random Access Point Instantaneous Decoding Refresh
     IRAP_CRA, // Intra Random Access Point Clean Random Access
@@ -23,6 +24,24 @@ pub enum PictureType {
     BiPredictive,
 }
 
+impl PictureType {
+    pub fn is_irap(&self) -> bool {
+        matches!(self, PictureType::IRAP_IDR | PictureType::IRAP_CRA)
+    }
+
+    pub fn nal_unit_type(&self) -> NALUnitType {
+        match self {
+            PictureType::IRAP_IDR => NALUnitType::IDR_W_RADL,
+            PictureType::IRAP_CRA => NALUnitType::CRA_NUT,
+            PictureType::GDR => NALUnitType::GDR_NUT,
+            PictureType::RADL => NALUnitType::RADL_NUT,
+            PictureType::RASL => NALUnitType::RASL_NUT,
+            PictureType::STLA => NALUnitType::STSA_NUT,
+            _ => NALUnitType::TRAIL_NUT,
+        }
+    }
+}
+
 pub struct Picture {
     pub picture_order_count: usize,
     pub picture_type: PictureType,
@@ -153,12 +172,12 @@ impl Picture {
         ctu_cols.push(self.num_ctu_cols);
         ctu_rows.push(self.num_ctu_rows);
         let mut tiles = vec![];
-        for (ctu_col_start, ctu_col_end) in
-            (0..(ctu_cols.len() - 1)).map(|i| (ctu_cols[i], ctu_cols[i + 1]))
+        for (ctu_row_start, ctu_row_end) in
+            (0..(ctu_rows.len() - 1)).map(|i| (ctu_rows[i], ctu_rows[i + 1]))
         {
             let mut row_tiles = vec![];
-            for (ctu_row_start, ctu_row_end) in
-                (0..(ctu_rows.len() - 1)).map(|i| (ctu_rows[i], ctu_rows[i + 1]))
+            for (ctu_col_start, ctu_col_end) in
+                (0..(ctu_cols.len() - 1)).map(|i| (ctu_cols[i], ctu_cols[i + 1]))
             {
                 let tile = Tile::new_arc_mutex(
                     ctu_col_start,
@@ -167,42 +186,15 @@ impl Picture {
                     ctu_row_end - ctu_row_start,
                     self.log2_ctu_size,
                     self.ctus.clone(),
+                    &self.pixels,
                 );
                 let ctus = self.ctus.lock().unwrap();
-                for row_ctus in ctus.iter() {
-                    for ctu in row_ctus.iter() {
+                for row_ctus in ctus[ctu_row_start..ctu_row_end].iter() {
+                    for ctu in row_ctus[ctu_col_start..ctu_col_end].iter() {
                         let ctu = &mut ctu.lock().unwrap();
                         ctu.set_tile(tile.clone());
                     }
                 }
-                {
-                    let tile = &mut tile.lock().unwrap();
-                    for c_idx in 0..3 {
-                        let ctu_size = 1 << tile.log2_ctu_size;
-                        if c_idx == 0 {
-                            let tile_pixels = &mut tile.original_pixels.borrow_mut()[c_idx];
-                            let pixels = &self.pixels[c_idx];
-                            for y in ctu_row_start * ctu_size..ctu_row_end * ctu_size {
-                                let tile_pixels = &mut tile_pixels[y - ctu_row_start * ctu_size];
-                                let pixels = &pixels[y][ctu_col_start * ctu_size..];
-                                let end = (ctu_col_end - ctu_col_start) * ctu_size;
-                                tile_pixels[..end].copy_from_slice(&pixels[..end]);
-                            }
-                        } else {
-                            // check chroma format
-                            let tile_pixels = &mut tile.original_pixels.borrow_mut()[c_idx];
-                            let pixels = &self.pixels[c_idx];
-                            for y in ctu_row_start * ctu_size / 2..ctu_row_end * ctu_size / 2 {
-                                let tile_pixels =
-                                    &mut tile_pixels[y - ctu_row_start * ctu_size / 2];
-                                let pixels = &pixels[y][ctu_col_start * ctu_size / 2..]
-                                    [ctu_col_start * ctu_size / 2..];
-                                let end = (ctu_col_end - ctu_col_start) * ctu_size / 2;
-                                tile_pixels[..end].copy_from_slice(&pixels[..end]);
-                            }
-                        }
-                    }
-                }
                 row_tiles.push(tile);
             }
             tiles.push(row_tiles);
@@ -216,13 +208,14 @@ impl Picture {
             assert!(!slice_structs.is_empty(), "slice_types must not be empty.");
         }
 
+        let num_tile_cols = self.tiles.lock().unwrap()[0].len();
         let mut slices = vec![];
         for slice_struct in slice_structs {
             let slice = Slice::new(
                 slice_struct,
                 nal_unit_type,
                 self.tiles.clone(),
-                self.num_ctu_cols,
+                num_tile_cols,
             );
             slices.push(Arc::new(Mutex::new(slice)));
         }
@@ -259,18 +252,18 @@ impl Picture {
                 }
             };
             let mut pixels = vec![0; width * height];
+            // each tile has its own reconstructed pixels, which are stitched into the picture
             let tiles = self.tiles.lock().unwrap();
-            for tile_rows in tiles.iter() {
-                for tile in tile_rows {
-                    let tile = tile.lock().unwrap();
-                    let reconst_pixels = &tile.reconst_pixels.borrow()[c_idx];
-                    let ty = tile.ctu_row << tile.log2_ctu_size;
-                    let tx = tile.ctu_col << tile.log2_ctu_size;
-                    for dy in 0..reconst_pixels.height {
-                        let reconst_pixels = &reconst_pixels[dy];
-                        let pixels = &mut pixels[(ty + dy) * width + tx..];
-                        pixels[..reconst_pixels.len()].copy_from_slice(reconst_pixels);
-                    }
+            for tile in tiles.iter().flatten() {
+                let tile = tile.lock().unwrap();
+                let tile_reconst_pixels = &tile.reconst_pixels.lock().unwrap()[c_idx];
+                let x_start = tile_reconst_pixels.x_origin;
+                let x_end = (x_start + tile_reconst_pixels.width).min(width);
+                let y_start = tile_reconst_pixels.y_origin;
+                let y_end = (y_start + tile_reconst_pixels.height).min(height);
+                for y in y_start..y_end {
+                    pixels[y * width + x_start..y * width + x_end]
+                        .copy_from_slice(&tile_reconst_pixels[y][x_start..x_end]);
                 }
             }
             reconst_pixels.push(pixels);
@@ -278,3 +271,48 @@ impl Picture {
         reconst_pixels
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_pixels_work() {
+        // 3x2 CTUs split into 2x2 tiles, with partial CTUs at the right and bottom
+        let mut picture = Picture::new(80, 48, None);
+        for pixels in picture.pixels.iter_mut() {
+            for y in 0..pixels.height {
+                for x in 0..pixels.width {
+                    pixels[y][x] = (x * 3 + y * 7) as u8;
+                }
+            }
+        }
+        picture.init_ctus(5);
+        picture.init_tiles(vec![0, 2], vec![0, 1]);
+
+        let tiles = picture.tiles.lock().unwrap().clone();
+        for tile in tiles.iter().flatten() {
+            let tile = tile.lock().unwrap();
+            let original_pixels = tile.original_pixels.lock().unwrap();
+            let reconst_pixels = &mut tile.reconst_pixels.lock().unwrap();
+            for c_idx in 0..3 {
+                let (tile_pixels, pixels) = (&original_pixels[c_idx], &picture.pixels[c_idx]);
+                let (x_start, y_start) = (tile_pixels.x_origin, tile_pixels.y_origin);
+                for y in y_start..(y_start + tile_pixels.height).min(pixels.height) {
+                    for x in x_start..(x_start + tile_pixels.width).min(pixels.width) {
+                        assert_eq!(tile_pixels[y][x], pixels[y][x]);
+                    }
+                    reconst_pixels[c_idx][y][x_start..].copy_from_slice(&tile_pixels[y][x_start..]);
+                }
+            }
+        }
+
+        let reconst_pixels = picture.get_reconst_pixels();
+        for (c_idx, pixels) in picture.pixels.iter().enumerate() {
+            let expected = (0..pixels.height)
+                .flat_map(|y| pixels[y].to_vec())
+                .collect::<Vec<u8>>();
+            assert_eq!(reconst_pixels[c_idx], expected);
+        }
+    }
+}
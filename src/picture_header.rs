@@ -1,4 +1,5 @@
 use super::partition::*;
+use super::picture::*;
 use super::pps::*;
 use super::pred_weight_table::*;
 use super::reference_picture::*;
@@ -88,15 +89,20 @@ pub struct PictureHeader {
 }
 
 impl PictureHeader {
-    pub fn new(pps: &PictureParameterSet, intra: bool, poc: usize) -> PictureHeader {
+    pub fn new(
+        pps: &PictureParameterSet,
+        picture_type: PictureType,
+        intra: bool,
+        pic_order_cnt_lsb: usize,
+    ) -> PictureHeader {
         PictureHeader {
-            gdr_or_irap_pic_flag: true,
+            gdr_or_irap_pic_flag: picture_type.is_irap() || picture_type == PictureType::GDR,
             non_ref_pic_flag: false,
-            gdr_pic_flag: false,
+            gdr_pic_flag: picture_type == PictureType::GDR,
             inter_slice_allowed_flag: !intra,
             intra_slice_allowed_flag: true,
             pic_parameter_set_id: pps.id,
-            pic_order_cnt_lsb: poc & 0b1111, // FIXME
+            pic_order_cnt_lsb,
             recovery_poc_cnt: 0,
             extra_bit: vec![],
             poc_msb_cycle_val: 0,
@@ -109,7 +115,11 @@ impl PictureHeader {
             scaling_list_aps_id: 0,
             virtual_boundary: VirtualBoundaryParameters::new(),
             pic_output_flag: true,
-            ref_pic_lists: [RefPicList::new(0), RefPicList::new(1)],
+            ref_pic_lists: if intra {
+                [RefPicList::new_empty(0), RefPicList::new_empty(1)]
+            } else {
+                [RefPicList::new(0), RefPicList::new(1)]
+            },
             partition_constraints_override_flag: false,
             partition_constraints: None,
             cu_qp_delta_subdiv_inter_slice: 0,
@@ -1,6 +1,8 @@
 use super::common::*;
+use super::slice::*;
 use super::sps::*;
 
+#[derive(Clone)]
 pub struct PpsSlice {
     pub slice_width_in_tiles: usize,
     pub slice_height_in_tiles: usize,
@@ -9,6 +11,7 @@ pub struct PpsSlice {
     pub tile_idx_delta_val: isize,
 }
 
+#[derive(Clone)]
 pub struct PartitionParameters {
     pub loop_filter_across_tiles_enabled_flag: bool,
     pub rect_slice_flag: bool,
@@ -43,6 +46,7 @@ impl PartitionParameters {
     }
 }
 
+#[derive(Clone)]
 pub struct PpsChromaToolOffsets {
     pub cb_qp_offset: isize,
     pub cr_qp_offset: isize,
@@ -73,6 +77,7 @@ impl PpsChromaToolOffsets {
     }
 }
 
+#[derive(Clone)]
 pub struct PpsDeblockingFilterControl {
     pub deblocking_filter_override_enabled_flag: bool,
     pub deblocking_filter_disabled_flag: bool,
@@ -101,6 +106,7 @@ impl PpsDeblockingFilterControl {
     }
 }
 
+#[derive(Clone)]
 pub struct PictureParameterSet {
     pub id: usize,
     pub seq_parameter_set_id: usize,
@@ -195,4 +201,284 @@ impl PictureParameterSet {
             extension_data: vec![],
         }
     }
+    /// Sets the tile grid given by the first CTU column of each tile column and the first CTU row
+    /// of each tile row. Trailing sizes that can be inferred from the last explicit one are not
+    /// signalled.
+    pub fn set_tiles(
+        &mut self,
+        log2_ctu_size: usize,
+        ctu_cols: &[usize],
+        ctu_rows: &[usize],
+        num_ctu_cols: usize,
+        num_ctu_rows: usize,
+    ) {
+        let get_sizes = |starts: &[usize], num_ctus: usize| {
+            (0..starts.len())
+                .map(|i| starts.get(i + 1).unwrap_or(&num_ctus) - starts[i])
+                .collect::<Vec<usize>>()
+        };
+        let tile_column_widths = get_sizes(ctu_cols, num_ctu_cols);
+        let tile_row_heights = get_sizes(ctu_rows, num_ctu_rows);
+        self.log2_ctu_size = log2_ctu_size;
+        self.num_exp_tile_columns = get_num_exp_tile_sizes(&tile_column_widths, num_ctu_cols);
+        self.num_exp_tile_rows = get_num_exp_tile_sizes(&tile_row_heights, num_ctu_rows);
+        self.tile_column_widths = tile_column_widths[..self.num_exp_tile_columns].to_vec();
+        self.tile_column_heights = tile_row_heights[..self.num_exp_tile_rows].to_vec();
+        if ctu_cols.len() * ctu_rows.len() > 1 {
+            self.no_pic_partition_flag = false;
+            // a single rectangular slice covering all tiles
+            self.partition_parameters.rect_slice_flag = true;
+            self.partition_parameters.single_slice_per_subpic_flag = true;
+        }
+    }
+
+    /// Sets the slice layout given by the number of tile columns and the height in CTUs of each
+    /// tile row. Rectangular slices are signalled here unless the picture is a single slice,
+    /// while raster-scan slices are given by slice headers. Returns an error if the layout cannot
+    /// be signalled.
+    pub fn set_slices(
+        &mut self,
+        slice_types: &[SliceStruct],
+        num_tile_cols: usize,
+        tile_row_heights: &[usize],
+    ) -> Result<(), String> {
+        if slice_types.len() == 1 {
+            return Ok(());
+        }
+        let num_raster_slices = slice_types
+            .iter()
+            .filter(|slice_type| matches!(slice_type, SliceStruct::Raster { .. }))
+            .count();
+        if num_raster_slices > 0 && num_raster_slices < slice_types.len() {
+            return Err("raster-scan and rectangular slices cannot be mixed".to_string());
+        }
+        self.no_pic_partition_flag = false;
+        let partition_parameters = &mut self.partition_parameters;
+        partition_parameters.single_slice_per_subpic_flag = false;
+        if num_raster_slices > 0 {
+            partition_parameters.rect_slice_flag = false;
+            return Ok(());
+        }
+        partition_parameters.rect_slice_flag = true;
+        partition_parameters.num_slices_in_pic = slice_types.len();
+        partition_parameters.slices = slice_types
+            .iter()
+            .map(|_| PpsSlice {
+                slice_width_in_tiles: 1,
+                slice_height_in_tiles: 1,
+                num_exp_slices_in_tile: 0,
+                exp_slice_height_in_ctus: vec![],
+                tile_idx_delta_val: 0,
+            })
+            .collect();
+        let get_top_left_tile_idx = |slice_type: &SliceStruct| match *slice_type {
+            SliceStruct::Rectangle {
+                tile_col, tile_row, ..
+            }
+            | SliceStruct::RectangleInTile {
+                tile_col, tile_row, ..
+            } => tile_row * num_tile_cols + tile_col,
+            SliceStruct::Raster { .. } => unreachable!(),
+        };
+        // whether tile indices of slices can be inferred from the sizes of preceding slices
+        let mut is_tile_idx_inferable = true;
+        let mut i = 0;
+        while i < slice_types.len() {
+            let tile_idx = get_top_left_tile_idx(&slice_types[i]);
+            match slice_types[i] {
+                SliceStruct::Rectangle {
+                    num_tile_cols: slice_width_in_tiles,
+                    num_tile_rows: slice_height_in_tiles,
+                    ..
+                } => {
+                    let slice = &mut partition_parameters.slices[i];
+                    slice.slice_width_in_tiles = slice_width_in_tiles;
+                    slice.slice_height_in_tiles = slice_height_in_tiles;
+                }
+                SliceStruct::RectangleInTile { tile_row, .. } => {
+                    let num_slices_in_tile = slice_types[i..]
+                        .iter()
+                        .take_while(|slice_type| {
+                            matches!(slice_type, SliceStruct::RectangleInTile { .. })
+                                && get_top_left_tile_idx(slice_type) == tile_idx
+                        })
+                        .count();
+                    let slice_heights = slice_types[i..i + num_slices_in_tile]
+                        .iter()
+                        .map(|slice_type| match *slice_type {
+                            SliceStruct::RectangleInTile { num_ctu_rows, .. } => num_ctu_rows,
+                            _ => unreachable!(),
+                        })
+                        .collect::<Vec<usize>>();
+                    let tile_height = tile_row_heights[tile_row];
+                    if slice_heights.iter().sum::<usize>() != tile_height {
+                        return Err(format!(
+                            "slices in tile {} do not cover its {} CTU rows",
+                            tile_idx, tile_height
+                        ));
+                    }
+                    let slice = &mut partition_parameters.slices[i];
+                    slice.num_exp_slices_in_tile = (1..=num_slices_in_tile)
+                        .find(|&n| {
+                            derive_tile_sizes(&slice_heights[..n], tile_height) == slice_heights
+                        })
+                        .unwrap();
+                    slice.exp_slice_height_in_ctus =
+                        slice_heights[..slice.num_exp_slices_in_tile].to_vec();
+                    i += num_slices_in_tile - 1;
+                }
+                SliceStruct::Raster { .. } => unreachable!(),
+            }
+            if i + 1 < slice_types.len() {
+                // slice heights not in the first or the last tile row are inferred from the
+                // preceding slice unless tile indices are signalled
+                if i > 0
+                    && tile_idx % num_tile_cols != 0
+                    && tile_idx / num_tile_cols != tile_row_heights.len() - 1
+                {
+                    is_tile_idx_inferable &= partition_parameters.slices[i].slice_height_in_tiles
+                        == partition_parameters.slices[i - 1].slice_height_in_tiles;
+                }
+                let slice = &mut partition_parameters.slices[i];
+                let next_tile_idx = get_top_left_tile_idx(&slice_types[i + 1]);
+                let mut inferred_tile_idx = tile_idx + slice.slice_width_in_tiles;
+                // the next slice starts below the slice if the slice reaches the right end
+                if inferred_tile_idx / num_tile_cols != tile_idx / num_tile_cols {
+                    inferred_tile_idx += (slice.slice_height_in_tiles - 1) * num_tile_cols;
+                }
+                slice.tile_idx_delta_val = next_tile_idx as isize - tile_idx as isize;
+                is_tile_idx_inferable &= next_tile_idx == inferred_tile_idx;
+            }
+            i += 1;
+        }
+        // tile indices of slices are signalled only with more than two slices
+        if !is_tile_idx_inferable && slice_types.len() <= 2 {
+            return Err("tile indices of the slices cannot be signalled".to_string());
+        }
+        partition_parameters.tile_idx_delta_present_flag = !is_tile_idx_inferable;
+        Ok(())
+    }
+}
+
+/// Derives the widths or heights of all tile columns or rows in CTBs from the explicitly
+/// signalled ones, where the last one is repeated for the rest of the picture (6.5.1).
+/// Returns the number of explicit tile sizes needed to signal sizes, where trailing sizes that
+/// can be inferred from the last explicit one are not signalled.
+pub fn get_num_exp_tile_sizes(sizes: &[usize], num_ctbs: usize) -> usize {
+    (1..=sizes.len())
+        .find(|&n| derive_tile_sizes(&sizes[..n], num_ctbs) == sizes)
+        .unwrap()
+}
+
+pub fn derive_tile_sizes(exp_sizes: &[usize], num_ctbs: usize) -> Vec<usize> {
+    let mut sizes = vec![];
+    let mut remaining = num_ctbs;
+    for &size in exp_sizes {
+        sizes.push(size);
+        remaining -= size;
+    }
+    let uniform_size = *exp_sizes.last().unwrap();
+    while remaining >= uniform_size {
+        sizes.push(uniform_size);
+        remaining -= uniform_size;
+    }
+    if remaining > 0 {
+        sizes.push(remaining);
+    }
+    sizes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slice_splitter::*;
+
+    fn rect(tile_col: usize, tile_row: usize) -> SliceStruct {
+        SliceStruct::Rectangle {
+            tile_col,
+            tile_row,
+            num_tile_cols: 1,
+            num_tile_rows: 1,
+        }
+    }
+
+    fn set_slices(slice_types: &[SliceStruct], tile_row_heights: &[usize]) -> Result<(), String> {
+        let sps = SequenceParameterSet::new(1, 8, 128, 128, 8, 1);
+        let mut pps = PictureParameterSet::new(1, &sps, None);
+        pps.set_slices(slice_types, 2, tile_row_heights)
+    }
+
+    #[test]
+    fn set_slices_works() {
+        let slice_types = [rect(0, 0), rect(1, 0), rect(0, 1), rect(1, 1)];
+        assert!(set_slices(&slice_types, &[2, 2]).is_ok());
+        let slice_types = [
+            SliceStruct::RectangleInTile {
+                tile_col: 0,
+                tile_row: 0,
+                ctu_row_offset: 0,
+                num_ctu_rows: 3,
+            },
+            SliceStruct::RectangleInTile {
+                tile_col: 0,
+                tile_row: 0,
+                ctu_row_offset: 3,
+                num_ctu_rows: 1,
+            },
+            rect(1, 0),
+        ];
+        assert!(set_slices(&slice_types, &[4]).is_ok());
+    }
+
+    #[test]
+    fn set_slices_works_with_tile_slice_types() {
+        // slices of CTU rows of different heights in the first tile, as ended during encoding
+        let mut slice_types = get_tile_slice_types(0, 0, &[2, 1, 1]);
+        slice_types.extend(get_tile_slice_types(1, 0, &[4]));
+        let sps = SequenceParameterSet::new(1, 8, 128, 128, 8, 1);
+        let mut pps = PictureParameterSet::new(1, &sps, None);
+        assert!(pps.set_slices(&slice_types, 2, &[4]).is_ok());
+        let partition_parameters = &pps.partition_parameters;
+        assert_eq!(partition_parameters.num_slices_in_pic, 4);
+        assert_eq!(partition_parameters.slices[0].num_exp_slices_in_tile, 2);
+        assert_eq!(
+            partition_parameters.slices[0].exp_slice_height_in_ctus,
+            vec![2, 1]
+        );
+        assert_eq!(partition_parameters.slices[3].slice_width_in_tiles, 1);
+    }
+
+    #[test]
+    fn set_slices_rejects_unsignallable_layouts() {
+        let mixed_slice_types = [
+            SliceStruct::Raster {
+                tile_col: 0,
+                tile_row: 0,
+                num_tiles: 1,
+            },
+            rect(1, 0),
+        ];
+        assert!(set_slices(&mixed_slice_types, &[4]).is_err());
+        // the slices in the first tile cover 3 of its 4 CTU rows
+        let uncovered_slice_types = [
+            SliceStruct::RectangleInTile {
+                tile_col: 0,
+                tile_row: 0,
+                ctu_row_offset: 0,
+                num_ctu_rows: 2,
+            },
+            SliceStruct::RectangleInTile {
+                tile_col: 0,
+                tile_row: 0,
+                ctu_row_offset: 2,
+                num_ctu_rows: 1,
+            },
+            rect(1, 0),
+        ];
+        assert!(set_slices(&uncovered_slice_types, &[4]).is_err());
+        // the second slice starts below the first one, which is not inferable, while tile
+        // indices are signalled only with more than two slices
+        let skipping_slice_types = [rect(0, 0), rect(0, 1)];
+        assert!(set_slices(&skipping_slice_types, &[2, 2]).is_err());
+    }
 }
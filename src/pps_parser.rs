@@ -0,0 +1,368 @@
+use super::binary_reader::*;
+use super::common::*;
+use super::encoder_context::*;
+use super::pps::*;
+use super::sps::*;
+use std::sync::{Arc, Mutex};
+
+pub struct PpsParser<'a, 'b> {
+    reader: &'a mut BinaryReader<'b>,
+    _encoder_context: Arc<Mutex<EncoderContext>>,
+}
+
+impl<'a, 'b> PpsParser<'a, 'b> {
+    pub fn new(
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        reader: &'a mut BinaryReader<'b>,
+    ) -> PpsParser<'a, 'b> {
+        PpsParser {
+            reader,
+            _encoder_context: encoder_context.clone(),
+        }
+    }
+
+    /// Parses pic_parameter_set_rbsp( ) referring to sps. The tile and rectangular slice layout
+    /// needed during parsing is derived here, and the caller is expected to call
+    /// update_from_sps_and_pps afterwards.
+    pub fn parse(&mut self, sps: &SequenceParameterSet) -> PictureParameterSet {
+        let id = self.reader.read_value(6);
+        let mut pps = PictureParameterSet::new(id, sps, None);
+        pps.seq_parameter_set_id = self.reader.read_value(4);
+        assert_eq!(pps.seq_parameter_set_id, sps.id);
+        pps.mixed_nalu_types_in_pic_flag = self.reader.read_bit();
+        pps.pic_width_in_luma_samples = self.reader.read_unsigned_exp_golomb();
+        pps.pic_height_in_luma_samples = self.reader.read_unsigned_exp_golomb();
+        let conformance_window_present_flag = self.reader.read_bit();
+        if conformance_window_present_flag {
+            let mut conformance_window = WindowOffset::new();
+            conformance_window.left_offset = self.reader.read_unsigned_exp_golomb() as isize;
+            conformance_window.right_offset = self.reader.read_unsigned_exp_golomb() as isize;
+            conformance_window.top_offset = self.reader.read_unsigned_exp_golomb() as isize;
+            conformance_window.bottom_offset = self.reader.read_unsigned_exp_golomb() as isize;
+            pps.conformance_window = Some(conformance_window);
+        }
+        pps.scaling_window_explicit_signalling_flag = self.reader.read_bit();
+        if pps.scaling_window_explicit_signalling_flag {
+            pps.scaling_window.left_offset = self.reader.read_signed_exp_golomb();
+            pps.scaling_window.right_offset = self.reader.read_signed_exp_golomb();
+            pps.scaling_window.top_offset = self.reader.read_signed_exp_golomb();
+            pps.scaling_window.bottom_offset = self.reader.read_signed_exp_golomb();
+        }
+        pps.output_flag_present_flag = self.reader.read_bit();
+        pps.no_pic_partition_flag = self.reader.read_bit();
+        pps.subpic_id_mapping_present_flag = self.reader.read_bit();
+        pps.num_subpics = match &sps.subpic_info {
+            Some(subpic_info) => subpic_info.num_subpics,
+            None => 1,
+        };
+        if pps.subpic_id_mapping_present_flag {
+            if !pps.no_pic_partition_flag {
+                pps.num_subpics = self.reader.read_unsigned_exp_golomb() + 1;
+            }
+            pps.subpic_id_len = self.reader.read_unsigned_exp_golomb() + 1;
+            pps.subpic_id = (0..pps.num_subpics)
+                .map(|_| self.reader.read_value(pps.subpic_id_len))
+                .collect();
+        }
+        if !pps.no_pic_partition_flag {
+            self.parse_partition_parameters(&mut pps);
+        }
+        pps.cabac_init_present_flag = self.reader.read_bit();
+        for i in 0..2 {
+            pps.num_ref_idx_default_active[i] = self.reader.read_unsigned_exp_golomb() + 1;
+        }
+        pps.rpl1_idx_present_flag = self.reader.read_bit();
+        pps.weighted_pred_flag = self.reader.read_bit();
+        pps.weighted_bipred_flag = self.reader.read_bit();
+        pps.ref_wraparound_enabled_flag = self.reader.read_bit();
+        if pps.ref_wraparound_enabled_flag {
+            pps.pic_width_minus_wraparound_offset = self.reader.read_unsigned_exp_golomb();
+        }
+        pps.init_qp = self.reader.read_signed_exp_golomb() + 26;
+        pps.cu_qp_delta_enabled_flag = self.reader.read_bit();
+        pps.chroma_tool_offsets_present_flag = self.reader.read_bit();
+        if pps.chroma_tool_offsets_present_flag {
+            let chroma_tool_offsets = &mut pps.chroma_tool_offsets;
+            chroma_tool_offsets.cb_qp_offset = self.reader.read_signed_exp_golomb();
+            chroma_tool_offsets.cr_qp_offset = self.reader.read_signed_exp_golomb();
+            chroma_tool_offsets.joint_cbcr_qp_offset_present_flag = self.reader.read_bit();
+            if chroma_tool_offsets.joint_cbcr_qp_offset_present_flag {
+                chroma_tool_offsets.joint_cbcr_qp_offset_value =
+                    self.reader.read_signed_exp_golomb();
+            }
+            chroma_tool_offsets.slice_chroma_qp_offsets_present_flag = self.reader.read_bit();
+            chroma_tool_offsets.cu_chroma_qp_offset_list_enabled_flag = self.reader.read_bit();
+            if chroma_tool_offsets.cu_chroma_qp_offset_list_enabled_flag {
+                chroma_tool_offsets.chroma_qp_offset_list_len =
+                    self.reader.read_unsigned_exp_golomb() + 1;
+                for _ in 0..chroma_tool_offsets.chroma_qp_offset_list_len {
+                    chroma_tool_offsets
+                        .cb_qp_offset_list
+                        .push(self.reader.read_signed_exp_golomb());
+                    chroma_tool_offsets
+                        .cr_qp_offset_list
+                        .push(self.reader.read_signed_exp_golomb());
+                    if chroma_tool_offsets.joint_cbcr_qp_offset_present_flag {
+                        chroma_tool_offsets
+                            .joint_cbcr_qp_offset_list
+                            .push(self.reader.read_signed_exp_golomb());
+                    }
+                }
+            }
+        }
+        pps.deblocking_filter_control_present_flag = self.reader.read_bit();
+        let dfc = &mut pps.deblocking_filter_control;
+        if pps.deblocking_filter_control_present_flag {
+            dfc.deblocking_filter_override_enabled_flag = self.reader.read_bit();
+            dfc.deblocking_filter_disabled_flag = self.reader.read_bit();
+            if !pps.no_pic_partition_flag && dfc.deblocking_filter_override_enabled_flag {
+                dfc.dbf_info_in_ph_flag = self.reader.read_bit();
+            }
+            if !dfc.deblocking_filter_disabled_flag {
+                dfc.luma_beta_offset = self.reader.read_signed_exp_golomb() * 2;
+                dfc.luma_tc_offset = self.reader.read_signed_exp_golomb() * 2;
+                if pps.chroma_tool_offsets_present_flag {
+                    dfc.cb_beta_offset = self.reader.read_signed_exp_golomb() * 2;
+                    dfc.cb_tc_offset = self.reader.read_signed_exp_golomb() * 2;
+                    dfc.cr_beta_offset = self.reader.read_signed_exp_golomb() * 2;
+                    dfc.cr_tc_offset = self.reader.read_signed_exp_golomb() * 2;
+                } else {
+                    // the chroma offsets are inferred to be equal to the luma ones
+                    dfc.cb_beta_offset = dfc.luma_beta_offset;
+                    dfc.cb_tc_offset = dfc.luma_tc_offset;
+                    dfc.cr_beta_offset = dfc.luma_beta_offset;
+                    dfc.cr_tc_offset = dfc.luma_tc_offset;
+                }
+            }
+        } else {
+            dfc.deblocking_filter_disabled_flag = false;
+        }
+        if !pps.no_pic_partition_flag {
+            let partition_parameters = &mut pps.partition_parameters;
+            partition_parameters.rpl_info_in_ph_flag = self.reader.read_bit();
+            partition_parameters.sao_info_in_ph_flag = self.reader.read_bit();
+            partition_parameters.alf_info_in_ph_flag = self.reader.read_bit();
+            if (pps.weighted_pred_flag || pps.weighted_bipred_flag)
+                && partition_parameters.rpl_info_in_ph_flag
+            {
+                partition_parameters.wp_info_in_ph_flag = self.reader.read_bit();
+            }
+            partition_parameters.qp_delta_info_in_ph_flag = self.reader.read_bit();
+        }
+        pps.picture_header_extension_present_flag = self.reader.read_bit();
+        pps.slice_header_extension_present_flag = self.reader.read_bit();
+        let extension_data_present_flag = self.reader.read_bit();
+        if extension_data_present_flag {
+            while self.reader.more_rbsp_data() {
+                pps.extension_data.push(self.reader.read_bit());
+            }
+        }
+        let _rbsp_stop_one_bit = self.reader.read_bit();
+        pps
+    }
+
+    fn parse_partition_parameters(&mut self, pps: &mut PictureParameterSet) {
+        pps.log2_ctu_size = self.reader.read_value(2) + 5;
+        pps.num_exp_tile_columns = self.reader.read_unsigned_exp_golomb() + 1;
+        pps.num_exp_tile_rows = self.reader.read_unsigned_exp_golomb() + 1;
+        pps.tile_column_widths = (0..pps.num_exp_tile_columns)
+            .map(|_| self.reader.read_unsigned_exp_golomb() + 1)
+            .collect();
+        pps.tile_column_heights = (0..pps.num_exp_tile_rows)
+            .map(|_| self.reader.read_unsigned_exp_golomb() + 1)
+            .collect();
+        let ctb_size_y = 1 << pps.log2_ctu_size;
+        let num_tile_columns = derive_tile_sizes(
+            &pps.tile_column_widths,
+            pps.pic_width_in_luma_samples.div_ceil(ctb_size_y),
+        )
+        .len();
+        let row_height_val = derive_tile_sizes(
+            &pps.tile_column_heights,
+            pps.pic_height_in_luma_samples.div_ceil(ctb_size_y),
+        );
+        let num_tile_rows = row_height_val.len();
+        let num_tiles_in_pic = num_tile_columns * num_tile_rows;
+        let partition_parameters = &mut pps.partition_parameters;
+        if num_tiles_in_pic > 1 {
+            partition_parameters.loop_filter_across_tiles_enabled_flag = self.reader.read_bit();
+            partition_parameters.rect_slice_flag = self.reader.read_bit();
+        } else {
+            partition_parameters.rect_slice_flag = true;
+        }
+        partition_parameters.single_slice_per_subpic_flag = if partition_parameters.rect_slice_flag
+        {
+            self.reader.read_bit()
+        } else {
+            false
+        };
+        if partition_parameters.rect_slice_flag
+            && !partition_parameters.single_slice_per_subpic_flag
+        {
+            let num_slices_in_pic = self.reader.read_unsigned_exp_golomb() + 1;
+            partition_parameters.num_slices_in_pic = num_slices_in_pic;
+            partition_parameters.tile_idx_delta_present_flag = if num_slices_in_pic > 2 {
+                self.reader.read_bit()
+            } else {
+                false
+            };
+            let slices = &mut partition_parameters.slices;
+            *slices = (0..num_slices_in_pic)
+                .map(|_| PpsSlice {
+                    slice_width_in_tiles: 1,
+                    slice_height_in_tiles: 1,
+                    num_exp_slices_in_tile: 0,
+                    exp_slice_height_in_ctus: vec![],
+                    tile_idx_delta_val: 0,
+                })
+                .collect();
+            let mut tile_idx = 0;
+            let mut i = 0;
+            while i < num_slices_in_pic - 1 {
+                let tile_x = tile_idx % num_tile_columns;
+                let tile_y = tile_idx / num_tile_columns;
+                if tile_x != num_tile_columns - 1 {
+                    slices[i].slice_width_in_tiles = self.reader.read_unsigned_exp_golomb() + 1;
+                }
+                if tile_y != num_tile_rows - 1
+                    && (partition_parameters.tile_idx_delta_present_flag || tile_x == 0)
+                {
+                    slices[i].slice_height_in_tiles = self.reader.read_unsigned_exp_golomb() + 1;
+                } else if tile_y != num_tile_rows - 1 && i > 0 {
+                    slices[i].slice_height_in_tiles = slices[i - 1].slice_height_in_tiles;
+                }
+                let slice_width_in_tiles = slices[i].slice_width_in_tiles;
+                let slice_height_in_tiles = slices[i].slice_height_in_tiles;
+                if slice_width_in_tiles == 1
+                    && slice_height_in_tiles == 1
+                    && row_height_val[tile_y] > 1
+                {
+                    let slice = &mut slices[i];
+                    slice.num_exp_slices_in_tile = self.reader.read_unsigned_exp_golomb();
+                    slice.exp_slice_height_in_ctus = (0..slice.num_exp_slices_in_tile)
+                        .map(|_| self.reader.read_unsigned_exp_golomb() + 1)
+                        .collect();
+                    if slice.num_exp_slices_in_tile > 0 {
+                        i += derive_tile_sizes(
+                            &slice.exp_slice_height_in_ctus,
+                            row_height_val[tile_y],
+                        )
+                        .len()
+                            - 1;
+                    }
+                }
+                if partition_parameters.tile_idx_delta_present_flag && i < num_slices_in_pic - 1 {
+                    slices[i].tile_idx_delta_val = self.reader.read_signed_exp_golomb();
+                    tile_idx = (tile_idx as isize + slices[i].tile_idx_delta_val) as usize;
+                } else {
+                    tile_idx += slice_width_in_tiles;
+                    let next_tile_x = tile_idx % num_tile_columns;
+                    if next_tile_x == 0 {
+                        tile_idx += (slice_height_in_tiles - 1) * num_tile_columns;
+                    }
+                }
+                i += 1;
+            }
+        }
+        if !partition_parameters.rect_slice_flag
+            || partition_parameters.single_slice_per_subpic_flag
+            || partition_parameters.num_slices_in_pic > 1
+        {
+            partition_parameters.loop_filter_across_slices_enabled_flag = self.reader.read_bit();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bool_coder::*;
+    use crate::nal::*;
+    use crate::pps_encoder::*;
+    use crate::slice::*;
+
+    fn round_trip(sps: &SequenceParameterSet, pps: &PictureParameterSet) -> PictureParameterSet {
+        let ectx = Arc::new(Mutex::new(EncoderContext::new()));
+        ectx.lock().unwrap().update_from_sps(sps);
+        ectx.lock().unwrap().update_from_sps_and_pps(sps, pps);
+        let mut coder = BoolCoder::new();
+        let bits = PpsEncoder::new(&ectx, &mut coder).encode(pps);
+
+        let rbsp = write_and_read_rbsp(NALUnitType::PPS_NUT, &bits);
+        let mut reader = BinaryReader::vec(&rbsp).unwrap();
+        let parsed_ectx = Arc::new(Mutex::new(EncoderContext::new()));
+        parsed_ectx.lock().unwrap().update_from_sps(sps);
+        let parsed = PpsParser::new(&parsed_ectx, &mut reader).parse(sps);
+        parsed_ectx
+            .lock()
+            .unwrap()
+            .update_from_sps_and_pps(sps, &parsed);
+        let reencoded = PpsEncoder::new(&parsed_ectx, &mut coder).encode(&parsed);
+        assert_eq!(reencoded, bits);
+        parsed
+    }
+
+    #[test]
+    fn pps_round_trip_works() {
+        let sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 1);
+        let pps = PictureParameterSet::new(1, &sps, Some(32));
+        let parsed = round_trip(&sps, &pps);
+        assert_eq!(parsed.id, 1);
+        assert!(parsed.no_pic_partition_flag);
+        assert_eq!(parsed.init_qp, pps.init_qp);
+    }
+
+    #[test]
+    fn pps_with_rect_slices_round_trip_works() {
+        let sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 1);
+        let mut pps = PictureParameterSet::new(1, &sps, None);
+        pps.set_tiles(sps.log2_ctu_size, &[0, 2], &[0, 1], 4, 2);
+        let slice_types = (0..4)
+            .map(|i| SliceStruct::Rectangle {
+                tile_col: i % 2,
+                tile_row: i / 2,
+                num_tile_cols: 1,
+                num_tile_rows: 1,
+            })
+            .collect::<Vec<SliceStruct>>();
+        pps.set_slices(&slice_types, 2, &[1, 1]).unwrap();
+        let parsed = round_trip(&sps, &pps);
+        assert!(!parsed.no_pic_partition_flag);
+        let partition_parameters = &parsed.partition_parameters;
+        assert!(partition_parameters.rect_slice_flag);
+        assert_eq!(partition_parameters.num_slices_in_pic, 4);
+    }
+
+    #[test]
+    fn pps_with_raster_slices_round_trip_works() {
+        let sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 1);
+        let mut pps = PictureParameterSet::new(1, &sps, None);
+        pps.set_tiles(sps.log2_ctu_size, &[0, 1, 3], &[0], 4, 2);
+        let slice_types = [
+            SliceStruct::Raster {
+                tile_col: 0,
+                tile_row: 0,
+                num_tiles: 1,
+            },
+            SliceStruct::Raster {
+                tile_col: 1,
+                tile_row: 0,
+                num_tiles: 2,
+            },
+        ];
+        pps.set_slices(&slice_types, 3, &[2]).unwrap();
+        let parsed = round_trip(&sps, &pps);
+        assert!(!parsed.partition_parameters.rect_slice_flag);
+    }
+
+    #[test]
+    fn pps_with_subpics_round_trip_works() {
+        let mut sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 1);
+        sps.set_subpics(&[(0, 0, 2, 2), (2, 0, 2, 2)]);
+        let mut pps = PictureParameterSet::new(1, &sps, None);
+        pps.set_tiles(sps.log2_ctu_size, &[0, 2], &[0], 4, 2);
+        let parsed = round_trip(&sps, &pps);
+        assert_eq!(parsed.num_subpics, 2);
+        assert!(parsed.partition_parameters.single_slice_per_subpic_flag);
+    }
+}
@@ -27,7 +27,7 @@ impl ProfileTierLevel {
             general_constraints_info: None,
             ptl_num_sub_profiles: 0,
             general_sub_profile_idcs: vec![],
-            sub_layer_level_idcs: vec![],
+            sub_layer_level_idcs: vec![None; 6],
         }
     }
 }
@@ -0,0 +1,59 @@
+use super::binary_reader::*;
+use super::encoder_context::*;
+use super::gci_parser::*;
+use super::ptl::*;
+use std::sync::{Arc, Mutex};
+
+pub struct PtlParser<'a, 'b> {
+    reader: &'a mut BinaryReader<'b>,
+    encoder_context: Arc<Mutex<EncoderContext>>,
+}
+
+impl<'a, 'b> PtlParser<'a, 'b> {
+    pub fn new(
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        reader: &'a mut BinaryReader<'b>,
+    ) -> PtlParser<'a, 'b> {
+        PtlParser {
+            reader,
+            encoder_context: encoder_context.clone(),
+        }
+    }
+
+    pub fn parse(
+        &mut self,
+        profile_tier_present_flag: bool,
+        max_num_sublayers: usize,
+    ) -> ProfileTierLevel {
+        let mut ptl = ProfileTierLevel::new(profile_tier_present_flag);
+        if ptl.pt_present_flags {
+            ptl.general_profile_idc = self.reader.read_value(7);
+            ptl.general_tier_flag = self.reader.read_bit();
+        }
+        ptl.general_level_idc = self.reader.read_value(8);
+        ptl.ptl_frame_only_constraint_flag = self.reader.read_bit();
+        ptl.ptl_multilayer_enabled_flag = self.reader.read_bit();
+        if ptl.pt_present_flags {
+            let ectx = self.encoder_context.clone();
+            let mut gci_parser = GCIParser::new(&ectx, self.reader);
+            ptl.general_constraints_info = gci_parser.parse();
+        }
+        let mut sublayer_level_present_flags = vec![false; max_num_sublayers];
+        for i in (0..max_num_sublayers - 1).rev() {
+            sublayer_level_present_flags[i] = self.reader.read_bit();
+        }
+        self.reader.byte_align();
+        for i in (0..max_num_sublayers - 1).rev() {
+            if sublayer_level_present_flags[i] {
+                ptl.sub_layer_level_idcs[i] = Some(self.reader.read_value(8));
+            }
+        }
+        if ptl.pt_present_flags {
+            ptl.ptl_num_sub_profiles = self.reader.read_value(8);
+            ptl.general_sub_profile_idcs = (0..ptl.ptl_num_sub_profiles)
+                .map(|_| Some(self.reader.read_value(32)))
+                .collect();
+        }
+        ptl
+    }
+}
@@ -0,0 +1,141 @@
+use super::binary_reader::*;
+use super::common::*;
+use super::encoder_context::*;
+use super::pps::*;
+use super::pred_weight_table::*;
+use super::reference_picture::*;
+use super::sps::*;
+use std::sync::{Arc, Mutex};
+
+pub struct PredWeightTableParser<'a, 'b> {
+    reader: &'a mut BinaryReader<'b>,
+    encoder_context: Arc<Mutex<EncoderContext>>,
+}
+
+impl<'a, 'b> PredWeightTableParser<'a, 'b> {
+    pub fn new(
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        reader: &'a mut BinaryReader<'b>,
+    ) -> PredWeightTableParser<'a, 'b> {
+        PredWeightTableParser {
+            reader,
+            encoder_context: encoder_context.clone(),
+        }
+    }
+
+    /// Parses pred_weight_table( ). ref_pic_lists are the lists of the picture header, which are
+    /// only referred when the table is signalled in the picture header.
+    pub fn parse(
+        &mut self,
+        sps: &SequenceParameterSet,
+        pps: &PictureParameterSet,
+        ref_pic_lists: &[RefPicList; 2],
+    ) -> PredWeightTable {
+        let ectx = self.encoder_context.clone();
+        let mut ectx = ectx.lock().unwrap();
+        let luma_log2_weight_denom = self.reader.read_unsigned_exp_golomb();
+        let delta_chroma_log2_weight_denom = if sps.chroma_format != ChromaFormat::Monochrome {
+            self.reader.read_signed_exp_golomb()
+        } else {
+            0
+        };
+        let wp_info_in_ph_flag = pps.partition_parameters.wp_info_in_ph_flag;
+        let num_l0_weights = if wp_info_in_ph_flag {
+            ectx.num_weights_l0 = self.reader.read_unsigned_exp_golomb();
+            ectx.num_weights_l0
+        } else {
+            // FIXME NumWeightsL0 should be NumRefIdxActive[ 0 ]
+            0
+        };
+        let (
+            luma_weight_l0_flag,
+            chroma_weight_l0_flag,
+            delta_luma_weight_l0,
+            luma_offset_l0,
+            delta_chroma_weight_l0,
+            delta_chroma_offset_l0,
+        ) = self.parse_weights(ectx.num_weights_l0, sps);
+        let num_l1_weights = if pps.weighted_bipred_flag
+            && wp_info_in_ph_flag
+            && ref_pic_lists[1].ref_pic_list_structs[ectx.rpls_idx[1]].num_ref_entries > 0
+        {
+            ectx.num_weights_l1 = self.reader.read_unsigned_exp_golomb();
+            ectx.num_weights_l1
+        } else {
+            // FIXME NumWeightsL1 should be NumRefIdxActive[ 1 ] for B slices
+            0
+        };
+        let (
+            luma_weight_l1_flag,
+            chroma_weight_l1_flag,
+            delta_luma_weight_l1,
+            luma_offset_l1,
+            delta_chroma_weight_l1,
+            delta_chroma_offset_l1,
+        ) = self.parse_weights(ectx.num_weights_l1, sps);
+        PredWeightTable {
+            luma_log2_weight_denom,
+            delta_chroma_log2_weight_denom,
+            num_l0_weights,
+            luma_weight_l0_flag,
+            chroma_weight_l0_flag,
+            delta_luma_weight_l0,
+            luma_offset_l0,
+            delta_chroma_weight_l0,
+            delta_chroma_offset_l0,
+            num_l1_weights,
+            luma_weight_l1_flag,
+            chroma_weight_l1_flag,
+            delta_luma_weight_l1,
+            luma_offset_l1,
+            delta_chroma_weight_l1,
+            delta_chroma_offset_l1,
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn parse_weights(
+        &mut self,
+        num_weights: usize,
+        sps: &SequenceParameterSet,
+    ) -> (
+        Vec<bool>,
+        Vec<bool>,
+        Vec<isize>,
+        Vec<isize>,
+        Vec<[isize; 2]>,
+        Vec<[isize; 2]>,
+    ) {
+        let luma_weight_flag: Vec<bool> =
+            (0..num_weights).map(|_| self.reader.read_bit()).collect();
+        let chroma_weight_flag: Vec<bool> = if sps.chroma_format != ChromaFormat::Monochrome {
+            (0..num_weights).map(|_| self.reader.read_bit()).collect()
+        } else {
+            vec![false; num_weights]
+        };
+        let mut delta_luma_weight = vec![0; num_weights];
+        let mut luma_offset = vec![0; num_weights];
+        let mut delta_chroma_weight = vec![[0; 2]; num_weights];
+        let mut delta_chroma_offset = vec![[0; 2]; num_weights];
+        for i in 0..num_weights {
+            if luma_weight_flag[i] {
+                delta_luma_weight[i] = self.reader.read_signed_exp_golomb();
+                luma_offset[i] = self.reader.read_signed_exp_golomb();
+            }
+            if chroma_weight_flag[i] {
+                for j in 0..2 {
+                    delta_chroma_weight[i][j] = self.reader.read_signed_exp_golomb();
+                    delta_chroma_offset[i][j] = self.reader.read_signed_exp_golomb();
+                }
+            }
+        }
+        (
+            luma_weight_flag,
+            chroma_weight_flag,
+            delta_luma_weight,
+            luma_offset,
+            delta_chroma_weight,
+            delta_chroma_offset,
+        )
+    }
+}
@@ -1,3 +1,6 @@
+use std::sync::Arc;
+
+#[derive(Clone)]
 pub struct RefPicListStruct {
     pub num_ref_entries: usize,
     pub ltrp_in_header_flag: bool,
@@ -22,8 +25,73 @@ impl RefPicListStruct {
             ilrp_idx: vec![0; 3],
         }
     }
+
+    pub fn new_empty() -> RefPicListStruct {
+        RefPicListStruct {
+            num_ref_entries: 0,
+            ltrp_in_header_flag: false,
+            inter_layer_ref_pic_flag: vec![],
+            st_ref_pic_flag: vec![],
+            abs_delta_poc_st: vec![],
+            strp_entry_sign_flag: vec![],
+            rpls_poc_lsb_lt: vec![],
+            ilrp_idx: vec![],
+        }
+    }
+
+    /// Explicit RPL structure of short-term pictures, where delta_pocs are the POC differences
+    /// of the reference pictures from the current picture (7.4.11).
+    pub fn new_st(delta_pocs: &[isize]) -> RefPicListStruct {
+        let num_ref_entries = delta_pocs.len();
+        let mut prev_delta_poc = 0;
+        let mut abs_delta_poc_st = vec![];
+        let mut strp_entry_sign_flag = vec![];
+        for &delta_poc in delta_pocs.iter() {
+            let delta = delta_poc - prev_delta_poc;
+            // AbsDeltaPocSt is abs_delta_poc_st + 1 without weighted prediction, where no
+            // entries refer to the same picture
+            abs_delta_poc_st.push(delta.unsigned_abs() - 1);
+            strp_entry_sign_flag.push(delta < 0);
+            prev_delta_poc = delta_poc;
+        }
+        RefPicListStruct {
+            num_ref_entries,
+            ltrp_in_header_flag: false,
+            inter_layer_ref_pic_flag: vec![false; num_ref_entries],
+            st_ref_pic_flag: vec![true; num_ref_entries],
+            abs_delta_poc_st,
+            strp_entry_sign_flag,
+            rpls_poc_lsb_lt: vec![0; num_ref_entries],
+            ilrp_idx: vec![0; num_ref_entries],
+        }
+    }
+
+    /// The POC differences of the short-term entries from the current picture, the inverse of
+    /// new_st.
+    pub fn delta_pocs(&self) -> Vec<isize> {
+        let mut delta_poc = 0;
+        (0..self.num_ref_entries)
+            .filter(|&i| !self.inter_layer_ref_pic_flag[i] && self.st_ref_pic_flag[i])
+            .map(|i| {
+                let abs_delta_poc_st = self.abs_delta_poc_st[i] as isize + 1;
+                delta_poc += if self.strp_entry_sign_flag[i] {
+                    -abs_delta_poc_st
+                } else {
+                    abs_delta_poc_st
+                };
+                delta_poc
+            })
+            .collect()
+    }
+
+    pub fn num_ltrp_entries(&self) -> usize {
+        (0..self.num_ref_entries)
+            .filter(|&i| !self.inter_layer_ref_pic_flag[i] && !self.st_ref_pic_flag[i])
+            .count()
+    }
 }
 
+#[derive(Clone)]
 pub struct RefPicList {
     pub num_ref_pic_list: usize,
     pub rpl_sps_flag: bool,
@@ -46,4 +114,53 @@ impl RefPicList {
             delta_poc_msb_cycle_lt: vec![0],
         }
     }
+
+    /// RPL without any entries signalled in a picture or slice header, used by intra pictures
+    /// so that they don't refer to pictures which may not exist in the DPB.
+    pub fn new_empty(lx: usize) -> RefPicList {
+        let mut rpl = RefPicList::new(lx);
+        rpl.rpl_sps_flag = false;
+        rpl.ref_pic_list_structs.push(RefPicListStruct::new_empty());
+        rpl
+    }
+
+    /// RPL with the given structure explicitly signalled in a picture or slice header.
+    pub fn new_explicit(lx: usize, rpls: RefPicListStruct) -> RefPicList {
+        let mut rpl = RefPicList::new(lx);
+        rpl.rpl_sps_flag = false;
+        rpl.ref_pic_list_structs.push(rpls);
+        rpl
+    }
+}
+
+/// Reconstructed picture kept for inter prediction of the following pictures.
+#[derive(Clone)]
+pub struct ReferencePicture {
+    pub pic_order_cnt: isize,
+    pub temporal_id: usize,
+    pub width: usize,
+    /// reconstructed samples of each component in raster order
+    pub pixels: Arc<Vec<Vec<u8>>>,
+    /// the right boundary of the area which is refreshed by GDR, from which the area of the
+    /// clean pictures may only be predicted
+    pub clean_area_bd_x: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpl_struct_st_works() {
+        let rpls = RefPicListStruct::new_st(&[-1, -2, -4]);
+        assert_eq!(rpls.num_ref_entries, 3);
+        assert_eq!(rpls.abs_delta_poc_st, vec![0, 0, 1]);
+        assert_eq!(rpls.strp_entry_sign_flag, vec![true, true, true]);
+        assert_eq!(rpls.delta_pocs(), vec![-1, -2, -4]);
+
+        let rpls = RefPicListStruct::new_st(&[2, -2, 6]);
+        assert_eq!(rpls.abs_delta_poc_st, vec![1, 3, 7]);
+        assert_eq!(rpls.strp_entry_sign_flag, vec![false, true, false]);
+        assert_eq!(rpls.delta_pocs(), vec![2, -2, 6]);
+    }
 }
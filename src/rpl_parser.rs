@@ -0,0 +1,139 @@
+use super::binary_reader::*;
+use super::encoder_context::*;
+use super::pps::*;
+use super::reference_picture::*;
+use super::sps::*;
+use std::sync::{Arc, Mutex};
+
+pub struct RefPicListStructParser<'a, 'b> {
+    reader: &'a mut BinaryReader<'b>,
+    encoder_context: Arc<Mutex<EncoderContext>>,
+}
+
+impl<'a, 'b> RefPicListStructParser<'a, 'b> {
+    pub fn new(
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        reader: &'a mut BinaryReader<'b>,
+    ) -> RefPicListStructParser<'a, 'b> {
+        RefPicListStructParser {
+            reader,
+            encoder_context: encoder_context.clone(),
+        }
+    }
+
+    /// Parses ref_pic_lists( ) of a picture header or a slice header. The returned lists hold the
+    /// ref_pic_list_struct( )s of the SPS followed by the explicitly signalled one, and the
+    /// derived indices are stored to the encoder context.
+    pub fn parse(
+        &mut self,
+        sps: &SequenceParameterSet,
+        pps: &PictureParameterSet,
+    ) -> [RefPicList; 2] {
+        let mut ref_pic_lists = [RefPicList::new_empty(0), RefPicList::new_empty(1)];
+        for i in 0..2 {
+            let num_ref_pic_list = sps.ref_pic_lists[i].num_ref_pic_list;
+            let mut rpl_structs =
+                sps.ref_pic_lists[i].ref_pic_list_structs[..num_ref_pic_list].to_vec();
+            let rpl_sps_flag =
+                if num_ref_pic_list > 0 && (i == 0 || (i == 1 && pps.rpl1_idx_present_flag)) {
+                    self.reader.read_bit()
+                } else if num_ref_pic_list == 0 {
+                    false
+                } else {
+                    ref_pic_lists[0].rpl_sps_flag
+                };
+            let mut rpl_idx = 0;
+            if rpl_sps_flag {
+                if num_ref_pic_list > 1 && (i == 0 || (i == 1 && pps.rpl1_idx_present_flag)) {
+                    let n = (num_ref_pic_list as f64).log2().ceil() as usize;
+                    rpl_idx = self.reader.read_value(n);
+                } else if i == 1 && num_ref_pic_list > 1 {
+                    rpl_idx = ref_pic_lists[0].rpl_idx;
+                }
+                rpl_structs.push(RefPicListStruct::new_empty());
+            } else {
+                rpl_structs.push(self.parse_rpls(num_ref_pic_list, num_ref_pic_list, sps));
+            }
+            let rpl = &mut ref_pic_lists[i];
+            rpl.num_ref_pic_list = num_ref_pic_list;
+            rpl.rpl_sps_flag = rpl_sps_flag;
+            rpl.rpl_idx = rpl_idx;
+            rpl.ref_pic_list_structs = rpl_structs;
+            let rpls_idx = if rpl_sps_flag {
+                rpl_idx
+            } else {
+                num_ref_pic_list
+            };
+            let num_ltrp_entries = rpl.ref_pic_list_structs[rpls_idx].num_ltrp_entries();
+            let ltrp_in_header_flag = rpl.ref_pic_list_structs[rpls_idx].ltrp_in_header_flag;
+            rpl.poc_lsb_lt = vec![0; num_ltrp_entries];
+            rpl.delta_poc_msb_cycle_present_flag = vec![false; num_ltrp_entries];
+            rpl.delta_poc_msb_cycle_lt = vec![0; num_ltrp_entries];
+            for j in 0..num_ltrp_entries {
+                if ltrp_in_header_flag {
+                    rpl.poc_lsb_lt[j] = self.reader.read_value(sps.log2_max_pic_order_cnt_lsb);
+                }
+                rpl.delta_poc_msb_cycle_present_flag[j] = self.reader.read_bit();
+                if rpl.delta_poc_msb_cycle_present_flag[j] {
+                    rpl.delta_poc_msb_cycle_lt[j] = self.reader.read_unsigned_exp_golomb();
+                }
+            }
+        }
+        self.encoder_context
+            .lock()
+            .unwrap()
+            .update_from_ref_pic_lists(&ref_pic_lists, sps);
+        ref_pic_lists
+    }
+
+    pub fn parse_rpls(
+        &mut self,
+        rpls_idx: usize,
+        num_ref_pic_list: usize,
+        sps: &SequenceParameterSet,
+    ) -> RefPicListStruct {
+        let mut rpls = RefPicListStruct::new_empty();
+        rpls.num_ref_entries = self.reader.read_unsigned_exp_golomb();
+        let num_ref_entries = rpls.num_ref_entries;
+        if sps.long_term_ref_pics_flag && rpls_idx < num_ref_pic_list && num_ref_entries > 0 {
+            rpls.ltrp_in_header_flag = self.reader.read_bit();
+        }
+        rpls.inter_layer_ref_pic_flag = vec![false; num_ref_entries];
+        rpls.st_ref_pic_flag = vec![true; num_ref_entries];
+        rpls.abs_delta_poc_st = vec![0; num_ref_entries];
+        rpls.strp_entry_sign_flag = vec![false; num_ref_entries];
+        rpls.rpls_poc_lsb_lt = vec![0; num_ref_entries];
+        rpls.ilrp_idx = vec![0; num_ref_entries];
+        let mut j = 0;
+        for i in 0..num_ref_entries {
+            if sps.inter_layer_prediction_enabled_flag {
+                rpls.inter_layer_ref_pic_flag[i] = self.reader.read_bit();
+            }
+            if !rpls.inter_layer_ref_pic_flag[i] {
+                if sps.long_term_ref_pics_flag {
+                    rpls.st_ref_pic_flag[i] = self.reader.read_bit();
+                }
+                if rpls.st_ref_pic_flag[i] {
+                    rpls.abs_delta_poc_st[i] = self.reader.read_unsigned_exp_golomb();
+                    let abs_delta_poc_st =
+                        if (sps.weighted_pred_flag || sps.weighted_bipred_flag) && i != 0 {
+                            rpls.abs_delta_poc_st[i]
+                        } else {
+                            rpls.abs_delta_poc_st[i] + 1
+                        };
+                    if abs_delta_poc_st > 0 {
+                        rpls.strp_entry_sign_flag[i] = self.reader.read_bit();
+                    }
+                } else if !rpls.ltrp_in_header_flag {
+                    // the encoder writes rpls_poc_lsb_lt with 4 bits
+                    // FIXME log2_max_pic_order_cnt_lsb bits
+                    rpls.rpls_poc_lsb_lt[i] = self.reader.read_value(4);
+                    j += 1;
+                }
+            } else {
+                rpls.ilrp_idx[j] = self.reader.read_unsigned_exp_golomb();
+            }
+        }
+        rpls
+    }
+}
@@ -0,0 +1,496 @@
+use super::aps::*;
+use super::binary_reader::*;
+use super::common::*;
+use super::encoder_context::*;
+use super::nal::*;
+use super::ph_parser::*;
+use super::picture_header::*;
+use super::pps::*;
+use super::pwt_parser::*;
+use super::rpl_parser::*;
+use super::slice_header::*;
+use super::sps::*;
+use std::sync::{Arc, Mutex};
+
+pub struct ShParser<'a, 'b> {
+    reader: &'a mut BinaryReader<'b>,
+    encoder_context: Arc<Mutex<EncoderContext>>,
+}
+
+impl<'a, 'b> ShParser<'a, 'b> {
+    pub fn new(
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        reader: &'a mut BinaryReader<'b>,
+    ) -> ShParser<'a, 'b> {
+        ShParser {
+            reader,
+            encoder_context: encoder_context.clone(),
+        }
+    }
+
+    /// Parses slice_header( ) of a slice in a NAL unit of nal_unit_type. ph is the picture header
+    /// of the preceding PH NAL unit, which can be None only when the picture header is carried in
+    /// the slice header. The encoder context is updated for the slice before the entry points are
+    /// parsed, so the slice data can be parsed right after this.
+    pub fn parse<'c>(
+        &mut self,
+        nal_unit_type: NALUnitType,
+        sps: &'c SequenceParameterSet,
+        pps: &'c PictureParameterSet,
+        aps: [&'c AdaptationParameterSet; 3],
+        ph: Option<&'c PictureHeader>,
+    ) -> SliceHeader<'c> {
+        let mut sh = {
+            let ectx = self.encoder_context.lock().unwrap();
+            SliceHeader::new(sps, pps, aps, ph, None, &ectx)
+        };
+        let picture_header_in_slice_header_flag = self.reader.read_bit();
+        if picture_header_in_slice_header_flag {
+            let ectx = self.encoder_context.clone();
+            let mut ph_parser = PhParser::new(&ectx, self.reader);
+            sh.ph_in_sh = Some(ph_parser.parse(sps, pps));
+        }
+        if let Some(subpic_info) = &sps.subpic_info {
+            sh.subpic_id = self.reader.read_value(subpic_info.subpic_id_len);
+            let mut ectx = self.encoder_context.lock().unwrap();
+            ectx.curr_subpic_idx = ectx
+                .subpic_id_val
+                .iter()
+                .position(|&subpic_id| subpic_id == sh.subpic_id)
+                .unwrap();
+        }
+        {
+            let ectx = self.encoder_context.lock().unwrap();
+            let rect_slice_flag = pps.partition_parameters.rect_slice_flag;
+            if (rect_slice_flag && ectx.num_slices_in_subpic[ectx.curr_subpic_idx] > 1)
+                || (!rect_slice_flag && ectx.num_tiles_in_pic > 1)
+            {
+                let n = if rect_slice_flag {
+                    (ectx.num_slices_in_subpic[ectx.curr_subpic_idx] as f64)
+                        .log2()
+                        .ceil() as usize
+                } else {
+                    (ectx.num_tiles_in_pic as f64).log2().ceil() as usize
+                };
+                sh.slice_address = self.reader.read_value(n);
+            }
+            sh.extra_bit = (0..ectx.num_extra_sh_bits)
+                .map(|_| self.reader.read_bit())
+                .collect();
+            if !rect_slice_flag && ectx.num_tiles_in_pic - sh.slice_address > 1 {
+                sh.num_tiles_in_slice = self.reader.read_unsigned_exp_golomb() + 1;
+            }
+        }
+        let ph = sh.ph_in_sh.as_ref().or(ph).unwrap();
+        sh.slice_type = if ph.inter_slice_allowed_flag {
+            match self.reader.read_unsigned_exp_golomb() {
+                0 => SliceType::B,
+                1 => SliceType::P,
+                2 => SliceType::I,
+                slice_type => panic!("invalid sh_slice_type {}", slice_type),
+            }
+        } else {
+            SliceType::I
+        };
+        if let NALUnitType::IDR_W_RADL
+        | NALUnitType::IDR_N_LP
+        | NALUnitType::CRA_NUT
+        | NALUnitType::GDR_NUT = nal_unit_type
+        {
+            sh.no_output_of_prior_pics_flag = self.reader.read_bit();
+        }
+        if sps.alf_enabled_flag && !pps.partition_parameters.alf_info_in_ph_flag {
+            sh.alf_enabled_flag = self.reader.read_bit();
+            if sh.alf_enabled_flag {
+                let alf_info = &mut sh.alf_info;
+                alf_info.num_alf_aps_ids_luma = self.reader.read_value(3);
+                alf_info.aps_id_luma = (0..alf_info.num_alf_aps_ids_luma)
+                    .map(|_| self.reader.read_value(3))
+                    .collect();
+                if sps.chroma_format != ChromaFormat::Monochrome {
+                    alf_info.cb_enabled_flag = self.reader.read_bit();
+                    alf_info.cr_enabled_flag = self.reader.read_bit();
+                }
+                if alf_info.cb_enabled_flag || alf_info.cr_enabled_flag {
+                    alf_info.aps_id_chroma = self.reader.read_value(3);
+                }
+                if sps.ccalf_enabled_flag {
+                    alf_info.cc_cb_enabled_flag = self.reader.read_bit();
+                    if alf_info.cc_cb_enabled_flag {
+                        alf_info.cc_cb_aps_id = self.reader.read_value(3);
+                    }
+                    alf_info.cc_cr_enabled_flag = self.reader.read_bit();
+                    if alf_info.cc_cr_enabled_flag {
+                        alf_info.cc_cr_aps_id = self.reader.read_value(3);
+                    }
+                }
+            }
+        } else if pps.partition_parameters.alf_info_in_ph_flag {
+            sh.alf_enabled_flag = ph.alf_enabled_flag;
+        }
+        if ph.lmcs_enabled_flag && !picture_header_in_slice_header_flag {
+            sh.lmcs_used_flag = self.reader.read_bit();
+        }
+        if ph.explicit_scaling_list_enabled_flag && !picture_header_in_slice_header_flag {
+            sh.explicit_scaling_list_used_flag = self.reader.read_bit();
+        }
+        if !pps.partition_parameters.rpl_info_in_ph_flag
+            && ((nal_unit_type != NALUnitType::IDR_W_RADL
+                && nal_unit_type != NALUnitType::IDR_N_LP)
+                || sps.idr_rpl_present_flag)
+        {
+            let ectx = self.encoder_context.clone();
+            let mut rpl_parser = RefPicListStructParser::new(&ectx, self.reader);
+            sh.ref_pic_lists = rpl_parser.parse(sps, pps);
+        } else if pps.partition_parameters.rpl_info_in_ph_flag {
+            sh.ref_pic_lists = ph.ref_pic_lists.clone();
+        }
+        {
+            let mut ectx = self.encoder_context.lock().unwrap();
+            let num_ref_entries = |i: usize| {
+                sh.ref_pic_lists[i].ref_pic_list_structs[ectx.rpls_idx[i]].num_ref_entries
+            };
+            let num_lists = match sh.slice_type {
+                SliceType::B => 2,
+                SliceType::P => 1,
+                SliceType::I => 0,
+            };
+            let mut num_ref_idx_active_minus1 = [0; 2];
+            sh.num_ref_idx_active_override_flag = true;
+            if (sh.slice_type != SliceType::I && num_ref_entries(0) > 1)
+                || (sh.slice_type == SliceType::B && num_ref_entries(1) > 1)
+            {
+                sh.num_ref_idx_active_override_flag = self.reader.read_bit();
+                if sh.num_ref_idx_active_override_flag {
+                    for (i, minus1) in num_ref_idx_active_minus1
+                        .iter_mut()
+                        .enumerate()
+                        .take(num_lists)
+                    {
+                        if num_ref_entries(i) > 1 {
+                            *minus1 = self.reader.read_unsigned_exp_golomb();
+                        }
+                    }
+                }
+            }
+            sh.num_ref_idx_active = (0..2)
+                .map(|i| {
+                    if i >= num_lists {
+                        0
+                    } else if sh.num_ref_idx_active_override_flag {
+                        num_ref_idx_active_minus1[i] + 1
+                    } else {
+                        num_ref_entries(i).min(pps.num_ref_idx_default_active[i])
+                    }
+                })
+                .collect();
+            ectx.num_ref_idx_active = [sh.num_ref_idx_active[0], sh.num_ref_idx_active[1]];
+        }
+        if sh.slice_type != SliceType::I {
+            if pps.cabac_init_present_flag {
+                sh.cabac_init_flag = self.reader.read_bit();
+            }
+            if ph.temporal_mvp_enabled_flag {
+                if !pps.partition_parameters.rpl_info_in_ph_flag {
+                    sh.collocated_from_l0_flag = if sh.slice_type == SliceType::B {
+                        self.reader.read_bit()
+                    } else {
+                        true
+                    };
+                    if (sh.collocated_from_l0_flag && sh.num_ref_idx_active[0] > 1)
+                        || (!sh.collocated_from_l0_flag && sh.num_ref_idx_active[1] > 1)
+                    {
+                        sh.collocated_ref_idx = self.reader.read_unsigned_exp_golomb();
+                    }
+                } else {
+                    sh.collocated_from_l0_flag = ph.collocated_from_l0_flag;
+                    sh.collocated_ref_idx = ph.collocated_ref_idx;
+                }
+            }
+            if !pps.partition_parameters.wp_info_in_ph_flag
+                && ((pps.weighted_pred_flag && sh.slice_type == SliceType::P)
+                    || (pps.weighted_bipred_flag && sh.slice_type == SliceType::B))
+            {
+                let ectx = self.encoder_context.clone();
+                let mut pwt_parser = PredWeightTableParser::new(&ectx, self.reader);
+                sh.pred_weight_table = Some(pwt_parser.parse(sps, pps, &ph.ref_pic_lists));
+            }
+        }
+        if !pps.partition_parameters.qp_delta_info_in_ph_flag {
+            sh.qp_delta = self.reader.read_signed_exp_golomb();
+        }
+        if pps.chroma_tool_offsets.slice_chroma_qp_offsets_present_flag {
+            sh.cb_qp_offset = self.reader.read_signed_exp_golomb();
+            sh.cr_qp_offset = self.reader.read_signed_exp_golomb();
+            if sps.joint_cbcr_enabled_flag {
+                sh.joint_cbcr_qp_offset = self.reader.read_signed_exp_golomb();
+            }
+        }
+        if pps
+            .chroma_tool_offsets
+            .cu_chroma_qp_offset_list_enabled_flag
+        {
+            sh.cu_chroma_qp_offset_enabled_flag = self.reader.read_bit();
+        }
+        if sps.sao_enabled_flag && !pps.partition_parameters.sao_info_in_ph_flag {
+            sh.sao_luma_used_flag = self.reader.read_bit();
+            if sps.chroma_format != ChromaFormat::Monochrome {
+                sh.sao_chroma_used_flag = self.reader.read_bit();
+            }
+        } else if pps.partition_parameters.sao_info_in_ph_flag {
+            sh.sao_luma_used_flag = ph.sao_luma_enabled_flag;
+            sh.sao_chroma_used_flag = ph.sao_chroma_enabled_flag;
+        }
+        let dfc = &pps.deblocking_filter_control;
+        if dfc.deblocking_filter_override_enabled_flag && !dfc.dbf_info_in_ph_flag {
+            sh.deblocking_params_present_flag = self.reader.read_bit();
+        }
+        sh.deblocking_filter_disabled_flag = ph.deblocking_filter_disabled_flag;
+        sh.luma_beta_offset = ph.luma_beta_offset;
+        sh.luma_tc_offset = ph.luma_tc_offset;
+        sh.cb_beta_offset = ph.cb_beta_offset;
+        sh.cb_tc_offset = ph.cb_tc_offset;
+        sh.cr_beta_offset = ph.cr_beta_offset;
+        sh.cr_tc_offset = ph.cr_tc_offset;
+        if sh.deblocking_params_present_flag {
+            sh.deblocking_filter_disabled_flag = if !dfc.deblocking_filter_disabled_flag {
+                self.reader.read_bit()
+            } else {
+                false
+            };
+            if !sh.deblocking_filter_disabled_flag {
+                sh.luma_beta_offset = self.reader.read_signed_exp_golomb() * 2;
+                sh.luma_tc_offset = self.reader.read_signed_exp_golomb() * 2;
+                if pps.chroma_tool_offsets_present_flag {
+                    sh.cb_beta_offset = self.reader.read_signed_exp_golomb() * 2;
+                    sh.cb_tc_offset = self.reader.read_signed_exp_golomb() * 2;
+                    sh.cr_beta_offset = self.reader.read_signed_exp_golomb() * 2;
+                    sh.cr_tc_offset = self.reader.read_signed_exp_golomb() * 2;
+                } else {
+                    sh.cb_beta_offset = sh.luma_beta_offset;
+                    sh.cb_tc_offset = sh.luma_tc_offset;
+                    sh.cr_beta_offset = sh.luma_beta_offset;
+                    sh.cr_tc_offset = sh.luma_tc_offset;
+                }
+            }
+        }
+        sh.dep_quant_used_flag = if sps.dep_quant_enabled_flag {
+            self.reader.read_bit()
+        } else {
+            false
+        };
+        if sps.sign_data_hiding_enabled_flag && !sh.dep_quant_used_flag {
+            sh.sign_data_hiding_used_flag = self.reader.read_bit();
+        }
+        if sps.transform_skip_enabled_flag
+            && !sh.dep_quant_used_flag
+            && !sh.sign_data_hiding_used_flag
+        {
+            sh.ts_residual_coding_disabled_flag = self.reader.read_bit();
+        }
+        if pps.slice_header_extension_present_flag {
+            sh.slice_header_extension_length = self.reader.read_unsigned_exp_golomb();
+            sh.slice_header_extension_data_byte = (0..sh.slice_header_extension_length)
+                .map(|_| self.reader.read_value(8))
+                .collect();
+        }
+
+        let ectx = self.encoder_context.clone();
+        let mut ectx = ectx.lock().unwrap();
+        ectx.update_from_sh(&sh, pps);
+        ectx.num_entry_points = 0;
+        if sps.entry_point_offsets_present_flag {
+            for i in 1..ectx.num_ctus_in_curr_slice {
+                let ctb_addr_x = ectx.ctb_addr_in_curr_slice[i] % ectx.pic_width_in_ctbs_y;
+                let ctb_addr_y = ectx.ctb_addr_in_curr_slice[i] / ectx.pic_width_in_ctbs_y;
+                let prev_ctb_addr_x = ectx.ctb_addr_in_curr_slice[i - 1] % ectx.pic_width_in_ctbs_y;
+                let prev_ctb_addr_y = ectx.ctb_addr_in_curr_slice[i - 1] / ectx.pic_width_in_ctbs_y;
+                if ectx.ctb_to_tile_row_bd[ctb_addr_y] != ectx.ctb_to_tile_row_bd[prev_ctb_addr_y]
+                    || ectx.ctb_to_tile_col_bd[ctb_addr_x]
+                        != ectx.ctb_to_tile_col_bd[prev_ctb_addr_x]
+                    || (ctb_addr_y != prev_ctb_addr_y && sps.entropy_coding_sync_enabled_flag)
+                {
+                    ectx.num_entry_points += 1;
+                }
+            }
+        }
+        if ectx.num_entry_points > 0 {
+            sh.entry_offset_len = self.reader.read_unsigned_exp_golomb() + 1;
+            sh.entry_point_offset = (0..ectx.num_entry_points)
+                .map(|_| self.reader.read_value(sh.entry_offset_len) + 1)
+                .collect();
+        }
+
+        let byte_alignment_bit_equal_to_one = self.reader.read_bit();
+        assert!(byte_alignment_bit_equal_to_one);
+        self.reader.byte_align();
+        sh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bins::*;
+    use crate::bool_coder::*;
+    use crate::picture::*;
+    use crate::slice::*;
+    use crate::slice_encoder::*;
+
+    fn round_trip<'a>(sh: &SliceHeader<'a>, nal_unit_type: NALUnitType) -> SliceHeader<'a> {
+        let new_encoder_context = || {
+            let mut ectx = EncoderContext::new();
+            ectx.update_from_sps(sh.sps);
+            ectx.update_from_sps_and_pps(sh.sps, sh.pps);
+            ectx.update_from_ph(sh.picture_header(), sh.sps, sh.pps);
+            Arc::new(Mutex::new(ectx))
+        };
+        let slice = Slice {
+            slice_struct: SliceStruct::Rectangle {
+                tile_col: 0,
+                tile_row: 0,
+                num_tile_cols: 1,
+                num_tile_rows: 1,
+            },
+            nal_unit_type,
+            tiles: Arc::new(Mutex::new(vec![])),
+        };
+        let encode = |ectx: &Arc<Mutex<EncoderContext>>, sh: &SliceHeader| {
+            ectx.lock().unwrap().update_from_sh(sh, sh.pps);
+            let mut coder = BoolCoder::new();
+            let mut bins = Bins::new();
+            SliceEncoder::new(ectx, &mut coder).encode_sh(&mut bins, sh, &slice);
+            bins.into_iter().collect::<Vec<bool>>()
+        };
+        let bits = encode(&new_encoder_context(), sh);
+
+        let rbsp = write_and_read_rbsp(nal_unit_type, &bits);
+        let mut reader = BinaryReader::vec(&rbsp).unwrap();
+        let parsed_ectx = new_encoder_context();
+        let parsed = ShParser::new(&parsed_ectx, &mut reader).parse(
+            nal_unit_type,
+            sh.sps,
+            sh.pps,
+            sh.aps,
+            sh.ph,
+        );
+        assert_eq!(encode(&parsed_ectx, &parsed), bits);
+        parsed
+    }
+
+    fn new_aps() -> [AdaptationParameterSet; 3] {
+        [
+            AdaptationParameterSet::new_alf(1),
+            AdaptationParameterSet::new_lmcs(2),
+            AdaptationParameterSet::new_sl(3),
+        ]
+    }
+
+    #[test]
+    fn sh_round_trip_works() {
+        let mut sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 1);
+        sps.entry_point_offsets_present_flag = true;
+        let mut pps = PictureParameterSet::new(1, &sps, None);
+        pps.set_tiles(sps.log2_ctu_size, &[0, 2], &[0, 1], 4, 2);
+        let ph = PictureHeader::new(&pps, PictureType::Trailing, true, 1);
+        let aps = new_aps();
+        let ectx = EncoderContext::new();
+        let mut sh = SliceHeader::new(
+            &sps,
+            &pps,
+            [&aps[0], &aps[1], &aps[2]],
+            Some(&ph),
+            None,
+            &ectx,
+        );
+        sh.qp_delta = 3;
+        sh.entry_offset_len = 8;
+        sh.entry_point_offset = vec![10, 20, 30];
+        let parsed = round_trip(&sh, NALUnitType::TRAIL_NUT);
+        assert!(parsed.slice_type == SliceType::I);
+        assert_eq!(parsed.qp_delta, 3);
+        assert_eq!(parsed.entry_point_offset, vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn sh_with_raster_slice_round_trip_works() {
+        let mut sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 1);
+        sps.entry_point_offsets_present_flag = true;
+        let mut pps = PictureParameterSet::new(1, &sps, None);
+        pps.set_tiles(sps.log2_ctu_size, &[0, 1, 3], &[0], 4, 2);
+        let slice_types = [
+            SliceStruct::Raster {
+                tile_col: 0,
+                tile_row: 0,
+                num_tiles: 1,
+            },
+            SliceStruct::Raster {
+                tile_col: 1,
+                tile_row: 0,
+                num_tiles: 2,
+            },
+        ];
+        pps.set_slices(&slice_types, 3, &[2]).unwrap();
+        let ph = PictureHeader::new(&pps, PictureType::IRAP_IDR, true, 0);
+        let aps = new_aps();
+        let ectx = EncoderContext::new();
+        let mut sh = SliceHeader::new(
+            &sps,
+            &pps,
+            [&aps[0], &aps[1], &aps[2]],
+            Some(&ph),
+            None,
+            &ectx,
+        );
+        sh.slice_address = 1;
+        sh.num_tiles_in_slice = 2;
+        sh.no_output_of_prior_pics_flag = true;
+        sh.entry_offset_len = 4;
+        sh.entry_point_offset = vec![5];
+        let parsed = round_trip(&sh, NALUnitType::IDR_W_RADL);
+        assert_eq!(parsed.slice_address, 1);
+        assert_eq!(parsed.num_tiles_in_slice, 2);
+        assert!(parsed.no_output_of_prior_pics_flag);
+        assert_eq!(parsed.entry_point_offset, vec![5]);
+    }
+
+    #[test]
+    fn sh_with_subpics_and_wpp_round_trip_works() {
+        let mut sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 1);
+        sps.set_subpics(&[(0, 0, 2, 2), (2, 0, 2, 2)]);
+        sps.entropy_coding_sync_enabled_flag = true;
+        sps.entry_point_offsets_present_flag = true;
+        let mut pps = PictureParameterSet::new(1, &sps, None);
+        pps.set_tiles(sps.log2_ctu_size, &[0, 2], &[0], 4, 2);
+        let ph = PictureHeader::new(&pps, PictureType::IRAP_IDR, true, 0);
+        let aps = new_aps();
+        let ectx = EncoderContext::new();
+        let mut sh = SliceHeader::new(
+            &sps,
+            &pps,
+            [&aps[0], &aps[1], &aps[2]],
+            Some(&ph),
+            None,
+            &ectx,
+        );
+        sh.subpic_id = 1;
+        sh.entry_offset_len = 6;
+        sh.entry_point_offset = vec![7];
+        let parsed = round_trip(&sh, NALUnitType::IDR_N_LP);
+        assert_eq!(parsed.subpic_id, 1);
+        assert_eq!(parsed.entry_point_offset, vec![7]);
+    }
+
+    #[test]
+    fn sh_with_ph_round_trip_works() {
+        let sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 1);
+        let pps = PictureParameterSet::new(1, &sps, None);
+        let aps = new_aps();
+        let ectx = EncoderContext::new();
+        let mut sh = SliceHeader::new(&sps, &pps, [&aps[0], &aps[1], &aps[2]], None, None, &ectx);
+        sh.ph_in_sh = Some(PictureHeader::new(&pps, PictureType::IRAP_IDR, true, 0));
+        let parsed = round_trip(&sh, NALUnitType::IDR_N_LP);
+        assert!(parsed.ph_in_sh.is_some());
+        assert!(parsed.picture_header().gdr_or_irap_pic_flag);
+    }
+}
@@ -1,10 +1,11 @@
 use super::common::*;
 use super::nal::*;
 use super::tile::*;
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
 
 // FIXME better name
-#[allow(dead_code)]
+#[derive(Clone, Copy)]
 pub enum SliceStruct {
     Raster {
         tile_col: usize,
@@ -17,11 +18,12 @@ pub enum SliceStruct {
         num_tile_cols: usize,
         num_tile_rows: usize,
     },
+    /// Consecutive CTU rows in a tile
     RectangleInTile {
         tile_col: usize,
         tile_row: usize,
-        ctu_col_offset: usize,
-        num_ctu_cols: usize,
+        ctu_row_offset: usize,
+        num_ctu_rows: usize,
     },
 }
 
@@ -69,10 +71,20 @@ impl Slice {
             SliceStruct::RectangleInTile {
                 tile_col,
                 tile_row,
-                ctu_col_offset: _,
-                num_ctu_cols: _,
+                ctu_row_offset,
+                num_ctu_rows,
             } => {
                 let tile = picture_tiles[tile_row][tile_col].clone();
+                {
+                    let tile = tile.lock().unwrap();
+                    assert!(
+                        num_ctu_rows > 0 && ctu_row_offset + num_ctu_rows <= tile.num_ctu_rows,
+                        "CTU rows {}..{} are out of the tile (num_ctu_rows={})",
+                        ctu_row_offset,
+                        ctu_row_offset + num_ctu_rows,
+                        tile.num_ctu_rows,
+                    );
+                }
                 tiles.push(tile);
             }
         }
@@ -82,4 +94,17 @@ impl Slice {
             tiles: Arc::new(Mutex::new(tiles)),
         }
     }
+
+    /// Returns the range of CTU rows in a tile of the slice, relative to the first CTU row of
+    /// the tile.
+    pub fn get_ctu_rows_in_tile(&self, tile: &Tile) -> Range<usize> {
+        match self.slice_struct {
+            SliceStruct::RectangleInTile {
+                ctu_row_offset,
+                num_ctu_rows,
+                ..
+            } => ctu_row_offset..ctu_row_offset + num_ctu_rows,
+            _ => 0..tile.num_ctu_rows,
+        }
+    }
 }
@@ -2,16 +2,21 @@ use super::bins::*;
 use super::bool_coder::*;
 //use super::cabac_contexts::*;
 use super::common::*;
+use super::ctu::*;
 use super::ctu_encoder::*;
 use super::encoder_context::*;
 use super::nal::*;
 use super::ph_encoder::*;
+use super::pps::*;
 use super::pwt_encoder::*;
 use super::rpl_encoder::*;
 use super::slice::*;
 use super::slice_header::*;
+use super::slice_splitter::*;
+use super::tile::*;
 use debug_print::*;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
 
 pub struct SliceEncoder<'a> {
     coder: &'a mut BoolCoder,
@@ -31,7 +36,7 @@ impl<'a> SliceEncoder<'a> {
 
     pub fn encode_sh(&mut self, bins: &mut Bins, sh: &SliceHeader, slice: &Slice) {
         let sh_picture_header_in_slice_header_flag = sh.ph_in_sh.is_some();
-        debug_eprint!("sh.picture_header_in_slice_header_flag ");
+        trace_syntax_element!(bins, "sh.picture_header_in_slice_header_flag ");
         bins.push_initial_bin(sh_picture_header_in_slice_header_flag);
         if let Some(ph) = &sh.ph_in_sh {
             let mut ph_encoder = PhEncoder::new(&self.encoder_context, self.coder);
@@ -39,7 +44,7 @@ impl<'a> SliceEncoder<'a> {
         }
         if let Some(subpic_info) = &sh.sps.subpic_info {
             let n = subpic_info.subpic_id_len;
-            debug_eprint!("sh.subpic_id ");
+            trace_syntax_element!(bins, "sh.subpic_id ");
             bins.push_bins_with_size(sh.subpic_id as u64, n);
         }
         {
@@ -55,23 +60,23 @@ impl<'a> SliceEncoder<'a> {
                 } else {
                     (ectx.num_tiles_in_pic as f64).log2().ceil() as usize
                 };
-                debug_eprint!("sh.slice_address ");
+                trace_syntax_element!(bins, "sh.slice_address ");
                 bins.push_bins_with_size(sh.slice_address as u64, n);
             }
             for i in 0..ectx.num_extra_sh_bits {
-                debug_eprint!("sh.extra_bit ");
+                trace_syntax_element!(bins, "sh.extra_bit ");
                 bins.push_bin(sh.extra_bit[i]);
             }
             if !sh.pps.partition_parameters.rect_slice_flag
                 && ectx.num_tiles_in_pic - sh.slice_address > 1
             {
-                debug_eprint!("sh.num_tiles_in_slice_minus1 ");
+                trace_syntax_element!(bins, "sh.num_tiles_in_slice_minus1 ");
                 self.coder
                     .encode_unsigned_exp_golomb(bins, sh.num_tiles_in_slice as u64 - 1);
             }
         }
-        if sh.ph.as_ref().unwrap().inter_slice_allowed_flag {
-            debug_eprint!("sh.slice_type ");
+        if sh.picture_header().inter_slice_allowed_flag {
+            trace_syntax_element!(bins, "sh.slice_type ");
             self.coder
                 .encode_unsigned_exp_golomb(bins, sh.slice_type as u64);
         }
@@ -80,51 +85,51 @@ impl<'a> SliceEncoder<'a> {
         | NALUnitType::CRA_NUT
         | NALUnitType::GDR_NUT = slice.nal_unit_type
         {
-            debug_eprint!("sh.no_output_of_prior_pics_flag ");
+            trace_syntax_element!(bins, "sh.no_output_of_prior_pics_flag ");
             bins.push_bin(sh.no_output_of_prior_pics_flag);
         }
         if sh.sps.alf_enabled_flag && !sh.pps.partition_parameters.alf_info_in_ph_flag {
-            debug_eprint!("sh.alf_enabled_flag ");
+            trace_syntax_element!(bins, "sh.alf_enabled_flag ");
             bins.push_bin(sh.alf_enabled_flag);
             if sh.alf_enabled_flag {
-                debug_eprint!("sh.alf_info.num_alf_aps_ids_luma ");
+                trace_syntax_element!(bins, "sh.alf_info.num_alf_aps_ids_luma ");
                 bins.push_bins_with_size(sh.alf_info.num_alf_aps_ids_luma as u64, 3);
                 for i in 0..sh.alf_info.num_alf_aps_ids_luma {
-                    debug_eprint!("sh.alf_info.aps_id_luma ");
+                    trace_syntax_element!(bins, "sh.alf_info.aps_id_luma ");
                     bins.push_bins_with_size(sh.alf_info.aps_id_luma[i] as u64, 3);
                 }
                 if sh.sps.chroma_format != ChromaFormat::Monochrome {
-                    debug_eprint!("sh.alf_info.cb_enabled_flag ");
+                    trace_syntax_element!(bins, "sh.alf_info.cb_enabled_flag ");
                     bins.push_bin(sh.alf_info.cb_enabled_flag);
-                    debug_eprint!("sh.alf_info.cr_enabled_flag ");
+                    trace_syntax_element!(bins, "sh.alf_info.cr_enabled_flag ");
                     bins.push_bin(sh.alf_info.cr_enabled_flag);
                 }
                 if sh.alf_info.cb_enabled_flag || sh.alf_info.cr_enabled_flag {
-                    debug_eprint!("sh.alf_info.aps_id_chroma ");
+                    trace_syntax_element!(bins, "sh.alf_info.aps_id_chroma ");
                     bins.push_bins_with_size(sh.alf_info.aps_id_chroma as u64, 3);
                 }
                 if sh.sps.ccalf_enabled_flag {
-                    debug_eprint!("sh.alf_info.cc_cb_enabled_flag ");
+                    trace_syntax_element!(bins, "sh.alf_info.cc_cb_enabled_flag ");
                     bins.push_bin(sh.alf_info.cc_cb_enabled_flag);
                     if sh.alf_info.cc_cb_enabled_flag {
-                        debug_eprint!("sh.alf_info.cc_cb_aps_id ");
+                        trace_syntax_element!(bins, "sh.alf_info.cc_cb_aps_id ");
                         bins.push_bins_with_size(sh.alf_info.cc_cb_aps_id as u64, 3);
                     }
-                    debug_eprint!("sh.alf_info.cc_cr_enabled_flag ");
+                    trace_syntax_element!(bins, "sh.alf_info.cc_cr_enabled_flag ");
                     bins.push_bin(sh.alf_info.cc_cr_enabled_flag);
                     if sh.alf_info.cc_cr_enabled_flag {
-                        debug_eprint!("sh.alf_info.cc_cr_aps_id ");
+                        trace_syntax_element!(bins, "sh.alf_info.cc_cr_aps_id ");
                         bins.push_bins_with_size(sh.alf_info.cc_cr_aps_id as u64, 3);
                     }
                 }
             }
         }
-        if sh.ph.as_ref().unwrap().lmcs_enabled_flag && sh.ph_in_sh.is_none() {
-            debug_eprint!("sh.alf_info.lmcs_used_flag ");
+        if sh.picture_header().lmcs_enabled_flag && sh.ph_in_sh.is_none() {
+            trace_syntax_element!(bins, "sh.alf_info.lmcs_used_flag ");
             bins.push_bin(sh.lmcs_used_flag);
         }
-        if sh.ph.as_ref().unwrap().explicit_scaling_list_enabled_flag && sh.ph_in_sh.is_none() {
-            debug_eprint!("sh.alf_info.explicit_scaling_list_used_flag ");
+        if sh.picture_header().explicit_scaling_list_enabled_flag && sh.ph_in_sh.is_none() {
+            trace_syntax_element!(bins, "sh.alf_info.explicit_scaling_list_used_flag ");
             bins.push_bin(sh.explicit_scaling_list_used_flag);
         }
         if !sh.pps.partition_parameters.rpl_info_in_ph_flag
@@ -134,28 +139,22 @@ impl<'a> SliceEncoder<'a> {
         {
             let ectx = self.encoder_context.clone();
             let mut rpl_encoder = RefPicListStructEncoder::new(&ectx, self.coder);
-            rpl_encoder.encode(
-                bins,
-                &sh.ref_pic_lists,
-                sh.sps,
-                sh.pps,
-                sh.ph.as_ref().unwrap(),
-            );
-        }
-        let ectx = &mut self.encoder_context.lock().unwrap();
+            rpl_encoder.encode(bins, &sh.ref_pic_lists, sh.sps, sh.pps);
+        }
+        let mut ectx = self.encoder_context.lock().unwrap();
         if (sh.slice_type != SliceType::I
             && sh.ref_pic_lists[0].ref_pic_list_structs[ectx.rpls_idx[0]].num_ref_entries > 1)
             || (sh.slice_type == SliceType::B
                 && sh.ref_pic_lists[1].ref_pic_list_structs[ectx.rpls_idx[1]].num_ref_entries > 1)
         {
-            debug_eprint!("sh.num_ref_idx_active_override_flag ");
+            trace_syntax_element!(bins, "sh.num_ref_idx_active_override_flag ");
             bins.push_bin(sh.num_ref_idx_active_override_flag);
             if sh.num_ref_idx_active_override_flag {
                 for i in 0..if sh.slice_type == SliceType::B { 2 } else { 1 } {
                     if sh.ref_pic_lists[i].ref_pic_list_structs[ectx.rpls_idx[i]].num_ref_entries
                         > 1
                     {
-                        debug_eprint!("sh.num_ref_idx_active_minus1 ");
+                        trace_syntax_element!(bins, "sh.num_ref_idx_active_minus1 ");
                         self.coder
                             .encode_unsigned_exp_golomb(bins, sh.num_ref_idx_active[i] as u64 - 1);
                     }
@@ -164,20 +163,20 @@ impl<'a> SliceEncoder<'a> {
         }
         if sh.slice_type != SliceType::I {
             if sh.pps.cabac_init_present_flag {
-                debug_eprint!("sh.cabac_init_flag ");
+                trace_syntax_element!(bins, "sh.cabac_init_flag ");
                 bins.push_bin(sh.cabac_init_flag);
             }
-            if sh.ph.as_ref().unwrap().temporal_mvp_enabled_flag
+            if sh.picture_header().temporal_mvp_enabled_flag
                 && !sh.pps.partition_parameters.rpl_info_in_ph_flag
             {
                 if sh.slice_type == SliceType::B {
-                    debug_eprint!("sh.collocated_from_l0_flag ");
+                    trace_syntax_element!(bins, "sh.collocated_from_l0_flag ");
                     bins.push_bin(sh.collocated_from_l0_flag);
                 }
                 if (sh.collocated_from_l0_flag && ectx.num_ref_idx_active[0] > 1)
                     || (!sh.collocated_from_l0_flag && ectx.num_ref_idx_active[1] > 1)
                 {
-                    debug_eprint!("sh.collocated_ref_idx ");
+                    trace_syntax_element!(bins, "sh.collocated_ref_idx ");
                     self.coder
                         .encode_unsigned_exp_golomb(bins, sh.collocated_ref_idx as u64);
                 }
@@ -186,19 +185,22 @@ impl<'a> SliceEncoder<'a> {
                 && ((sh.pps.weighted_pred_flag && sh.slice_type == SliceType::P)
                     || (sh.pps.weighted_bipred_flag && sh.slice_type == SliceType::B))
             {
-                let ectx = self.encoder_context.clone();
-                let mut pwt_encoder = PredWeightTableEncoder::new(&ectx, self.coder);
+                // the pred weight table encoder locks the context by itself
+                drop(ectx);
+                let mut pwt_encoder =
+                    PredWeightTableEncoder::new(&self.encoder_context, self.coder);
                 pwt_encoder.encode(
                     bins,
-                    sh.ph.as_ref().unwrap().pred_weight_table.as_ref().unwrap(),
+                    sh.picture_header().pred_weight_table.as_ref().unwrap(),
                     sh.sps,
                     sh.pps,
-                    sh.ph.as_ref().unwrap(),
+                    sh.picture_header(),
                 );
+                ectx = self.encoder_context.lock().unwrap();
             }
         }
         if !sh.pps.partition_parameters.qp_delta_info_in_ph_flag {
-            debug_eprint!("sh.qp_delta {}", sh.qp_delta);
+            trace_syntax_element!(bins, "sh.qp_delta ");
             self.coder
                 .encode_signed_exp_golomb(bins, sh.qp_delta as i64);
         }
@@ -207,14 +209,14 @@ impl<'a> SliceEncoder<'a> {
             .chroma_tool_offsets
             .slice_chroma_qp_offsets_present_flag
         {
-            debug_eprint!("sh.cb_qp_offset ");
+            trace_syntax_element!(bins, "sh.cb_qp_offset ");
             self.coder
                 .encode_signed_exp_golomb(bins, sh.cb_qp_offset as i64);
-            debug_eprint!("sh.cr_qp_offset ");
+            trace_syntax_element!(bins, "sh.cr_qp_offset ");
             self.coder
                 .encode_signed_exp_golomb(bins, sh.cr_qp_offset as i64);
             if sh.sps.joint_cbcr_enabled_flag {
-                debug_eprint!("sh.joint_cbcr_qp_offset ");
+                trace_syntax_element!(bins, "sh.joint_cbcr_qp_offset ");
                 self.coder
                     .encode_signed_exp_golomb(bins, sh.joint_cbcr_qp_offset as i64);
             }
@@ -224,14 +226,14 @@ impl<'a> SliceEncoder<'a> {
             .chroma_tool_offsets
             .cu_chroma_qp_offset_list_enabled_flag
         {
-            debug_eprint!("sh.cu_chroma_qp_offset_enabled_flag ");
+            trace_syntax_element!(bins, "sh.cu_chroma_qp_offset_enabled_flag ");
             bins.push_bin(sh.cu_chroma_qp_offset_enabled_flag);
         }
         if sh.sps.sao_enabled_flag && !sh.pps.partition_parameters.sao_info_in_ph_flag {
-            debug_eprint!("sh.sao_luma_used_flag ");
+            trace_syntax_element!(bins, "sh.sao_luma_used_flag ");
             bins.push_bin(sh.sao_luma_used_flag);
             if sh.sps.chroma_format != ChromaFormat::Monochrome {
-                debug_eprint!("sh.sao_chroma_used_flag ");
+                trace_syntax_element!(bins, "sh.sao_chroma_used_flag ");
                 bins.push_bin(sh.sao_chroma_used_flag);
             }
         }
@@ -241,7 +243,7 @@ impl<'a> SliceEncoder<'a> {
             .deblocking_filter_override_enabled_flag
             && !sh.pps.deblocking_filter_control.dbf_info_in_ph_flag
         {
-            debug_eprint!("sh.deblocking_params_present_flag ");
+            trace_syntax_element!(bins, "sh.deblocking_params_present_flag ");
             bins.push_bin(sh.deblocking_params_present_flag);
         }
         if sh.deblocking_params_present_flag {
@@ -250,49 +252,49 @@ impl<'a> SliceEncoder<'a> {
                 .deblocking_filter_control
                 .deblocking_filter_disabled_flag
             {
-                debug_eprint!("sh.deblocking_filter_disabled_flag ");
+                trace_syntax_element!(bins, "sh.deblocking_filter_disabled_flag ");
                 bins.push_bin(sh.deblocking_filter_disabled_flag);
             }
             if !sh.deblocking_filter_disabled_flag {
-                debug_eprint!("sh.luma_beta_offset_div2 ");
+                trace_syntax_element!(bins, "sh.luma_beta_offset_div2 ");
                 self.coder
                     .encode_signed_exp_golomb(bins, sh.luma_beta_offset as i64 / 2);
-                debug_eprint!("sh.luma_tc_offset_div2 ");
+                trace_syntax_element!(bins, "sh.luma_tc_offset_div2 ");
                 self.coder
                     .encode_signed_exp_golomb(bins, sh.luma_tc_offset as i64 / 2);
                 if sh.pps.chroma_tool_offsets_present_flag {
-                    debug_eprint!("sh.cb_beta_offset_div2 ");
+                    trace_syntax_element!(bins, "sh.cb_beta_offset_div2 ");
                     self.coder
                         .encode_signed_exp_golomb(bins, sh.cb_beta_offset as i64 / 2);
-                    debug_eprint!("sh.cb_tc_offset_div2 ");
+                    trace_syntax_element!(bins, "sh.cb_tc_offset_div2 ");
                     self.coder
                         .encode_signed_exp_golomb(bins, sh.cb_tc_offset as i64 / 2);
-                    debug_eprint!("sh.cr_beta_offset_div2 ");
+                    trace_syntax_element!(bins, "sh.cr_beta_offset_div2 ");
                     self.coder
                         .encode_signed_exp_golomb(bins, sh.cr_beta_offset as i64 / 2);
-                    debug_eprint!("sh.cr_tc_offset_div2 ");
+                    trace_syntax_element!(bins, "sh.cr_tc_offset_div2 ");
                     self.coder
                         .encode_signed_exp_golomb(bins, sh.cr_tc_offset as i64 / 2);
                 }
             }
         }
         if sh.sps.dep_quant_enabled_flag {
-            debug_eprint!("sh.dep_quant_used_flag ");
+            trace_syntax_element!(bins, "sh.dep_quant_used_flag ");
             bins.push_bin(sh.dep_quant_used_flag);
         }
         if sh.sps.sign_data_hiding_enabled_flag && !sh.dep_quant_used_flag {
-            debug_eprint!("sh.sign_data_hiding_used_flag ");
+            trace_syntax_element!(bins, "sh.sign_data_hiding_used_flag ");
             bins.push_bin(sh.sign_data_hiding_used_flag);
         }
         if sh.sps.transform_skip_enabled_flag
             && !sh.dep_quant_used_flag
             && !sh.sign_data_hiding_used_flag
         {
-            debug_eprint!("sh.ts_residual_coding_disabled_flag ");
+            trace_syntax_element!(bins, "sh.ts_residual_coding_disabled_flag ");
             bins.push_bin(sh.ts_residual_coding_disabled_flag);
         }
         if sh.pps.slice_header_extension_present_flag {
-            debug_eprint!("sh.slice_header_extension_length ");
+            trace_syntax_element!(bins, "sh.slice_header_extension_length ");
             self.coder
                 .encode_unsigned_exp_golomb(bins, sh.slice_header_extension_length as u64);
             for i in 0..sh.slice_header_extension_length {
@@ -310,8 +312,8 @@ impl<'a> SliceEncoder<'a> {
                         ectx.ctb_addr_in_curr_slice[i - 1] % ectx.pic_width_in_ctbs_y;
                     let prev_ctb_addr_y =
                         ectx.ctb_addr_in_curr_slice[i - 1] / ectx.pic_width_in_ctbs_y;
-                    if ectx.ctb_to_tile_col_bd[ctb_addr_y]
-                        != ectx.ctb_to_tile_col_bd[prev_ctb_addr_y]
+                    if ectx.ctb_to_tile_row_bd[ctb_addr_y]
+                        != ectx.ctb_to_tile_row_bd[prev_ctb_addr_y]
                         || ectx.ctb_to_tile_col_bd[ctb_addr_x]
                             != ectx.ctb_to_tile_col_bd[prev_ctb_addr_x]
                         || (ctb_addr_y != prev_ctb_addr_y
@@ -322,12 +324,13 @@ impl<'a> SliceEncoder<'a> {
                 }
             }
             if ectx.num_entry_points > 0 {
-                debug_eprint!("sh.entry_offset_len_minus1 ");
+                trace_syntax_element!(bins, "sh.entry_offset_len_minus1 ");
                 self.coder
                     .encode_unsigned_exp_golomb(bins, sh.entry_offset_len as u64 - 1);
                 for i in 0..ectx.num_entry_points {
                     let n = sh.entry_offset_len;
-                    bins.push_bins_with_size(sh.entry_point_offset[i] as u64, n);
+                    trace_syntax_element!(bins, "sh.entry_point_offset_minus1 ");
+                    bins.push_bins_with_size(sh.entry_point_offset[i] as u64 - 1, n);
                 }
             }
         }
@@ -336,81 +339,86 @@ impl<'a> SliceEncoder<'a> {
         //self.coder.byte_align(&mut bits);
         // byte_alignment
         let byte_alignment_bit_equal_to_one = true;
+        trace_syntax_element!(bins, "sh.byte_alignment_bit_equal_to_one ");
         bins.push_bin(byte_alignment_bit_equal_to_one);
         bins.byte_align();
     }
 
-    pub fn encode(&mut self, slice: &Slice, sh: &SliceHeader) -> Bins {
-        let mut bins = Bins::new();
-        self.encode_sh(&mut bins, sh, slice);
+    /// Codes the tiles in a slice into substreams, one per tile or one per CTU row with WPP, which
+    /// do not depend on the other tiles in the slice.
+    pub fn encode_tiles(&mut self, slice: &Slice, sh: &SliceHeader) -> Vec<Vec<Bins>> {
+        let tiles = slice.tiles.lock().unwrap().clone();
 
-        let ectx = self.encoder_context.clone();
-        {
-            let ectx = &mut ectx.lock().unwrap();
-            ectx.first_ctb_row_in_slice = true;
+        // each tile is coded into its own substream on its own thread with its own coder and
+        // encoder context, since no CABAC state or neighbouring information crosses tiles
+        if tiles.len() == 1 {
+            vec![Self::encode_tile(
+                slice,
+                &tiles[0],
+                true,
+                sh,
+                &self.encoder_context,
+                self.coder,
+            )]
+        } else {
+            thread::scope(|s| {
+                let handles = tiles
+                    .iter()
+                    .enumerate()
+                    .map(|(tile_idx, tile)| {
+                        let is_last_tile = tile_idx == tiles.len() - 1;
+                        let encoder_context =
+                            Arc::new(Mutex::new(self.encoder_context.lock().unwrap().clone()));
+                        s.spawn(move || {
+                            let mut coder = BoolCoder::new();
+                            Self::encode_tile(
+                                slice,
+                                tile,
+                                is_last_tile,
+                                sh,
+                                &encoder_context,
+                                &mut coder,
+                            )
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap())
+                    .collect::<Vec<_>>()
+            })
         }
-        let tiles = slice.tiles.lock().unwrap();
-        for (tile_idx, tile) in tiles.iter().enumerate() {
-            let (ctus, num_ctu_rows, num_ctu_cols) = {
-                let tile = tile.lock().unwrap();
-                (tile.ctus.clone(), tile.num_ctu_rows, tile.num_ctu_cols)
-            };
-            let ctus = {
-                let tmp = ctus.lock().unwrap();
-                tmp.clone()
-            };
-            for (ctu_row, row_ctus) in ctus.iter().enumerate().take(num_ctu_rows) {
-                for (ctu_col, ctu) in row_ctus.iter().enumerate().take(num_ctu_cols) {
-                    debug_eprintln!("ctu {} {}", ctu_row, ctu_col);
-                    {
-                        let mut ectx = ectx.lock().unwrap();
-                        ectx.ctb_addr_x = ctu_col + ctu_col;
-                        ectx.ctb_addr_y = ctu_row + ctu_row;
-                        ectx.ctb_addr_in_rs =
-                            ectx.ctb_addr_y * ectx.pic_width_in_ctbs_y + ectx.ctb_addr_y;
-                        if ctu_col == 0 {
-                            ectx.num_hmvp_cand = 0;
-                            ectx.num_hmvp_ibc_cand = 0;
-                            ectx.reset_ibc_buf = true;
-                        }
-                    }
-                    let ctu = ctu.clone();
-                    let mut ctu_encoder = CtuEncoder::new(&ectx, self.coder);
-                    ctu_encoder.encode(&mut bins, ctu.clone(), sh);
-                    if tile_idx == tiles.len() - 1
-                        && ctu_row == num_ctu_rows - 1
-                        && ctu_col == num_ctu_cols - 1
-                    {
-                        debug_eprintln!("slice end_of_slice_one_bit ");
-                        self.coder.encode_cabac_end_one_bit(
-                            &mut bins,
-                            //CabacContext::EndOfSliceOneBit
-                        );
-                    } else if ctu_col == num_ctu_cols - 1 {
-                        if ctu_row == num_ctu_rows - 1 {
-                            debug_eprintln!("slice end_of_tile_one_bit ");
-                            self.coder.encode_cabac_end_one_bit(
-                                &mut bins,
-                                //CabacContext::EndOfSliceOneBit,
-                            );
-                            let byte_alignment_bit_equal_to_one = true;
-                            bins.push_bin(byte_alignment_bit_equal_to_one);
-                            bins.byte_align();
-                        } else if sh.sps.entropy_coding_sync_enabled_flag {
-                            debug_eprintln!("slice end_of_subset_one_bit ");
-                            self.coder.encode_cabac_end_one_bit(
-                                &mut bins,
-                                //CabacContext::EndOfSliceOneBit,
-                            );
-                            let byte_alignment_bit_equal_to_one = true;
-                            bins.push_bin(byte_alignment_bit_equal_to_one);
-                            bins.byte_align();
-                        }
-                        let mut ectx = ectx.lock().unwrap();
-                        ectx.first_ctb_row_in_slice = false;
-                    }
-                }
-            }
+    }
+
+    /// Codes a slice NAL unit payload of a slice header followed by the substreams of the tiles
+    /// in the slice.
+    pub fn encode_substreams(
+        &mut self,
+        slice: &Slice,
+        sh: &mut SliceHeader,
+        tile_substreams: &[Vec<Bins>],
+    ) -> Bins {
+        let substreams = tile_substreams.iter().flatten().collect::<Vec<&Bins>>();
+        // entry points count emulation prevention bytes in the substreams
+        sh.entry_point_offset = substreams[..substreams.len() - 1]
+            .iter()
+            .map(|substream| {
+                let bytes: Vec<u8> = substream.bytes().collect();
+                bytes.len() + count_emulation_prevention_bytes(&bytes)
+            })
+            .collect();
+        sh.entry_offset_len = sh
+            .entry_point_offset
+            .iter()
+            .map(|&offset| (usize::BITS - (offset - 1).leading_zeros()) as usize)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+
+        let mut bins = Bins::new();
+        self.encode_sh(&mut bins, sh, slice);
+        for substream in substreams.iter() {
+            bins.append(substream);
         }
         // TODO See 9.3.4.3.5
         // rbsp_slice_trailing_bits
@@ -425,4 +433,466 @@ impl<'a> SliceEncoder<'a> {
         //bins.into_iter().collect()
         bins
     }
+
+    /// Codes the tile of a slice of a whole tile into slices of consecutive CTU rows, each of which
+    /// is ended at the CTU row before which its slice NAL unit exceeds max_slice_bytes, and
+    /// returns the number of CTU rows and the substreams of each slice. The CTU row is coded
+    /// again as the first one of the next slice, where CABAC is initialized.
+    pub fn encode_tile_within_bytes(
+        &mut self,
+        slice: &Slice,
+        sh: &SliceHeader,
+        max_slice_bytes: usize,
+    ) -> Vec<(usize, Vec<Bins>)> {
+        let (tile_col, tile_row) = match slice.slice_struct {
+            SliceStruct::Rectangle {
+                tile_col, tile_row, ..
+            } => (tile_col, tile_row),
+            _ => unreachable!(),
+        };
+        let tile = slice.tiles.lock().unwrap()[0].clone();
+        let (ctus, num_ctu_rows) = {
+            let tile = tile.lock().unwrap();
+            let ctus = tile.ctus.lock().unwrap().clone();
+            (ctus, tile.num_ctu_rows)
+        };
+        let (num_tile_cols, tile_row_heights) = {
+            let ectx = self.encoder_context.lock().unwrap();
+            (ectx.num_tile_columns, ectx.row_height_val.clone())
+        };
+        // a PPS where the tile is split into slices of slice_heights CTU rows, and each of the
+        // other tiles is a slice or is split into slices of single CTU rows, with the index of
+        // the slice_idx-th slice in the tile in the picture
+        let get_pps = |slice_heights: &[usize], slice_idx: usize, split_other_tiles: bool| {
+            let mut pps = sh.pps.clone();
+            let mut slice_types = vec![];
+            let mut picture_slice_idx = 0;
+            for (row, &tile_row_height) in tile_row_heights.iter().enumerate() {
+                for col in 0..num_tile_cols {
+                    if (col, row) == (tile_col, tile_row) {
+                        picture_slice_idx = slice_types.len() + slice_idx;
+                        slice_types.extend(get_tile_slice_types(col, row, slice_heights));
+                    } else if split_other_tiles {
+                        slice_types.extend(get_tile_slice_types(
+                            col,
+                            row,
+                            &vec![1; tile_row_height],
+                        ));
+                    } else {
+                        slice_types.extend(get_tile_slice_types(col, row, &[tile_row_height]));
+                    }
+                }
+            }
+            pps.set_slices(&slice_types, num_tile_cols, &tile_row_heights)
+                .unwrap();
+            let slice = Slice {
+                slice_struct: get_tile_slice_types(tile_col, tile_row, slice_heights)[slice_idx],
+                nal_unit_type: slice.nal_unit_type,
+                tiles: slice.tiles.clone(),
+            };
+            (pps, slice, picture_slice_idx)
+        };
+        fn new_sh<'b>(
+            sh: &SliceHeader<'b>,
+            pps: &'b PictureParameterSet,
+            slice: &Slice,
+            slice_idx: usize,
+            ectx: &Arc<Mutex<EncoderContext>>,
+        ) -> SliceHeader<'b> {
+            let ectx = &mut ectx.lock().unwrap();
+            ectx.update_from_sps_and_pps(sh.sps, pps);
+            let mut slice_sh = SliceHeader::new(sh.sps, pps, sh.aps, sh.ph, None, ectx);
+            slice_sh.qp_delta = sh.qp_delta;
+            slice_sh.set_slice_address(slice, slice_idx, ectx);
+            ectx.update_from_sh(&slice_sh, pps);
+            slice_sh
+        }
+
+        let mut slices: Vec<(usize, Vec<Bins>)> = vec![];
+        let mut first_ctu_row = 0;
+        while first_ctu_row < num_ctu_rows {
+            // the CTUs are coded as a slice of the rest of the tile, which is the same as a slice
+            // ending at any CTU row for the CTUs up to the row
+            let mut slice_heights = slices.iter().map(|(h, _)| *h).collect::<Vec<usize>>();
+            slice_heights.push(num_ctu_rows - first_ctu_row);
+            let (pps, coded_slice, slice_idx) = get_pps(&slice_heights, slices.len(), false);
+            let coded_sh = new_sh(sh, &pps, &coded_slice, slice_idx, &self.encoder_context);
+
+            // the first CTU row has been coded in the previous slice unless it is the first one
+            if !slices.is_empty() {
+                let fixed_qp = self.encoder_context.lock().unwrap().fixed_qp;
+                for ctu in ctus[first_ctu_row].iter() {
+                    CodingTreeUnit::reset_ct(ctu, fixed_qp);
+                }
+            }
+            let mut coder = BoolCoder::new();
+            let mut bins = Bins::new();
+            let mut row_substreams = vec![];
+            let mut fitting_slice: Option<(usize, Vec<Bins>)> = None;
+            for (ctu_row, row_ctus) in ctus.iter().enumerate().skip(first_ctu_row) {
+                if sh.sps.entropy_coding_sync_enabled_flag && ctu_row > first_ctu_row {
+                    // contexts are synchronized to those stored after the first CTU of the row
+                    // above
+                    let cabac_table_state_sync = coder.cabac_table_state_sync.clone();
+                    coder = BoolCoder::new();
+                    coder.cabac_table_state_sync = cabac_table_state_sync;
+                }
+                for (ctu_col, ctu) in row_ctus.iter().enumerate() {
+                    debug_eprintln!("ctu {} {}", ctu_row, ctu_col);
+                    Self::encode_ctu(&mut bins, &self.encoder_context, &mut coder, ctu, &coded_sh);
+                }
+                let substreams = if sh.sps.entropy_coding_sync_enabled_flag {
+                    let mut row_bins = std::mem::replace(&mut bins, Bins::new());
+                    let mut row_coder = coder.clone();
+                    row_coder.encode_cabac_end_one_bit(&mut row_bins);
+                    row_bins.byte_align();
+                    row_substreams.push(row_bins);
+                    row_substreams.clone()
+                } else {
+                    let mut slice_bins = bins.clone();
+                    let mut slice_coder = coder.clone();
+                    slice_coder.encode_cabac_end_one_bit(&mut slice_bins);
+                    slice_bins.byte_align();
+                    vec![slice_bins]
+                };
+
+                // the slice is measured in a PPS with as many slices as the final one at most, so
+                // that sh_slice_address is no shorter than the final one
+                let num_slice_ctu_rows = ctu_row + 1 - first_ctu_row;
+                let mut slice_heights = slices.iter().map(|(h, _)| *h).collect::<Vec<usize>>();
+                slice_heights.push(num_slice_ctu_rows);
+                slice_heights.extend(vec![1; num_ctu_rows - ctu_row - 1]);
+                let (pps, measured_slice, slice_idx) = get_pps(&slice_heights, slices.len(), true);
+                let ectx = Arc::new(Mutex::new(self.encoder_context.lock().unwrap().clone()));
+                let mut measured_sh = new_sh(sh, &pps, &measured_slice, slice_idx, &ectx);
+                let mut measure_coder = BoolCoder::new();
+                let slice_size = get_nal_unit_size(
+                    &SliceEncoder::new(&ectx, &mut measure_coder).encode_substreams(
+                        &measured_slice,
+                        &mut measured_sh,
+                        std::slice::from_ref(&substreams),
+                    ),
+                );
+                if slice_size > max_slice_bytes && fitting_slice.is_some() {
+                    break;
+                }
+                fitting_slice = Some((num_slice_ctu_rows, substreams));
+                if slice_size > max_slice_bytes {
+                    break;
+                }
+            }
+            let fitting_slice = fitting_slice.unwrap();
+            first_ctu_row += fitting_slice.0;
+            slices.push(fitting_slice);
+        }
+        slices
+    }
+
+    /// Codes a CTU of a tile, without the bits ending a slice, tile or CTU row after it.
+    fn encode_ctu(
+        bins: &mut Bins,
+        ectx: &Arc<Mutex<EncoderContext>>,
+        coder: &mut BoolCoder,
+        ctu: &ArcMutex<CodingTreeUnit>,
+        sh: &SliceHeader,
+    ) {
+        {
+            let ctu = ctu.lock().unwrap();
+            let mut ectx = ectx.lock().unwrap();
+            ectx.ctb_addr_x = ctu.x >> ectx.ctb_log2_size_y;
+            ectx.ctb_addr_y = ctu.y >> ectx.ctb_log2_size_y;
+            ectx.ctb_addr_in_rs = ectx.ctb_addr_y * ectx.pic_width_in_ctbs_y + ectx.ctb_addr_x;
+            if ctu.x == ctu.x_tile {
+                ectx.num_hmvp_cand = 0;
+                ectx.hmvp_cand_list.clear();
+                ectx.num_hmvp_ibc_cand = 0;
+                ectx.reset_ibc_buf = true;
+            }
+        }
+        let mut ctu_encoder = CtuEncoder::new(ectx, coder);
+        ctu_encoder.encode(bins, ctu.clone(), sh);
+    }
+
+    fn encode_tile(
+        slice: &Slice,
+        tile: &ArcMutex<Tile>,
+        is_last_tile: bool,
+        sh: &SliceHeader,
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        coder: &mut BoolCoder,
+    ) -> Vec<Bins> {
+        let (ctus, ctu_rows, num_ctu_cols) = {
+            let tile = tile.lock().unwrap();
+            (
+                tile.ctus.clone(),
+                slice.get_ctu_rows_in_tile(&tile),
+                tile.num_ctu_cols,
+            )
+        };
+        let ctus = {
+            let tmp = ctus.lock().unwrap();
+            tmp.clone()
+        };
+        let last_ctu_row = ctu_rows.end - 1;
+        let encode_ctu = |bins: &mut Bins,
+                          ectx: &Arc<Mutex<EncoderContext>>,
+                          coder: &mut BoolCoder,
+                          ctu_row: usize,
+                          ctu_col: usize| {
+            debug_eprintln!("ctu {} {}", ctu_row, ctu_col);
+            Self::encode_ctu(bins, ectx, coder, &ctus[ctu_row][ctu_col], sh);
+            if is_last_tile && ctu_row == last_ctu_row && ctu_col == num_ctu_cols - 1 {
+                debug_eprintln!("slice end_of_slice_one_bit ");
+                coder.encode_cabac_end_one_bit(
+                    bins,
+                    //CabacContext::EndOfSliceOneBit
+                );
+            } else if ctu_col == num_ctu_cols - 1 {
+                if ctu_row == last_ctu_row {
+                    debug_eprintln!("slice end_of_tile_one_bit ");
+                } else if sh.sps.entropy_coding_sync_enabled_flag {
+                    debug_eprintln!("slice end_of_subset_one_bit ");
+                } else {
+                    return;
+                }
+                // the last bit written by flushing the arithmetic encoder is
+                // byte_alignment_bit_equal_to_one
+                coder.encode_cabac_end_one_bit(
+                    bins,
+                    //CabacContext::EndOfSliceOneBit,
+                );
+                bins.byte_align();
+            }
+        };
+
+        if !sh.sps.entropy_coding_sync_enabled_flag {
+            let mut bins = Bins::new();
+            for ctu_row in ctu_rows {
+                for ctu_col in 0..num_ctu_cols {
+                    encode_ctu(&mut bins, encoder_context, coder, ctu_row, ctu_col);
+                }
+            }
+            bins.byte_align();
+            return vec![bins];
+        }
+
+        // with WPP, each CTU row is coded into its own substream on its own thread, two CTUs
+        // behind the row above, and its contexts are synchronized to those stored after the
+        // first CTU of the row above
+        let first_ctu_row = ctu_rows.start;
+        // each CTU row has its own buffers, into which the reconstructed pixels of the row above
+        // are copied as its CTUs are coded, and its reconstructed pixels are copied back into the
+        // tile when the row is coded
+        let row_tiles = ctu_rows
+            .clone()
+            .map(|ctu_row| {
+                let row_tile = tile
+                    .lock()
+                    .unwrap()
+                    .new_ctu_row_arc_mutex(ctu_row, ctu_row > first_ctu_row);
+                for ctu in ctus[ctu_row].iter() {
+                    ctu.lock().unwrap().set_tile(row_tile.clone());
+                }
+                row_tile
+            })
+            .collect::<Vec<_>>();
+        // the number of coded CTUs and the stored contexts of each row
+        let progress = ctu_rows
+            .clone()
+            .map(|_| (Mutex::new((0, None)), Condvar::new()))
+            .collect::<Vec<_>>();
+        let substreams = thread::scope(|s| {
+            let handles = ctu_rows
+                .map(|ctu_row| {
+                    let encoder_context =
+                        Arc::new(Mutex::new(encoder_context.lock().unwrap().clone()));
+                    let (progress, encode_ctu, row_tiles) = (&progress, &encode_ctu, &row_tiles);
+                    s.spawn(move || {
+                        let mut bins = Bins::new();
+                        let mut coder = BoolCoder::new();
+                        let row_idx = ctu_row - first_ctu_row;
+                        let row_tile = &row_tiles[row_idx];
+                        let mut num_copied_above_ctus = 0;
+                        for ctu_col in 0..num_ctu_cols {
+                            if row_idx > 0 {
+                                let num_coded_above_ctus = {
+                                    let (above_state, above_coded) = &progress[row_idx - 1];
+                                    let mut above_state = above_state.lock().unwrap();
+                                    while above_state.0 < num_ctu_cols.min(ctu_col + 2) {
+                                        above_state = above_coded.wait(above_state).unwrap();
+                                    }
+                                    if ctu_col == 0 {
+                                        coder.cabac_table_state_sync =
+                                            above_state.1.clone().unwrap();
+                                    }
+                                    above_state.0
+                                };
+                                if num_copied_above_ctus < num_coded_above_ctus {
+                                    let above_row_tile = row_tiles[row_idx - 1].lock().unwrap();
+                                    row_tile.lock().unwrap().copy_reconst_pixels(
+                                        &above_row_tile,
+                                        ctu_row - 1,
+                                        num_copied_above_ctus..num_coded_above_ctus,
+                                    );
+                                    num_copied_above_ctus = num_coded_above_ctus;
+                                }
+                            }
+                            encode_ctu(&mut bins, &encoder_context, &mut coder, ctu_row, ctu_col);
+                            let (state, coded) = &progress[row_idx];
+                            let mut state = state.lock().unwrap();
+                            state.0 = ctu_col + 1;
+                            if ctu_col == 0 {
+                                state.1 = Some(coder.cabac_table_state_sync.clone());
+                            }
+                            coded.notify_all();
+                        }
+                        tile.lock().unwrap().copy_reconst_pixels(
+                            &row_tile.lock().unwrap(),
+                            ctu_row,
+                            0..num_ctu_cols,
+                        );
+                        bins.byte_align();
+                        bins
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        for ctu in ctus[first_ctu_row..=last_ctu_row].iter().flatten() {
+            ctu.lock().unwrap().set_tile(tile.clone());
+        }
+        substreams
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aps::*;
+    use crate::binary_reader::*;
+    use crate::binary_writer::*;
+    use crate::picture::*;
+    use crate::picture_header::*;
+    use crate::sh_parser::*;
+    use crate::sps::*;
+
+    #[test]
+    fn entry_point_offsets_work() {
+        // 3 CTU rows coded into their own substreams with WPP
+        let mut sps = SequenceParameterSet::new(1, 8, 128, 96, 8, 1);
+        sps.entry_point_offsets_present_flag = true;
+        sps.entropy_coding_sync_enabled_flag = true;
+        let pps = PictureParameterSet::new(1, &sps, None);
+        let ph = PictureHeader::new(&pps, PictureType::IRAP_IDR, true, 0);
+        let aps = [
+            AdaptationParameterSet::new_alf(0),
+            AdaptationParameterSet::new_lmcs(0),
+            AdaptationParameterSet::new_sl(0),
+        ];
+        let new_encoder_context = || {
+            let mut ectx = EncoderContext::new();
+            ectx.update_from_sps(&sps);
+            ectx.update_from_sps_and_pps(&sps, &pps);
+            ectx.update_from_ph(&ph, &sps, &pps);
+            Arc::new(Mutex::new(ectx))
+        };
+        let ectx = new_encoder_context();
+        let mut sh = SliceHeader::new(
+            &sps,
+            &pps,
+            [&aps[0], &aps[1], &aps[2]],
+            Some(&ph),
+            None,
+            &ectx.lock().unwrap(),
+        );
+        ectx.lock().unwrap().update_from_sh(&sh, &pps);
+        let slice = Slice {
+            slice_struct: SliceStruct::Rectangle {
+                tile_col: 0,
+                tile_row: 0,
+                num_tile_cols: 1,
+                num_tile_rows: 1,
+            },
+            nal_unit_type: NALUnitType::IDR_N_LP,
+            tiles: Arc::new(Mutex::new(vec![])),
+        };
+
+        // substreams end with non-zero bytes as flushed arithmetic coders do, and the first two
+        // have 1 and 2 emulation prevention bytes
+        let substream_bytes: [&[u8]; 3] = [
+            &[0x00, 0x00, 0x00, 0x01, 0x80],
+            &[0x12, 0x00, 0x00, 0x02, 0x00, 0x00, 0x03, 0x80],
+            &[0x00, 0x00, 0x80],
+        ];
+        let substreams = substream_bytes
+            .iter()
+            .map(|bytes| {
+                let mut bins = Bins::new();
+                for &byte in bytes.iter() {
+                    bins.push_bins_with_size(byte as u64, 8);
+                }
+                bins
+            })
+            .collect::<Vec<Bins>>();
+        let mut coder = BoolCoder::new();
+        let bins =
+            SliceEncoder::new(&ectx, &mut coder).encode_substreams(&slice, &mut sh, &[substreams]);
+        assert_eq!(sh.entry_point_offset, vec![6, 10]);
+        let sh_len = {
+            let mut bins = Bins::new();
+            SliceEncoder::new(&ectx, &mut coder).encode_sh(&mut bins, &sh, &slice);
+            bins.num_bins / 8
+        };
+        let mut stream = vec![];
+        {
+            let bits = bins.into_iter().collect::<Vec<bool>>();
+            let mut writer = BinaryWriter::vec(&mut stream);
+            write_byte_stream_nal_unit_bits(0, NALUnitType::IDR_N_LP, 0, &bits, &mut writer);
+        }
+
+        let mut reader = BinaryReader::vec(&stream).unwrap();
+        let nal_unit = &read_byte_stream_nal_units(&mut reader)[0];
+        let rbsp = remove_emulation_prevention_bytes(nal_unit);
+        let mut rbsp_reader = BinaryReader::vec(&rbsp).unwrap();
+        let parsed_ectx = new_encoder_context();
+        let parsed = ShParser::new(&parsed_ectx, &mut rbsp_reader).parse(
+            NALUnitType::IDR_N_LP,
+            &sps,
+            &pps,
+            [&aps[0], &aps[1], &aps[2]],
+            Some(&ph),
+        );
+        assert_eq!(parsed.entry_point_offset, sh.entry_point_offset);
+
+        // positions in the NAL unit of the bytes which are not emulation prevention bytes
+        let mut rbsp_byte_positions = vec![];
+        let mut num_zeros = 0;
+        for (pos, &byte) in nal_unit.iter().enumerate().skip(2) {
+            if num_zeros >= 2 && byte == 3 {
+                num_zeros = 0;
+                continue;
+            }
+            num_zeros = if byte == 0 { num_zeros + 1 } else { 0 };
+            rbsp_byte_positions.push(pos);
+        }
+        // the substreams are found at the entry points in the NAL unit
+        let mut first_byte = rbsp_byte_positions[sh_len];
+        for (i, bytes) in substream_bytes.iter().enumerate() {
+            let last_byte = if i < parsed.entry_point_offset.len() {
+                first_byte + parsed.entry_point_offset[i]
+            } else {
+                nal_unit.len()
+            };
+            let substream = rbsp_byte_positions
+                .iter()
+                .filter(|&&pos| (first_byte..last_byte).contains(&pos))
+                .map(|&pos| nal_unit[pos])
+                .collect::<Vec<u8>>();
+            assert_eq!(&substream[..], *bytes);
+            first_byte = last_byte;
+        }
+    }
 }
@@ -4,6 +4,7 @@ use super::picture_header::*;
 use super::pps::*;
 use super::pred_weight_table::*;
 use super::reference_picture::*;
+use super::slice::*;
 use super::sps::*;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -61,6 +62,24 @@ pub struct SliceHeader<'a> {
 }
 
 impl<'a, 'b: 'a> SliceHeader<'a> {
+    /// Returns the RPLs of the slices of a picture, by which the pictures in the DPB are marked.
+    /// Inter slices refer to the pictures of the explicitly signalled RPLs, of which only the
+    /// first one is active and the others are kept in the DPB for the following pictures.
+    pub fn get_ref_pic_lists(ph: Option<&PictureHeader>, ectx: &EncoderContext) -> [RefPicList; 2] {
+        let inter_slice_allowed = ph.is_some_and(|ph| ph.inter_slice_allowed_flag);
+        if inter_slice_allowed && !ectx.ref_pictures[0].is_empty() {
+            let delta_pocs = ectx.ref_pictures[0]
+                .iter()
+                .map(|ref_picture| ref_picture.pic_order_cnt - ectx.pic_order_cnt_val)
+                .collect::<Vec<isize>>();
+            [0, 1].map(|lx| RefPicList::new_explicit(lx, RefPicListStruct::new_st(&delta_pocs)))
+        } else if !inter_slice_allowed {
+            [RefPicList::new_empty(0), RefPicList::new_empty(1)]
+        } else {
+            [RefPicList::new(0), RefPicList::new(1)]
+        }
+    }
+
     pub fn new(
         sps: &'b SequenceParameterSet,
         pps: &'b PictureParameterSet,
@@ -69,6 +88,13 @@ impl<'a, 'b: 'a> SliceHeader<'a> {
         fixed_qp: Option<isize>,
         ectx: &EncoderContext,
     ) -> SliceHeader<'a> {
+        let inter_slice_allowed = ph.is_some_and(|ph| ph.inter_slice_allowed_flag);
+        let slice_type = if inter_slice_allowed && !ectx.ref_pictures[0].is_empty() {
+            SliceType::P
+        } else {
+            SliceType::I
+        };
+        let ref_pic_lists = Self::get_ref_pic_lists(ph, ectx);
         SliceHeader {
             sps,
             pps,
@@ -79,21 +105,25 @@ impl<'a, 'b: 'a> SliceHeader<'a> {
             slice_address: 0,
             extra_bit: vec![],
             num_tiles_in_slice: 1,
-            slice_type: SliceType::I,
+            slice_type,
             no_output_of_prior_pics_flag: false,
             alf_enabled_flag: false,
             alf_info: AlfInfo::new(),
             lmcs_used_flag: false,
             explicit_scaling_list_used_flag: false,
-            ref_pic_lists: [RefPicList::new(0), RefPicList::new(1)],
-            num_ref_idx_active_override_flag: false,
-            num_ref_idx_active: vec![],
+            ref_pic_lists,
+            num_ref_idx_active_override_flag: slice_type == SliceType::P,
+            num_ref_idx_active: if slice_type == SliceType::P {
+                vec![1, 0]
+            } else {
+                vec![]
+            },
             cabac_init_flag: false,
             collocated_from_l0_flag: false,
             collocated_ref_idx: 0,
             pred_weight_table: None,
             qp_delta: if let Some(qp) = fixed_qp {
-                qp - ectx.slice_qp_y
+                qp - pps.init_qp
             } else {
                 0
             },
@@ -121,4 +151,28 @@ impl<'a, 'b: 'a> SliceHeader<'a> {
             entry_point_offset: vec![],
         }
     }
+    /// Returns the picture header in effect for this slice, which is either the one carried in the
+    /// slice header or the one in the preceding PH NAL unit.
+    pub fn picture_header(&self) -> &PictureHeader {
+        self.ph_in_sh.as_ref().or(self.ph).unwrap()
+    }
+
+    /// Sets sh_subpic_id, sh_slice_address and sh_num_tiles_in_slice_minus1 for the slice_idx-th
+    /// slice in the picture.
+    pub fn set_slice_address(&mut self, slice: &Slice, slice_idx: usize, ectx: &EncoderContext) {
+        if self.sps.subpic_info.is_some() {
+            self.subpic_id = ectx.subpic_id_val[ectx.subpic_idx_for_slice[slice_idx]];
+        }
+        if let SliceStruct::Raster {
+            tile_col,
+            tile_row,
+            num_tiles,
+        } = slice.slice_struct
+        {
+            self.slice_address = tile_row * ectx.num_tile_columns + tile_col;
+            self.num_tiles_in_slice = num_tiles;
+        } else if self.pps.partition_parameters.rect_slice_flag {
+            self.slice_address = ectx.subpic_level_slice_idx[slice_idx];
+        }
+    }
 }
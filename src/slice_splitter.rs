@@ -1,20 +1,193 @@
-use super::picture::*;
 use super::slice::*;
 
+// Rectangular slices are signalled in the PPS, so a SliceSplitter decides slices from the tile
+// grid only, given the number of tile columns and the height in CTUs of each tile row.
 pub trait SliceSplitter {
-    fn get_slice_types(self, picture: &Picture) -> Vec<SliceStruct>;
+    fn get_slice_types(&self, num_tile_cols: usize, tile_row_heights: &[usize])
+        -> Vec<SliceStruct>;
+
+    // A SliceSplitter can instead end slices in each tile during encoding at the CTU row before
+    // which a coded slice NAL unit exceeds a number of bytes, where the slices decided up front
+    // are the tiles. None means that the slices are decided up front only.
+    fn get_max_slice_bytes(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Returns the slices of slice_heights CTU rows from the top of a tile, where a single slice is
+/// the whole tile.
+pub fn get_tile_slice_types(
+    tile_col: usize,
+    tile_row: usize,
+    slice_heights: &[usize],
+) -> Vec<SliceStruct> {
+    if slice_heights.len() == 1 {
+        return vec![SliceStruct::Rectangle {
+            tile_col,
+            tile_row,
+            num_tile_cols: 1,
+            num_tile_rows: 1,
+        }];
+    }
+    let mut ctu_row_offset = 0;
+    slice_heights
+        .iter()
+        .map(|&num_ctu_rows| {
+            let slice_type = SliceStruct::RectangleInTile {
+                tile_col,
+                tile_row,
+                ctu_row_offset,
+                num_ctu_rows,
+            };
+            ctu_row_offset += num_ctu_rows;
+            slice_type
+        })
+        .collect()
 }
 
 pub struct UnitSliceSplitter {}
 
 impl SliceSplitter for UnitSliceSplitter {
-    fn get_slice_types(self, picture: &Picture) -> Vec<SliceStruct> {
-        let tiles = picture.tiles.lock().unwrap();
+    fn get_slice_types(
+        &self,
+        num_tile_cols: usize,
+        tile_row_heights: &[usize],
+    ) -> Vec<SliceStruct> {
         vec![SliceStruct::Rectangle {
             tile_col: 0,
             tile_row: 0,
-            num_tile_cols: tiles[0].len(),
-            num_tile_rows: tiles.len(),
+            num_tile_cols,
+            num_tile_rows: tile_row_heights.len(),
         }]
     }
 }
+
+/// Splits a picture into num_slices raster-scan slices whose numbers of tiles differ by one at
+/// most.
+pub struct RasterSliceSplitter {
+    pub num_slices: usize,
+}
+
+impl SliceSplitter for RasterSliceSplitter {
+    fn get_slice_types(
+        &self,
+        num_tile_cols: usize,
+        tile_row_heights: &[usize],
+    ) -> Vec<SliceStruct> {
+        let num_tiles = num_tile_cols * tile_row_heights.len();
+        let num_slices = self.num_slices.min(num_tiles);
+        (0..num_slices)
+            .map(|i| {
+                let tile_idx = i * num_tiles / num_slices;
+                SliceStruct::Raster {
+                    tile_col: tile_idx % num_tile_cols,
+                    tile_row: tile_idx / num_tile_cols,
+                    num_tiles: (i + 1) * num_tiles / num_slices - tile_idx,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Puts each tile into its own rectangular slice.
+pub struct TileSliceSplitter {}
+
+impl SliceSplitter for TileSliceSplitter {
+    fn get_slice_types(
+        &self,
+        num_tile_cols: usize,
+        tile_row_heights: &[usize],
+    ) -> Vec<SliceStruct> {
+        (0..tile_row_heights.len())
+            .flat_map(|tile_row| {
+                (0..num_tile_cols).map(move |tile_col| SliceStruct::Rectangle {
+                    tile_col,
+                    tile_row,
+                    num_tile_cols: 1,
+                    num_tile_rows: 1,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Splits each tile into rectangular slices of num_ctu_rows CTU rows, where the last slice in a
+/// tile has the remaining rows.
+pub struct CtuRowSliceSplitter {
+    pub num_ctu_rows: usize,
+}
+
+impl SliceSplitter for CtuRowSliceSplitter {
+    fn get_slice_types(
+        &self,
+        num_tile_cols: usize,
+        tile_row_heights: &[usize],
+    ) -> Vec<SliceStruct> {
+        let mut slice_types = vec![];
+        for (tile_row, &tile_row_height) in tile_row_heights.iter().enumerate() {
+            let slice_heights = (0..tile_row_height)
+                .step_by(self.num_ctu_rows)
+                .map(|ctu_row_offset| self.num_ctu_rows.min(tile_row_height - ctu_row_offset))
+                .collect::<Vec<usize>>();
+            for tile_col in 0..num_tile_cols {
+                slice_types.extend(get_tile_slice_types(tile_col, tile_row, &slice_heights));
+            }
+        }
+        slice_types
+    }
+}
+
+/// Puts each subpicture of a uniform num_subpic_cols x num_subpic_rows grid over tiles into its
+/// own rectangular slice, in raster-scan order of the subpictures.
+pub struct SubpictureSliceSplitter {
+    pub num_subpic_cols: usize,
+    pub num_subpic_rows: usize,
+}
+
+impl SliceSplitter for SubpictureSliceSplitter {
+    fn get_slice_types(
+        &self,
+        num_tile_cols: usize,
+        tile_row_heights: &[usize],
+    ) -> Vec<SliceStruct> {
+        let num_tile_rows = tile_row_heights.len();
+        let (num_subpic_cols, num_subpic_rows) = (self.num_subpic_cols, self.num_subpic_rows);
+        (0..num_subpic_rows)
+            .flat_map(|i| {
+                let tile_row = i * num_tile_rows / num_subpic_rows;
+                let end_tile_row = (i + 1) * num_tile_rows / num_subpic_rows;
+                (0..num_subpic_cols).map(move |j| {
+                    let tile_col = j * num_tile_cols / num_subpic_cols;
+                    let end_tile_col = (j + 1) * num_tile_cols / num_subpic_cols;
+                    SliceStruct::Rectangle {
+                        tile_col,
+                        tile_row,
+                        num_tile_cols: end_tile_col - tile_col,
+                        num_tile_rows: end_tile_row - tile_row,
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+/// Ends a slice in a tile at the CTU row before which the coded slice NAL unit exceeds
+/// max_slice_bytes, where a slice has one CTU row at least even if the row alone exceeds it, since
+/// slices in a tile consist of complete CTU rows.
+pub struct MaxBytesSliceSplitter {
+    pub max_slice_bytes: usize,
+}
+
+impl SliceSplitter for MaxBytesSliceSplitter {
+    fn get_slice_types(
+        &self,
+        num_tile_cols: usize,
+        tile_row_heights: &[usize],
+    ) -> Vec<SliceStruct> {
+        TileSliceSplitter {}.get_slice_types(num_tile_cols, tile_row_heights)
+    }
+
+    fn get_max_slice_bytes(&self) -> Option<usize> {
+        Some(self.max_slice_bytes)
+    }
+}
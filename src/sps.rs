@@ -6,6 +6,9 @@ use super::reference_picture::*;
 use super::timing_hrd::*;
 use super::virtual_boundary::*;
 
+/// Number of subpicture IDs that sps_subpic_id_len_minus1 allows at least.
+const MIN_NUM_SUBPIC_IDS: usize = 256;
+
 pub struct SpsSubpicInfo {
     pub num_subpics: usize,
     pub independent_subpics_flag: bool,
@@ -22,6 +25,7 @@ pub struct SpsSubpicInfo {
     pub subpic_id: Vec<usize>,
 }
 
+#[derive(Clone)]
 pub struct QpTable {
     pub qp_table_start: isize,
     pub num_points_in_qp_table: usize,
@@ -232,11 +236,12 @@ impl SequenceParameterSet {
         picture_width: usize,
         picture_height: usize,
         bit_depth: usize,
+        max_sublayers: usize,
     ) -> SequenceParameterSet {
         SequenceParameterSet {
             id,
             video_parameter_set_id,
-            max_sublayers: 1,
+            max_sublayers,
             chroma_format: ChromaFormat::YCbCr420,
             log2_ctu_size: 5,
             ptl_dpb_hrd_params_present_flag: true,
@@ -258,8 +263,8 @@ impl SequenceParameterSet {
             extra_ph_bit_present_flags: vec![],
             num_extra_sh_bytes: 0,
             extra_sh_bit_present_flags: vec![],
-            sublayer_dpb_params_flag: false,
-            dpb_parameters: vec![DpbParameter::new()],
+            sublayer_dpb_params_flag: max_sublayers > 1,
+            dpb_parameters: (0..max_sublayers).map(|_| DpbParameter::new()).collect(),
             log2_min_luma_coding_block_size: 2,
             partition_constraints_override_enabled_flag: false,
             partition_constraints: PartitionConstraints::new(),
@@ -345,4 +350,27 @@ impl SequenceParameterSet {
             extension_data: vec![],
         }
     }
+
+    /// Sets independent subpictures given by the top-left CTU and the size in CTUs of each
+    /// subpicture, whose boundaries are treated as picture boundaries. Subpicture IDs are their
+    /// indices but signalled explicitly with MIN_NUM_SUBPIC_IDS IDs at least, so that subpictures
+    /// of different streams can be merged by rewriting sh_subpic_id in place.
+    pub fn set_subpics(&mut self, subpic_rects: &[(usize, usize, usize, usize)]) {
+        let num_subpics = subpic_rects.len();
+        self.subpic_info = Some(SpsSubpicInfo {
+            num_subpics,
+            independent_subpics_flag: true,
+            subpic_same_size_flag: false,
+            subpic_ctu_top_left_xs: subpic_rects.iter().map(|rect| rect.0).collect(),
+            subpic_ctu_top_left_ys: subpic_rects.iter().map(|rect| rect.1).collect(),
+            subpic_widths: subpic_rects.iter().map(|rect| rect.2).collect(),
+            subpic_heights: subpic_rects.iter().map(|rect| rect.3).collect(),
+            subpic_treated_as_pic_flags: vec![true; num_subpics],
+            loop_filter_across_subpic_enabled_flags: vec![false; num_subpics],
+            subpic_id_len: (num_subpics.max(MIN_NUM_SUBPIC_IDS) - 1).ilog2() as usize + 1,
+            subpic_id_mapping_explicitly_signalled_flag: true,
+            subpic_id_mapping_present_flag: true,
+            subpic_id: (0..num_subpics).collect(),
+        });
+    }
 }
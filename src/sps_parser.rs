@@ -0,0 +1,518 @@
+use super::binary_reader::*;
+use super::common::*;
+use super::dpbp_parser::*;
+use super::encoder_context::*;
+use super::hrd_parser::*;
+use super::ptl_parser::*;
+use super::reference_picture::*;
+use super::rpl_parser::*;
+use super::sps::*;
+use std::sync::{Arc, Mutex};
+
+pub struct SpsParser<'a, 'b> {
+    reader: &'a mut BinaryReader<'b>,
+    encoder_context: Arc<Mutex<EncoderContext>>,
+}
+
+impl<'a, 'b> SpsParser<'a, 'b> {
+    pub fn new(
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        reader: &'a mut BinaryReader<'b>,
+    ) -> SpsParser<'a, 'b> {
+        SpsParser {
+            reader,
+            encoder_context: encoder_context.clone(),
+        }
+    }
+
+    /// Parses seq_parameter_set_rbsp( ). Syntax elements which are not present keep the values
+    /// of SequenceParameterSet::new unless the semantics infer another value. The caller is
+    /// expected to call update_from_sps afterwards.
+    pub fn parse(&mut self) -> SequenceParameterSet {
+        let id = self.reader.read_value(4);
+        let video_parameter_set_id = self.reader.read_value(4);
+        let max_sublayers = self.reader.read_value(3) + 1;
+        let mut sps = SequenceParameterSet::new(id, video_parameter_set_id, 0, 0, 8, max_sublayers);
+        sps.chroma_format = ChromaFormat::from_idc(self.reader.read_value(2));
+        sps.log2_ctu_size = self.reader.read_value(2) + 5;
+        let ctb_size_y = 1 << sps.log2_ctu_size;
+        sps.ptl_dpb_hrd_params_present_flag = self.reader.read_bit();
+        sps.profile_tier_level = if sps.ptl_dpb_hrd_params_present_flag {
+            let ectx = self.encoder_context.clone();
+            let mut ptl_parser = PtlParser::new(&ectx, self.reader);
+            Some(ptl_parser.parse(true, sps.max_sublayers))
+        } else {
+            None
+        };
+        sps.gdr_enabled_flag = self.reader.read_bit();
+        sps.ref_pic_resampling_enabled_flag = self.reader.read_bit();
+        if sps.ref_pic_resampling_enabled_flag {
+            sps.res_change_in_clvs_allowed_flag = self.reader.read_bit();
+        }
+        sps.pic_width_max_in_luma_samples = self.reader.read_unsigned_exp_golomb();
+        sps.pic_height_max_in_luma_samples = self.reader.read_unsigned_exp_golomb();
+        let conformance_window_present_flag = self.reader.read_bit();
+        if conformance_window_present_flag {
+            let mut conformance_window = WindowOffset::new();
+            conformance_window.left_offset = self.reader.read_unsigned_exp_golomb() as isize;
+            conformance_window.right_offset = self.reader.read_unsigned_exp_golomb() as isize;
+            conformance_window.top_offset = self.reader.read_unsigned_exp_golomb() as isize;
+            conformance_window.bottom_offset = self.reader.read_unsigned_exp_golomb() as isize;
+            sps.conformance_window = Some(conformance_window);
+        }
+        let subpic_info_present_flag = self.reader.read_bit();
+        if subpic_info_present_flag {
+            sps.subpic_info = Some(self.parse_subpic_info(
+                sps.pic_width_max_in_luma_samples,
+                sps.pic_height_max_in_luma_samples,
+                ctb_size_y,
+            ));
+        }
+        sps.bitdepth = self.reader.read_unsigned_exp_golomb() + 8;
+        sps.entropy_coding_sync_enabled_flag = self.reader.read_bit();
+        sps.entry_point_offsets_present_flag = self.reader.read_bit();
+        sps.log2_max_pic_order_cnt_lsb = self.reader.read_value(4) + 4;
+        sps.poc_msb_cycle_flag = self.reader.read_bit();
+        if sps.poc_msb_cycle_flag {
+            sps.poc_msb_cycle_len = self.reader.read_unsigned_exp_golomb() + 1;
+        }
+        sps.num_extra_ph_bytes = self.reader.read_value(2);
+        sps.extra_ph_bit_present_flags = self.reader.read_bits(sps.num_extra_ph_bytes * 8);
+        sps.num_extra_sh_bytes = self.reader.read_value(2);
+        sps.extra_sh_bit_present_flags = self.reader.read_bits(sps.num_extra_sh_bytes * 8);
+        if sps.ptl_dpb_hrd_params_present_flag {
+            sps.sublayer_dpb_params_flag = if sps.max_sublayers > 1 {
+                self.reader.read_bit()
+            } else {
+                false
+            };
+            let ectx = self.encoder_context.clone();
+            let mut dpbp_parser = DpbpParser::new(&ectx, self.reader);
+            sps.dpb_parameters = dpbp_parser.parse(sps.max_sublayers, sps.sublayer_dpb_params_flag);
+        }
+        sps.log2_min_luma_coding_block_size = self.reader.read_unsigned_exp_golomb() + 2;
+        sps.partition_constraints_override_enabled_flag = self.reader.read_bit();
+        let pc = &mut sps.partition_constraints;
+        pc.log2_diff_min_qt_min_cb_intra_slice_luma = self.reader.read_unsigned_exp_golomb();
+        pc.max_mtt_hierarchy_depth_intra_slice_luma = self.reader.read_unsigned_exp_golomb();
+        if pc.max_mtt_hierarchy_depth_intra_slice_luma != 0 {
+            pc.log2_diff_max_bt_min_qt_intra_slice_luma = self.reader.read_unsigned_exp_golomb();
+            pc.log2_diff_max_tt_min_qt_intra_slice_luma = self.reader.read_unsigned_exp_golomb();
+        }
+        pc.qtbtt_dual_tree_intra_flag = if sps.chroma_format != ChromaFormat::Monochrome {
+            self.reader.read_bit()
+        } else {
+            false
+        };
+        if pc.qtbtt_dual_tree_intra_flag {
+            pc.log2_diff_min_qt_min_cb_intra_slice_chroma = self.reader.read_unsigned_exp_golomb();
+            pc.max_mtt_hierarchy_depth_intra_slice_chroma = self.reader.read_unsigned_exp_golomb();
+            if pc.max_mtt_hierarchy_depth_intra_slice_chroma != 0 {
+                pc.log2_diff_max_bt_min_qt_intra_slice_chroma =
+                    self.reader.read_unsigned_exp_golomb();
+                pc.log2_diff_max_tt_min_qt_intra_slice_chroma =
+                    self.reader.read_unsigned_exp_golomb();
+            }
+        }
+        pc.log2_diff_min_qt_min_cb_inter_slice = self.reader.read_unsigned_exp_golomb();
+        pc.max_mtt_hierarchy_depth_inter_slice = self.reader.read_unsigned_exp_golomb();
+        if pc.max_mtt_hierarchy_depth_inter_slice != 0 {
+            pc.log2_diff_max_bt_min_qt_inter_slice = self.reader.read_unsigned_exp_golomb();
+            pc.log2_diff_max_tt_min_qt_inter_slice = self.reader.read_unsigned_exp_golomb();
+        }
+        sps.max_luma_transform_size_64_flag = if ctb_size_y > 32 {
+            self.reader.read_bit()
+        } else {
+            false
+        };
+        sps.transform_skip_enabled_flag = self.reader.read_bit();
+        if sps.transform_skip_enabled_flag {
+            sps.log2_transform_skip_max_size = self.reader.read_unsigned_exp_golomb();
+            sps.bdpcm_enabled_flag = self.reader.read_bit();
+        } else {
+            sps.bdpcm_enabled_flag = false;
+        }
+        sps.mts_enabled_flag = self.reader.read_bit();
+        if sps.mts_enabled_flag {
+            sps.explicit_mts_intra_enabled_flag = self.reader.read_bit();
+            sps.explicit_mts_inter_enabled_flag = self.reader.read_bit();
+        } else {
+            sps.explicit_mts_intra_enabled_flag = false;
+            sps.explicit_mts_inter_enabled_flag = false;
+        }
+        sps.lfnst_enabled_flag = self.reader.read_bit();
+        if sps.chroma_format != ChromaFormat::Monochrome {
+            sps.joint_cbcr_enabled_flag = self.reader.read_bit();
+            sps.same_qp_table_for_chroma_flag = self.reader.read_bit();
+            let num_qp_tables = if sps.same_qp_table_for_chroma_flag {
+                1
+            } else if sps.joint_cbcr_enabled_flag {
+                3
+            } else {
+                2
+            };
+            sps.qp_tables = (0..num_qp_tables)
+                .map(|_| {
+                    let qp_table_start = self.reader.read_signed_exp_golomb() + 26;
+                    let num_points_in_qp_table = self.reader.read_unsigned_exp_golomb() + 1;
+                    let mut delta_qp_in_val = vec![];
+                    let mut delta_qp_diff_val = vec![];
+                    for _ in 0..num_points_in_qp_table {
+                        delta_qp_in_val.push(self.reader.read_unsigned_exp_golomb() as isize + 1);
+                        delta_qp_diff_val.push(self.reader.read_unsigned_exp_golomb() as isize);
+                    }
+                    QpTable {
+                        qp_table_start,
+                        num_points_in_qp_table,
+                        delta_qp_in_val,
+                        delta_qp_diff_val,
+                    }
+                })
+                .collect();
+            // the tables for Cb, Cr and joint Cb-Cr are always kept since the quantizer indexes
+            // all of them
+            while sps.qp_tables.len() < sps.num_qp_tables {
+                let qp_table = sps.qp_tables[0].clone();
+                sps.qp_tables.push(qp_table);
+            }
+        }
+        sps.sao_enabled_flag = self.reader.read_bit();
+        sps.alf_enabled_flag = self.reader.read_bit();
+        sps.ccalf_enabled_flag =
+            if sps.alf_enabled_flag && sps.chroma_format != ChromaFormat::Monochrome {
+                self.reader.read_bit()
+            } else {
+                false
+            };
+        sps.lmcs_enabled_flag = self.reader.read_bit();
+        sps.weighted_pred_flag = self.reader.read_bit();
+        sps.weighted_bipred_flag = self.reader.read_bit();
+        sps.long_term_ref_pics_flag = self.reader.read_bit();
+        if sps.video_parameter_set_id > 0 {
+            sps.inter_layer_prediction_enabled_flag = self.reader.read_bit();
+        }
+        sps.idr_rpl_present_flag = self.reader.read_bit();
+        sps.rpl1_same_as_rpl0_flag = self.reader.read_bit();
+        let n = if sps.rpl1_same_as_rpl0_flag { 1 } else { 2 };
+        for i in 0..n {
+            let num_ref_pic_list = self.reader.read_unsigned_exp_golomb();
+            let ectx = self.encoder_context.clone();
+            let mut ref_pic_list_struct_parser = RefPicListStructParser::new(&ectx, self.reader);
+            sps.ref_pic_lists[i].num_ref_pic_list = num_ref_pic_list;
+            sps.ref_pic_lists[i].ref_pic_list_structs = (0..num_ref_pic_list)
+                .map(|j| ref_pic_list_struct_parser.parse_rpls(j, num_ref_pic_list, &sps))
+                .collect();
+        }
+        if sps.rpl1_same_as_rpl0_flag {
+            let ref_pic_list_structs = sps.ref_pic_lists[0].ref_pic_list_structs.clone();
+            sps.ref_pic_lists[1] = RefPicList::new(1);
+            sps.ref_pic_lists[1].num_ref_pic_list = sps.ref_pic_lists[0].num_ref_pic_list;
+            sps.ref_pic_lists[1].ref_pic_list_structs = ref_pic_list_structs;
+        }
+        sps.ref_wraparound_enabled_flag = self.reader.read_bit();
+        sps.temporal_mvp_enabled_flag = self.reader.read_bit();
+        if sps.temporal_mvp_enabled_flag {
+            sps.sbtmvp_enabled_flag = self.reader.read_bit();
+        }
+        sps.amvr_enabled_flag = self.reader.read_bit();
+        sps.bdof_enabled_flag = self.reader.read_bit();
+        if sps.bdof_enabled_flag {
+            sps.bdof_control_present_in_ph_flag = self.reader.read_bit();
+        }
+        sps.smvd_enabled_flag = self.reader.read_bit();
+        sps.dmvr_enabled_flag = self.reader.read_bit();
+        if sps.dmvr_enabled_flag {
+            sps.dmvr_control_present_in_ph_flag = self.reader.read_bit();
+        }
+        sps.mmvd_enabled_flag = self.reader.read_bit();
+        if sps.mmvd_enabled_flag {
+            sps.mmvd_fullpel_only_enabled_flag = self.reader.read_bit();
+        }
+        sps.six_minus_max_num_merge_cand = self.reader.read_unsigned_exp_golomb();
+        let max_num_merge_cand = 6 - sps.six_minus_max_num_merge_cand;
+        sps.sbt_enabled_flag = self.reader.read_bit();
+        sps.affine_enabled_flag = self.reader.read_bit();
+        if sps.affine_enabled_flag {
+            sps.five_minus_max_num_subblock_merge_cand = self.reader.read_unsigned_exp_golomb();
+            sps.six_param_affine_enabled_flag = self.reader.read_bit();
+            if sps.amvr_enabled_flag {
+                sps.affine_amvr_enabled_flag = self.reader.read_bit();
+            }
+            sps.affine_prof_enabled_flag = self.reader.read_bit();
+            if sps.affine_prof_enabled_flag {
+                sps.prof_control_present_in_ph_flag = self.reader.read_bit();
+            }
+        }
+        sps.bcw_enabled_flag = self.reader.read_bit();
+        sps.ciip_enabled_flag = self.reader.read_bit();
+        if max_num_merge_cand >= 2 {
+            sps.gpm_enabled_flag = self.reader.read_bit();
+            if sps.gpm_enabled_flag && max_num_merge_cand >= 3 {
+                sps.max_num_merge_cand_minus_max_num_gpm_cand =
+                    self.reader.read_unsigned_exp_golomb();
+            }
+        }
+        sps.log2_parallel_merge_level = self.reader.read_unsigned_exp_golomb() + 2;
+        sps.isp_enabled_flag = self.reader.read_bit();
+        sps.mrl_enabled_flag = self.reader.read_bit();
+        sps.mip_enabled_flag = self.reader.read_bit();
+        sps.cclm_enabled_flag = if sps.chroma_format != ChromaFormat::Monochrome {
+            self.reader.read_bit()
+        } else {
+            false
+        };
+        if sps.chroma_format == ChromaFormat::YCbCr420 {
+            sps.chroma_horizontal_collocated_flag = self.reader.read_bit();
+            sps.chroma_vertical_collocated_flag = self.reader.read_bit();
+        }
+        sps.palette_enabled_flag = self.reader.read_bit();
+        if sps.chroma_format == ChromaFormat::YCbCr444 && !sps.max_luma_transform_size_64_flag {
+            sps.act_enabled_flag = self.reader.read_bit();
+        }
+        if sps.transform_skip_enabled_flag || sps.palette_enabled_flag {
+            sps.min_qp_prime_ts = self.reader.read_unsigned_exp_golomb();
+        }
+        sps.ibc_enabled_flag = self.reader.read_bit();
+        if sps.ibc_enabled_flag {
+            sps.six_minus_max_num_ibc_merge_cand = self.reader.read_unsigned_exp_golomb();
+        }
+        let ladf_parameters_present_flag = self.reader.read_bit();
+        if ladf_parameters_present_flag {
+            let num_ladf_intervals = self.reader.read_value(4) + 2;
+            let lowest_interval_qp_offset = self.reader.read_signed_exp_golomb();
+            let mut qp_offset = vec![];
+            let mut delta_threshold = vec![];
+            for _ in 0..num_ladf_intervals - 1 {
+                qp_offset.push(self.reader.read_signed_exp_golomb());
+                delta_threshold.push(self.reader.read_unsigned_exp_golomb() + 1);
+            }
+            sps.ladf_parameters = Some(LadfParameters {
+                num_ladf_intervals,
+                lowest_interval_qp_offset,
+                qp_offset,
+                delta_threshold,
+            });
+        }
+        sps.explicit_scaling_list_enabled_flag = self.reader.read_bit();
+        if sps.lfnst_enabled_flag && sps.explicit_scaling_list_enabled_flag {
+            sps.scaling_matrix_for_lfnst_disabled_flag = self.reader.read_bit();
+        }
+        if sps.act_enabled_flag && sps.explicit_scaling_list_enabled_flag {
+            sps.scaling_matrix_for_alternative_colour_space_disabled_flag = self.reader.read_bit();
+        }
+        if sps.scaling_matrix_for_alternative_colour_space_disabled_flag {
+            sps.scaling_matrix_designated_colour_space_flag = self.reader.read_bit();
+        }
+        sps.dep_quant_enabled_flag = self.reader.read_bit();
+        sps.sign_data_hiding_enabled_flag = self.reader.read_bit();
+        sps.virtual_boundaries_enabled_flag = self.reader.read_bit();
+        if sps.virtual_boundaries_enabled_flag {
+            let vbp = &mut sps.virtual_boundary_parameters;
+            vbp.virtual_boundaries_present_flag = self.reader.read_bit();
+            if vbp.virtual_boundaries_present_flag {
+                vbp.num_ver_virtual_boundaries = self.reader.read_unsigned_exp_golomb();
+                vbp.virtual_boundary_pos_xs = (0..vbp.num_ver_virtual_boundaries)
+                    .map(|_| self.reader.read_unsigned_exp_golomb() + 1)
+                    .collect();
+                vbp.num_hor_virtual_boundaries = self.reader.read_unsigned_exp_golomb();
+                vbp.virtual_boundary_pos_ys = (0..vbp.num_hor_virtual_boundaries)
+                    .map(|_| self.reader.read_unsigned_exp_golomb() + 1)
+                    .collect();
+            }
+        }
+        if sps.ptl_dpb_hrd_params_present_flag {
+            sps.timing_hrd_params_present_flag = self.reader.read_bit();
+            if sps.timing_hrd_params_present_flag {
+                let ectx = self.encoder_context.clone();
+                let general_timing_hrd_parameters = {
+                    let mut hrd_parser = HrdParser::new(&ectx, self.reader);
+                    hrd_parser.parse_general_timing_hrd_parameters()
+                };
+                if sps.max_sublayers > 1 {
+                    sps.sublayer_cpb_params_present_flag = self.reader.read_bit();
+                }
+                let first_sublayer = if sps.sublayer_cpb_params_present_flag {
+                    0
+                } else {
+                    sps.max_sublayers - 1
+                };
+                {
+                    let mut hrd_parser = HrdParser::new(&ectx, self.reader);
+                    hrd_parser.parse_ols_timing_hrd_parameters(
+                        &mut sps.ols_timing_hrd_parameters,
+                        &general_timing_hrd_parameters,
+                        first_sublayer,
+                        sps.max_sublayers,
+                    );
+                }
+                sps.general_timing_hrd_parameters = Some(general_timing_hrd_parameters);
+            }
+        }
+        sps.field_seq_flag = self.reader.read_bit();
+        sps.vui_parameters_present_flag = self.reader.read_bit();
+        if sps.vui_parameters_present_flag {
+            sps.vui_payload_size = self.reader.read_unsigned_exp_golomb() + 1;
+            self.reader.byte_align();
+            self.parse_vui_payload(sps.vui_payload_size, &mut sps);
+        }
+        let extension_data_present_flag = self.reader.read_bit();
+        if extension_data_present_flag {
+            while self.reader.more_rbsp_data() {
+                // FIXME the encoder writes inverted extension data
+                sps.extension_data.push(!self.reader.read_bit());
+            }
+        }
+        let _rbsp_stop_one_bit = self.reader.read_bit();
+        sps
+    }
+
+    fn parse_subpic_info(
+        &mut self,
+        pic_width: usize,
+        pic_height: usize,
+        ctb_size_y: usize,
+    ) -> SpsSubpicInfo {
+        let num_subpics = self.reader.read_unsigned_exp_golomb() + 1;
+        let (independent_subpics_flag, subpic_same_size_flag) = if num_subpics > 1 {
+            (self.reader.read_bit(), self.reader.read_bit())
+        } else {
+            (true, false)
+        };
+        let tmp_width_val = pic_width.div_ceil(ctb_size_y);
+        let tmp_height_val = pic_height.div_ceil(ctb_size_y);
+        let mut subpic_info = SpsSubpicInfo {
+            num_subpics,
+            independent_subpics_flag,
+            subpic_same_size_flag,
+            subpic_ctu_top_left_xs: vec![0; num_subpics],
+            subpic_ctu_top_left_ys: vec![0; num_subpics],
+            subpic_widths: vec![tmp_width_val; num_subpics],
+            subpic_heights: vec![tmp_height_val; num_subpics],
+            subpic_treated_as_pic_flags: vec![true; num_subpics],
+            loop_filter_across_subpic_enabled_flags: vec![false; num_subpics],
+            subpic_id_len: 0,
+            subpic_id_mapping_explicitly_signalled_flag: false,
+            subpic_id_mapping_present_flag: false,
+            subpic_id: (0..num_subpics).collect(),
+        };
+        if num_subpics > 1 {
+            let n_x = (tmp_width_val as f64).log2().ceil() as usize;
+            let n_y = (tmp_height_val as f64).log2().ceil() as usize;
+            for i in 0..num_subpics {
+                if !subpic_same_size_flag || i == 0 {
+                    if i > 0 && pic_width > ctb_size_y {
+                        subpic_info.subpic_ctu_top_left_xs[i] = self.reader.read_value(n_x);
+                    }
+                    if i > 0 && pic_height > ctb_size_y {
+                        subpic_info.subpic_ctu_top_left_ys[i] = self.reader.read_value(n_y);
+                    }
+                    if i < num_subpics - 1 && pic_width > ctb_size_y {
+                        subpic_info.subpic_widths[i] = self.reader.read_value(n_x) + 1;
+                    } else {
+                        subpic_info.subpic_widths[i] =
+                            tmp_width_val - subpic_info.subpic_ctu_top_left_xs[i];
+                    }
+                    if i < num_subpics - 1 && pic_height > ctb_size_y {
+                        subpic_info.subpic_heights[i] = self.reader.read_value(n_y) + 1;
+                    } else {
+                        subpic_info.subpic_heights[i] =
+                            tmp_height_val - subpic_info.subpic_ctu_top_left_ys[i];
+                    }
+                } else {
+                    let num_subpic_cols = tmp_width_val / subpic_info.subpic_widths[0];
+                    subpic_info.subpic_ctu_top_left_xs[i] =
+                        (i % num_subpic_cols) * subpic_info.subpic_widths[0];
+                    subpic_info.subpic_ctu_top_left_ys[i] =
+                        (i / num_subpic_cols) * subpic_info.subpic_heights[0];
+                    subpic_info.subpic_widths[i] = subpic_info.subpic_widths[0];
+                    subpic_info.subpic_heights[i] = subpic_info.subpic_heights[0];
+                }
+                if !independent_subpics_flag {
+                    subpic_info.subpic_treated_as_pic_flags[i] = self.reader.read_bit();
+                    subpic_info.loop_filter_across_subpic_enabled_flags[i] = self.reader.read_bit();
+                }
+            }
+        }
+        subpic_info.subpic_id_len = self.reader.read_unsigned_exp_golomb() + 1;
+        subpic_info.subpic_id_mapping_explicitly_signalled_flag = self.reader.read_bit();
+        if subpic_info.subpic_id_mapping_explicitly_signalled_flag {
+            subpic_info.subpic_id_mapping_present_flag = self.reader.read_bit();
+            if subpic_info.subpic_id_mapping_present_flag {
+                let n = subpic_info.subpic_id_len;
+                subpic_info.subpic_id = (0..num_subpics)
+                    .map(|_| self.reader.read_value(n))
+                    .collect();
+            }
+        }
+        subpic_info
+    }
+
+    pub fn parse_vui_payload(&mut self, _payload_size: usize, _sps: &mut SequenceParameterSet) {
+        // TODO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bool_coder::*;
+    use crate::nal::*;
+    use crate::sps_encoder::*;
+
+    fn round_trip(sps: &SequenceParameterSet) -> SequenceParameterSet {
+        let ectx = Arc::new(Mutex::new(EncoderContext::new()));
+        ectx.lock().unwrap().update_from_sps(sps);
+        let mut coder = BoolCoder::new();
+        let bits = SpsEncoder::new(&ectx, &mut coder).encode(sps);
+
+        let rbsp = write_and_read_rbsp(NALUnitType::SPS_NUT, &bits);
+        let mut reader = BinaryReader::vec(&rbsp).unwrap();
+        let parsed_ectx = Arc::new(Mutex::new(EncoderContext::new()));
+        let parsed = SpsParser::new(&parsed_ectx, &mut reader).parse();
+        parsed_ectx.lock().unwrap().update_from_sps(&parsed);
+        let reencoded = SpsEncoder::new(&parsed_ectx, &mut coder).encode(&parsed);
+        assert_eq!(reencoded, bits);
+        parsed
+    }
+
+    #[test]
+    fn sps_round_trip_works() {
+        let sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 3);
+        let parsed = round_trip(&sps);
+        assert_eq!(parsed.video_parameter_set_id, 8);
+        assert_eq!(parsed.pic_width_max_in_luma_samples, 128);
+        assert_eq!(parsed.pic_height_max_in_luma_samples, 64);
+        assert_eq!(parsed.log2_ctu_size, sps.log2_ctu_size);
+        assert!(parsed.subpic_info.is_none());
+    }
+
+    #[test]
+    fn sps_with_subpics_and_wpp_round_trip_works() {
+        let mut sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 1);
+        sps.set_subpics(&[(0, 0, 2, 2), (2, 0, 2, 2)]);
+        sps.entropy_coding_sync_enabled_flag = true;
+        sps.entry_point_offsets_present_flag = true;
+        sps.gdr_enabled_flag = true;
+        let parsed = round_trip(&sps);
+        assert!(parsed.entropy_coding_sync_enabled_flag);
+        assert!(parsed.gdr_enabled_flag);
+        let subpic_info = parsed.subpic_info.unwrap();
+        assert_eq!(subpic_info.num_subpics, 2);
+        assert_eq!(subpic_info.subpic_ctu_top_left_xs, vec![0, 2]);
+        assert_eq!(subpic_info.subpic_widths, vec![2, 2]);
+        assert_eq!(subpic_info.subpic_heights, vec![2, 2]);
+        assert_eq!(subpic_info.subpic_id, vec![0, 1]);
+    }
+
+    #[test]
+    fn sps_with_same_size_subpics_round_trip_works() {
+        let mut sps = SequenceParameterSet::new(1, 8, 128, 64, 8, 1);
+        sps.set_subpics(&[(0, 0, 2, 1), (2, 0, 2, 1), (0, 1, 2, 1), (2, 1, 2, 1)]);
+        sps.subpic_info.as_mut().unwrap().subpic_same_size_flag = true;
+        let subpic_info = round_trip(&sps).subpic_info.unwrap();
+        assert_eq!(subpic_info.subpic_ctu_top_left_xs, vec![0, 2, 0, 2]);
+        assert_eq!(subpic_info.subpic_ctu_top_left_ys, vec![0, 0, 1, 1]);
+        assert_eq!(subpic_info.subpic_widths, vec![2; 4]);
+        assert_eq!(subpic_info.subpic_heights, vec![1; 4]);
+    }
+}
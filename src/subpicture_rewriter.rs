@@ -0,0 +1,1237 @@
+use super::binary_reader::*;
+use super::binary_writer::*;
+use super::nal::*;
+use super::pps::*;
+use std::collections::{HashMap, HashSet};
+
+/// Reads syntax elements from an RBSP while keeping the bits read, so that the parts of
+/// parameter sets which do not depend on the picture layout can be copied as they are.
+struct RbspReader {
+    bits: Vec<bool>,
+    pos: usize,
+    // position of rbsp_stop_one_bit
+    end: usize,
+}
+
+impl RbspReader {
+    fn new(rbsp: &[u8]) -> RbspReader {
+        let bits = rbsp
+            .iter()
+            .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1 > 0))
+            .collect::<Vec<bool>>();
+        let end = bits.iter().rposition(|&bit| bit).unwrap_or(0);
+        RbspReader { bits, pos: 0, end }
+    }
+
+    fn read_flag(&mut self) -> Result<bool, String> {
+        if self.pos >= self.end {
+            return Err("unexpected end of RBSP".to_string());
+        }
+        self.pos += 1;
+        Ok(self.bits[self.pos - 1])
+    }
+
+    fn read_bits(&mut self, n_bits: usize) -> Result<usize, String> {
+        (0..n_bits).try_fold(0, |acc, _| Ok((acc << 1) | self.read_flag()? as usize))
+    }
+
+    fn read_ue(&mut self) -> Result<usize, String> {
+        let mut leading_zero_bits = 0;
+        while !self.read_flag()? {
+            leading_zero_bits += 1;
+            if leading_zero_bits > 32 {
+                return Err("invalid exp-Golomb code".to_string());
+            }
+        }
+        Ok((1 << leading_zero_bits) - 1 + self.read_bits(leading_zero_bits)?)
+    }
+
+    fn read_se(&mut self) -> Result<isize, String> {
+        let k = self.read_ue()? as isize;
+        Ok(if k & 1 == 1 { (k + 1) / 2 } else { -k / 2 })
+    }
+
+    fn byte_align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+
+    fn bits_from(&self, start: usize) -> Vec<bool> {
+        self.bits[start..self.pos].to_vec()
+    }
+
+    fn rest(&self) -> Vec<bool> {
+        self.bits[self.pos..self.end].to_vec()
+    }
+}
+
+fn push_bits(bits: &mut Vec<bool>, value: usize, n_bits: usize) {
+    bits.extend((0..n_bits).rev().map(|i| (value >> i) & 1 > 0));
+}
+
+fn push_ue(bits: &mut Vec<bool>, value: usize) {
+    let len = (value + 1).ilog2() as usize;
+    push_bits(bits, 0, len);
+    push_bits(bits, value + 1, len + 1);
+}
+
+fn push_rbsp_trailing_bits(bits: &mut Vec<bool>) {
+    let rbsp_stop_one_bit = true;
+    bits.push(rbsp_stop_one_bit);
+    bits.resize(bits.len().div_ceil(8) * 8, false);
+}
+
+/// Subpicture layout of an SPS, where each rect is given by the top-left CTU and the size in
+/// CTUs of a subpicture.
+#[derive(Clone)]
+struct SubpicLayout {
+    rects: Vec<(usize, usize, usize, usize)>,
+    // whether all subpictures are treated as pictures without loop filtering across them
+    independent: bool,
+    id_len: usize,
+    ids: Vec<usize>,
+}
+
+/// An SPS split into the syntax elements depending on the picture layout and the bits around
+/// them.
+#[derive(Clone)]
+struct SpsParts {
+    // sps_seq_parameter_set_id to sps_res_change_in_clvs_allowed_flag
+    head: Vec<bool>,
+    log2_ctu_size: usize,
+    pic_width: usize,
+    pic_height: usize,
+    // left, right, top and bottom offsets
+    conformance_window: Option<[usize; 4]>,
+    subpic_layout: Option<SubpicLayout>,
+    // sps_bitdepth_minus8 to the end of the RBSP data
+    tail: Vec<bool>,
+}
+
+/// A PPS split as SpsParts, where the tile grid is given by the width and the height in CTUs of
+/// every tile column and row.
+#[derive(Clone, PartialEq)]
+struct PpsParts {
+    // pps_pic_parameter_set_id to pps_mixed_nalu_types_in_pic_flag
+    head: Vec<bool>,
+    pic_width: usize,
+    pic_height: usize,
+    conformance_window: Option<[usize; 4]>,
+    output_flag_present_flag: bool,
+    tile_column_widths: Vec<usize>,
+    tile_row_heights: Vec<usize>,
+    loop_filter_across_tiles_enabled_flag: bool,
+    loop_filter_across_slices_enabled_flag: bool,
+    // pps_cabac_init_present_flag to the chroma tool offsets
+    middle: Vec<bool>,
+    // pps_deblocking_filter_control_present_flag to pps_deblocking_filter_disabled_flag
+    dbf_head: Vec<bool>,
+    dbf_override_enabled_flag: bool,
+    // None if pps_no_pic_partition_flag is 1 or pps_dbf_info_in_ph_flag is not present
+    dbf_info_in_ph_flag: Option<bool>,
+    dbf_tail: Vec<bool>,
+    // pps_rpl_info_in_ph_flag to pps_qp_delta_info_in_ph_flag, None if pps_no_pic_partition_flag
+    // is 1
+    info_in_ph_flags: Option<Vec<bool>>,
+    // pps_picture_header_extension_present_flag to the end of the RBSP data
+    tail: Vec<bool>,
+}
+
+fn read_conformance_window(reader: &mut RbspReader) -> Result<Option<[usize; 4]>, String> {
+    if reader.read_flag()? {
+        Ok(Some([
+            reader.read_ue()?,
+            reader.read_ue()?,
+            reader.read_ue()?,
+            reader.read_ue()?,
+        ]))
+    } else {
+        Ok(None)
+    }
+}
+
+fn push_conformance_window(bits: &mut Vec<bool>, conformance_window: &Option<[usize; 4]>) {
+    bits.push(conformance_window.is_some());
+    if let Some(offsets) = conformance_window {
+        for &offset in offsets {
+            push_ue(bits, offset);
+        }
+    }
+}
+
+fn skip_profile_tier_level(
+    reader: &mut RbspReader,
+    max_sublayers_minus1: usize,
+) -> Result<(), String> {
+    // general_profile_idc, general_tier_flag, general_level_idc, ptl_frame_only_constraint_flag
+    // and ptl_multilayer_enabled_flag
+    reader.read_bits(18)?;
+    // gci_present_flag
+    if reader.read_flag()? {
+        // constraint flags from gci_intra_only_constraint_flag to
+        // gci_no_virtual_boundaries_constraint_flag
+        reader.read_bits(71)?;
+        let gci_num_additional_bits = reader.read_bits(8)?;
+        for _ in 0..gci_num_additional_bits {
+            reader.read_flag()?;
+        }
+    }
+    reader.byte_align();
+    let mut sublayer_level_present_flags = vec![];
+    for _ in 0..max_sublayers_minus1 {
+        sublayer_level_present_flags.push(reader.read_flag()?);
+    }
+    reader.byte_align();
+    for sublayer_level_present_flag in sublayer_level_present_flags {
+        if sublayer_level_present_flag {
+            reader.read_bits(8)?;
+        }
+    }
+    let ptl_num_sub_profiles = reader.read_bits(8)?;
+    for _ in 0..ptl_num_sub_profiles {
+        reader.read_bits(32)?;
+    }
+    Ok(())
+}
+
+fn parse_sps(rbsp: &[u8]) -> Result<SpsParts, String> {
+    let mut reader = RbspReader::new(rbsp);
+    // sps_seq_parameter_set_id and sps_video_parameter_set_id
+    reader.read_bits(8)?;
+    let max_sublayers_minus1 = reader.read_bits(3)?;
+    // sps_chroma_format_idc
+    reader.read_bits(2)?;
+    let log2_ctu_size = reader.read_bits(2)? + 5;
+    let ptl_dpb_hrd_params_present_flag = reader.read_flag()?;
+    if ptl_dpb_hrd_params_present_flag {
+        skip_profile_tier_level(&mut reader, max_sublayers_minus1)?;
+    }
+    // sps_gdr_enabled_flag
+    reader.read_flag()?;
+    let ref_pic_resampling_enabled_flag = reader.read_flag()?;
+    if ref_pic_resampling_enabled_flag && reader.read_flag()? {
+        return Err("resolution changes in CLVSs are not supported".to_string());
+    }
+    let head = reader.bits_from(0);
+    let pic_width = reader.read_ue()?;
+    let pic_height = reader.read_ue()?;
+    let conformance_window = read_conformance_window(&mut reader)?;
+    let subpic_layout = if reader.read_flag()? {
+        let ctb_size = 1 << log2_ctu_size;
+        let tmp_width_val = pic_width.div_ceil(ctb_size);
+        let tmp_height_val = pic_height.div_ceil(ctb_size);
+        let num_subpics = reader.read_ue()? + 1;
+        let (independent_subpics_flag, subpic_same_size_flag) = if num_subpics > 1 {
+            (reader.read_flag()?, reader.read_flag()?)
+        } else {
+            (true, false)
+        };
+        let mut rects: Vec<(usize, usize, usize, usize)> = vec![];
+        let mut independent = true;
+        if num_subpics == 1 {
+            rects.push((0, 0, tmp_width_val, tmp_height_val));
+        } else {
+            for i in 0..num_subpics {
+                let n_x = (tmp_width_val as f64).log2().ceil() as usize;
+                let n_y = (tmp_height_val as f64).log2().ceil() as usize;
+                let (x, y, width, height) = if !subpic_same_size_flag || i == 0 {
+                    let x = if i > 0 && tmp_width_val > 1 {
+                        reader.read_bits(n_x)?
+                    } else {
+                        0
+                    };
+                    let y = if i > 0 && tmp_height_val > 1 {
+                        reader.read_bits(n_y)?
+                    } else {
+                        0
+                    };
+                    let width = if i < num_subpics - 1 && tmp_width_val > 1 {
+                        reader.read_bits(n_x)? + 1
+                    } else {
+                        tmp_width_val - x
+                    };
+                    let height = if i < num_subpics - 1 && tmp_height_val > 1 {
+                        reader.read_bits(n_y)? + 1
+                    } else {
+                        tmp_height_val - y
+                    };
+                    (x, y, width, height)
+                } else {
+                    let (_, _, width, height) = rects[0];
+                    let num_subpic_cols = tmp_width_val / width;
+                    (
+                        i % num_subpic_cols * width,
+                        i / num_subpic_cols * height,
+                        width,
+                        height,
+                    )
+                };
+                rects.push((x, y, width, height));
+                if !independent_subpics_flag {
+                    let subpic_treated_as_pic_flag = reader.read_flag()?;
+                    let loop_filter_across_subpic_enabled_flag = reader.read_flag()?;
+                    independent &=
+                        subpic_treated_as_pic_flag && !loop_filter_across_subpic_enabled_flag;
+                }
+            }
+        }
+        let id_len = reader.read_ue()? + 1;
+        let ids = if reader.read_flag()? {
+            if !reader.read_flag()? {
+                return Err("subpicture IDs signalled in PPSs are not supported".to_string());
+            }
+            (0..num_subpics)
+                .map(|_| reader.read_bits(id_len))
+                .collect::<Result<Vec<usize>, String>>()?
+        } else {
+            (0..num_subpics).collect()
+        };
+        Some(SubpicLayout {
+            rects,
+            independent,
+            id_len,
+            ids,
+        })
+    } else {
+        None
+    };
+    Ok(SpsParts {
+        head,
+        log2_ctu_size,
+        pic_width,
+        pic_height,
+        conformance_window,
+        subpic_layout,
+        tail: reader.rest(),
+    })
+}
+
+/// Writes an SPS with independent subpictures whose IDs are signalled explicitly.
+fn write_sps(sps: &SpsParts) -> Vec<bool> {
+    let mut bits = sps.head.clone();
+    push_ue(&mut bits, sps.pic_width);
+    push_ue(&mut bits, sps.pic_height);
+    push_conformance_window(&mut bits, &sps.conformance_window);
+    let subpic_layout = sps.subpic_layout.as_ref().unwrap();
+    let num_subpics = subpic_layout.rects.len();
+    // sps_subpic_info_present_flag
+    bits.push(true);
+    push_ue(&mut bits, num_subpics - 1);
+    if num_subpics > 1 {
+        let ctb_size = 1 << sps.log2_ctu_size;
+        let tmp_width_val = sps.pic_width.div_ceil(ctb_size);
+        let tmp_height_val = sps.pic_height.div_ceil(ctb_size);
+        let n_x = (tmp_width_val as f64).log2().ceil() as usize;
+        let n_y = (tmp_height_val as f64).log2().ceil() as usize;
+        // sps_independent_subpics_flag and sps_subpic_same_size_flag
+        bits.push(true);
+        bits.push(false);
+        for (i, &(x, y, width, height)) in subpic_layout.rects.iter().enumerate() {
+            if i > 0 && tmp_width_val > 1 {
+                push_bits(&mut bits, x, n_x);
+            }
+            if i > 0 && tmp_height_val > 1 {
+                push_bits(&mut bits, y, n_y);
+            }
+            if i < num_subpics - 1 && tmp_width_val > 1 {
+                push_bits(&mut bits, width - 1, n_x);
+            }
+            if i < num_subpics - 1 && tmp_height_val > 1 {
+                push_bits(&mut bits, height - 1, n_y);
+            }
+        }
+    }
+    push_ue(&mut bits, subpic_layout.id_len - 1);
+    // sps_subpic_id_mapping_explicitly_signalled_flag and sps_subpic_id_mapping_present_flag
+    bits.push(true);
+    bits.push(true);
+    for &id in subpic_layout.ids.iter() {
+        push_bits(&mut bits, id, subpic_layout.id_len);
+    }
+    bits.extend(&sps.tail);
+    push_rbsp_trailing_bits(&mut bits);
+    bits
+}
+
+/// Parses a PPS of pictures in which each subpicture is a single slice.
+fn parse_pps(rbsp: &[u8], log2_ctu_size: usize) -> Result<PpsParts, String> {
+    let mut reader = RbspReader::new(rbsp);
+    // pps_pic_parameter_set_id, pps_seq_parameter_set_id and pps_mixed_nalu_types_in_pic_flag
+    reader.read_bits(11)?;
+    let head = reader.bits_from(0);
+    let pic_width = reader.read_ue()?;
+    let pic_height = reader.read_ue()?;
+    let conformance_window = read_conformance_window(&mut reader)?;
+    if reader.read_flag()? {
+        return Err("scaling windows are not supported".to_string());
+    }
+    let output_flag_present_flag = reader.read_flag()?;
+    let no_pic_partition_flag = reader.read_flag()?;
+    if reader.read_flag()? {
+        return Err("subpicture IDs signalled in PPSs are not supported".to_string());
+    }
+    let ctb_size = 1 << log2_ctu_size;
+    let pic_width_in_ctbs = pic_width.div_ceil(ctb_size);
+    let pic_height_in_ctbs = pic_height.div_ceil(ctb_size);
+    let mut tile_column_widths = vec![pic_width_in_ctbs];
+    let mut tile_row_heights = vec![pic_height_in_ctbs];
+    let mut loop_filter_across_tiles_enabled_flag = false;
+    let mut loop_filter_across_slices_enabled_flag = false;
+    if !no_pic_partition_flag {
+        if reader.read_bits(2)? + 5 != log2_ctu_size {
+            return Err("CTU sizes in the SPS and the PPS differ".to_string());
+        }
+        let num_exp_tile_columns = reader.read_ue()? + 1;
+        let num_exp_tile_rows = reader.read_ue()? + 1;
+        let exp_tile_column_widths = (0..num_exp_tile_columns)
+            .map(|_| Ok(reader.read_ue()? + 1))
+            .collect::<Result<Vec<usize>, String>>()?;
+        let exp_tile_row_heights = (0..num_exp_tile_rows)
+            .map(|_| Ok(reader.read_ue()? + 1))
+            .collect::<Result<Vec<usize>, String>>()?;
+        tile_column_widths = derive_tile_sizes(&exp_tile_column_widths, pic_width_in_ctbs);
+        tile_row_heights = derive_tile_sizes(&exp_tile_row_heights, pic_height_in_ctbs);
+        let mut rect_slice_flag = true;
+        if tile_column_widths.len() * tile_row_heights.len() > 1 {
+            loop_filter_across_tiles_enabled_flag = reader.read_flag()?;
+            rect_slice_flag = reader.read_flag()?;
+        }
+        if !rect_slice_flag || !reader.read_flag()? {
+            return Err("each subpicture must be a single slice".to_string());
+        }
+        loop_filter_across_slices_enabled_flag = reader.read_flag()?;
+    }
+    let start = reader.pos;
+    // pps_cabac_init_present_flag, pps_num_ref_idx_default_active_minus1 and
+    // pps_rpl1_idx_present_flag
+    reader.read_flag()?;
+    reader.read_ue()?;
+    reader.read_ue()?;
+    reader.read_flag()?;
+    let weighted_pred_flag = reader.read_flag()?;
+    let weighted_bipred_flag = reader.read_flag()?;
+    if reader.read_flag()? {
+        // pps_pic_width_minus_wraparound_offset
+        reader.read_ue()?;
+    }
+    // pps_init_qp_minus26 and pps_cu_qp_delta_enabled_flag
+    reader.read_se()?;
+    reader.read_flag()?;
+    let chroma_tool_offsets_present_flag = reader.read_flag()?;
+    if chroma_tool_offsets_present_flag {
+        reader.read_se()?;
+        reader.read_se()?;
+        let joint_cbcr_qp_offset_present_flag = reader.read_flag()?;
+        if joint_cbcr_qp_offset_present_flag {
+            reader.read_se()?;
+        }
+        // pps_slice_chroma_qp_offsets_present_flag
+        reader.read_flag()?;
+        if reader.read_flag()? {
+            let chroma_qp_offset_list_len = reader.read_ue()? + 1;
+            for _ in 0..chroma_qp_offset_list_len {
+                reader.read_se()?;
+                reader.read_se()?;
+                if joint_cbcr_qp_offset_present_flag {
+                    reader.read_se()?;
+                }
+            }
+        }
+    }
+    let middle = reader.bits_from(start);
+    let start = reader.pos;
+    let mut dbf_override_enabled_flag = false;
+    let mut dbf_info_in_ph_flag = None;
+    let mut deblocking_filter_disabled_flag = true;
+    if reader.read_flag()? {
+        dbf_override_enabled_flag = reader.read_flag()?;
+        deblocking_filter_disabled_flag = reader.read_flag()?;
+    }
+    let dbf_head = reader.bits_from(start);
+    if !no_pic_partition_flag && dbf_override_enabled_flag {
+        dbf_info_in_ph_flag = Some(reader.read_flag()?);
+    }
+    let start = reader.pos;
+    if !deblocking_filter_disabled_flag {
+        let num_offsets = if chroma_tool_offsets_present_flag {
+            6
+        } else {
+            2
+        };
+        for _ in 0..num_offsets {
+            reader.read_se()?;
+        }
+    }
+    let dbf_tail = reader.bits_from(start);
+    let info_in_ph_flags = if !no_pic_partition_flag {
+        let start = reader.pos;
+        let rpl_info_in_ph_flag = reader.read_flag()?;
+        // pps_sao_info_in_ph_flag and pps_alf_info_in_ph_flag
+        reader.read_bits(2)?;
+        if (weighted_pred_flag || weighted_bipred_flag) && rpl_info_in_ph_flag {
+            reader.read_flag()?;
+        }
+        reader.read_flag()?;
+        Some(reader.bits_from(start))
+    } else {
+        None
+    };
+    Ok(PpsParts {
+        head,
+        pic_width,
+        pic_height,
+        conformance_window,
+        output_flag_present_flag,
+        tile_column_widths,
+        tile_row_heights,
+        loop_filter_across_tiles_enabled_flag,
+        loop_filter_across_slices_enabled_flag,
+        middle,
+        dbf_head,
+        dbf_override_enabled_flag,
+        dbf_info_in_ph_flag,
+        dbf_tail,
+        info_in_ph_flags,
+        tail: reader.rest(),
+    })
+}
+
+/// Writes a PPS in which each subpicture is a single slice. The picture is always partitioned
+/// as it may have multiple subpictures, where information absent from a PPS without partitioning
+/// is signalled as inferred, in slice headers.
+fn write_pps(pps: &PpsParts, log2_ctu_size: usize) -> Vec<bool> {
+    let mut bits = pps.head.clone();
+    push_ue(&mut bits, pps.pic_width);
+    push_ue(&mut bits, pps.pic_height);
+    push_conformance_window(&mut bits, &pps.conformance_window);
+    // pps_scaling_window_explicit_signalling_flag
+    bits.push(false);
+    bits.push(pps.output_flag_present_flag);
+    // pps_no_pic_partition_flag and pps_subpic_id_mapping_present_flag
+    bits.push(false);
+    bits.push(false);
+    let ctb_size = 1 << log2_ctu_size;
+    let num_exp_tile_columns =
+        get_num_exp_tile_sizes(&pps.tile_column_widths, pps.pic_width.div_ceil(ctb_size));
+    let num_exp_tile_rows =
+        get_num_exp_tile_sizes(&pps.tile_row_heights, pps.pic_height.div_ceil(ctb_size));
+    push_bits(&mut bits, log2_ctu_size - 5, 2);
+    push_ue(&mut bits, num_exp_tile_columns - 1);
+    push_ue(&mut bits, num_exp_tile_rows - 1);
+    for &width in &pps.tile_column_widths[..num_exp_tile_columns] {
+        push_ue(&mut bits, width - 1);
+    }
+    for &height in &pps.tile_row_heights[..num_exp_tile_rows] {
+        push_ue(&mut bits, height - 1);
+    }
+    if pps.tile_column_widths.len() * pps.tile_row_heights.len() > 1 {
+        bits.push(pps.loop_filter_across_tiles_enabled_flag);
+        // pps_rect_slice_flag
+        bits.push(true);
+    }
+    // pps_single_slice_per_subpic_flag
+    bits.push(true);
+    bits.push(pps.loop_filter_across_slices_enabled_flag);
+    bits.extend(&pps.middle);
+    bits.extend(&pps.dbf_head);
+    if pps.dbf_override_enabled_flag {
+        bits.push(pps.dbf_info_in_ph_flag.unwrap_or(false));
+    }
+    bits.extend(&pps.dbf_tail);
+    match &pps.info_in_ph_flags {
+        Some(info_in_ph_flags) => bits.extend(info_in_ph_flags),
+        // pps_rpl_info_in_ph_flag, pps_sao_info_in_ph_flag, pps_alf_info_in_ph_flag and
+        // pps_qp_delta_info_in_ph_flag
+        None => bits.extend([false; 4]),
+    }
+    bits.extend(&pps.tail);
+    push_rbsp_trailing_bits(&mut bits);
+    bits
+}
+
+/// Returns the part of tile sizes covering CTUs from first_ctb to first_ctb + num_ctbs, which
+/// must consist of whole tiles.
+fn get_tile_sizes_in_range(
+    tile_sizes: &[usize],
+    first_ctb: usize,
+    num_ctbs: usize,
+) -> Result<Vec<usize>, String> {
+    let mut sizes = vec![];
+    let mut start = 0;
+    for &size in tile_sizes {
+        if start >= first_ctb && start < first_ctb + num_ctbs {
+            if sizes.is_empty() && start > first_ctb {
+                break;
+            }
+            sizes.push(size);
+        }
+        start += size;
+    }
+    if sizes.is_empty() || sizes.iter().sum::<usize>() != num_ctbs {
+        return Err("subpictures must consist of whole tiles".to_string());
+    }
+    Ok(sizes)
+}
+
+/// Crops a conformance window to the subpicture at (x, y) of width x height luma samples in a
+/// picture of pic_width x pic_height luma samples, keeping the offsets on picture boundaries.
+fn crop_conformance_window(
+    conformance_window: &Option<[usize; 4]>,
+    (x, y, width, height): (usize, usize, usize, usize),
+    pic_width: usize,
+    pic_height: usize,
+) -> Option<[usize; 4]> {
+    conformance_window
+        .map(|[left, right, top, bottom]| {
+            [
+                if x == 0 { left } else { 0 },
+                if x + width == pic_width { right } else { 0 },
+                if y == 0 { top } else { 0 },
+                if y + height == pic_height { bottom } else { 0 },
+            ]
+        })
+        .filter(|offsets| offsets.iter().any(|&offset| offset > 0))
+}
+
+/// Returns the position and the size in luma samples of the subpicture of index subpic_idx.
+fn get_subpic_rect_in_luma_samples(
+    sps: &SpsParts,
+    subpic_idx: usize,
+) -> (usize, usize, usize, usize) {
+    let (x, y, width, height) = sps.subpic_layout.as_ref().unwrap().rects[subpic_idx];
+    let ctb_size = 1 << sps.log2_ctu_size;
+    (
+        x * ctb_size,
+        y * ctb_size,
+        ((x + width) * ctb_size).min(sps.pic_width) - x * ctb_size,
+        ((y + height) * ctb_size).min(sps.pic_height) - y * ctb_size,
+    )
+}
+
+fn is_vcl_nal_unit_type(nal_unit_type: NALUnitType) -> bool {
+    (nal_unit_type as usize) < NALUnitType::OPI_NUT as usize
+}
+
+/// Reads sh_subpic_id, which follows sh_picture_header_in_slice_header_flag equal to 0 at the
+/// beginning of a slice header.
+fn read_sh_subpic_id(nal_unit: &[u8], id_len: usize) -> Result<usize, String> {
+    let mut reader = RbspReader::new(&remove_emulation_prevention_bytes(nal_unit));
+    if reader.read_flag()? {
+        return Err("picture headers in slice headers are not supported".to_string());
+    }
+    reader.read_bits(id_len)
+}
+
+/// Writes a coded slice NAL unit whose sh_subpic_id is replaced with subpic_id in place, so that
+/// the rest of the slice header and the slice data are kept as they are.
+fn write_slice_with_subpic_id(
+    nal_unit: &[u8],
+    id_len: usize,
+    subpic_id: usize,
+    writer: &mut BinaryWriter,
+) -> Result<(), String> {
+    let (nuh_layer_id, nal_unit_type, nuh_temporal_id) = read_nal_unit_header(nal_unit)?;
+    let reader = RbspReader::new(&remove_emulation_prevention_bytes(nal_unit));
+    let mut bits = reader.bits;
+    let mut id_bits = vec![];
+    push_bits(&mut id_bits, subpic_id, id_len);
+    bits.splice(1..1 + id_len, id_bits);
+    write_byte_stream_nal_unit_bits(nuh_layer_id, nal_unit_type, nuh_temporal_id, &bits, writer);
+    Ok(())
+}
+
+/// Extracts the subpicture of index subpic_idx as a standalone bitstream from a byte stream with
+/// independent subpictures. The picture size, the conformance window and the subpicture layout in
+/// SPSs and the tile grid in PPSs are rewritten for the subpicture, while slice headers are kept
+/// with their subpicture IDs.
+pub fn extract_subpicture(
+    reader: &mut BinaryReader,
+    writer: &mut BinaryWriter,
+    subpic_idx: usize,
+) -> Result<(), String> {
+    let mut sps: Option<SpsParts> = None;
+    for nal_unit in read_byte_stream_nal_units(reader) {
+        let (nuh_layer_id, nal_unit_type, nuh_temporal_id) = read_nal_unit_header(&nal_unit)?;
+        match nal_unit_type {
+            NALUnitType::SPS_NUT => {
+                let parts = parse_sps(&remove_emulation_prevention_bytes(&nal_unit))?;
+                let subpic_layout = match &parts.subpic_layout {
+                    Some(subpic_layout) if subpic_layout.independent => subpic_layout,
+                    _ => return Err("subpictures must be coded independently".to_string()),
+                };
+                if subpic_idx >= subpic_layout.rects.len() {
+                    return Err(format!(
+                        "subpic-idx ({}) must be less than the number of subpictures ({})",
+                        subpic_idx,
+                        subpic_layout.rects.len()
+                    ));
+                }
+                let (_, _, width, height) = subpic_layout.rects[subpic_idx];
+                let rect = get_subpic_rect_in_luma_samples(&parts, subpic_idx);
+                let mut extracted = parts.clone();
+                extracted.pic_width = rect.2;
+                extracted.pic_height = rect.3;
+                extracted.conformance_window = crop_conformance_window(
+                    &parts.conformance_window,
+                    rect,
+                    parts.pic_width,
+                    parts.pic_height,
+                );
+                extracted.subpic_layout = Some(SubpicLayout {
+                    rects: vec![(0, 0, width, height)],
+                    independent: true,
+                    id_len: subpic_layout.id_len,
+                    ids: vec![subpic_layout.ids[subpic_idx]],
+                });
+                write_byte_stream_nal_unit_bits(
+                    nuh_layer_id,
+                    nal_unit_type,
+                    nuh_temporal_id,
+                    &write_sps(&extracted),
+                    writer,
+                );
+                sps = Some(parts);
+            }
+            NALUnitType::PPS_NUT => {
+                let sps = sps.as_ref().ok_or("a PPS precedes SPSs")?;
+                let mut parts = parse_pps(
+                    &remove_emulation_prevention_bytes(&nal_unit),
+                    sps.log2_ctu_size,
+                )?;
+                let (x, y, width, height) = sps.subpic_layout.as_ref().unwrap().rects[subpic_idx];
+                let rect = get_subpic_rect_in_luma_samples(sps, subpic_idx);
+                parts.tile_column_widths =
+                    get_tile_sizes_in_range(&parts.tile_column_widths, x, width)?;
+                parts.tile_row_heights =
+                    get_tile_sizes_in_range(&parts.tile_row_heights, y, height)?;
+                parts.conformance_window = crop_conformance_window(
+                    &parts.conformance_window,
+                    rect,
+                    parts.pic_width,
+                    parts.pic_height,
+                );
+                parts.pic_width = rect.2;
+                parts.pic_height = rect.3;
+                write_byte_stream_nal_unit_bits(
+                    nuh_layer_id,
+                    nal_unit_type,
+                    nuh_temporal_id,
+                    &write_pps(&parts, sps.log2_ctu_size),
+                    writer,
+                );
+            }
+            _ if is_vcl_nal_unit_type(nal_unit_type) => {
+                let subpic_layout = sps
+                    .as_ref()
+                    .ok_or("a slice precedes SPSs")?
+                    .subpic_layout
+                    .as_ref()
+                    .unwrap();
+                let subpic_id = read_sh_subpic_id(&nal_unit, subpic_layout.id_len)?;
+                if subpic_id == subpic_layout.ids[subpic_idx] {
+                    write_byte_stream_nal_unit_bytes(&nal_unit, writer);
+                }
+            }
+            _ => write_byte_stream_nal_unit_bytes(&nal_unit, writer),
+        }
+    }
+    Ok(())
+}
+
+enum Segment {
+    NonVcl(Vec<u8>),
+    // consecutive coded slice NAL units of a picture
+    Vcl(Vec<Vec<u8>>),
+}
+
+fn split_into_segments(nal_units: Vec<Vec<u8>>) -> Result<Vec<Segment>, String> {
+    let mut segments = vec![];
+    for nal_unit in nal_units {
+        let (_, nal_unit_type, _) = read_nal_unit_header(&nal_unit)?;
+        if !is_vcl_nal_unit_type(nal_unit_type) {
+            segments.push(Segment::NonVcl(nal_unit));
+        } else if let Some(Segment::Vcl(slices)) = segments.last_mut() {
+            slices.push(nal_unit);
+        } else {
+            segments.push(Segment::Vcl(vec![nal_unit]));
+        }
+    }
+    Ok(segments)
+}
+
+/// Merges pictures of byte streams with independent subpictures into pictures of a grid of
+/// num_cols columns, where the pictures of readers are placed in raster-scan order and all
+/// streams must be coded with the same parameters other than the subpicture layout. Subpicture
+/// IDs are kept if they are unique in the merged pictures, and renumbered in slice headers
+/// otherwise.
+pub fn merge_subpictures(
+    readers: &mut [BinaryReader],
+    writer: &mut BinaryWriter,
+    num_cols: usize,
+) -> Result<(), String> {
+    let streams = readers
+        .iter_mut()
+        .map(|reader| split_into_segments(read_byte_stream_nal_units(reader)))
+        .collect::<Result<Vec<Vec<Segment>>, String>>()?;
+    let num_rows = streams.len() / num_cols;
+    if streams
+        .iter()
+        .any(|stream| stream.len() != streams[0].len())
+    {
+        return Err("streams have different numbers of NAL units".to_string());
+    }
+    let mut sps: Option<SpsParts> = None;
+    // subpicture IDs in each stream mapped to those in the merged stream
+    let mut subpic_id_maps: Vec<HashMap<usize, usize>> = vec![];
+    for i in 0..streams[0].len() {
+        match &streams[0][i] {
+            Segment::NonVcl(nal_unit) => {
+                let nal_units = streams
+                    .iter()
+                    .map(|stream| match &stream[i] {
+                        Segment::NonVcl(nal_unit) => Ok(nal_unit),
+                        Segment::Vcl(_) => Err("streams have different structures".to_string()),
+                    })
+                    .collect::<Result<Vec<&Vec<u8>>, String>>()?;
+                let (nuh_layer_id, nal_unit_type, nuh_temporal_id) =
+                    read_nal_unit_header(nal_unit)?;
+                match nal_unit_type {
+                    NALUnitType::SPS_NUT => {
+                        let parts = nal_units
+                            .iter()
+                            .map(|nal_unit| parse_sps(&remove_emulation_prevention_bytes(nal_unit)))
+                            .collect::<Result<Vec<SpsParts>, String>>()?;
+                        let first = &parts[0];
+                        let ctb_size = 1 << first.log2_ctu_size;
+                        let mut rects = vec![];
+                        let mut ids = vec![];
+                        for (k, stream_sps) in parts.iter().enumerate() {
+                            let subpic_layout = match &stream_sps.subpic_layout {
+                                Some(subpic_layout) if subpic_layout.independent => subpic_layout,
+                                _ => {
+                                    return Err(
+                                        "subpictures must be coded independently".to_string()
+                                    )
+                                }
+                            };
+                            if stream_sps.head != first.head
+                                || stream_sps.tail != first.tail
+                                || stream_sps.pic_width != first.pic_width
+                                || stream_sps.pic_height != first.pic_height
+                                || subpic_layout.id_len
+                                    != first.subpic_layout.as_ref().unwrap().id_len
+                            {
+                                return Err(
+                                    "SPSs differ in other than subpicture layouts".to_string()
+                                );
+                            }
+                            if stream_sps.conformance_window.is_some()
+                                || stream_sps.pic_width % ctb_size > 0
+                                || stream_sps.pic_height % ctb_size > 0
+                            {
+                                return Err(
+                                    "picture sizes must be multiples of the CTU size".to_string()
+                                );
+                            }
+                            let offset_x = k % num_cols * stream_sps.pic_width / ctb_size;
+                            let offset_y = k / num_cols * stream_sps.pic_height / ctb_size;
+                            rects.extend(
+                                subpic_layout
+                                    .rects
+                                    .iter()
+                                    .map(|&(x, y, w, h)| (x + offset_x, y + offset_y, w, h)),
+                            );
+                            ids.extend(subpic_layout.ids.iter().map(|&id| (k, id)));
+                        }
+                        let id_len = first.subpic_layout.as_ref().unwrap().id_len;
+                        let is_unique = ids
+                            .iter()
+                            .map(|&(_, id)| id)
+                            .collect::<HashSet<usize>>()
+                            .len()
+                            == ids.len();
+                        if !is_unique && ids.len() > 1 << id_len {
+                            return Err(format!(
+                                "{} subpictures cannot be identified by {}-bit subpicture IDs",
+                                ids.len(),
+                                id_len
+                            ));
+                        }
+                        subpic_id_maps = vec![HashMap::new(); streams.len()];
+                        let merged_ids = ids
+                            .iter()
+                            .enumerate()
+                            .map(|(j, &(k, id))| {
+                                let merged_id = if is_unique { id } else { j };
+                                subpic_id_maps[k].insert(id, merged_id);
+                                merged_id
+                            })
+                            .collect();
+                        let mut merged = first.clone();
+                        merged.pic_width *= num_cols;
+                        merged.pic_height *= num_rows;
+                        merged.subpic_layout = Some(SubpicLayout {
+                            rects,
+                            independent: true,
+                            id_len,
+                            ids: merged_ids,
+                        });
+                        write_byte_stream_nal_unit_bits(
+                            nuh_layer_id,
+                            nal_unit_type,
+                            nuh_temporal_id,
+                            &write_sps(&merged),
+                            writer,
+                        );
+                        sps = Some(merged);
+                    }
+                    NALUnitType::PPS_NUT => {
+                        let log2_ctu_size =
+                            sps.as_ref().ok_or("a PPS precedes SPSs")?.log2_ctu_size;
+                        let parts = nal_units
+                            .iter()
+                            .map(|nal_unit| {
+                                parse_pps(
+                                    &remove_emulation_prevention_bytes(nal_unit),
+                                    log2_ctu_size,
+                                )
+                            })
+                            .collect::<Result<Vec<PpsParts>, String>>()?;
+                        if parts.iter().any(|pps| *pps != parts[0]) {
+                            return Err("PPSs differ in other than subpicture layouts".to_string());
+                        }
+                        if parts[0].conformance_window.is_some() {
+                            return Err(
+                                "picture sizes must be multiples of the CTU size".to_string()
+                            );
+                        }
+                        let mut merged = parts[0].clone();
+                        merged.pic_width *= num_cols;
+                        merged.pic_height *= num_rows;
+                        merged.tile_column_widths = merged.tile_column_widths.repeat(num_cols);
+                        merged.tile_row_heights = merged.tile_row_heights.repeat(num_rows);
+                        write_byte_stream_nal_unit_bits(
+                            nuh_layer_id,
+                            nal_unit_type,
+                            nuh_temporal_id,
+                            &write_pps(&merged, log2_ctu_size),
+                            writer,
+                        );
+                    }
+                    _ => {
+                        if nal_units.iter().any(|&other| other != nal_unit) {
+                            return Err(format!("{:?} NAL units differ", nal_unit_type));
+                        }
+                        write_byte_stream_nal_unit_bytes(nal_unit, writer);
+                    }
+                }
+            }
+            Segment::Vcl(_) => {
+                let id_len = sps
+                    .as_ref()
+                    .ok_or("a slice precedes SPSs")?
+                    .subpic_layout
+                    .as_ref()
+                    .unwrap()
+                    .id_len;
+                let mut nal_unit_types = HashSet::new();
+                for (k, stream) in streams.iter().enumerate() {
+                    let slices = match &stream[i] {
+                        Segment::Vcl(slices) => slices,
+                        Segment::NonVcl(_) => {
+                            return Err("streams have different structures".to_string())
+                        }
+                    };
+                    for slice in slices {
+                        let (_, nal_unit_type, _) = read_nal_unit_header(slice)?;
+                        nal_unit_types.insert(nal_unit_type as usize);
+                        if nal_unit_types.len() > 1 {
+                            return Err("NAL unit types of pictures differ".to_string());
+                        }
+                        let subpic_id = read_sh_subpic_id(slice, id_len)?;
+                        match subpic_id_maps[k].get(&subpic_id) {
+                            Some(&merged_id) if merged_id == subpic_id => {
+                                write_byte_stream_nal_unit_bytes(slice, writer);
+                            }
+                            Some(&merged_id) => {
+                                write_slice_with_subpic_id(slice, id_len, merged_id, writer)?;
+                            }
+                            None => return Err(format!("unknown subpicture ID {}", subpic_id)),
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aps::*;
+    use crate::bins::*;
+    use crate::bool_coder::*;
+    use crate::encoder_context::*;
+    use crate::ph_encoder::*;
+    use crate::ph_parser::*;
+    use crate::picture::*;
+    use crate::picture_header::*;
+    use crate::pps_encoder::*;
+    use crate::pps_parser::*;
+    use crate::sh_parser::*;
+    use crate::slice::*;
+    use crate::slice_encoder::*;
+    use crate::slice_header::*;
+    use crate::sps::*;
+    use crate::sps_encoder::*;
+    use crate::sps_parser::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Encodes a stream of 2 intra pictures of 128x64 luma samples with 32x32 CTUs, where each
+    /// tile of the grid given by the first CTU column and row of each tile is a subpicture. Only
+    /// slice headers are coded for slices.
+    fn encode_stream(tile_ctu_cols: &[usize], tile_ctu_rows: &[usize]) -> Vec<u8> {
+        let (num_ctu_cols, num_ctu_rows) = (4, 2);
+        let mut sps = SequenceParameterSet::new(0, 0, 128, 64, 8, 1);
+        let subpic_rects = tile_ctu_rows
+            .iter()
+            .enumerate()
+            .flat_map(|(j, &y)| {
+                let bottom = *tile_ctu_rows.get(j + 1).unwrap_or(&num_ctu_rows);
+                tile_ctu_cols.iter().enumerate().map(move |(i, &x)| {
+                    let right = *tile_ctu_cols.get(i + 1).unwrap_or(&num_ctu_cols);
+                    (x, y, right - x, bottom - y)
+                })
+            })
+            .collect::<Vec<(usize, usize, usize, usize)>>();
+        sps.set_subpics(&subpic_rects);
+        let mut pps = PictureParameterSet::new(0, &sps, None);
+        pps.set_tiles(
+            sps.log2_ctu_size,
+            tile_ctu_cols,
+            tile_ctu_rows,
+            num_ctu_cols,
+            num_ctu_rows,
+        );
+        let aps = [
+            AdaptationParameterSet::new_alf(0),
+            AdaptationParameterSet::new_lmcs(0),
+            AdaptationParameterSet::new_sl(0),
+        ];
+        let mut ectx = EncoderContext::new();
+        ectx.update_from_sps(&sps);
+        ectx.update_from_sps_and_pps(&sps, &pps);
+        let ectx = Arc::new(Mutex::new(ectx));
+        let mut coder = BoolCoder::new();
+        let mut stream = vec![];
+        {
+            let mut writer = BinaryWriter::vec(&mut stream);
+            let sps_bits = SpsEncoder::new(&ectx, &mut coder).encode(&sps);
+            write_byte_stream_nal_unit_bits(0, NALUnitType::SPS_NUT, 0, &sps_bits, &mut writer);
+            let pps_bits = PpsEncoder::new(&ectx, &mut coder).encode(&pps);
+            write_byte_stream_nal_unit_bits(0, NALUnitType::PPS_NUT, 0, &pps_bits, &mut writer);
+            for poc in 0..2 {
+                let (picture_type, nal_unit_type) = if poc == 0 {
+                    (PictureType::IRAP_IDR, NALUnitType::IDR_N_LP)
+                } else {
+                    (PictureType::Trailing, NALUnitType::TRAIL_NUT)
+                };
+                let ph = PictureHeader::new(&pps, picture_type, true, poc);
+                ectx.lock().unwrap().update_from_ph(&ph, &sps, &pps);
+                let mut bins = Bins::new();
+                PhEncoder::new(&ectx, &mut coder).encode(&mut bins, &ph, &sps, &pps);
+                let bits = bins.into_iter().collect::<Vec<bool>>();
+                write_byte_stream_nal_unit_bits(0, NALUnitType::PH_NUT, 0, &bits, &mut writer);
+                for slice_idx in 0..subpic_rects.len() {
+                    let slice = Slice {
+                        slice_struct: SliceStruct::Rectangle {
+                            tile_col: slice_idx % tile_ctu_cols.len(),
+                            tile_row: slice_idx / tile_ctu_cols.len(),
+                            num_tile_cols: 1,
+                            num_tile_rows: 1,
+                        },
+                        nal_unit_type,
+                        tiles: Arc::new(Mutex::new(vec![])),
+                    };
+                    let sh = {
+                        let ectx = ectx.lock().unwrap();
+                        let mut sh = SliceHeader::new(
+                            &sps,
+                            &pps,
+                            [&aps[0], &aps[1], &aps[2]],
+                            Some(&ph),
+                            None,
+                            &ectx,
+                        );
+                        sh.set_slice_address(&slice, slice_idx, &ectx);
+                        sh
+                    };
+                    ectx.lock().unwrap().update_from_sh(&sh, &pps);
+                    let mut bins = Bins::new();
+                    SliceEncoder::new(&ectx, &mut coder).encode_sh(&mut bins, &sh, &slice);
+                    let bits = bins.into_iter().collect::<Vec<bool>>();
+                    write_byte_stream_nal_unit_bits(0, nal_unit_type, 0, &bits, &mut writer);
+                }
+            }
+        }
+        stream
+    }
+
+    /// Layout of a parsed stream: the picture size, the top-left CTU, the size in CTUs and the ID
+    /// of each subpicture, the tile column widths and row heights in CTUs, and sh_subpic_id and
+    /// sh_slice_address of each slice.
+    #[derive(Debug, PartialEq)]
+    struct StreamLayout {
+        pic_size: (usize, usize),
+        subpics: Vec<(usize, usize, usize, usize, usize)>,
+        tile_sizes: (Vec<usize>, Vec<usize>),
+        slices: Vec<(usize, usize)>,
+    }
+
+    fn parse_stream(stream: &[u8]) -> StreamLayout {
+        let aps = [
+            AdaptationParameterSet::new_alf(0),
+            AdaptationParameterSet::new_lmcs(0),
+            AdaptationParameterSet::new_sl(0),
+        ];
+        let ectx = Arc::new(Mutex::new(EncoderContext::new()));
+        let mut sps: Option<SequenceParameterSet> = None;
+        let mut pps: Option<PictureParameterSet> = None;
+        let mut ph: Option<PictureHeader> = None;
+        let mut slices = vec![];
+        let mut reader = BinaryReader::vec(stream).unwrap();
+        for nal_unit in read_byte_stream_nal_units(&mut reader) {
+            let (_, nal_unit_type, _) = read_nal_unit_header(&nal_unit).unwrap();
+            let rbsp = remove_emulation_prevention_bytes(&nal_unit);
+            let mut rbsp_reader = BinaryReader::vec(&rbsp).unwrap();
+            match nal_unit_type {
+                NALUnitType::SPS_NUT => {
+                    let parsed_sps = SpsParser::new(&ectx, &mut rbsp_reader).parse();
+                    ectx.lock().unwrap().update_from_sps(&parsed_sps);
+                    sps = Some(parsed_sps);
+                }
+                NALUnitType::PPS_NUT => {
+                    let sps = sps.as_ref().unwrap();
+                    let parsed_pps = PpsParser::new(&ectx, &mut rbsp_reader).parse(sps);
+                    ectx.lock()
+                        .unwrap()
+                        .update_from_sps_and_pps(sps, &parsed_pps);
+                    pps = Some(parsed_pps);
+                }
+                NALUnitType::PH_NUT => {
+                    let (sps, pps) = (sps.as_ref().unwrap(), pps.as_ref().unwrap());
+                    let parsed_ph = PhParser::new(&ectx, &mut rbsp_reader).parse(sps, pps);
+                    ectx.lock().unwrap().update_from_ph(&parsed_ph, sps, pps);
+                    ph = Some(parsed_ph);
+                }
+                _ => {
+                    let (sps, pps) = (sps.as_ref().unwrap(), pps.as_ref().unwrap());
+                    let sh = ShParser::new(&ectx, &mut rbsp_reader).parse(
+                        nal_unit_type,
+                        sps,
+                        pps,
+                        [&aps[0], &aps[1], &aps[2]],
+                        ph.as_ref(),
+                    );
+                    slices.push((sh.subpic_id, sh.slice_address));
+                }
+            }
+        }
+        let (sps, pps) = (sps.unwrap(), pps.unwrap());
+        let subpic_info = sps.subpic_info.as_ref().unwrap();
+        assert!(subpic_info.independent_subpics_flag);
+        let ectx = ectx.lock().unwrap();
+        StreamLayout {
+            pic_size: (
+                pps.pic_width_in_luma_samples,
+                pps.pic_height_in_luma_samples,
+            ),
+            subpics: (0..subpic_info.num_subpics)
+                .map(|i| {
+                    (
+                        subpic_info.subpic_ctu_top_left_xs[i],
+                        subpic_info.subpic_ctu_top_left_ys[i],
+                        subpic_info.subpic_widths[i],
+                        subpic_info.subpic_heights[i],
+                        ectx.subpic_id_val[i],
+                    )
+                })
+                .collect(),
+            tile_sizes: (ectx.col_width_val.clone(), ectx.row_height_val.clone()),
+            slices,
+        }
+    }
+
+    fn extract(stream: &[u8], subpic_idx: usize) -> Vec<u8> {
+        let mut reader = BinaryReader::vec(stream).unwrap();
+        let mut extracted = vec![];
+        extract_subpicture(
+            &mut reader,
+            &mut BinaryWriter::vec(&mut extracted),
+            subpic_idx,
+        )
+        .unwrap();
+        extracted
+    }
+
+    fn merge(streams: &[Vec<u8>], num_cols: usize) -> Vec<u8> {
+        let mut readers = streams
+            .iter()
+            .map(|stream| BinaryReader::vec(stream).unwrap())
+            .collect::<Vec<BinaryReader>>();
+        let mut merged = vec![];
+        merge_subpictures(&mut readers, &mut BinaryWriter::vec(&mut merged), num_cols).unwrap();
+        merged
+    }
+
+    #[test]
+    fn extract_subpicture_works() {
+        let stream = encode_stream(&[0, 2], &[0, 1]);
+        let layout = parse_stream(&extract(&stream, 2));
+        assert_eq!(
+            layout,
+            StreamLayout {
+                pic_size: (64, 32),
+                subpics: vec![(0, 0, 2, 1, 2)],
+                tile_sizes: (vec![2], vec![1]),
+                slices: vec![(2, 0); 2],
+            }
+        );
+    }
+
+    #[test]
+    fn merge_subpictures_works() {
+        let stream = encode_stream(&[0, 2], &[0, 1]);
+        let layout = parse_stream(&stream);
+        assert_eq!(layout.subpics.len(), 4);
+        let extracted = (0..4)
+            .map(|subpic_idx| extract(&stream, subpic_idx))
+            .collect::<Vec<Vec<u8>>>();
+        assert_eq!(parse_stream(&merge(&extracted, 2)), layout);
+    }
+
+    #[test]
+    fn merge_subpictures_renumbers_duplicate_ids() {
+        let stream = encode_stream(&[0, 2], &[0, 1]);
+        let extracted = extract(&stream, 1);
+        let layout = parse_stream(&merge(&[extracted.clone(), extracted], 1));
+        assert_eq!(
+            layout,
+            StreamLayout {
+                pic_size: (64, 64),
+                subpics: vec![(0, 0, 2, 1, 0), (0, 1, 2, 1, 1)],
+                tile_sizes: (vec![2], vec![1, 1]),
+                slices: vec![(0, 0), (1, 0), (0, 0), (1, 0)],
+            }
+        );
+    }
+
+    #[test]
+    fn extract_subpicture_rejects_out_of_range_subpic_idx() {
+        let stream = encode_stream(&[0, 2], &[0, 1]);
+        let mut reader = BinaryReader::vec(&stream).unwrap();
+        let mut extracted = vec![];
+        let mut writer = BinaryWriter::vec(&mut extracted);
+        assert!(extract_subpicture(&mut reader, &mut writer, 4).is_err());
+    }
+}
@@ -1,15 +1,25 @@
 use super::picture::*;
 
 pub trait SubpictureSplitter {
-    fn get_subpicture_slice_index_groups(self, picture: &Picture) -> Vec<Vec<usize>>;
+    fn get_subpicture_slice_index_groups(&self, picture: &Picture) -> Vec<Vec<usize>>;
 }
 
 pub struct UnitSubpictureSplitter {}
 
 impl SubpictureSplitter for UnitSubpictureSplitter {
-    fn get_subpicture_slice_index_groups(self, picture: &Picture) -> Vec<Vec<usize>> {
+    fn get_subpicture_slice_index_groups(&self, picture: &Picture) -> Vec<Vec<usize>> {
         let slices = picture.slices.lock().unwrap();
         let slice_indices = (0..slices.len()).collect();
         vec![slice_indices]
     }
 }
+
+/// Puts each slice into its own subpicture.
+pub struct SlicePerSubpictureSplitter {}
+
+impl SubpictureSplitter for SlicePerSubpictureSplitter {
+    fn get_subpicture_slice_index_groups(&self, picture: &Picture) -> Vec<Vec<usize>> {
+        let slices = picture.slices.lock().unwrap();
+        (0..slices.len()).map(|i| vec![i]).collect()
+    }
+}
@@ -1,9 +1,22 @@
 use super::common::*;
 use super::ctu::*;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
 
+fn new_tile_buffers<T: Copy>(
+    v: T,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+) -> Vec<Vec2d<T>> {
+    vec![
+        Vec2d::new_at(v, x, y, height, width),
+        Vec2d::new_at(v, x / 2, y / 2, height / 2, width / 2),
+        Vec2d::new_at(v, x / 2, y / 2, height / 2, width / 2),
+    ]
+}
+
 pub struct Tile {
     pub ctu_col: usize,
     pub ctu_row: usize,
@@ -11,10 +24,10 @@ pub struct Tile {
     pub num_ctu_rows: usize,
     pub log2_ctu_size: usize,
     pub ctus: ArcMutex<Vec<Vec<ArcMutex<CodingTreeUnit>>>>,
-    pub original_pixels: Rc<RefCell<Vec<Vec2d<u8>>>>,
-    pub pred_pixels: Rc<RefCell<Vec<Vec2d<u8>>>>,
-    pub residual_pixels: Rc<RefCell<Vec<Vec2d<i16>>>>,
-    pub reconst_pixels: Rc<RefCell<Vec<Vec2d<u8>>>>,
+    pub original_pixels: Arc<Mutex<Vec<Vec2d<u8>>>>,
+    pub pred_pixels: Arc<Mutex<Vec<Vec2d<u8>>>>,
+    pub residual_pixels: Arc<Mutex<Vec<Vec2d<i16>>>>,
+    pub reconst_pixels: Arc<Mutex<Vec<Vec2d<u8>>>>,
 }
 
 impl Tile {
@@ -25,6 +38,7 @@ impl Tile {
         num_ctu_rows: usize,
         log2_ctu_size: usize,
         picture_ctus: ArcMutex<Vec<Vec<ArcMutex<CodingTreeUnit>>>>,
+        picture_pixels: &[Vec2d<u8>],
     ) -> ArcMutex<Tile> {
         let mut ctus = vec![];
         let picture_ctus = &picture_ctus.lock().unwrap();
@@ -35,6 +49,19 @@ impl Tile {
             }
             ctus.push(row_ctus);
         }
+        // each tile has its own buffers covering the tile, which are addressed by picture
+        // coordinates, so that tiles coded on their own threads never contend for them
+        let (x, y) = (ctu_col << log2_ctu_size, ctu_row << log2_ctu_size);
+        let (width, height) = (num_ctu_cols << log2_ctu_size, num_ctu_rows << log2_ctu_size);
+        let mut original_pixels = new_tile_buffers(0, x, y, width, height);
+        for (tile_pixels, pixels) in original_pixels.iter_mut().zip(picture_pixels.iter()) {
+            let (x_start, y_start) = (tile_pixels.x_origin, tile_pixels.y_origin);
+            let x_end = (x_start + tile_pixels.width).min(pixels.width);
+            let y_end = (y_start + tile_pixels.height).min(pixels.height);
+            for y in y_start..y_end {
+                tile_pixels[y][x_start..x_end].copy_from_slice(&pixels[y][x_start..x_end]);
+            }
+        }
         Arc::new(Mutex::new(Tile {
             ctu_col,
             ctu_row,
@@ -42,40 +69,84 @@ impl Tile {
             num_ctu_rows,
             log2_ctu_size,
             ctus: Arc::new(Mutex::new(ctus)),
-            original_pixels: Rc::new(RefCell::new(vec![
-                vec2d![0; num_ctu_rows * (1 << log2_ctu_size); num_ctu_cols * (1 << log2_ctu_size)],
-                vec2d![0; num_ctu_rows * (1 << log2_ctu_size) / 2; num_ctu_cols * (1 << log2_ctu_size) / 2],
-                vec2d![0; num_ctu_rows * (1 << log2_ctu_size) / 2; num_ctu_cols * (1 << log2_ctu_size) / 2],
-            ])),
-            pred_pixels: Rc::new(RefCell::new(vec![
-                vec2d![0; num_ctu_rows * (1 << log2_ctu_size); num_ctu_cols * (1 << log2_ctu_size)],
-                vec2d![0; num_ctu_rows * (1 << log2_ctu_size) / 2; num_ctu_cols * (1 << log2_ctu_size) / 2],
-                vec2d![0; num_ctu_rows * (1 << log2_ctu_size) / 2; num_ctu_cols * (1 << log2_ctu_size) / 2],
-            ])),
-            residual_pixels: Rc::new(RefCell::new(vec![
-                vec2d![0; num_ctu_rows * (1 << log2_ctu_size); num_ctu_cols * (1 << log2_ctu_size)],
-                vec2d![0; num_ctu_rows * (1 << log2_ctu_size) / 2; num_ctu_cols * (1 << log2_ctu_size) / 2],
-                vec2d![0; num_ctu_rows * (1 << log2_ctu_size) / 2; num_ctu_cols * (1 << log2_ctu_size) / 2],
-            ])),
-            reconst_pixels: Rc::new(RefCell::new(vec![
-                vec2d![0; num_ctu_rows * (1 << log2_ctu_size); num_ctu_cols * (1 << log2_ctu_size)],
-                vec2d![0; num_ctu_rows * (1 << log2_ctu_size) / 2; num_ctu_cols * (1 << log2_ctu_size) / 2],
-                vec2d![0; num_ctu_rows * (1 << log2_ctu_size) / 2; num_ctu_cols * (1 << log2_ctu_size) / 2],
-            ])),
+            original_pixels: Arc::new(Mutex::new(original_pixels)),
+            pred_pixels: Arc::new(Mutex::new(new_tile_buffers(0, x, y, width, height))),
+            residual_pixels: Arc::new(Mutex::new(new_tile_buffers(0, x, y, width, height))),
+            reconst_pixels: Arc::new(Mutex::new(new_tile_buffers(0, x, y, width, height))),
         }))
     }
 
+    /// Creates a tile sharing the position and CTUs of this tile but with its own buffers
+    /// covering a CTU row of the tile and, if with_above_ctu_row, the CTU row above it, so that
+    /// CTU rows coded on their own threads with WPP never contend for pixel buffers.
+    pub fn new_ctu_row_arc_mutex(
+        &self,
+        ctu_row: usize,
+        with_above_ctu_row: bool,
+    ) -> ArcMutex<Tile> {
+        let log2_ctu_size = self.log2_ctu_size;
+        let first_ctu_row = self.ctu_row + ctu_row - with_above_ctu_row as usize;
+        let (x, y) = (
+            self.ctu_col << log2_ctu_size,
+            first_ctu_row << log2_ctu_size,
+        );
+        let width = self.num_ctu_cols << log2_ctu_size;
+        let height = (1 + with_above_ctu_row as usize) << log2_ctu_size;
+        let mut original_pixels = new_tile_buffers(0, x, y, width, height);
+        for (row_pixels, tile_pixels) in original_pixels
+            .iter_mut()
+            .zip(self.original_pixels.lock().unwrap().iter())
+        {
+            let x_start = row_pixels.x_origin;
+            for y in row_pixels.y_origin..row_pixels.y_origin + row_pixels.height {
+                row_pixels[y][x_start..].copy_from_slice(&tile_pixels[y][x_start..]);
+            }
+        }
+        Arc::new(Mutex::new(Tile {
+            ctu_col: self.ctu_col,
+            ctu_row: self.ctu_row,
+            num_ctu_cols: self.num_ctu_cols,
+            num_ctu_rows: self.num_ctu_rows,
+            log2_ctu_size,
+            ctus: self.ctus.clone(),
+            original_pixels: Arc::new(Mutex::new(original_pixels)),
+            pred_pixels: Arc::new(Mutex::new(new_tile_buffers(0, x, y, width, height))),
+            residual_pixels: Arc::new(Mutex::new(new_tile_buffers(0, x, y, width, height))),
+            reconst_pixels: Arc::new(Mutex::new(new_tile_buffers(0, x, y, width, height))),
+        }))
+    }
+
+    /// Copies the reconstructed pixels of CTUs in a CTU row of the tile from another tile whose
+    /// buffers cover them.
+    pub fn copy_reconst_pixels(&self, src: &Tile, ctu_row: usize, ctu_cols: Range<usize>) {
+        let src_reconst_pixels = src.reconst_pixels.lock().unwrap();
+        let reconst_pixels = &mut self.reconst_pixels.lock().unwrap();
+        for (c_idx, (dst, src)) in reconst_pixels
+            .iter_mut()
+            .zip(src_reconst_pixels.iter())
+            .enumerate()
+        {
+            let shift = self.log2_ctu_size - (c_idx > 0) as usize;
+            let x_start = (self.ctu_col + ctu_cols.start) << shift;
+            let x_end = (self.ctu_col + ctu_cols.end) << shift;
+            let y_start = (self.ctu_row + ctu_row) << shift;
+            for y in y_start..y_start + (1 << shift) {
+                dst[y][x_start..x_end].copy_from_slice(&src[y][x_start..x_end]);
+            }
+        }
+    }
+
     pub fn get_cu(&self, x: isize, y: isize) -> Option<ArcMutex<CodingUnit>> {
-        if x < 0
-            || y < 0
+        if x < (self.ctu_col << self.log2_ctu_size) as isize
+            || y < (self.ctu_row << self.log2_ctu_size) as isize
             || x as usize >= (self.ctu_col + self.num_ctu_cols) << self.log2_ctu_size
             || y as usize >= (self.ctu_row + self.num_ctu_rows) << self.log2_ctu_size
         {
             return None;
         }
         let (x, y) = (x as usize, y as usize);
-        let row = y >> self.log2_ctu_size;
-        let col = x >> self.log2_ctu_size;
+        let row = (y >> self.log2_ctu_size) - self.ctu_row;
+        let col = (x >> self.log2_ctu_size) - self.ctu_col;
         let ctus = self.ctus.lock().unwrap();
         let ctu = &ctus[row][col];
         let ctu = ctu.lock().unwrap();
@@ -1,13 +1,185 @@
-use super::picture::*;
-
+// The tile grid is signalled in the PPS, so a TileSplitter decides it from the picture size in
+// CTUs only and returns the first CTU column of each tile column and the first CTU row of each
+// tile row.
 pub trait TileSplitter {
-    fn get_ctu_cols_and_rows(self, picture: &Picture) -> (Vec<usize>, Vec<usize>);
+    fn get_ctu_cols_and_rows(
+        &self,
+        num_ctu_cols: usize,
+        num_ctu_rows: usize,
+    ) -> (Vec<usize>, Vec<usize>);
 }
 
 pub struct UnitTileSplitter {}
 
 impl TileSplitter for UnitTileSplitter {
-    fn get_ctu_cols_and_rows(self, _picture: &Picture) -> (Vec<usize>, Vec<usize>) {
+    fn get_ctu_cols_and_rows(
+        &self,
+        _num_ctu_cols: usize,
+        _num_ctu_rows: usize,
+    ) -> (Vec<usize>, Vec<usize>) {
         (vec![0], vec![0])
     }
 }
+
+/// Splits a picture into num_tile_cols x num_tile_rows tiles whose sizes differ by one CTU at
+/// most.
+pub struct UniformTileSplitter {
+    pub num_tile_cols: usize,
+    pub num_tile_rows: usize,
+}
+
+impl TileSplitter for UniformTileSplitter {
+    fn get_ctu_cols_and_rows(
+        &self,
+        num_ctu_cols: usize,
+        num_ctu_rows: usize,
+    ) -> (Vec<usize>, Vec<usize>) {
+        let num_tile_cols = self.num_tile_cols.min(num_ctu_cols);
+        let num_tile_rows = self.num_tile_rows.min(num_ctu_rows);
+        (
+            (0..num_tile_cols)
+                .map(|i| i * num_ctu_cols / num_tile_cols)
+                .collect(),
+            (0..num_tile_rows)
+                .map(|i| i * num_ctu_rows / num_tile_rows)
+                .collect(),
+        )
+    }
+}
+
+/// Splits a picture into tiles with explicit widths and heights in CTUs. As in the PPS, the last
+/// width and height are repeated to cover the rest of the picture, and no widths or heights mean
+/// a single tile column or row.
+pub struct ExplicitTileSplitter {
+    pub tile_column_widths: Vec<usize>,
+    pub tile_row_heights: Vec<usize>,
+}
+
+impl ExplicitTileSplitter {
+    fn get_ctu_starts(sizes: &[usize], num_ctus: usize) -> Vec<usize> {
+        if sizes.is_empty() {
+            return vec![0];
+        }
+        let mut starts = vec![];
+        let mut start = 0;
+        let mut sizes = sizes.iter();
+        let mut size = 0;
+        while start < num_ctus {
+            starts.push(start);
+            if let Some(&s) = sizes.next() {
+                size = s;
+            }
+            start += size;
+        }
+        starts
+    }
+}
+
+impl TileSplitter for ExplicitTileSplitter {
+    fn get_ctu_cols_and_rows(
+        &self,
+        num_ctu_cols: usize,
+        num_ctu_rows: usize,
+    ) -> (Vec<usize>, Vec<usize>) {
+        (
+            Self::get_ctu_starts(&self.tile_column_widths, num_ctu_cols),
+            Self::get_ctu_starts(&self.tile_row_heights, num_ctu_rows),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pps::*;
+
+    fn get_sizes(starts: &[usize], num_ctus: usize) -> Vec<usize> {
+        starts
+            .iter()
+            .zip(starts.iter().skip(1).chain([num_ctus].iter()))
+            .map(|(start, end)| end - start)
+            .collect()
+    }
+
+    #[test]
+    fn derive_tile_sizes_works() {
+        // explicit sizes, number of CTBs, derived sizes
+        let cases: [(&[usize], usize, &[usize]); 7] = [
+            (&[4], 4, &[4]),
+            (&[1], 4, &[1, 1, 1, 1]),
+            (&[3], 8, &[3, 3, 2]),
+            (&[2, 3], 5, &[2, 3]),
+            (&[2, 3], 10, &[2, 3, 3, 2]),
+            (&[1, 2], 8, &[1, 2, 2, 2, 1]),
+            (&[2, 1], 6, &[2, 1, 1, 1, 1]),
+        ];
+        for (exp_sizes, num_ctbs, sizes) in cases {
+            assert_eq!(derive_tile_sizes(exp_sizes, num_ctbs), sizes);
+        }
+    }
+
+    #[test]
+    fn get_num_exp_tile_sizes_works() {
+        let cases: [(&[usize], usize, usize); 5] = [
+            (&[4], 4, 1),
+            (&[1, 1, 1, 1], 4, 1),
+            (&[3, 3, 2], 8, 1),
+            (&[2, 3, 3, 2], 10, 2),
+            (&[1, 2, 2, 2, 1], 8, 2),
+        ];
+        for (sizes, num_ctbs, num_exp_sizes) in cases {
+            assert_eq!(get_num_exp_tile_sizes(sizes, num_ctbs), num_exp_sizes);
+            assert_eq!(derive_tile_sizes(&sizes[..num_exp_sizes], num_ctbs), sizes);
+        }
+    }
+
+    #[test]
+    fn explicit_tile_splitter_works() {
+        // widths, number of CTU columns, first CTU column of each tile column
+        let cases: [(&[usize], usize, &[usize]); 7] = [
+            (&[], 5, &[0]),
+            (&[5], 5, &[0]),
+            (&[7], 5, &[0]),
+            (&[2], 5, &[0, 2, 4]),
+            (&[1, 3], 8, &[0, 1, 4, 7]),
+            (&[3, 3], 4, &[0, 3]),
+            (&[2, 1, 3], 12, &[0, 2, 3, 6, 9]),
+        ];
+        for (widths, num_ctu_cols, ctu_cols) in cases {
+            let splitter = ExplicitTileSplitter {
+                tile_column_widths: widths.to_vec(),
+                tile_row_heights: widths.to_vec(),
+            };
+            let (cols, rows) = splitter.get_ctu_cols_and_rows(num_ctu_cols, num_ctu_cols);
+            assert_eq!(cols, ctu_cols);
+            assert_eq!(rows, ctu_cols);
+            // the tiles are signalled in the PPS by the fewest explicit sizes deriving them
+            let sizes = get_sizes(&cols, num_ctu_cols);
+            let num_exp_sizes = get_num_exp_tile_sizes(&sizes, num_ctu_cols);
+            assert_eq!(
+                derive_tile_sizes(&sizes[..num_exp_sizes], num_ctu_cols),
+                sizes
+            );
+        }
+    }
+
+    #[test]
+    fn uniform_tile_splitter_works() {
+        // number of tiles, number of CTU columns, first CTU column of each tile column
+        let cases: [(usize, usize, &[usize]); 4] = [
+            (1, 5, &[0]),
+            (2, 5, &[0, 2]),
+            (3, 8, &[0, 2, 5]),
+            (4, 2, &[0, 1]),
+        ];
+        for (num_tiles, num_ctu_cols, ctu_cols) in cases {
+            let splitter = UniformTileSplitter {
+                num_tile_cols: num_tiles,
+                num_tile_rows: 1,
+            };
+            let (cols, rows) = splitter.get_ctu_cols_and_rows(num_ctu_cols, num_ctu_cols);
+            assert_eq!(cols, ctu_cols);
+            assert_eq!(rows, vec![0]);
+        }
+    }
+}
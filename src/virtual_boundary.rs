@@ -16,4 +16,15 @@ impl VirtualBoundaryParameters {
             virtual_boundary_pos_ys: vec![],
         }
     }
+    /// Returns parameters of a single vertical virtual boundary at pos_x luma samples, which is a
+    /// multiple of 8.
+    pub fn new_vertical(pos_x: usize) -> VirtualBoundaryParameters {
+        VirtualBoundaryParameters {
+            virtual_boundaries_present_flag: true,
+            num_ver_virtual_boundaries: 1,
+            virtual_boundary_pos_xs: vec![pos_x / 8],
+            num_hor_virtual_boundaries: 0,
+            virtual_boundary_pos_ys: vec![],
+        }
+    }
 }
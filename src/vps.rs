@@ -64,7 +64,8 @@ pub struct VideoParameterSet {
     pub profile_tier_levels: Vec<ProfileTierLevel>,
     pub general_timing_hrd_parameters: Option<GeneralTimingHrdParameters>,
     pub sublayer_dpb_params_present_flag: bool,
-    pub dpb_parameters: Vec<DpbParameter>,
+    /// dpb_parameters( ) syntax structures, each of which has the parameters for each sublayer
+    pub dpb_parameters: Vec<Vec<DpbParameter>>,
     pub ols_dpb_parameters: Vec<OlsDpbParameter>,
     pub sublayer_cpb_params_present_flag: bool,
     pub num_ols_timing_hrd_params: usize,
@@ -81,24 +82,25 @@ impl VideoParameterSet {
         height: usize,
         bitdepth: usize,
         chroma_format: ChromaFormat,
+        max_sublayers: usize,
     ) -> VideoParameterSet {
         VideoParameterSet {
             id,
             max_layers: 1,
-            max_sublayers: 1,
+            max_sublayers,
             layers: vec![VpsLayer::new(9), VpsLayer::new(10)],
             each_layer_is_an_ols: false,
             ols_mode: OlsMode::All,
             num_output_layer_sets: 2,
-            ptl_max_tids: vec![1, 2],
+            ptl_max_tids: vec![max_sublayers, max_sublayers],
             ols_ptl_idx: vec![0, 1],
             ols_output_layer_flags: vec![vec![true, true], vec![true, true]],
             num_ptls: 1,
             default_ptl_dpb_hrd_max_tid_flag: true,
             profile_tier_levels: vec![ProfileTierLevel::new(true), ProfileTierLevel::new(false)],
             general_timing_hrd_parameters: None,
-            sublayer_dpb_params_present_flag: false,
-            dpb_parameters: vec![DpbParameter::new()],
+            sublayer_dpb_params_present_flag: max_sublayers > 1,
+            dpb_parameters: vec![(0..max_sublayers).map(|_| DpbParameter::new()).collect()],
             ols_dpb_parameters: vec![OlsDpbParameter::new(
                 width,
                 height,
@@ -121,7 +123,10 @@ impl VideoParameterSet {
 
     /// equal to 1 specifies that all layers specified by the VPS are independently coded without using inter-layer prediction. vps_all_independent_layers_flag equal to 0 specifies that one or more of the layers specified by the VPS might use inter-layer prediction. When not present, the value of vps_all_independent_layers_flag is inferred to be equal to 1.
     pub fn all_layers_are_independent(&self) -> bool {
-        self.layers.iter().any(|layer| layer.is_independent_layer)
+        self.layers
+            .iter()
+            .take(self.max_layers)
+            .all(|layer| layer.is_independent_layer)
     }
 
     pub fn get_ols_mode_idc(&self) -> usize {
@@ -26,37 +26,37 @@ impl<'a> VpsEncoder<'a> {
 
     pub fn encode(&mut self, vps: &VideoParameterSet) -> Vec<bool> {
         let mut bins = Bins::new();
-        debug_eprint!("vps.id ");
+        trace_syntax_element!(bins, "vps.id ");
         bins.push_initial_bins_with_size(vps.id as u64, 4);
-        debug_eprint!("vps.max_layers ");
+        trace_syntax_element!(bins, "vps.max_layers ");
         bins.push_bins_with_size(vps.max_layers as u64 - 1, 6);
-        debug_eprint!("vps.max_sublayers ");
+        trace_syntax_element!(bins, "vps.max_sublayers ");
         bins.push_bins_with_size(vps.max_sublayers as u64 - 1, 3);
         if vps.max_layers > 1 && vps.max_sublayers > 1 {
-            debug_eprint!("vps.default_ptl_dpb_hrd_max_tid_flag ");
+            trace_syntax_element!(bins, "vps.default_ptl_dpb_hrd_max_tid_flag ");
             bins.push_bin(vps.default_ptl_dpb_hrd_max_tid_flag);
         }
         if vps.max_layers > 1 {
-            debug_eprint!("vps.all_layers_are_independent ");
+            trace_syntax_element!(bins, "vps.all_layers_are_independent ");
             bins.push_bin(vps.all_layers_are_independent());
         }
         for i in 0..vps.max_layers {
             //debug_eprintln!("layer id {}", vps.layers[i].id);
-            debug_eprint!("vps.layer_id ");
+            trace_syntax_element!(bins, "vps.layer_id ");
             bins.push_bins_with_size(vps.layers[i].id as u64, 6);
             if i > 0 && !vps.all_layers_are_independent() {
-                debug_eprint!("vps.layer_is_independent ");
+                trace_syntax_element!(bins, "vps.layer_is_independent ");
                 bins.push_bin(vps.layers[i].is_independent_layer);
                 if !vps.layers[i].is_independent_layer {
-                    debug_eprint!("vps.layer_max_tid_ref_present_flag ");
+                    trace_syntax_element!(bins, "vps.layer_max_tid_ref_present_flag ");
                     bins.push_bin(vps.layers[i].max_tid_ref_present_flag);
                     for j in 0..i {
-                        debug_eprint!("vps.layer_direct_ref_layer_flag ");
+                        trace_syntax_element!(bins, "vps.layer_direct_ref_layer_flag ");
                         bins.push_bin(vps.layers[i].direct_ref_layer_flag[j]);
                         if vps.layers[i].max_tid_ref_present_flag
                             && vps.layers[i].direct_ref_layer_flag[j]
                         {
-                            debug_eprint!("vps.layer_max_tid_il_ref_pics ");
+                            trace_syntax_element!(bins, "vps.layer_max_tid_il_ref_pics ");
                             bins.push_bins_with_size(
                                 vps.layers[i].max_tid_il_ref_pics[j] as u64 + 1,
                                 3,
@@ -68,41 +68,41 @@ impl<'a> VpsEncoder<'a> {
         }
         if vps.max_layers > 1 {
             if vps.all_layers_are_independent() {
-                debug_eprint!("vps.each_layer_is_an_ols ");
+                trace_syntax_element!(bins, "vps.each_layer_is_an_ols ");
                 bins.push_bin(vps.each_layer_is_an_ols);
             }
             if !vps.each_layer_is_an_ols {
                 //debug_eprintln!("each layer is an ols");
                 if !vps.all_layers_are_independent() {
-                    debug_eprint!("vps.old_mode ");
+                    trace_syntax_element!(bins, "vps.old_mode ");
                     bins.push_bins_with_size(vps.ols_mode as u64, 2);
                 }
                 if let OlsMode::Explicit = vps.ols_mode {
                     //debug_eprintln!("output_layer_sets");
-                    debug_eprint!("vps.num_output_layer_sets ");
+                    trace_syntax_element!(bins, "vps.num_output_layer_sets ");
                     bins.push_bins_with_size(vps.num_output_layer_sets as u64 - 2, 8);
                     for i in 1..vps.num_output_layer_sets {
                         for j in 0..vps.max_layers {
                             //debug_eprintln!("{}, {}", i, j);
-                            debug_eprint!("vps.ols_output_layer_flags ");
+                            trace_syntax_element!(bins, "vps.ols_output_layer_flags ");
                             bins.push_bin(vps.ols_output_layer_flags[i][j]);
                         }
                     }
                 }
             }
             //debug_eprintln!("vps_num_ptls: {}", vps.num_ptls);
-            debug_eprint!("vps.num_ptls ");
+            trace_syntax_element!(bins, "vps.num_ptls ");
             bins.push_bins_with_size(vps.num_ptls as u64 - 1, 8);
         }
         for i in 0..vps.num_ptls {
             //debug_eprintln!("np");
             if i > 0 {
-                debug_eprint!("vps.pt_present_flags ");
+                trace_syntax_element!(bins, "vps.pt_present_flags ");
                 bins.push_bin(vps.profile_tier_levels[i].pt_present_flags);
             }
             if !vps.default_ptl_dpb_hrd_max_tid_flag {
                 //debug_eprintln!("max tid {}", vps.ptl_max_tids[i]);
-                debug_eprint!("vps.max_tids ");
+                trace_syntax_element!(bins, "vps.max_tids ");
                 bins.push_bins_with_size(vps.ptl_max_tids[i] as u64, 3);
             }
         }
@@ -138,17 +138,17 @@ impl<'a> VpsEncoder<'a> {
             for i in 0..ectx.total_num_olss {
                 if vps.num_ptls > 1 && vps.num_ptls != ectx.total_num_olss {
                     //debug_eprintln!("vps_ols_ptl_idx: {} {}", i, vps.ols_ptl_idx[i]);
-                    debug_eprint!("vps.ols_ptl_idx ");
+                    trace_syntax_element!(bins, "vps.ols_ptl_idx ");
                     bins.push_bins_with_size(vps.ols_ptl_idx[i] as u64, 8);
                 }
             }
         }
         if !vps.each_layer_is_an_ols {
-            debug_eprint!("vps.num_dpb_params ");
+            trace_syntax_element!(bins, "vps.num_dpb_params ");
             self.coder
                 .encode_unsigned_exp_golomb(&mut bins, vps.dpb_parameters.len() as u64 - 1);
             if vps.max_sublayers > 1 {
-                debug_eprint!("vps.sublayer_dpb_params_present_flag ");
+                trace_syntax_element!(bins, "vps.sublayer_dpb_params_present_flag ");
                 bins.push_bin(vps.sublayer_dpb_params_present_flag);
             }
             // FIXME
@@ -163,35 +163,36 @@ impl<'a> VpsEncoder<'a> {
                 ectx.vps_num_dpb_params
             };
             for i in 0..vps_num_dpb_params {
+                let num_sublayers = vps.dpb_parameters[i].len();
                 if !vps.default_ptl_dpb_hrd_max_tid_flag {
-                    debug_eprint!("dpb.max_tid ");
-                    bins.push_bins_with_size(vps.dpb_parameters[i].max_tid as u64, 3);
+                    trace_syntax_element!(bins, "dpb.max_tid ");
+                    bins.push_bins_with_size(num_sublayers as u64 - 1, 3);
                 }
                 let ectx = self.encoder_context.clone();
                 let mut dpbp_encoder = DpbpEncoder::new(&ectx, self.coder);
                 dpbp_encoder.encode(
                     &mut bins,
-                    &vps.dpb_parameters,
-                    vps.dpb_parameters[i].max_tid,
+                    &vps.dpb_parameters[i],
+                    num_sublayers,
                     vps.sublayer_dpb_params_present_flag,
                 );
             }
             let ectx = self.encoder_context.clone();
             let ectx = ectx.lock().unwrap();
             for i in 0..ectx.num_multi_layer_olss {
-                debug_eprint!("vps.ols_dpbp_pic_width ");
+                trace_syntax_element!(bins, "vps.ols_dpbp_pic_width ");
                 self.coder.encode_unsigned_exp_golomb(
                     &mut bins,
                     vps.ols_dpb_parameters[i].pic_width as u64,
                 );
-                debug_eprint!("vps.ols_dpbp_pic_height ");
+                trace_syntax_element!(bins, "vps.ols_dpbp_pic_height ");
                 self.coder.encode_unsigned_exp_golomb(
                     &mut bins,
                     vps.ols_dpb_parameters[i].pic_height as u64,
                 );
-                debug_eprint!("dpb.chroma_format ");
+                trace_syntax_element!(bins, "dpb.chroma_format ");
                 bins.push_bins_with_size(vps.ols_dpb_parameters[i].chroma_format as u64, 2);
-                debug_eprint!("vps.ols_dpbp_bitdepth ");
+                trace_syntax_element!(bins, "vps.ols_dpbp_bitdepth ");
                 self.coder.encode_unsigned_exp_golomb(
                     &mut bins,
                     vps.ols_dpb_parameters[i].bitdepth as u64 - 8,
@@ -200,7 +201,7 @@ impl<'a> VpsEncoder<'a> {
                 if ectx.vps_num_dpb_params > 1
                     && ectx.vps_num_dpb_params != ectx.num_multi_layer_olss
                 {
-                    debug_eprint!("vps.ols_dpbp_params_idx ");
+                    trace_syntax_element!(bins, "vps.ols_dpbp_params_idx ");
                     self.coder.encode_unsigned_exp_golomb(
                         &mut bins,
                         vps.ols_dpb_parameters[i].params_idx as u64,
@@ -208,7 +209,7 @@ impl<'a> VpsEncoder<'a> {
                 }
             }
 
-            debug_eprint!("vps.general_timing_hrd_parameters ");
+            trace_syntax_element!(bins, "vps.general_timing_hrd_parameters ");
             bins.push_bin(vps.general_timing_hrd_parameters.is_some());
             if let Some(hrd_params) = &vps.general_timing_hrd_parameters {
                 let ectx = self.encoder_context.clone();
@@ -217,17 +218,17 @@ impl<'a> VpsEncoder<'a> {
                     hrd_encoder.encode_general_timing_hrd_parameters(&mut bins, hrd_params);
                 }
                 if vps.max_sublayers > 1 {
-                    debug_eprint!("vps.sublayer_cpb_params_present_flag ");
+                    trace_syntax_element!(bins, "vps.sublayer_cpb_params_present_flag ");
                     bins.push_bin(vps.sublayer_cpb_params_present_flag);
                 }
-                debug_eprint!("vps.num_ols_timing_hrd_params ");
+                trace_syntax_element!(bins, "vps.num_ols_timing_hrd_params ");
                 self.coder.encode_unsigned_exp_golomb(
                     &mut bins,
                     vps.num_ols_timing_hrd_params as u64 - 1,
                 );
                 for i in 0..vps.num_ols_timing_hrd_params {
                     if !vps.default_ptl_dpb_hrd_max_tid_flag {
-                        debug_eprint!("vps.hrd_max_tids ");
+                        trace_syntax_element!(bins, "vps.hrd_max_tids ");
                         bins.push_bins_with_size(vps.hrd_max_tids[i] as u64, 3);
                     }
                     let first_sublayer = if vps.sublayer_cpb_params_present_flag {
@@ -254,7 +255,7 @@ impl<'a> VpsEncoder<'a> {
                     && vps.num_ols_timing_hrd_params != ectx.num_multi_layer_olss
                 {
                     for i in 0..ectx.num_multi_layer_olss {
-                        debug_eprint!("vps.ols_timing_hrd_idxs ");
+                        trace_syntax_element!(bins, "vps.ols_timing_hrd_idxs ");
                         self.coder.encode_unsigned_exp_golomb(
                             &mut bins,
                             vps.ols_timing_hrd_idxs[i] as u64,
@@ -263,15 +264,15 @@ impl<'a> VpsEncoder<'a> {
                 }
             }
         }
-        debug_eprint!("vps.extension_data_present ");
+        trace_syntax_element!(bins, "vps.extension_data_present ");
         bins.push_bin(!vps.extension_data.is_empty());
         // FIXME
         for i in 0..vps.extension_data.len() {
-            debug_eprint!("vps.extension_data ");
+            trace_syntax_element!(bins, "vps.extension_data ");
             bins.push_bin(vps.extension_data[i]);
         }
         let rbsp_stop_one_bit = true;
-        debug_eprint!("rbsp_stop_one_bit ");
+        trace_syntax_element!(bins, "rbsp_stop_one_bit ");
         bins.push_bin(rbsp_stop_one_bit);
         bins.byte_align();
         //debug_eprintln!("{}", bits.len());
@@ -0,0 +1,256 @@
+use super::binary_reader::*;
+use super::common::*;
+use super::dpb::*;
+use super::dpbp_parser::*;
+use super::encoder_context::*;
+use super::hrd_parser::*;
+use super::ptl_parser::*;
+use super::vps::*;
+use std::sync::{Arc, Mutex};
+
+pub struct VpsParser<'a, 'b> {
+    reader: &'a mut BinaryReader<'b>,
+    encoder_context: Arc<Mutex<EncoderContext>>,
+}
+
+impl<'a, 'b> VpsParser<'a, 'b> {
+    pub fn new(
+        encoder_context: &Arc<Mutex<EncoderContext>>,
+        reader: &'a mut BinaryReader<'b>,
+    ) -> VpsParser<'a, 'b> {
+        VpsParser {
+            reader,
+            encoder_context: encoder_context.clone(),
+        }
+    }
+
+    /// Parses video_parameter_set_rbsp( ). The derived variables are stored to the encoder
+    /// context as update_from_vps does.
+    pub fn parse(&mut self) -> VideoParameterSet {
+        let id = self.reader.read_value(4);
+        let max_layers = self.reader.read_value(6) + 1;
+        let max_sublayers = self.reader.read_value(3) + 1;
+        let mut vps = VideoParameterSet::new(id, 0, 0, 8, ChromaFormat::YCbCr420, max_sublayers);
+        vps.max_layers = max_layers;
+        if vps.max_layers > 1 && vps.max_sublayers > 1 {
+            vps.default_ptl_dpb_hrd_max_tid_flag = self.reader.read_bit();
+        }
+        let all_independent_layers_flag = if vps.max_layers > 1 {
+            self.reader.read_bit()
+        } else {
+            true
+        };
+        vps.layers = vec![];
+        for i in 0..vps.max_layers {
+            let mut layer = VpsLayer::new(self.reader.read_value(6));
+            layer.max_tid_il_ref_pics = vec![max_sublayers; i];
+            layer.direct_ref_layer_flag = vec![false; i];
+            if i > 0 && !all_independent_layers_flag {
+                layer.is_independent_layer = self.reader.read_bit();
+                if !layer.is_independent_layer {
+                    layer.max_tid_ref_present_flag = self.reader.read_bit();
+                    for j in 0..i {
+                        layer.direct_ref_layer_flag[j] = self.reader.read_bit();
+                        if layer.max_tid_ref_present_flag && layer.direct_ref_layer_flag[j] {
+                            layer.max_tid_il_ref_pics[j] =
+                                self.reader.read_value(3).saturating_sub(1);
+                        }
+                    }
+                }
+            }
+            vps.layers.push(layer);
+        }
+        // the encoder signals vps_each_layer_is_an_ols_flag only with multiple layers and keeps
+        // it 0 otherwise
+        // FIXME inferred to be equal to 1 when vps_max_layers_minus1 is equal to 0
+        vps.each_layer_is_an_ols = false;
+        vps.num_ptls = 1;
+        if vps.max_layers > 1 {
+            if all_independent_layers_flag {
+                vps.each_layer_is_an_ols = self.reader.read_bit();
+            }
+            if !vps.each_layer_is_an_ols {
+                if !all_independent_layers_flag {
+                    vps.ols_mode = match self.reader.read_value(2) {
+                        0 => OlsMode::Highest,
+                        1 => OlsMode::All,
+                        2 => OlsMode::Explicit,
+                        _ => panic!(),
+                    };
+                }
+                if let OlsMode::Explicit = vps.ols_mode {
+                    vps.num_output_layer_sets = self.reader.read_value(8) + 2;
+                    vps.ols_output_layer_flags =
+                        vec![vec![false; vps.max_layers]; vps.num_output_layer_sets];
+                    for i in 1..vps.num_output_layer_sets {
+                        for j in 0..vps.max_layers {
+                            vps.ols_output_layer_flags[i][j] = self.reader.read_bit();
+                        }
+                    }
+                }
+            }
+            vps.num_ptls = self.reader.read_value(8) + 1;
+        }
+        let mut pt_present_flags = vec![true; vps.num_ptls];
+        vps.ptl_max_tids = vec![vps.max_sublayers; vps.num_ptls];
+        for (i, (pt_present_flag, ptl_max_tid)) in pt_present_flags
+            .iter_mut()
+            .zip(vps.ptl_max_tids.iter_mut())
+            .enumerate()
+        {
+            if i > 0 {
+                *pt_present_flag = self.reader.read_bit();
+            }
+            if !vps.default_ptl_dpb_hrd_max_tid_flag {
+                *ptl_max_tid = self.reader.read_value(3);
+            }
+        }
+        self.reader.byte_align();
+        let ectx = self.encoder_context.clone();
+        {
+            let mut ptl_parser = PtlParser::new(&ectx, self.reader);
+            vps.profile_tier_levels = (0..vps.num_ptls)
+                .map(|i| ptl_parser.parse(pt_present_flags[i], vps.ptl_max_tids[i]))
+                .collect();
+        }
+        let total_num_olss = match vps.each_layer_is_an_ols {
+            true => vps.max_layers,
+            false => match vps.ols_mode {
+                OlsMode::Highest | OlsMode::All => vps.max_layers,
+                OlsMode::Explicit => vps.num_output_layer_sets,
+            },
+        };
+        vps.ols_ptl_idx = (0..total_num_olss)
+            .map(|i| {
+                if vps.num_ptls > 1 && vps.num_ptls != total_num_olss {
+                    self.reader.read_value(8)
+                } else if vps.num_ptls == total_num_olss {
+                    i
+                } else {
+                    0
+                }
+            })
+            .collect();
+        vps.dpb_parameters = vec![];
+        vps.ols_dpb_parameters = vec![];
+        vps.sublayer_dpb_params_present_flag = false;
+        if !vps.each_layer_is_an_ols {
+            let num_dpb_params = self.reader.read_unsigned_exp_golomb() + 1;
+            if vps.max_sublayers > 1 {
+                vps.sublayer_dpb_params_present_flag = self.reader.read_bit();
+            }
+            for _ in 0..num_dpb_params {
+                let num_sublayers = if !vps.default_ptl_dpb_hrd_max_tid_flag {
+                    self.reader.read_value(3) + 1
+                } else {
+                    vps.max_sublayers
+                };
+                let mut dpbp_parser = DpbpParser::new(&ectx, self.reader);
+                vps.dpb_parameters
+                    .push(dpbp_parser.parse(num_sublayers, vps.sublayer_dpb_params_present_flag));
+            }
+        }
+        ectx.lock().unwrap().update_from_vps(&vps);
+        if !vps.each_layer_is_an_ols {
+            let (vps_num_dpb_params, num_multi_layer_olss) = {
+                let ectx = ectx.lock().unwrap();
+                (ectx.vps_num_dpb_params, ectx.num_multi_layer_olss)
+            };
+            for _ in 0..num_multi_layer_olss {
+                let pic_width = self.reader.read_unsigned_exp_golomb();
+                let pic_height = self.reader.read_unsigned_exp_golomb();
+                let chroma_format = ChromaFormat::from_idc(self.reader.read_value(2));
+                let bitdepth = self.reader.read_unsigned_exp_golomb() + 8;
+                let params_idx =
+                    if vps_num_dpb_params > 1 && vps_num_dpb_params != num_multi_layer_olss {
+                        self.reader.read_unsigned_exp_golomb()
+                    } else {
+                        0
+                    };
+                vps.ols_dpb_parameters.push(OlsDpbParameter::new(
+                    pic_width,
+                    pic_height,
+                    chroma_format,
+                    bitdepth,
+                    params_idx,
+                ));
+            }
+
+            let general_timing_hrd_parameters_present_flag = self.reader.read_bit();
+            if general_timing_hrd_parameters_present_flag {
+                let mut hrd_parser = HrdParser::new(&ectx, self.reader);
+                let general_timing_hrd_parameters =
+                    hrd_parser.parse_general_timing_hrd_parameters();
+                if vps.max_sublayers > 1 {
+                    vps.sublayer_cpb_params_present_flag = self.reader.read_bit();
+                }
+                vps.num_ols_timing_hrd_params = self.reader.read_unsigned_exp_golomb() + 1;
+                vps.hrd_max_tids = vec![vps.max_sublayers - 1; vps.num_ols_timing_hrd_params];
+                for i in 0..vps.num_ols_timing_hrd_params {
+                    if !vps.default_ptl_dpb_hrd_max_tid_flag {
+                        vps.hrd_max_tids[i] = self.reader.read_value(3);
+                    }
+                    let first_sublayer = if vps.sublayer_cpb_params_present_flag {
+                        0
+                    } else {
+                        vps.hrd_max_tids[i]
+                    };
+                    let mut hrd_parser = HrdParser::new(&ectx, self.reader);
+                    hrd_parser.parse_ols_timing_hrd_parameters(
+                        &mut vps.ols_timing_hrd_parameters,
+                        &general_timing_hrd_parameters,
+                        first_sublayer,
+                        vps.hrd_max_tids[i],
+                    );
+                }
+                if vps.num_ols_timing_hrd_params > 1
+                    && vps.num_ols_timing_hrd_params != num_multi_layer_olss
+                {
+                    vps.ols_timing_hrd_idxs = (0..num_multi_layer_olss)
+                        .map(|_| self.reader.read_unsigned_exp_golomb())
+                        .collect();
+                }
+                vps.general_timing_hrd_parameters = Some(general_timing_hrd_parameters);
+            }
+        }
+        let extension_flag = self.reader.read_bit();
+        if extension_flag {
+            while self.reader.more_rbsp_data() {
+                vps.extension_data.push(self.reader.read_bit());
+            }
+        }
+        let _rbsp_stop_one_bit = self.reader.read_bit();
+        vps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bool_coder::*;
+    use crate::nal::*;
+    use crate::vps_encoder::*;
+
+    #[test]
+    fn vps_round_trip_works() {
+        let vps = VideoParameterSet::new(8, 128, 64, 8, ChromaFormat::YCbCr420, 3);
+        let ectx = Arc::new(Mutex::new(EncoderContext::new()));
+        ectx.lock().unwrap().update_from_vps(&vps);
+        let mut coder = BoolCoder::new();
+        let bits = VpsEncoder::new(&ectx, &mut coder).encode(&vps);
+
+        let rbsp = write_and_read_rbsp(NALUnitType::VPS_NUT, &bits);
+        let mut reader = BinaryReader::vec(&rbsp).unwrap();
+        let parsed_ectx = Arc::new(Mutex::new(EncoderContext::new()));
+        let parsed = VpsParser::new(&parsed_ectx, &mut reader).parse();
+        assert_eq!(parsed.id, 8);
+        assert_eq!(parsed.max_sublayers, 3);
+        assert_eq!(parsed.num_ptls, vps.num_ptls);
+        assert_eq!(
+            parsed_ectx.lock().unwrap().total_num_olss,
+            ectx.lock().unwrap().total_num_olss
+        );
+        let reencoded = VpsEncoder::new(&parsed_ectx, &mut coder).encode(&parsed);
+        assert_eq!(reencoded, bits);
+    }
+}